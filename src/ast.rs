@@ -0,0 +1,386 @@
+use crate::value::Value;
+
+/// A statement that can be run against a database
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Select),
+    /// EXPLAIN QUERY PLAN, which describes how the SELECT would be run instead of running it
+    ExplainQueryPlan(Select),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Select {
+    pub with: Option<With>,
+    /// SELECT DISTINCT, which drops rows equal to an earlier one
+    pub distinct: bool,
+    pub columns: Vec<ResultColumn>,
+    pub from: Option<FromClause>,
+    pub where_clause: Option<Expr>,
+    pub group_by: Vec<Expr>,
+    pub having: Option<Expr>,
+    /// The windows named by the WINDOW clause
+    pub windows: Vec<(String, WindowDefinition)>,
+    /// The SELECTs combined with this one, from left to right. ORDER BY and LIMIT apply to the
+    /// combined rows, and the combined SELECTs have neither.
+    pub compound: Vec<(CompoundOperator, Select)>,
+    pub order_by: Vec<OrderingTerm>,
+    pub limit: Option<Limit>,
+}
+
+/// The WITH clause, which names tables that the statement can refer to
+#[derive(Debug, Clone, PartialEq)]
+pub struct With {
+    pub recursive: bool,
+    pub tables: Vec<CommonTableExpression>,
+}
+
+/// `name(columns) AS (select)`. The columns are named after the SELECT's result columns when
+/// the list is empty.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommonTableExpression {
+    pub name: String,
+    pub columns: Vec<String>,
+    pub select: Select,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CompoundOperator {
+    Union,
+    UnionAll,
+    Intersect,
+    Except,
+}
+
+/// The FROM clause: a table and the tables joined to it, from left to right
+#[derive(Debug, Clone, PartialEq)]
+pub struct FromClause {
+    pub table: TableRef,
+    pub joins: Vec<Join>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Join {
+    pub operator: JoinOperator,
+    pub natural: bool,
+    pub table: TableRef,
+    pub constraint: Option<JoinConstraint>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum JoinOperator {
+    /// `,`, `JOIN` or `INNER JOIN`
+    Inner,
+    /// `LEFT [OUTER] JOIN`
+    Left,
+    /// `CROSS JOIN`
+    Cross,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JoinConstraint {
+    On(Expr),
+    Using(Vec<String>),
+}
+
+/// A table in the FROM clause
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRef {
+    pub source: TableSource,
+    pub alias: Option<String>,
+}
+
+impl TableRef {
+    /// The name columns of the table are qualified with
+    pub fn name(&self) -> &str {
+        match (&self.alias, &self.source) {
+            (Some(alias), _) => alias,
+            (None, TableSource::Table(name)) => name,
+            (None, TableSource::Function { name, .. }) => name,
+            // The columns of an unnamed subquery can only be referred to without a table name
+            (None, TableSource::Subquery(_)) => "",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableSource {
+    Table(String),
+    /// A table-valued function like `json_each(...)`
+    Function {
+        name: String,
+        args: Vec<Expr>,
+    },
+    /// `(select)`
+    Subquery(Box<Select>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResultColumn {
+    /// `*`
+    Star,
+    /// `table.*`
+    TableStar(String),
+    /// An expression, with the text it was parsed from so that the output column can be
+    /// named after it
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        text: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderingTerm {
+    pub expr: Expr,
+    pub descending: bool,
+    /// Explicit `NULLS FIRST` (true) or `NULLS LAST` (false)
+    pub nulls_first: Option<bool>,
+}
+
+/// `LIMIT limit OFFSET offset`, or the equivalent `LIMIT offset, limit`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Limit {
+    pub limit: Expr,
+    pub offset: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Value),
+    Column {
+        table: Option<String>,
+        name: String,
+    },
+    Unary(UnaryOperator, Box<Expr>),
+    Binary(Box<Expr>, BinaryOperator, Box<Expr>),
+    /// `expr IS NULL`, or `expr IS NOT NULL` when negated
+    IsNull {
+        expr: Box<Expr>,
+        negated: bool,
+    },
+    Between {
+        expr: Box<Expr>,
+        low: Box<Expr>,
+        high: Box<Expr>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<Expr>,
+        list: Vec<Expr>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<Expr>,
+        pattern: Box<Expr>,
+        escape: Option<Box<Expr>>,
+        glob: bool,
+        negated: bool,
+    },
+    Function(FunctionCall),
+    Collate(Box<Expr>, String),
+    /// `CAST(expr AS type_name)`
+    Cast {
+        expr: Box<Expr>,
+        type_name: String,
+    },
+    /// `CASE [operand] WHEN .. THEN .. [ELSE ..] END`
+    Case {
+        operand: Option<Box<Expr>>,
+        branches: Vec<(Expr, Expr)>,
+        else_result: Option<Box<Expr>>,
+    },
+    /// A scalar subquery, whose value is the first column of its first row
+    Subquery(Box<Select>),
+    /// `EXISTS (select)`
+    Exists(Box<Select>),
+    /// `expr IN (select)`
+    InSelect {
+        expr: Box<Expr>,
+        select: Box<Select>,
+        negated: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionCall {
+    pub name: String,
+    pub distinct: bool,
+    pub args: Vec<Expr>,
+    /// `count(*)`
+    pub wildcard: bool,
+    /// `FILTER (WHERE expr)` of an aggregate function
+    pub filter: Option<Box<Expr>>,
+    /// The window of a window function call
+    pub over: Option<Box<Over>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Over {
+    /// `OVER name`, a window named by the WINDOW clause
+    Window(String),
+    /// `OVER (definition)`
+    Definition(WindowDefinition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowDefinition {
+    /// A window named by the WINDOW clause that this one adds to
+    pub base: Option<String>,
+    pub partition_by: Vec<Expr>,
+    pub order_by: Vec<OrderingTerm>,
+    pub frame: Option<Frame>,
+}
+
+/// Which rows of its partition the window of a row holds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub units: FrameUnits,
+    pub start: FrameBound,
+    pub end: FrameBound,
+    pub exclude: FrameExclude,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameUnits {
+    Rows,
+    Range,
+    Groups,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameBound {
+    UnboundedPreceding,
+    Preceding(Box<Expr>),
+    CurrentRow,
+    Following(Box<Expr>),
+    UnboundedFollowing,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameExclude {
+    NoOthers,
+    CurrentRow,
+    Group,
+    Ties,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum UnaryOperator {
+    Negate,
+    Plus,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BinaryOperator {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Is,
+    IsNot,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    BitAnd,
+    BitOr,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Concat,
+    /// `->`, which extracts a JSON subcomponent as JSON
+    Extract,
+    /// `->>`, which extracts a JSON subcomponent as an SQL value
+    ExtractValue,
+}
+
+impl BinaryOperator {
+    pub fn is_comparison(self) -> bool {
+        use BinaryOperator::*;
+        matches!(self, Eq | NotEq | Is | IsNot | Lt | LtEq | Gt | GtEq)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateView {
+    pub name: String,
+    /// Names given to the columns of the SELECT, if any
+    pub columns: Vec<String>,
+    pub select: Select,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateTable {
+    pub name: String,
+    pub columns: Vec<ColumnDefinition>,
+    pub constraints: Vec<TableConstraint>,
+    /// Whether the rows are stored in a b-tree keyed by the PRIMARY KEY instead of the rowid
+    pub without_rowid: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnDefinition {
+    pub name: String,
+    pub type_name: Option<String>,
+    pub constraints: Vec<ColumnConstraint>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnConstraint {
+    PrimaryKey {
+        descending: bool,
+        autoincrement: bool,
+    },
+    NotNull,
+    Unique,
+    Check(Expr),
+    Default(Expr),
+    Collate(String),
+    ForeignKey,
+    /// `GENERATED ALWAYS AS (expr)`, which is VIRTUAL unless it's STORED
+    Generated {
+        expr: Expr,
+        stored: bool,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TableConstraint {
+    PrimaryKey(Vec<IndexedColumn>),
+    Unique(Vec<IndexedColumn>),
+    Check(Expr),
+    ForeignKey,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CreateIndex {
+    pub name: String,
+    pub table: String,
+    pub unique: bool,
+    pub columns: Vec<IndexedColumn>,
+    pub where_clause: Option<Expr>,
+}
+
+/// A column of an index or of a PRIMARY KEY/UNIQUE constraint
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedColumn {
+    pub expr: Expr,
+    pub collation: Option<String>,
+    pub descending: bool,
+}
+
+impl IndexedColumn {
+    /// Name of the column, when the indexed expression is a plain column reference
+    pub fn column_name(&self) -> Option<&str> {
+        match &self.expr {
+            Expr::Column { table: None, name } => Some(name),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use crate::database::Database;
+use crate::header::{BTreePage, PageHeader};
+use crate::varint::parse_varint;
+use anyhow::{bail, Result};
+use std::borrow::Cow;
+use std::convert::TryInto;
+
+/// A page on the path from the root of a b-tree to the cell the cursor is positioned on
+struct Frame<'a> {
+    page: &'a [u8],
+    header: PageHeader,
+    /// Offset of the cell pointer array within the page
+    cell_pointers: usize,
+    /// Next cell to visit. `number_of_cells` refers to the right-most pointer. When the cursor
+    /// goes backwards, the cell before `next` is visited next.
+    next: usize,
+    /// Whether the left child of the `next` cell has already been visited, or going backwards,
+    /// whether the cell before `next` has. Only index interior pages need this, since their
+    /// cells carry keys that sit between two subtrees.
+    visited_child: bool,
+}
+
+/// In-order traversal over the cells of a b-tree, in either direction. Pages are only read when
+/// the traversal reaches them, so dropping the cursor early skips the rest of the tree.
+struct Cursor<'a> {
+    db: &'a Database,
+    stack: Vec<Frame<'a>>,
+    /// Whether cells are visited from the last one to the first
+    reverse: bool,
+}
+
+/// A cell of a b-tree page. Cells of table interior pages have no payload.
+struct Cell<'a> {
+    rowid: i64,
+    payload: Cow<'a, [u8]>,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(db: &'a Database, root_page: u32, reverse: bool) -> Result<Self> {
+        let mut cursor = Cursor {
+            db,
+            stack: vec![],
+            reverse,
+        };
+        cursor.push(root_page)?;
+        Ok(cursor)
+    }
+
+    fn push(&mut self, page_number: u32) -> Result<()> {
+        let page = self.db.page(page_number)?;
+        // Page 1 starts with the 100 byte database header
+        let header_offset = if page_number == 1 { 100 } else { 0 };
+        let (read, header) = PageHeader::parse(&page[header_offset..])?;
+
+        // Going backwards starts after the right-most pointer, or after the last cell of a leaf
+        let next = match self.reverse {
+            true => header.number_of_cells as usize + header.right_most_pointer.is_some() as usize,
+            false => 0,
+        };
+        self.stack.push(Frame {
+            page,
+            header,
+            cell_pointers: header_offset + read,
+            next,
+            visited_child: false,
+        });
+        Ok(())
+    }
+
+    fn next_cell(&mut self) -> Result<Option<Cell<'a>>> {
+        match self.reverse {
+            true => self.previous_cell(),
+            false => self.following_cell(),
+        }
+    }
+
+    fn following_cell(&mut self) -> Result<Option<Cell<'a>>> {
+        loop {
+            let frame = match self.stack.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            let number_of_cells = frame.header.number_of_cells as usize;
+
+            if frame.next > number_of_cells
+                || (frame.next == number_of_cells && frame.header.right_most_pointer.is_none())
+            {
+                self.stack.pop();
+                continue;
+            }
+
+            if frame.next == number_of_cells {
+                frame.next += 1;
+                let right_most_pointer = frame.header.right_most_pointer.unwrap();
+                self.push(right_most_pointer)?;
+                continue;
+            }
+
+            let index = frame.next;
+            match frame.header.page_type {
+                BTreePage::LeafTable | BTreePage::LeafIndex => {
+                    frame.next += 1;
+                    return self.read_cell(index).map(Some);
+                }
+                BTreePage::InteriorTable => {
+                    frame.next += 1;
+                    let left_child = self.left_child(index)?;
+                    self.push(left_child)?;
+                }
+                BTreePage::InteriorIndex => {
+                    if !frame.visited_child {
+                        frame.visited_child = true;
+                        let left_child = self.left_child(index)?;
+                        self.push(left_child)?;
+                        continue;
+                    }
+
+                    frame.visited_child = false;
+                    frame.next += 1;
+                    return self.read_cell(index).map(Some);
+                }
+            }
+        }
+    }
+
+    fn previous_cell(&mut self) -> Result<Option<Cell<'a>>> {
+        loop {
+            let frame = match self.stack.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(None),
+            };
+            if frame.next == 0 {
+                self.stack.pop();
+                continue;
+            }
+
+            let index = frame.next - 1;
+            if index == frame.header.number_of_cells as usize {
+                frame.next -= 1;
+                let right_most_pointer = frame.header.right_most_pointer.unwrap();
+                self.push(right_most_pointer)?;
+                continue;
+            }
+            match frame.header.page_type {
+                BTreePage::LeafTable | BTreePage::LeafIndex => {
+                    frame.next -= 1;
+                    return self.read_cell(index).map(Some);
+                }
+                BTreePage::InteriorTable => {
+                    frame.next -= 1;
+                    let left_child = self.left_child(index)?;
+                    self.push(left_child)?;
+                }
+                BTreePage::InteriorIndex => {
+                    // The cell comes before the subtree to its right and after its left child
+                    if !frame.visited_child {
+                        frame.visited_child = true;
+                        return self.read_cell(index).map(Some);
+                    }
+
+                    frame.visited_child = false;
+                    frame.next -= 1;
+                    let left_child = self.left_child(index)?;
+                    self.push(left_child)?;
+                }
+            }
+        }
+    }
+
+    /// The content of the cell at `index` on the current page
+    fn cell_content(&self, index: usize) -> Result<&'a [u8]> {
+        let frame = self.stack.last().unwrap();
+        let pointer = frame.cell_pointers + 2 * index;
+        let cell_offset = u16::from_be_bytes(frame.page[pointer..pointer + 2].try_into()?) as usize;
+        Ok(&frame.page[cell_offset..])
+    }
+
+    /// The page that the cell at `index` on the current interior page points to
+    fn left_child(&self, index: usize) -> Result<u32> {
+        let cell = self.cell_content(index)?;
+        Ok(u32::from_be_bytes(cell[0..4].try_into()?))
+    }
+
+    /// Reads the cell at `index` on the current page. Interior table cells only carry a rowid,
+    /// the largest one in their left child.
+    fn read_cell(&self, index: usize) -> Result<Cell<'a>> {
+        let cell = self.cell_content(index)?;
+        let page_type = &self.stack.last().unwrap().header.page_type;
+
+        Ok(match page_type {
+            BTreePage::LeafTable => {
+                let (payload_size, offset) = parse_varint(cell);
+                let (rowid, read_bytes) = parse_varint(&cell[offset..]);
+                let payload =
+                    read_payload(self.db, &cell[offset + read_bytes..], payload_size, true)?;
+                Cell {
+                    rowid: rowid as i64,
+                    payload,
+                }
+            }
+            BTreePage::LeafIndex => {
+                let (payload_size, offset) = parse_varint(cell);
+                let payload = read_payload(self.db, &cell[offset..], payload_size, false)?;
+                Cell { rowid: 0, payload }
+            }
+            BTreePage::InteriorTable => {
+                let (rowid, _) = parse_varint(&cell[4..]);
+                Cell {
+                    rowid: rowid as i64,
+                    payload: Cow::Borrowed(&[]),
+                }
+            }
+            BTreePage::InteriorIndex => {
+                let (payload_size, offset) = parse_varint(&cell[4..]);
+                let payload = read_payload(self.db, &cell[4 + offset..], payload_size, false)?;
+                Cell { rowid: 0, payload }
+            }
+        })
+    }
+
+    /// Moves the cursor down from the root to the first cell that `is_before` is false for, so
+    /// that the traversal continues from there. `is_before` must hold for the cells that sort
+    /// before some key and for no others.
+    fn seek(&mut self, mut is_before: impl FnMut(&Cell) -> Result<bool>) -> Result<()> {
+        loop {
+            let number_of_cells = match self.stack.last() {
+                Some(frame) => frame.header.number_of_cells as usize,
+                None => return Ok(()),
+            };
+            let (mut low, mut high) = (0, number_of_cells);
+            while low < high {
+                let middle = (low + high) / 2;
+                if is_before(&self.read_cell(middle)?)? {
+                    low = middle + 1;
+                } else {
+                    high = middle;
+                }
+            }
+
+            let left_child = match self.stack.last().unwrap().header.page_type {
+                BTreePage::InteriorTable | BTreePage::InteriorIndex if low < number_of_cells => {
+                    Some(self.left_child(low)?)
+                }
+                _ => None,
+            };
+            let frame = self.stack.last_mut().unwrap();
+            let child = match (&frame.header.page_type, left_child) {
+                (BTreePage::LeafTable, _) | (BTreePage::LeafIndex, _) => {
+                    frame.next = low;
+                    return Ok(());
+                }
+                (_, None) => {
+                    frame.next = number_of_cells + 1;
+                    frame.header.right_most_pointer.unwrap()
+                }
+                (BTreePage::InteriorTable, Some(left_child)) => {
+                    frame.next = low + 1;
+                    left_child
+                }
+                (BTreePage::InteriorIndex, Some(left_child)) => {
+                    // The cell itself comes after its left child
+                    frame.next = low;
+                    frame.visited_child = true;
+                    left_child
+                }
+            };
+            self.push(child)?;
+        }
+    }
+
+    /// Moves the cursor down from the root to the last cell that `is_after` is false for, so
+    /// that the traversal continues backwards from there. `is_after` must hold for the cells
+    /// that sort after some key and for no others.
+    fn seek_back(&mut self, mut is_after: impl FnMut(&Cell) -> Result<bool>) -> Result<()> {
+        loop {
+            let number_of_cells = match self.stack.last() {
+                Some(frame) => frame.header.number_of_cells as usize,
+                None => return Ok(()),
+            };
+            let (mut low, mut high) = (0, number_of_cells);
+            while low < high {
+                let middle = (low + high) / 2;
+                if is_after(&self.read_cell(middle)?)? {
+                    high = middle;
+                } else {
+                    low = middle + 1;
+                }
+            }
+
+            // The cells before the first one after the key come next, and the subtree to its
+            // left may hold some that aren't after it
+            let left_child = match self.stack.last().unwrap().header.page_type {
+                BTreePage::InteriorTable | BTreePage::InteriorIndex if low < number_of_cells => {
+                    Some(self.left_child(low)?)
+                }
+                _ => None,
+            };
+            let frame = self.stack.last_mut().unwrap();
+            frame.next = low;
+            frame.visited_child = false;
+            let child = match (&frame.header.page_type, left_child) {
+                (BTreePage::LeafTable, _) | (BTreePage::LeafIndex, _) => return Ok(()),
+                (_, None) => frame.header.right_most_pointer.unwrap(),
+                (_, Some(left_child)) => left_child,
+            };
+            self.push(child)?;
+        }
+    }
+}
+
+/// Reads a cell's payload, following the chain of overflow pages when the payload doesn't fit
+/// in the cell. See [b-tree pages](https://www.sqlite.org/fileformat.html#b_tree_pages).
+fn read_payload<'a>(
+    db: &'a Database,
+    stream: &'a [u8],
+    payload_size: usize,
+    is_table_leaf: bool,
+) -> Result<Cow<'a, [u8]>> {
+    let usable_size = db.usable_size;
+    let max_local = if is_table_leaf {
+        usable_size - 35
+    } else {
+        (usable_size - 12) * 64 / 255 - 23
+    };
+
+    if payload_size <= max_local {
+        return Ok(Cow::Borrowed(&stream[..payload_size]));
+    }
+
+    let min_local = (usable_size - 12) * 32 / 255 - 23;
+    let surplus = min_local + (payload_size - min_local) % (usable_size - 4);
+    let local_size = if surplus <= max_local {
+        surplus
+    } else {
+        min_local
+    };
+
+    let mut payload = Vec::with_capacity(payload_size);
+    payload.extend_from_slice(&stream[..local_size]);
+
+    let mut overflow_page = u32::from_be_bytes(stream[local_size..local_size + 4].try_into()?);
+    while payload.len() < payload_size {
+        if overflow_page == 0 {
+            bail!("Overflow chain ended before the end of the payload");
+        }
+        let page = db.page(overflow_page)?;
+        let remaining = (payload_size - payload.len()).min(usable_size - 4);
+        payload.extend_from_slice(&page[4..4 + remaining]);
+        overflow_page = u32::from_be_bytes(page[0..4].try_into()?);
+    }
+
+    Ok(Cow::Owned(payload))
+}
+
+/// Iterator over the (rowid, record) pairs of a table b-tree, in rowid order
+pub struct TableScan<'a> {
+    cursor: Option<Cursor<'a>>,
+}
+
+/// Iterator over the records of an index b-tree, in key order
+pub struct IndexScan<'a> {
+    cursor: Option<Cursor<'a>>,
+}
+
+/// Scans every row of the table b-tree rooted at `root_page`
+pub fn scan_table(db: &Database, root_page: u32) -> Result<TableScan<'_>> {
+    Ok(TableScan {
+        cursor: Some(Cursor::new(db, root_page, false)?),
+    })
+}
+
+/// Scans every row of the table b-tree rooted at `root_page`, from the last one to the first
+pub fn scan_table_reverse(db: &Database, root_page: u32) -> Result<TableScan<'_>> {
+    Ok(TableScan {
+        cursor: Some(Cursor::new(db, root_page, true)?),
+    })
+}
+
+/// Scans the rows of a table b-tree from the first one whose rowid is at least `rowid`
+pub fn scan_table_from(db: &Database, root_page: u32, rowid: i64) -> Result<TableScan<'_>> {
+    let mut cursor = Cursor::new(db, root_page, false)?;
+    cursor.seek(|cell| Ok(cell.rowid < rowid))?;
+    Ok(TableScan {
+        cursor: Some(cursor),
+    })
+}
+
+/// Scans the rows of a table b-tree backwards from the last one whose rowid is at most `rowid`
+pub fn scan_table_back_from(db: &Database, root_page: u32, rowid: i64) -> Result<TableScan<'_>> {
+    let mut cursor = Cursor::new(db, root_page, true)?;
+    // The rowid of an interior cell is the largest one in its left child
+    cursor.seek_back(|cell| Ok(cell.rowid > rowid))?;
+    Ok(TableScan {
+        cursor: Some(cursor),
+    })
+}
+
+/// Scans every entry of the index b-tree rooted at `root_page`
+pub fn scan_index(db: &Database, root_page: u32) -> Result<IndexScan<'_>> {
+    Ok(IndexScan {
+        cursor: Some(Cursor::new(db, root_page, false)?),
+    })
+}
+
+/// Scans the entries of an index b-tree from the first record that `is_before` is false for.
+/// `is_before` tells whether a record sorts before the wanted key.
+pub fn scan_index_from<'a>(
+    db: &'a Database,
+    root_page: u32,
+    mut is_before: impl FnMut(&[u8]) -> Result<bool>,
+) -> Result<IndexScan<'a>> {
+    let mut cursor = Cursor::new(db, root_page, false)?;
+    cursor.seek(|cell| is_before(&cell.payload))?;
+    Ok(IndexScan {
+        cursor: Some(cursor),
+    })
+}
+
+/// Scans the entries of an index b-tree backwards from the last record that `is_after` is false
+/// for. `is_after` tells whether a record sorts after the wanted key.
+pub fn scan_index_back_from<'a>(
+    db: &'a Database,
+    root_page: u32,
+    mut is_after: impl FnMut(&[u8]) -> Result<bool>,
+) -> Result<IndexScan<'a>> {
+    let mut cursor = Cursor::new(db, root_page, true)?;
+    cursor.seek_back(|cell| is_after(&cell.payload))?;
+    Ok(IndexScan {
+        cursor: Some(cursor),
+    })
+}
+
+impl<'a> Iterator for TableScan<'a> {
+    type Item = Result<(i64, Cow<'a, [u8]>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.cursor.as_mut()?;
+        match cursor.next_cell() {
+            Ok(cell) => cell.map(|cell| Ok((cell.rowid, cell.payload))),
+            Err(e) => {
+                self.cursor = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for IndexScan<'a> {
+    type Item = Result<Cow<'a, [u8]>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let cursor = self.cursor.as_mut()?;
+        match cursor.next_cell() {
+            Ok(cell) => cell.map(|cell| Ok(cell.payload)),
+            Err(e) => {
+                self.cursor = None;
+                Some(Err(e))
+            }
+        }
+    }
+}
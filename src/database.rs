@@ -0,0 +1,100 @@
+use crate::btree::scan_table;
+use crate::record::parse_record;
+use crate::schema::Schema;
+use crate::statistics::Statistics;
+use anyhow::{anyhow, bail, Result};
+
+/// An opened database file along with the contents of its `sqlite_schema` table
+#[derive(Debug)]
+pub struct Database {
+    data: Vec<u8>,
+    pub page_size: usize,
+    pub usable_size: usize,
+    pub schemas: Vec<Schema>,
+    /// What ANALYZE found out about the tables and indexes
+    pub statistics: Statistics,
+    /// Most rows a recursive common table expression may recurse on before its query fails,
+    /// so that a recursion that never ends doesn't run forever. The shell sets it with the
+    /// `-recursionlimit` option and the `.recursionlimit` command.
+    pub recursion_limit: usize,
+}
+
+/// The default `recursion_limit`
+pub const DEFAULT_RECURSION_LIMIT: usize = 1_000_000;
+
+impl Database {
+    /// Reads the database header and the schema table out of a database file's contents
+    pub fn new(data: Vec<u8>) -> Result<Self> {
+        if data.len() < 100 || !data.starts_with(b"SQLite format 3\0") {
+            bail!("file is not a database");
+        }
+
+        // A page size of 1 represents 65536, which doesn't fit in two bytes
+        let page_size = match u16::from_be_bytes([data[16], data[17]]) {
+            1 => 65536,
+            n => n as usize,
+        };
+        let usable_size = page_size - data[20] as usize;
+
+        let mut database = Database {
+            data,
+            page_size,
+            usable_size,
+            schemas: vec![],
+            statistics: Statistics::default(),
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+        };
+
+        // sqlite_schema is always rooted at page 1
+        let schemas = scan_table(&database, 1)?
+            .map(|row| {
+                let (_rowid, payload) = row?;
+                let record = parse_record(&payload, 5)?;
+                Schema::parse(record).ok_or_else(|| anyhow!("Invalid record"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        database.schemas = schemas;
+        database.statistics = Statistics::load(&database)?;
+
+        Ok(database)
+    }
+
+    /// Raw contents of the database file
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the contents of a page. Pages are numbered starting from 1.
+    pub fn page(&self, page_number: u32) -> Result<&[u8]> {
+        let start = (page_number as usize)
+            .checked_sub(1)
+            .ok_or_else(|| anyhow!("Invalid page number: {}", page_number))?
+            * self.page_size;
+
+        self.data
+            .get(start..start + self.page_size)
+            .ok_or_else(|| anyhow!("Page {} is out of bounds", page_number))
+    }
+
+    /// Finds the schema entry of a table
+    pub fn table(&self, name: &str) -> Result<&Schema> {
+        self.schemas
+            .iter()
+            .find(|schema| schema.kind == "table" && schema.name.eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("no such table: {}", name))
+    }
+
+    /// Finds the schema entry of a view
+    pub fn view(&self, name: &str) -> Option<&Schema> {
+        self.schemas
+            .iter()
+            .find(|schema| schema.kind == "view" && schema.name.eq_ignore_ascii_case(name))
+    }
+
+    /// All indexes defined on a table
+    pub fn indexes<'a>(&'a self, table: &'a str) -> impl Iterator<Item = &'a Schema> + 'a {
+        self.schemas.iter().filter(move |schema| {
+            schema.kind == "index" && schema.table_name.eq_ignore_ascii_case(table)
+        })
+    }
+}
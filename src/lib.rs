@@ -1,4 +1,12 @@
+pub mod ast;
+pub mod btree;
+pub mod database;
 pub mod header;
+pub mod parser;
+pub mod query;
 pub mod record;
 pub mod schema;
+pub mod shell;
+pub mod statistics;
+pub mod value;
 pub mod varint;
@@ -0,0 +1,823 @@
+use crate::ast::*;
+use crate::value::Value;
+use anyhow::{anyhow, bail, Result};
+use std::cell::Cell;
+
+/// How deep an expression tree can be, like SQLite's `SQLITE_MAX_EXPR_DEPTH`. Parentheses don't
+/// add to the depth.
+pub const MAX_EXPR_DEPTH: usize = 1000;
+
+/// How deeply expressions and subqueries can be nested, parentheses included, before the
+/// grammar's recursion would run out of stack
+const MAX_NESTING: usize = 5000;
+
+/// Words that can't be used as bare identifiers because they would make the grammar ambiguous
+const RESERVED_WORDS: &[&str] = &[
+    "ALL",
+    "AND",
+    "AS",
+    "ASC",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "CAST",
+    "COLLATE",
+    "CREATE",
+    "DESC",
+    "DISTINCT",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXISTS",
+    "FROM",
+    "GLOB",
+    "GROUP",
+    "HAVING",
+    "IN",
+    "INDEX",
+    "INTERSECT",
+    "IS",
+    "ISNULL",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "NOTNULL",
+    "NULL",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "SELECT",
+    "TABLE",
+    "THEN",
+    "UNION",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+];
+
+fn is_reserved(word: &str) -> bool {
+    RESERVED_WORDS
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(word))
+}
+
+/// Why a statement that nests too deeply can't be parsed
+#[derive(Clone, Copy)]
+enum TooDeep {
+    Expression,
+    Nesting,
+}
+
+/// Keeps track of how deeply the statement being parsed nests, for the limits on it
+#[derive(Default)]
+struct Nesting {
+    /// How many of the rules that recurse are being matched
+    depth: Cell<usize>,
+    /// The limit that was reached, which ends the parse whatever else matches
+    too_deep: Cell<Option<TooDeep>>,
+}
+
+impl Nesting {
+    fn enter(&self) -> Result<(), &'static str> {
+        if self.depth.get() >= MAX_NESTING {
+            return Err(self.fail(TooDeep::Nesting));
+        }
+        self.depth.set(self.depth.get() + 1);
+        Ok(())
+    }
+
+    fn leave(&self) {
+        self.depth.set(self.depth.get() - 1);
+    }
+
+    fn fail(&self, too_deep: TooDeep) -> &'static str {
+        self.too_deep.set(Some(too_deep));
+        "shallower statement"
+    }
+
+    /// Checks that an expression is no deeper than SQLite allows
+    fn check(&self, expr: Expr) -> Result<Expr, &'static str> {
+        if deeper_than(&expr, MAX_EXPR_DEPTH) {
+            return Err(self.fail(TooDeep::Expression));
+        }
+        Ok(expr)
+    }
+
+    /// Checks that a chain of operators is short enough to fold without making a tree that's
+    /// deeper than SQLite allows
+    fn check_chain<T>(&self, chain: Vec<T>) -> Result<Vec<T>, &'static str> {
+        if chain.len() >= MAX_EXPR_DEPTH {
+            return Err(self.fail(TooDeep::Expression));
+        }
+        Ok(chain)
+    }
+}
+
+/// Whether an expression tree is more than `depth` deep, not counting the subqueries in it
+fn deeper_than(expr: &Expr, depth: usize) -> bool {
+    if depth == 0 {
+        return true;
+    }
+    let children: Vec<&Expr> = match expr {
+        Expr::Literal(_) | Expr::Column { .. } | Expr::Subquery(_) | Expr::Exists(_) => vec![],
+        Expr::Unary(_, expr)
+        | Expr::IsNull { expr, .. }
+        | Expr::Collate(expr, _)
+        | Expr::Cast { expr, .. }
+        | Expr::InSelect { expr, .. } => vec![expr],
+        Expr::Binary(left, _, right) => vec![left, right],
+        Expr::Between {
+            expr, low, high, ..
+        } => vec![expr, low, high],
+        Expr::InList { expr, list, .. } => std::iter::once(&**expr).chain(list).collect(),
+        Expr::Like {
+            expr,
+            pattern,
+            escape,
+            ..
+        } => std::iter::once(&**expr)
+            .chain(Some(&**pattern))
+            .chain(escape.as_deref())
+            .collect(),
+        Expr::Function(call) => call.args.iter().chain(call.filter.as_deref()).collect(),
+        Expr::Case {
+            operand,
+            branches,
+            else_result,
+        } => operand
+            .as_deref()
+            .into_iter()
+            .chain(branches.iter().flat_map(|(when, then)| vec![when, then]))
+            .chain(else_result.as_deref())
+            .collect(),
+    };
+    children
+        .into_iter()
+        .any(|child| deeper_than(child, depth - 1))
+}
+
+/// Applies an operator that follows its left operand
+type Postfix = Box<dyn FnOnce(Expr) -> Expr>;
+
+/// Folds a left-associative chain of binary operators
+fn fold_binary(first: Expr, rest: Vec<(BinaryOperator, Expr)>) -> Expr {
+    rest.into_iter().fold(first, |left, (op, right)| {
+        Expr::Binary(Box::new(left), op, Box::new(right))
+    })
+}
+
+fn parse_blob(hex: &str) -> Vec<u8> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn parse_number(text: &str) -> Value {
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        return Value::Integer(u64::from_str_radix(hex, 16).unwrap_or(u64::MAX) as i64);
+    }
+
+    match text.parse::<i64>() {
+        Ok(v) => Value::Integer(v),
+        Err(_) => Value::Real(text.parse().unwrap_or(f64::INFINITY)),
+    }
+}
+
+peg::parser! {
+    grammar sql(source: &str, nesting: &Nesting) for str {
+        pub rule statement() -> Statement
+            = _ K("EXPLAIN") _ K("QUERY") _ K("PLAN") _ s:select() _ (";" _)? {
+                Statement::ExplainQueryPlan(s)
+            }
+            / _ s:select() _ (";" _)? { Statement::Select(s) }
+
+        pub rule create_table() -> CreateTable
+            = _ K("CREATE") _ ((K("TEMP") / K("TEMPORARY")) _)? K("TABLE") _ if_not_exists()
+              name:ident() _ "(" _ elements:(table_element() ++ comma()) _ ")"
+              options:(_ o:(table_option() ++ comma()) { o })? _ (";" _)?
+            {
+                let mut columns = vec![];
+                let mut constraints = vec![];
+                for element in elements {
+                    match element {
+                        Ok(column) => columns.push(column),
+                        Err(constraint) => constraints.push(constraint),
+                    }
+                }
+                let without_rowid = options.unwrap_or_default().contains(&true);
+                CreateTable { name, columns, constraints, without_rowid }
+            }
+
+        // Whether the option is WITHOUT ROWID, rather than STRICT
+        rule table_option() -> bool
+            = K("WITHOUT") _ K("ROWID") { true }
+            / K("STRICT") { false }
+
+        pub rule create_index() -> CreateIndex
+            = _ K("CREATE") _ unique:(K("UNIQUE") _)? K("INDEX") _ if_not_exists()
+              name:ident() _ K("ON") _ table:ident() _
+              "(" _ columns:(indexed_column() ++ comma()) _ ")"
+              where_clause:(_ K("WHERE") _ e:expr() { e })? _ (";" _)?
+            {
+                CreateIndex { name, table, unique: unique.is_some(), columns, where_clause }
+            }
+
+        pub rule create_view() -> CreateView
+            = _ K("CREATE") _ ((K("TEMP") / K("TEMPORARY")) _)? K("VIEW") _ if_not_exists()
+              name:ident() _ columns:("(" _ c:(ident() ++ comma()) _ ")" _ { c })?
+              K("AS") _ select:select() _ (";" _)?
+            {
+                CreateView { name, columns: columns.unwrap_or_default(), select }
+            }
+
+        rule if_not_exists() = (K("IF") _ K("NOT") _ K("EXISTS") _)?
+
+        // SELECT
+
+        rule select() -> Select = nested(<select_body()>)
+
+        rule select_body() -> Select
+            = with:(w:with() _ { w })? first:select_core()
+              compound:(_ op:compound_operator() _ s:select_core() { (op, s) })*
+              order_by:(_ K("ORDER") _ K("BY") _ terms:(ordering_term() ++ comma()) { terms })?
+              limit:(_ l:limit() { l })?
+            {
+                Select {
+                    with,
+                    compound,
+                    order_by: order_by.unwrap_or_default(),
+                    limit,
+                    ..first
+                }
+            }
+
+        rule select_core() -> Select
+            = K("SELECT") _ distinct:(K("DISTINCT") _ { true } / K("ALL") _ { false })?
+              columns:(result_column() ++ comma())
+              from:(_ K("FROM") _ f:from_clause() { f })?
+              where_clause:(_ K("WHERE") _ e:expr() { e })?
+              group_by:(_ K("GROUP") _ K("BY") _ terms:(expr() ++ comma()) { terms })?
+              having:(_ K("HAVING") _ e:expr() { e })?
+              windows:(_ K("WINDOW") _ w:(named_window() ++ comma()) { w })?
+            {
+                Select {
+                    with: None,
+                    distinct: distinct.unwrap_or(false),
+                    columns,
+                    from,
+                    where_clause,
+                    group_by: group_by.unwrap_or_default(),
+                    having,
+                    windows: windows.unwrap_or_default(),
+                    compound: vec![],
+                    order_by: vec![],
+                    limit: None,
+                }
+            }
+
+        rule named_window() -> (String, WindowDefinition)
+            = name:ident() _ K("AS") _ "(" _ definition:window_definition() _ ")"
+            {
+                (name, definition)
+            }
+
+        rule over() -> Over
+            = "(" _ definition:window_definition() _ ")" { Over::Definition(definition) }
+            / name:ident() { Over::Window(name) }
+
+        rule window_definition() -> WindowDefinition
+            = base:(!window_keyword() name:ident() _ { name })?
+              partition_by:(K("PARTITION") _ K("BY") _ terms:(expr() ++ comma()) _ { terms })?
+              order_by:(K("ORDER") _ K("BY") _ terms:(ordering_term() ++ comma()) _ { terms })?
+              frame:frame()?
+            {
+                WindowDefinition {
+                    base,
+                    partition_by: partition_by.unwrap_or_default(),
+                    order_by: order_by.unwrap_or_default(),
+                    frame,
+                }
+            }
+
+        rule window_keyword()
+            = K("PARTITION") / K("ORDER") / K("RANGE") / K("ROWS") / K("GROUPS")
+
+        rule frame() -> Frame
+            = units:frame_units() _ bounds:(
+                K("BETWEEN") _ start:frame_start() _ K("AND") _ end:frame_end() { (start, end) }
+                / start:frame_start() { (start, FrameBound::CurrentRow) }
+              )
+              exclude:(_ K("EXCLUDE") _ e:frame_exclude() { e })?
+            {
+                let (start, end) = bounds;
+                Frame { units, start, end, exclude: exclude.unwrap_or(FrameExclude::NoOthers) }
+            }
+
+        rule frame_units() -> FrameUnits
+            = K("ROWS") { FrameUnits::Rows }
+            / K("RANGE") { FrameUnits::Range }
+            / K("GROUPS") { FrameUnits::Groups }
+
+        // A frame can't start after the last row or end before the first one
+        rule frame_start() -> FrameBound
+            = !(K("UNBOUNDED") _ K("FOLLOWING")) bound:frame_bound() { bound }
+
+        rule frame_end() -> FrameBound
+            = !(K("UNBOUNDED") _ K("PRECEDING")) bound:frame_bound() { bound }
+
+        rule frame_bound() -> FrameBound
+            = K("UNBOUNDED") _ K("PRECEDING") { FrameBound::UnboundedPreceding }
+            / K("UNBOUNDED") _ K("FOLLOWING") { FrameBound::UnboundedFollowing }
+            / K("CURRENT") _ K("ROW") { FrameBound::CurrentRow }
+            / e:expr() _ K("PRECEDING") { FrameBound::Preceding(Box::new(e)) }
+            / e:expr() _ K("FOLLOWING") { FrameBound::Following(Box::new(e)) }
+
+        rule frame_exclude() -> FrameExclude
+            = K("NO") _ K("OTHERS") { FrameExclude::NoOthers }
+            / K("CURRENT") _ K("ROW") { FrameExclude::CurrentRow }
+            / K("GROUP") { FrameExclude::Group }
+            / K("TIES") { FrameExclude::Ties }
+
+        rule with() -> With
+            = K("WITH") _ recursive:(K("RECURSIVE") _)? tables:(common_table_expression() ++ comma())
+            {
+                With { recursive: recursive.is_some(), tables }
+            }
+
+        rule common_table_expression() -> CommonTableExpression
+            = name:ident() _ columns:("(" _ c:(ident() ++ comma()) _ ")" _ { c })?
+              K("AS") _ "(" _ select:select() _ ")"
+            {
+                CommonTableExpression { name, columns: columns.unwrap_or_default(), select }
+            }
+
+        rule compound_operator() -> CompoundOperator
+            = K("UNION") _ K("ALL") { CompoundOperator::UnionAll }
+            / K("UNION") { CompoundOperator::Union }
+            / K("INTERSECT") { CompoundOperator::Intersect }
+            / K("EXCEPT") { CompoundOperator::Except }
+
+        rule from_clause() -> FromClause
+            = table:table_ref() joins:(_ j:join() { j })*
+            {
+                FromClause { table, joins }
+            }
+
+        rule join() -> Join
+            = operator:join_operator() _ table:table_ref() constraint:(_ c:join_constraint() { c })?
+            {
+                let (natural, operator) = operator;
+                Join { operator, natural, table, constraint }
+            }
+
+        rule join_operator() -> (bool, JoinOperator)
+            = "," { (false, JoinOperator::Inner) }
+            / natural:(K("NATURAL") _)? operator:(
+                K("LEFT") _ (K("OUTER") _)? K("JOIN") { JoinOperator::Left }
+                / K("INNER") _ K("JOIN") { JoinOperator::Inner }
+                / K("CROSS") _ K("JOIN") { JoinOperator::Cross }
+                / K("JOIN") { JoinOperator::Inner }
+              )
+            {
+                (natural.is_some(), operator)
+            }
+
+        rule join_constraint() -> JoinConstraint
+            = K("ON") _ e:expr() { JoinConstraint::On(e) }
+            / K("USING") _ "(" _ columns:(ident() ++ comma()) _ ")" { JoinConstraint::Using(columns) }
+
+        rule join_keyword()
+            = K("CROSS") / K("FULL") / K("INNER") / K("JOIN") / K("LEFT") / K("NATURAL") / K("OUTER")
+            / K("RIGHT") / K("USING")
+
+        rule table_ref() -> TableRef
+            = "(" _ select:select() _ ")" alias:(_ a:table_alias() { a })?
+            {
+                TableRef { source: TableSource::Subquery(Box::new(select)), alias }
+            }
+            / name:ident() _ "(" _ args:(expr() ** comma()) _ ")" alias:(_ a:table_alias() { a })?
+            {
+                TableRef { source: TableSource::Function { name, args }, alias }
+            }
+            / name:ident() alias:(_ a:table_alias() { a })?
+            {
+                TableRef { source: TableSource::Table(name), alias }
+            }
+
+        rule table_alias() -> String
+            = K("AS") _ a:ident() { a }
+            / !join_keyword() a:ident() { a }
+
+        rule result_column() -> ResultColumn
+            = "*" { ResultColumn::Star }
+            / table:ident() _ "." _ "*" { ResultColumn::TableStar(table) }
+            / start:position!() expr:expr() end:position!() alias:(_ a:alias() { a })?
+            {
+                ResultColumn::Expr { expr, alias, text: source[start..end].to_string() }
+            }
+
+        rule alias() -> String
+            = K("AS") _ a:(ident() / string()) { a }
+            / ident()
+
+        rule ordering_term() -> OrderingTerm
+            = expr:expr() descending:(_ d:order() { d })?
+              nulls_first:(_ K("NULLS") _ f:(K("FIRST") { true } / K("LAST") { false }) { f })?
+            {
+                OrderingTerm { expr, descending: descending.unwrap_or(false), nulls_first }
+            }
+
+        rule order() -> bool
+            = K("ASC") { false }
+            / K("DESC") { true }
+
+        rule limit() -> Limit
+            = K("LIMIT") _ first:expr()
+              second:(_ K("OFFSET") _ e:expr() { (e, false) } / comma() e:expr() { (e, true) })?
+            {
+                match second {
+                    None => Limit { limit: first, offset: None },
+                    Some((offset, false)) => Limit { limit: first, offset: Some(offset) },
+                    // LIMIT <offset>, <limit>
+                    Some((limit, true)) => Limit { limit, offset: Some(first) },
+                }
+            }
+
+        // Expressions, from the loosest to the tightest binding operators
+
+        pub rule expr() -> Expr = e:nested(<or()>) {? nesting.check(e) }
+
+        /// Matches a rule that recurses, unless it's nested too deeply already
+        rule nested<T>(r: rule<T>) -> T
+            = enter() v:r()? {? nesting.leave(); v.ok_or("nested") }
+
+        rule enter() = position!() {? nesting.enter() }
+
+        rule or() -> Expr
+            = first:and() rest:(_ K("OR") _ e:and() { (BinaryOperator::Or, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule and() -> Expr
+            = first:not() rest:(_ K("AND") _ e:not() { (BinaryOperator::And, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule not() -> Expr
+            = K("NOT") _ e:nested(<not()>) { Expr::Unary(UnaryOperator::Not, Box::new(e)) }
+            / equality()
+
+        rule equality() -> Expr
+            = first:comparison() rest:(_ t:equality_tail() { t })*
+            {? Ok(nesting.check_chain(rest)?.into_iter().fold(first, |e, tail| tail(e))) }
+
+        rule equality_tail() -> Postfix
+            = op:equality_operator() _ right:comparison()
+            { Box::new(move |left| Expr::Binary(Box::new(left), op, Box::new(right))) }
+            / (K("ISNULL") / K("NOT") _ K("NULL"))
+            { Box::new(|expr| Expr::IsNull { expr: Box::new(expr), negated: false }) }
+            / K("NOTNULL")
+            { Box::new(|expr| Expr::IsNull { expr: Box::new(expr), negated: true }) }
+            / negated:not_keyword() K("IN") _ "(" _ select:select() _ ")"
+            {
+                Box::new(move |expr| Expr::InSelect {
+                    expr: Box::new(expr),
+                    select: Box::new(select),
+                    negated,
+                })
+            }
+            / negated:not_keyword() K("IN") _ "(" _ list:(expr() ** comma()) _ ")"
+            { Box::new(move |expr| Expr::InList { expr: Box::new(expr), list, negated }) }
+            / negated:not_keyword() glob:(K("LIKE") { false } / K("GLOB") { true }) _
+              pattern:comparison() escape:(_ K("ESCAPE") _ e:comparison() { Box::new(e) })?
+            {
+                Box::new(move |expr| Expr::Like {
+                    expr: Box::new(expr),
+                    pattern: Box::new(pattern),
+                    escape,
+                    glob,
+                    negated,
+                })
+            }
+            / negated:not_keyword() K("BETWEEN") _ low:comparison() _ K("AND") _ high:comparison()
+            {
+                Box::new(move |expr| Expr::Between {
+                    expr: Box::new(expr),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                })
+            }
+
+        rule equality_operator() -> BinaryOperator
+            = ("==" / "=") { BinaryOperator::Eq }
+            / ("!=" / "<>") { BinaryOperator::NotEq }
+            / K("IS") _ K("NOT") _ K("DISTINCT") _ K("FROM") { BinaryOperator::Is }
+            / K("IS") _ K("DISTINCT") _ K("FROM") { BinaryOperator::IsNot }
+            / K("IS") _ K("NOT") { BinaryOperator::IsNot }
+            / K("IS") { BinaryOperator::Is }
+
+        rule not_keyword() -> bool
+            = n:(K("NOT") _)? { n.is_some() }
+
+        rule comparison() -> Expr
+            = first:bitwise() rest:(_ op:comparison_operator() _ e:bitwise() { (op, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule comparison_operator() -> BinaryOperator
+            = "<=" { BinaryOperator::LtEq }
+            / ">=" { BinaryOperator::GtEq }
+            / "<" !['<' | '>'] { BinaryOperator::Lt }
+            / ">" !['>'] { BinaryOperator::Gt }
+
+        rule bitwise() -> Expr
+            = first:additive() rest:(_ op:bitwise_operator() _ e:additive() { (op, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule bitwise_operator() -> BinaryOperator
+            = "<<" { BinaryOperator::ShiftLeft }
+            / ">>" { BinaryOperator::ShiftRight }
+            / "&" { BinaryOperator::BitAnd }
+            / "|" !"|" { BinaryOperator::BitOr }
+
+        rule additive() -> Expr
+            = first:multiplicative() rest:(_ op:additive_operator() _ e:multiplicative() { (op, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule additive_operator() -> BinaryOperator
+            = "+" { BinaryOperator::Add }
+            / "-" !">" { BinaryOperator::Subtract }
+
+        rule multiplicative() -> Expr
+            = first:concat() rest:(_ op:multiplicative_operator() _ e:concat() { (op, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule multiplicative_operator() -> BinaryOperator
+            = "*" { BinaryOperator::Multiply }
+            / "/" { BinaryOperator::Divide }
+            / "%" { BinaryOperator::Modulo }
+
+        rule concat() -> Expr
+            = first:collate() rest:(_ op:concat_operator() _ e:collate() { (op, e) })*
+            {? Ok(fold_binary(first, nesting.check_chain(rest)?)) }
+
+        rule concat_operator() -> BinaryOperator
+            = "||" { BinaryOperator::Concat }
+            / "->>" { BinaryOperator::ExtractValue }
+            / "->" { BinaryOperator::Extract }
+
+        rule collate() -> Expr
+            = e:unary() collations:(_ K("COLLATE") _ c:ident() { c })*
+            {?
+                Ok(nesting
+                    .check_chain(collations)?
+                    .into_iter()
+                    .fold(e, |e, collation| Expr::Collate(Box::new(e), collation)))
+            }
+
+        rule unary() -> Expr
+            = "-" _ "9223372036854775808" !['0'..='9' | '.' | 'e' | 'E'] {
+                // The smallest integer can only be written as a negated literal
+                Expr::Literal(Value::Integer(i64::MIN))
+            }
+            / "-" _ e:nested(<unary()>) { Expr::Unary(UnaryOperator::Negate, Box::new(e)) }
+            / "+" _ e:nested(<unary()>) { Expr::Unary(UnaryOperator::Plus, Box::new(e)) }
+            / "~" _ e:nested(<unary()>) { Expr::Unary(UnaryOperator::BitNot, Box::new(e)) }
+            / primary()
+
+        rule primary() -> Expr
+            = v:literal() { Expr::Literal(v) }
+            / "(" _ s:select() _ ")" { Expr::Subquery(Box::new(s)) }
+            / K("EXISTS") _ "(" _ s:select() _ ")" { Expr::Exists(Box::new(s)) }
+            / "(" _ e:expr() _ ")" { e }
+            / K("CAST") _ "(" _ e:expr() _ K("AS") _ type_name:type_name() _ ")"
+            {
+                Expr::Cast { expr: Box::new(e), type_name }
+            }
+            / K("CASE") _ operand:(e:expr() _ { Box::new(e) })?
+              branches:(K("WHEN") _ w:expr() _ K("THEN") _ t:expr() _ { (w, t) })+
+              else_result:(K("ELSE") _ e:expr() _ { Box::new(e) })? K("END")
+            {
+                Expr::Case { operand, branches, else_result }
+            }
+            / f:function_call() { Expr::Function(f) }
+            / table:ident() _ "." _ name:ident() { Expr::Column { table: Some(table), name } }
+            / name:ident() { Expr::Column { table: None, name } }
+
+        rule function_call() -> FunctionCall
+            = name:ident() _ "(" _ call:(
+                "*" { (false, vec![], true) }
+                / distinct:(K("DISTINCT") _)? args:(expr() ** comma())
+                    { (distinct.is_some(), args, false) }
+              ) _ ")"
+              filter:(_ K("FILTER") _ "(" _ K("WHERE") _ e:expr() _ ")" { Box::new(e) })?
+              over:(_ K("OVER") _ o:over() { Box::new(o) })?
+            {
+                let (distinct, args, wildcard) = call;
+                FunctionCall { name, distinct, args, wildcard, filter, over }
+            }
+
+        // Literals
+
+        rule literal() -> Value
+            = n:number() { n }
+            / s:string() { Value::Text(s) }
+            / b:blob() { Value::Blob(b) }
+            / K("NULL") { Value::Null }
+            / K("TRUE") { Value::Integer(1) }
+            / K("FALSE") { Value::Integer(0) }
+
+        rule number() -> Value
+            = quiet!{
+                n:$("0" ['x' | 'X'] ['0'..='9' | 'a'..='f' | 'A'..='F']+
+                    / (['0'..='9']+ ("." ['0'..='9']*)? / "." ['0'..='9']+)
+                      (['e' | 'E'] ['+' | '-']? ['0'..='9']+)?)
+                !identifier_char()
+                { parse_number(n) }
+            } / expected!("number")
+
+        rule string() -> String
+            = quiet!{ "'" s:$(([^ '\''] / "''")*) "'" { s.replace("''", "'") } }
+            / expected!("string")
+
+        rule blob() -> Vec<u8>
+            = ['x' | 'X'] "'" hex:$(['0'..='9' | 'a'..='f' | 'A'..='F']*) "'"
+            {? if hex.len() % 2 == 0 { Ok(parse_blob(hex)) } else { Err("blob") } }
+
+        // Table definitions
+
+        rule table_element() -> Result<ColumnDefinition, TableConstraint>
+            = c:table_constraint() { Err(c) }
+            / c:column_definition() { Ok(c) }
+
+        rule column_definition() -> ColumnDefinition
+            = name:ident() type_name:(_ t:type_name() { t })?
+              constraints:(_ c:column_constraint() { c })*
+            { ColumnDefinition { name, type_name, constraints } }
+
+        rule type_name() -> String
+            = t:$(type_word() ++ _ (_ "(" _ signed_number() _ (comma() signed_number() _)? ")")?)
+            { t.to_string() }
+
+        rule type_word()
+            = !(K("CONSTRAINT") / K("PRIMARY") / K("NOT") / K("NULL") / K("UNIQUE") / K("CHECK")
+                / K("DEFAULT") / K("COLLATE") / K("REFERENCES") / K("GENERATED") / K("AS"))
+              word()
+
+        rule signed_number() = ['+' | '-']? _ number()
+
+        rule column_constraint() -> ColumnConstraint
+            = (K("CONSTRAINT") _ ident() _)? c:(
+                K("PRIMARY") _ K("KEY") descending:(_ d:order() { d })? conflict_clause()
+                  autoincrement:(_ K("AUTOINCREMENT"))?
+                {
+                    ColumnConstraint::PrimaryKey {
+                        descending: descending.unwrap_or(false),
+                        autoincrement: autoincrement.is_some(),
+                    }
+                }
+                / K("NOT") _ K("NULL") conflict_clause() { ColumnConstraint::NotNull }
+                / K("UNIQUE") conflict_clause() { ColumnConstraint::Unique }
+                / K("CHECK") _ "(" _ e:expr() _ ")" { ColumnConstraint::Check(e) }
+                / K("DEFAULT") _ e:default_value() { ColumnConstraint::Default(e) }
+                / K("COLLATE") _ c:ident() { ColumnConstraint::Collate(c) }
+                / foreign_key_clause() { ColumnConstraint::ForeignKey }
+                / (K("GENERATED") _ K("ALWAYS") _)? K("AS") _ "(" _ expr:expr() _ ")"
+                  stored:(_ s:(K("STORED") { true } / K("VIRTUAL") { false }) { s })?
+                {
+                    ColumnConstraint::Generated { expr, stored: stored.unwrap_or(false) }
+                }
+              ) { c }
+
+        rule default_value() -> Expr
+            = "(" _ e:expr() _ ")" { e }
+            / "-" _ "9223372036854775808" !['0'..='9' | '.' | 'e' | 'E'] {
+                Expr::Literal(Value::Integer(i64::MIN))
+            }
+            / "-" _ v:literal() { Expr::Unary(UnaryOperator::Negate, Box::new(Expr::Literal(v))) }
+            / "+" _ v:literal() { Expr::Literal(v) }
+            / v:literal() { Expr::Literal(v) }
+            / name:ident() { Expr::Literal(Value::Text(name)) }
+
+        rule table_constraint() -> TableConstraint
+            = (K("CONSTRAINT") _ ident() _)? c:(
+                K("PRIMARY") _ K("KEY") _ "(" _ columns:(indexed_column() ++ comma()) _ ")"
+                  conflict_clause()
+                { TableConstraint::PrimaryKey(columns) }
+                / K("UNIQUE") _ "(" _ columns:(indexed_column() ++ comma()) _ ")" conflict_clause()
+                { TableConstraint::Unique(columns) }
+                / K("CHECK") _ "(" _ e:expr() _ ")" { TableConstraint::Check(e) }
+                / K("FOREIGN") _ K("KEY") _ "(" _ (ident() ++ comma()) _ ")" _ foreign_key_clause()
+                { TableConstraint::ForeignKey }
+              ) { c }
+
+        rule conflict_clause()
+            = (_ K("ON") _ K("CONFLICT") _
+                (K("ROLLBACK") / K("ABORT") / K("FAIL") / K("IGNORE") / K("REPLACE")))?
+
+        rule foreign_key_clause()
+            = K("REFERENCES") _ ident() (_ "(" _ (ident() ++ comma()) _ ")")?
+              (_ (K("ON") _ (K("DELETE") / K("UPDATE")) _ foreign_key_action()
+                  / K("MATCH") _ ident()))*
+              (_ K("NOT"))? (_ K("DEFERRABLE") (_ K("INITIALLY") _ (K("DEFERRED") / K("IMMEDIATE")))?)?
+
+        rule foreign_key_action()
+            = K("SET") _ (K("NULL") / K("DEFAULT"))
+            / K("CASCADE")
+            / K("RESTRICT")
+            / K("NO") _ K("ACTION")
+
+        rule indexed_column() -> IndexedColumn
+            = expr:expr() descending:(_ d:order() { d })?
+            {
+                let (expr, collation) = match expr {
+                    Expr::Collate(expr, collation) => (*expr, Some(collation)),
+                    expr => (expr, None),
+                };
+                IndexedColumn { expr, collation, descending: descending.unwrap_or(false) }
+            }
+
+        // Tokens
+
+        rule ident() -> String
+            = quiet!{
+                w:word() {? if is_reserved(w) { Err("identifier") } else { Ok(w.to_string()) } }
+                / "\"" s:$(([^ '"'] / "\"\"")*) "\"" { s.replace("\"\"", "\"") }
+                / "[" s:$([^ ']']*) "]" { s.to_string() }
+                / "`" s:$(([^ '`'] / "``")*) "`" { s.replace("``", "`") }
+            } / expected!("identifier")
+
+        rule word() -> &'input str
+            = $(['a'..='z' | 'A'..='Z' | '_' | '\u{80}'..='\u{10FFFF}'] identifier_char()*)
+
+        rule identifier_char()
+            = ['a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '$' | '\u{80}'..='\u{10FFFF}']
+
+        /// Case insensitive keyword
+        rule K(keyword: &'static str)
+            = w:word() {? if w.eq_ignore_ascii_case(keyword) { Ok(()) } else { Err(keyword) } }
+
+        rule comma() = _ "," _
+
+        rule _ = quiet!{ ([' ' | '\t' | '\n' | '\r'] / "--" [^ '\n']* / "/*" (!"*/" [_])* "*/")* }
+    }
+}
+
+/// Converts a parse error into SQLite's `near "token": syntax error` message
+fn syntax_error(input: &str, offset: usize) -> anyhow::Error {
+    let rest = input[offset..].trim_start();
+    if rest.is_empty() {
+        return anyhow!("incomplete input");
+    }
+
+    let token: String = match rest.chars().next() {
+        Some(c) if c.is_alphanumeric() || c == '_' => rest
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_')
+            .collect(),
+        Some(c) => c.to_string(),
+        None => String::new(),
+    };
+    anyhow!("near \"{}\": syntax error", token)
+}
+
+/// A rule of the grammar that can start a parse
+type Rule<T> = fn(&str, &str, &Nesting) -> Result<T, peg::error::ParseError<peg::str::LineCol>>;
+
+/// Parses the input with a rule, failing when it nests more deeply than the limits allow
+fn parse<T>(input: &str, rule: Rule<T>) -> Result<T> {
+    let nesting = Nesting::default();
+    let result = rule(input, input, &nesting);
+    match nesting.too_deep.get() {
+        Some(TooDeep::Expression) => {
+            bail!(
+                "Expression tree is too large (maximum depth {})",
+                MAX_EXPR_DEPTH
+            )
+        }
+        Some(TooDeep::Nesting) => bail!("parser stack overflow"),
+        None => result.map_err(|e| syntax_error(input, e.location.offset)),
+    }
+}
+
+/// Parses a single SQL statement
+pub fn parse_statement(input: &str) -> Result<Statement> {
+    parse(input, sql::statement)
+}
+
+/// Parses the `CREATE TABLE` statement stored in `sqlite_schema`
+pub fn parse_create_table(input: &str) -> Result<CreateTable> {
+    parse(input, sql::create_table)
+}
+
+/// Parses the `CREATE INDEX` statement stored in `sqlite_schema`
+pub fn parse_create_index(input: &str) -> Result<CreateIndex> {
+    parse(input, sql::create_index)
+}
+
+/// Parses the `CREATE VIEW` statement stored in `sqlite_schema`
+pub fn parse_create_view(input: &str) -> Result<CreateView> {
+    parse(input, sql::create_view)
+}
@@ -0,0 +1,531 @@
+use super::expr::BoundExpr;
+use super::sort::{compare_keys, SortOrder};
+use super::{project, Row, Rows};
+use crate::record::{encode_record, parse_record};
+use crate::value::{parse_numeric, Collation, Value};
+use anyhow::{bail, Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of groups kept in memory before rows of new groups are written to temporary files
+const MAX_GROUPS_IN_MEMORY: usize = 100_000;
+
+/// Number of temporary files rows are spread over when the groups don't fit in memory
+const SPILL_PARTITIONS: usize = 16;
+
+/// How many times partitions are split again before giving up and keeping everything in memory
+const MAX_SPILL_DEPTH: usize = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum,
+    Total,
+    Avg,
+    Min,
+    Max,
+    GroupConcat,
+}
+
+impl AggregateFunction {
+    /// Looks up an aggregate function. `min()` and `max()` with more than one argument are
+    /// scalar functions instead.
+    pub fn from_name(name: &str, arg_count: usize) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "count" => AggregateFunction::Count,
+            "sum" => AggregateFunction::Sum,
+            "total" => AggregateFunction::Total,
+            "avg" => AggregateFunction::Avg,
+            "min" if arg_count <= 1 => AggregateFunction::Min,
+            "max" if arg_count <= 1 => AggregateFunction::Max,
+            "group_concat" | "string_agg" => AggregateFunction::GroupConcat,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function accepts that many arguments
+    pub fn accepts(self, name: &str, arg_count: usize) -> bool {
+        match self {
+            AggregateFunction::Count => arg_count <= 1,
+            AggregateFunction::GroupConcat if name.eq_ignore_ascii_case("string_agg") => {
+                arg_count == 2
+            }
+            AggregateFunction::GroupConcat => arg_count == 1 || arg_count == 2,
+            _ => arg_count == 1,
+        }
+    }
+}
+
+/// An aggregate function call, with its arguments bound against the rows being aggregated
+#[derive(Debug, Clone)]
+pub struct AggregateCall<'a> {
+    pub function: AggregateFunction,
+    pub args: Vec<BoundExpr<'a>>,
+    pub distinct: bool,
+    pub filter: Option<BoundExpr<'a>>,
+    /// Collation used by `min()` and `max()`, and to tell DISTINCT values apart
+    pub collation: Collation,
+}
+
+/// A value normalized so that values comparing equal under a collation hash the same way
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub enum KeyValue {
+    Null,
+    Integer(i64),
+    Real(u64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl KeyValue {
+    pub fn new(value: &Value, collation: Collation) -> Self {
+        match value {
+            Value::Null => KeyValue::Null,
+            Value::Integer(v) => KeyValue::Integer(*v),
+            // 1.0 and 1 are equal, so they belong to the same group
+            Value::Real(v) if v.fract() == 0.0 && v.abs() < 9.2e18 => KeyValue::Integer(*v as i64),
+            Value::Real(v) => KeyValue::Real(v.to_bits()),
+            Value::Text(v) => KeyValue::Text(match collation {
+                Collation::Binary => v.clone(),
+                Collation::NoCase => v.to_ascii_lowercase(),
+                Collation::RTrim => v.trim_end_matches(' ').to_string(),
+            }),
+            Value::Blob(v) => KeyValue::Blob(v.clone()),
+        }
+    }
+}
+
+/// Normalizes a row of values with the collation of each of them
+pub fn key_values(values: &[Value], collations: &[Collation]) -> Vec<KeyValue> {
+    values
+        .iter()
+        .zip(collations)
+        .map(|(value, collation)| KeyValue::new(value, *collation))
+        .collect()
+}
+
+/// Running state of one aggregate call within one group, or within the frame of a window
+#[derive(Debug, Clone)]
+pub enum Accumulator {
+    Count(i64),
+    Sum {
+        integer: i64,
+        /// The sum as a floating point number, which is `real + error` to more precision than
+        /// a double has
+        real: f64,
+        error: f64,
+        count: i64,
+        approximate: bool,
+        overflow: bool,
+    },
+    Extreme(Option<Value>),
+    GroupConcat(Option<String>),
+}
+
+impl Accumulator {
+    pub fn new(function: AggregateFunction) -> Self {
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum | AggregateFunction::Total | AggregateFunction::Avg => {
+                Accumulator::Sum {
+                    integer: 0,
+                    real: 0.0,
+                    error: 0.0,
+                    count: 0,
+                    approximate: false,
+                    overflow: false,
+                }
+            }
+            AggregateFunction::Min | AggregateFunction::Max => Accumulator::Extreme(None),
+            AggregateFunction::GroupConcat => Accumulator::GroupConcat(None),
+        }
+    }
+
+    /// Adds the arguments of one row. Returns whether `min()` or `max()` found a new extreme.
+    pub fn step(&mut self, call: &AggregateCall, args: &[Value]) -> bool {
+        match self {
+            Accumulator::Count(count) => {
+                if !matches!(args.first(), Some(Value::Null)) {
+                    *count += 1;
+                }
+            }
+            Accumulator::Sum {
+                integer,
+                real,
+                error,
+                count,
+                approximate,
+                overflow,
+            } => {
+                // Only integers, and text that reads as one, are summed as integers
+                let as_integer = match &args[0] {
+                    Value::Integer(v) => Some(*v),
+                    Value::Text(text) => match parse_numeric(text) {
+                        Some(Value::Integer(v)) => Some(v),
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                match (&args[0], as_integer) {
+                    (Value::Null, _) => {}
+                    (_, Some(v)) => {
+                        *count += 1;
+                        add_integer(real, error, v);
+                        match integer.checked_add(v) {
+                            Some(sum) => *integer = sum,
+                            None => *overflow = true,
+                        }
+                    }
+                    (value, None) => {
+                        *count += 1;
+                        add_real(real, error, value.to_number().to_real().unwrap_or(0.0));
+                        *approximate = true;
+                    }
+                }
+            }
+            Accumulator::Extreme(extreme) => {
+                let value = &args[0];
+                if value.is_null() {
+                    return false;
+                }
+                let replace = match extreme {
+                    None => true,
+                    Some(current) => {
+                        let ordering = value.compare(current, call.collation);
+                        match call.function {
+                            AggregateFunction::Min => ordering == std::cmp::Ordering::Less,
+                            _ => ordering == std::cmp::Ordering::Greater,
+                        }
+                    }
+                };
+                if replace {
+                    *extreme = Some(value.clone());
+                }
+                return replace;
+            }
+            Accumulator::GroupConcat(text) => {
+                let value = match args[0].to_text() {
+                    Some(value) => value,
+                    None => return false,
+                };
+                match text {
+                    Some(text) => {
+                        match args.get(1) {
+                            Some(separator) => {
+                                text.push_str(&separator.to_text().unwrap_or_default())
+                            }
+                            None => text.push(','),
+                        }
+                        text.push_str(&value);
+                    }
+                    None => *text = Some(value),
+                }
+            }
+        }
+        false
+    }
+
+    pub fn finish(self, function: AggregateFunction) -> Result<Value> {
+        Ok(match self {
+            Accumulator::Count(count) => Value::Integer(count),
+            Accumulator::Sum {
+                integer,
+                real,
+                error,
+                count,
+                approximate,
+                overflow,
+            } => {
+                // The error is infinite or NaN when the sum is
+                let real = match error.is_finite() {
+                    true => real + error,
+                    false => real,
+                };
+                match function {
+                    AggregateFunction::Total => Value::Real(real),
+                    _ if count == 0 => Value::Null,
+                    AggregateFunction::Avg => Value::Real(real / count as f64),
+                    _ if approximate => Value::Real(real),
+                    _ if overflow => bail!("integer overflow"),
+                    _ => Value::Integer(integer),
+                }
+            }
+            Accumulator::Extreme(extreme) => extreme.unwrap_or(Value::Null),
+            Accumulator::GroupConcat(text) => text.map_or(Value::Null, Value::Text),
+        })
+    }
+}
+
+/// State of one group: its key, the row its bare columns come from, and its aggregates
+struct Group {
+    keys: Vec<Value>,
+    row: Row,
+    accumulators: Vec<Accumulator>,
+    distinct: Vec<HashSet<KeyValue>>,
+}
+
+/// Groups rows and computes aggregate functions over each group, like
+/// [the aggregate functions](https://www.sqlite.org/lang_aggfunc.html) do.
+pub struct Aggregate<'a> {
+    /// Whether the rows are read in the order of the GROUP BY terms, through an index, so that
+    /// the rows of each group come one after the other
+    pub grouped: bool,
+    pub group_by: Vec<BoundExpr<'a>>,
+    pub collations: Vec<Collation>,
+    /// The order groups come out in, unless they are `grouped`, in which case they come in
+    /// the order of their rows
+    pub orders: Vec<SortOrder>,
+    pub calls: Vec<AggregateCall<'a>>,
+    /// Number of values in an input row
+    pub width: usize,
+}
+
+impl<'a> Aggregate<'a> {
+    /// Produces one row per group, sorted by the GROUP BY terms in `orders`. Each row is the input row the
+    /// bare columns of the group are taken from, followed by the values of the aggregate calls.
+    /// Without GROUP BY there is a single group, even when there are no rows at all.
+    pub fn run<'r>(&self, rows: Rows<'r>) -> Result<Rows<'r>> {
+        if self.grouped {
+            let groups = self.aggregate_grouped(rows)?;
+            return Ok(Box::new(groups.into_iter().map(|(_, row)| Ok(row))));
+        }
+
+        let mut groups = self.aggregate(rows, 0)?;
+        if groups.is_empty() && self.group_by.is_empty() {
+            groups.push(self.finish(self.new_group(vec![], vec![Value::Null; self.width]))?);
+        }
+        groups.sort_by(|(a, _), (b, _)| compare_keys(&self.orders, a, b));
+
+        Ok(Box::new(groups.into_iter().map(|(_, row)| Ok(row))))
+    }
+
+    /// Aggregates rows that come grouped, finishing each group when the next one starts
+    fn aggregate_grouped(
+        &self,
+        rows: impl Iterator<Item = Result<Row>>,
+    ) -> Result<Vec<(Vec<Value>, Row)>> {
+        let extreme = self.extreme();
+        let mut finished = vec![];
+        let mut current: Option<(Vec<KeyValue>, Group)> = None;
+        for row in rows {
+            let row = row?;
+            let keys = project(&self.group_by, &row)?;
+            let key = key_values(&keys, &self.collations);
+            if !matches!(&current, Some((current, _)) if *current == key) {
+                let group = self.new_group(keys, row.clone());
+                if let Some((_, group)) = current.replace((key, group)) {
+                    finished.push(self.finish(group)?);
+                }
+            }
+            if let Some((_, group)) = &mut current {
+                self.step(group, &row, extreme)?;
+            }
+        }
+        if let Some((_, group)) = current {
+            finished.push(self.finish(group)?);
+        }
+        Ok(finished)
+    }
+
+    /// The call that bare columns take their values from: with a single min() or max(), the
+    /// row holding the extreme value
+    fn extreme(&self) -> Option<usize> {
+        match self
+            .calls
+            .iter()
+            .enumerate()
+            .filter(|(_, call)| {
+                matches!(
+                    call.function,
+                    AggregateFunction::Min | AggregateFunction::Max
+                )
+            })
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [(i, _)] => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Adds a row to the aggregate calls of its group
+    fn step(&self, group: &mut Group, row: &[Value], extreme: Option<usize>) -> Result<()> {
+        for (i, call) in self.calls.iter().enumerate() {
+            if let Some(filter) = &call.filter {
+                if filter.eval(row)?.truth() != Some(true) {
+                    continue;
+                }
+            }
+            let args = project(&call.args, row)?;
+            if call.distinct && !args[0].is_null() {
+                let key = KeyValue::new(&args[0], call.collation);
+                if !group.distinct[i].insert(key) {
+                    continue;
+                }
+            }
+            if group.accumulators[i].step(call, &args) && extreme == Some(i) {
+                group.row = row.to_vec();
+            }
+        }
+        Ok(())
+    }
+
+    /// Aggregates rows into finished groups. Once there are too many groups to keep in memory,
+    /// rows of groups that haven't been seen yet are spilled into temporary files by the hash
+    /// of their key, and every file is aggregated separately afterwards.
+    fn aggregate(
+        &self,
+        rows: impl Iterator<Item = Result<Row>>,
+        depth: usize,
+    ) -> Result<Vec<(Vec<Value>, Row)>> {
+        let mut groups: HashMap<Vec<KeyValue>, Group> = HashMap::new();
+        let mut partitions: Vec<SpillFile> = vec![];
+        let extreme = self.extreme();
+        for row in rows {
+            let row = row?;
+            let keys = project(&self.group_by, &row)?;
+            let key = key_values(&keys, &self.collations);
+
+            if groups.len() >= MAX_GROUPS_IN_MEMORY
+                && depth < MAX_SPILL_DEPTH
+                && !groups.contains_key(&key)
+            {
+                if partitions.is_empty() {
+                    for _ in 0..SPILL_PARTITIONS {
+                        partitions.push(SpillFile::create()?);
+                    }
+                }
+                let mut hasher = DefaultHasher::new();
+                depth.hash(&mut hasher);
+                key.hash(&mut hasher);
+                let partition = hasher.finish() as usize % SPILL_PARTITIONS;
+                partitions[partition].write(&row)?;
+                continue;
+            }
+            let group = groups
+                .entry(key)
+                .or_insert_with(|| self.new_group(keys, row.clone()));
+            self.step(group, &row, extreme)?;
+        }
+
+        let mut finished = groups
+            .into_values()
+            .map(|group| self.finish(group))
+            .collect::<Result<Vec<_>>>()?;
+        for partition in partitions {
+            finished.extend(self.aggregate(partition.read(self.width)?, depth + 1)?);
+        }
+        Ok(finished)
+    }
+
+    fn new_group(&self, keys: Vec<Value>, row: Row) -> Group {
+        Group {
+            keys,
+            row,
+            accumulators: self
+                .calls
+                .iter()
+                .map(|call| Accumulator::new(call.function))
+                .collect(),
+            distinct: self.calls.iter().map(|_| HashSet::new()).collect(),
+        }
+    }
+
+    fn finish(&self, group: Group) -> Result<(Vec<Value>, Row)> {
+        let mut row = group.row;
+        for (accumulator, call) in group.accumulators.into_iter().zip(&self.calls) {
+            row.push(accumulator.finish(call.function)?);
+        }
+        Ok((group.keys, row))
+    }
+}
+
+/// A temporary file holding rows in record format, each preceded by its length
+struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl SpillFile {
+    fn create() -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "sqlite-spill-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = File::create(&path)
+            .with_context(|| format!("unable to create temporary file {}", path.display()))?;
+        Ok(SpillFile {
+            path,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    fn write(&mut self, row: &[Value]) -> Result<()> {
+        let record = encode_record(row);
+        self.writer
+            .write_all(&(record.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
+
+    /// Reads the rows back. The file is removed once they have all been read.
+    fn read(mut self, width: usize) -> Result<impl Iterator<Item = Result<Row>>> {
+        self.writer.flush()?;
+        let mut reader = BufReader::new(File::open(&self.path)?);
+
+        Ok(std::iter::from_fn(move || {
+            let _file = &self;
+            let mut length = [0; 4];
+            match reader.read_exact(&mut length) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return None,
+                Err(e) => return Some(Err(e.into())),
+            }
+            let mut record = vec![0; u32::from_le_bytes(length) as usize];
+            if let Err(e) = reader.read_exact(&mut record) {
+                return Some(Err(e.into()));
+            }
+            Some(
+                parse_record(&record, width)
+                    .map(|values| values.into_iter().map(Value::from).collect()),
+            )
+        }))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Adds a number to a sum kept as `sum + error`, with the Kahan-Babuška-Neumaier compensated
+/// summation that SQLite uses so the rounding of each addition isn't lost
+fn add_real(sum: &mut f64, error: &mut f64, v: f64) {
+    let t = *sum + v;
+    if sum.abs() > v.abs() {
+        *error += (*sum - t) + v;
+    } else {
+        *error += (v - t) + *sum;
+    }
+    *sum = t;
+}
+
+/// Adds an integer to a compensated sum. One too large to be exact as a double is added in two
+/// parts that are.
+fn add_integer(sum: &mut f64, error: &mut f64, v: i64) {
+    if v.unsigned_abs() >= 1 << 52 {
+        let small = v % 16384;
+        add_real(sum, error, (v - small) as f64);
+        add_real(sum, error, small as f64);
+    } else {
+        add_real(sum, error, v as f64);
+    }
+}
@@ -0,0 +1,214 @@
+use super::aggregate::key_values;
+use super::explain::{Explainer, Step};
+use super::expr::{BoundExpr, Scope};
+use super::sort::{compare_keys, sort_rows, SortOrder};
+use super::{prepare_limit, resolve_compound_ordering_term, Plan, Row, Rows};
+use crate::ast::{CompoundOperator, Select};
+use crate::database::Database;
+use crate::value::{Collation, Value};
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// SELECTs combined by UNION, UNION ALL, INTERSECT or EXCEPT, from left to right
+pub struct Compound<'a> {
+    first: Rc<Plan<'a>>,
+    rest: Vec<(CompoundOperator, Rc<Plan<'a>>)>,
+    /// Collating sequences of the result columns, which tell whether two rows are the same
+    collations: Vec<Collation>,
+}
+
+/// Binds a compound SELECT. Its result columns are named after those of the first SELECT, and
+/// ORDER BY and LIMIT apply to the combined rows.
+pub fn prepare<'a>(db: &'a Database, select: &Select, outer: &Scope) -> Result<Plan<'a>> {
+    let first = Select {
+        with: None,
+        compound: vec![],
+        order_by: vec![],
+        limit: None,
+        ..select.clone()
+    };
+    let first = Plan::prepare(db, &first, outer)?;
+    let width = first.columns.len();
+    let mut rest = vec![];
+    for (operator, select) in &select.compound {
+        let plan = Plan::prepare(db, select, outer)?;
+        if plan.columns.len() != width {
+            bail!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                keyword(*operator)
+            );
+        }
+        rest.push((*operator, Rc::new(plan)));
+    }
+
+    // A column has the collating sequence of the leftmost SELECT that gives it one
+    let plans: Vec<_> = std::iter::once(&first)
+        .chain(rest.iter().map(|(_, plan)| &**plan))
+        .collect();
+    let column_types: Vec<_> = (0..width)
+        .map(|i| {
+            let collation = plans.iter().find_map(|plan| plan.column_types[i].1);
+            (first.column_types[i].0, collation)
+        })
+        .collect();
+    let collations: Vec<_> = column_types
+        .iter()
+        .map(|(_, collation)| collation.unwrap_or(Collation::Binary))
+        .collect();
+    let outer_depth = plans.iter().map(|plan| plan.outer_depth).max().unwrap_or(0);
+
+    let selects: Vec<_> = plans.iter().map(|plan| &plan.result_columns[..]).collect();
+    let mut keys = vec![];
+    let mut orders = vec![];
+    for (i, term) in select.order_by.iter().enumerate() {
+        let (column, collation) = resolve_compound_ordering_term(&term.expr, &selects, i)?;
+        keys.push(BoundExpr::Column(column));
+        orders.push(SortOrder::new(
+            term.descending,
+            term.nulls_first,
+            collation.unwrap_or(collations[column]),
+        ));
+    }
+    let (limit, offset) = prepare_limit(db, select.limit.as_ref())?;
+
+    Ok(Plan {
+        columns: first.columns.clone(),
+        column_types,
+        outer_width: outer.columns.len(),
+        outer_depth,
+        result_columns: first.result_columns.clone(),
+        tables: None,
+        filter: None,
+        aggregate: None,
+        having: None,
+        windows: None,
+        projection: (0..width).map(BoundExpr::Column).collect(),
+        distinct: false,
+        keys,
+        orders,
+        sorted: false,
+        limit,
+        offset,
+        compound: Some(Compound {
+            first: Rc::new(first),
+            rest,
+            collations,
+        }),
+    })
+}
+
+pub fn keyword(operator: CompoundOperator) -> &'static str {
+    match operator {
+        CompoundOperator::Union => "UNION",
+        CompoundOperator::UnionAll => "UNION ALL",
+        CompoundOperator::Intersect => "INTERSECT",
+        CompoundOperator::Except => "EXCEPT",
+    }
+}
+
+impl<'a> Compound<'a> {
+    /// The combined rows of the SELECTs for a row of the enclosing query. Apart from UNION ALL,
+    /// the operators drop duplicate rows, which leaves the rows sorted.
+    pub fn rows(&self, outer: &[Value]) -> Result<Rows<'a>> {
+        let mut rows = self.first.clone().run(outer)?;
+        for (operator, plan) in &self.rest {
+            rows = match operator {
+                CompoundOperator::UnionAll => {
+                    // The right SELECT only runs once the rows of the left one run out
+                    let plan = plan.clone();
+                    let outer = outer.to_vec();
+                    let right = std::iter::once(()).flat_map(move |_| -> Rows<'a> {
+                        match plan.clone().run(&outer) {
+                            Ok(rows) => rows,
+                            Err(e) => Box::new(std::iter::once(Err(e))),
+                        }
+                    });
+                    Box::new(rows.chain(right))
+                }
+                CompoundOperator::Union => {
+                    let rows = self.distinct(rows.chain(plan.clone().run(outer)?))?;
+                    Box::new(rows.into_iter().map(Ok))
+                }
+                CompoundOperator::Intersect | CompoundOperator::Except => {
+                    let right = plan
+                        .clone()
+                        .run(outer)?
+                        .map(|row| row.map(|row| key_values(&row, &self.collations)))
+                        .collect::<Result<HashSet<_>>>()?;
+                    let keep = *operator == CompoundOperator::Intersect;
+                    let collations = self.collations.clone();
+                    let rows = self
+                        .distinct(rows)?
+                        .into_iter()
+                        .filter(move |row| right.contains(&key_values(row, &collations)) == keep);
+                    Box::new(rows.map(Ok))
+                }
+            };
+        }
+        Ok(rows)
+    }
+
+    /// The SELECTs, from left to right
+    pub fn plans(&self) -> Vec<&Plan<'a>> {
+        std::iter::once(&*self.first)
+            .chain(self.rest.iter().map(|(_, plan)| &**plan))
+            .collect()
+    }
+
+    /// The step of EXPLAIN QUERY PLAN for combining the SELECTs. With ORDER BY, SQLite merges
+    /// the sorted rows of each SELECT with those of the SELECTs on its left instead.
+    pub fn explain(&self, explainer: &mut Explainer, ordered: bool) -> Step {
+        if !ordered {
+            let mut children = vec![Step::with_children(
+                "LEFT-MOST SUBQUERY",
+                self.first.explain(explainer),
+            )];
+            for (operator, plan) in &self.rest {
+                let detail = match operator {
+                    CompoundOperator::UnionAll => "UNION ALL".to_string(),
+                    operator => format!("{} USING TEMP B-TREE", keyword(*operator)),
+                };
+                children.push(Step::with_children(detail, plan.explain(explainer)));
+            }
+            return Step::with_children("COMPOUND QUERY", children);
+        }
+
+        let sorted = |plan: &Plan<'a>, explainer: &mut Explainer| {
+            let mut steps = plan.explain(explainer);
+            steps.push(Step::new("USE TEMP B-TREE FOR ORDER BY"));
+            steps
+        };
+        let mut left = sorted(&self.first, explainer);
+        for (operator, plan) in &self.rest {
+            let right = sorted(plan, explainer);
+            left = vec![Step::with_children(
+                format!("MERGE ({})", keyword(*operator)),
+                vec![
+                    Step::with_children("LEFT", left),
+                    Step::with_children("RIGHT", right),
+                ],
+            )];
+        }
+        left.pop().unwrap()
+    }
+
+    /// Sorts the rows and drops duplicates. Of equal rows, the last one is kept.
+    fn distinct(&self, rows: impl Iterator<Item = Result<Row>>) -> Result<Vec<Row>> {
+        let orders: Vec<_> = self
+            .collations
+            .iter()
+            .map(|collation| SortOrder::new(false, None, *collation))
+            .collect();
+        let rows = rows.map(|row| row.map(|row| (row.clone(), row)));
+        let mut distinct: Vec<Row> = vec![];
+        for row in sort_rows(rows, orders.clone(), None)? {
+            match distinct.last_mut() {
+                Some(last) if compare_keys(&orders, last, &row) == Ordering::Equal => *last = row,
+                _ => distinct.push(row),
+            }
+        }
+        Ok(distinct)
+    }
+}
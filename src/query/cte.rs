@@ -0,0 +1,400 @@
+use super::aggregate::{key_values, KeyValue};
+use super::compound::keyword;
+use super::explain::{Explainer, Step};
+use super::expr::{BoundExpr, Scope, ScopeColumn};
+use super::sort::{SortOrder, SortQueue};
+use super::{prepare_limit, project, resolve_compound_ordering_term, Plan, Row, Rows};
+use crate::ast::{CompoundOperator, Select, With};
+use crate::database::Database;
+use crate::value::{Affinity, Collation, Value};
+use anyhow::{bail, Result};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+/// A table named by a WITH clause
+#[derive(Debug)]
+pub struct CommonTable {
+    pub name: String,
+    definition: Definition,
+}
+
+#[derive(Debug)]
+enum Definition {
+    /// A SELECT, planned again wherever the table is used
+    Select {
+        columns: Vec<String>,
+        select: Box<Select>,
+        /// Number of columns of the scope of the statement with the WITH clause. The SELECT can
+        /// refer to them like a subquery of the statement would.
+        width: usize,
+        /// The tables named before this one, which the SELECT can refer to
+        common_tables: Vec<Rc<CommonTable>>,
+    },
+    /// The table inside the first SELECT of its own definition, where it can't be used yet
+    Circular,
+    /// The table inside the recursive SELECTs of its own definition, where it holds the row
+    /// being recursed on
+    Current {
+        columns: Vec<ScopeColumn>,
+        row: Rc<RefCell<Row>>,
+        references: Rc<Cell<usize>>,
+    },
+}
+
+/// What a reference to a common table reads
+pub enum Reference<'a> {
+    Select(Box<Plan<'a>>),
+    Recursive(Box<RecursiveQuery<'a>>),
+    Current {
+        row: Rc<RefCell<Row>>,
+        columns: Vec<ScopeColumn>,
+    },
+}
+
+/// Adds the tables of a WITH clause to the scope of its statement
+pub fn define(with: &With, outer: &Scope) -> Result<Scope> {
+    let mut scope = outer.clone();
+    for (i, table) in with.tables.iter().enumerate() {
+        if with.tables[..i]
+            .iter()
+            .any(|other| other.name.eq_ignore_ascii_case(&table.name))
+        {
+            bail!("duplicate WITH table name: {}", table.name);
+        }
+        let definition = Definition::Select {
+            columns: table.columns.clone(),
+            select: Box::new(table.select.clone()),
+            width: outer.columns.len(),
+            common_tables: scope.common_tables.clone(),
+        };
+        scope.common_tables.push(Rc::new(CommonTable {
+            name: table.name.clone(),
+            definition,
+        }));
+    }
+    Ok(scope)
+}
+
+/// Plans a reference to a common table from a query nested in `outer`. A table that refers to
+/// itself from SELECTs after a UNION or UNION ALL is recursive.
+pub fn prepare<'a>(db: &'a Database, table: &CommonTable, outer: &Scope) -> Result<Reference<'a>> {
+    let (columns, select, width, common_tables) = match &table.definition {
+        Definition::Select {
+            columns,
+            select,
+            width,
+            common_tables,
+        } => (columns, &**select, *width, common_tables),
+        Definition::Circular => bail!("circular reference: {}", table.name),
+        Definition::Current {
+            columns,
+            row,
+            references,
+        } => {
+            if references.get() > 0 {
+                bail!("multiple references to recursive table: {}", table.name);
+            }
+            references.set(1);
+            return Ok(Reference::Current {
+                row: row.clone(),
+                columns: columns.clone(),
+            });
+        }
+    };
+
+    // The statement with the WITH clause is an enclosing query of `outer`, so its columns come
+    // first
+    let mut scope = Scope::new(outer.columns[..width].to_vec());
+    scope.common_tables = common_tables.clone();
+    scope.views = outer.views.clone();
+    if let Some(with) = &select.with {
+        scope = define(with, &scope)?;
+    }
+    let mut first_scope = scope.clone();
+    first_scope.common_tables.push(Rc::new(CommonTable {
+        name: table.name.clone(),
+        definition: Definition::Circular,
+    }));
+
+    if select.compound.is_empty() {
+        let mut plan = Plan::prepare(db, select, &first_scope)?;
+        plan.columns = column_names(&table.name, columns, plan.columns)?;
+        return Ok(Reference::Select(Box::new(plan)));
+    }
+
+    let core = Select {
+        with: None,
+        compound: vec![],
+        order_by: vec![],
+        limit: None,
+        ..select.clone()
+    };
+    let first = Plan::prepare(db, &core, &first_scope)?;
+    let first_columns = first.result_columns.clone();
+    let names = column_names(&table.name, columns, first.columns.clone())?;
+    let columns: Vec<_> = names
+        .iter()
+        .zip(&first.column_types)
+        .map(|(name, (affinity, collation))| ScopeColumn {
+            table: None,
+            name: name.clone(),
+            affinity: affinity.unwrap_or(Affinity::Blob),
+            collation: collation.unwrap_or(Collation::Binary),
+            hidden: false,
+            using: false,
+            depth: 0,
+        })
+        .collect();
+
+    let row = Rc::new(RefCell::new(vec![]));
+    let references = Rc::new(Cell::new(0));
+    let mut recursive_scope = scope.clone();
+    recursive_scope.common_tables.push(Rc::new(CommonTable {
+        name: table.name.clone(),
+        definition: Definition::Current {
+            columns: columns.clone(),
+            row: row.clone(),
+            references: references.clone(),
+        },
+    }));
+
+    // The SELECTs before the first one that refers to the table set it up
+    let mut setup_len = 0;
+    let mut recursive = vec![];
+    let mut distinct = false;
+    let mut result_columns = vec![];
+    for (i, (operator, select)) in select.compound.iter().enumerate() {
+        references.set(0);
+        let plan = Plan::prepare(db, select, &recursive_scope)?;
+        if plan.columns.len() != names.len() {
+            bail!(
+                "SELECTs to the left and right of {} do not have the same number of result columns",
+                keyword(*operator)
+            );
+        }
+        result_columns.push(plan.result_columns.clone());
+        match references.get() {
+            0 if recursive.is_empty() => setup_len = i + 1,
+            1 if matches!(
+                operator,
+                CompoundOperator::Union | CompoundOperator::UnionAll
+            ) =>
+            {
+                if plan.aggregate.is_some() {
+                    bail!("recursive aggregate queries not supported");
+                }
+                if recursive.is_empty() {
+                    distinct = *operator == CompoundOperator::Union;
+                }
+                recursive.push(Rc::new(plan));
+            }
+            0 | 1 => bail!("circular reference: {}", table.name),
+            _ => bail!("multiple references to recursive table: {}", table.name),
+        }
+    }
+    if recursive.is_empty() {
+        let mut plan = Plan::prepare(db, select, &first_scope)?;
+        plan.columns = names;
+        return Ok(Reference::Select(Box::new(plan)));
+    }
+    let setup = if setup_len == 0 {
+        first
+    } else {
+        let setup = Select {
+            compound: select.compound[..setup_len].to_vec(),
+            ..core
+        };
+        Plan::prepare(db, &setup, &first_scope)?
+    };
+
+    // ORDER BY decides which row is recursed on next
+    let selects: Vec<_> = std::iter::once(&first_columns)
+        .chain(&result_columns)
+        .map(|columns| &columns[..])
+        .collect();
+    let mut keys = vec![];
+    let mut orders = vec![];
+    for (i, term) in select.order_by.iter().enumerate() {
+        let (column, collation) = resolve_compound_ordering_term(&term.expr, &selects, i)?;
+        keys.push(BoundExpr::Column(column));
+        orders.push(SortOrder::new(
+            term.descending,
+            term.nulls_first,
+            collation.unwrap_or(columns[column].collation),
+        ));
+    }
+    let (limit, offset) = prepare_limit(db, select.limit.as_ref())?;
+
+    let outer_depth = recursive
+        .iter()
+        .map(|plan| plan.outer_depth)
+        .fold(setup.outer_depth, usize::max);
+    Ok(Reference::Recursive(Box::new(RecursiveQuery {
+        name: table.name.clone(),
+        columns: names,
+        column_types: setup.column_types.clone(),
+        outer_width: width,
+        outer_depth,
+        setup: Rc::new(setup),
+        recursive,
+        distinct,
+        collations: columns.iter().map(|column| column.collation).collect(),
+        row,
+        keys,
+        orders,
+        limit,
+        offset,
+        recursion_limit: db.recursion_limit,
+    })))
+}
+
+/// The declared column names of a common table, or else those of its SELECT
+fn column_names(name: &str, declared: &[String], names: Vec<String>) -> Result<Vec<String>> {
+    if declared.is_empty() {
+        return Ok(names);
+    }
+    if declared.len() != names.len() {
+        bail!(
+            "table {} has {} values for {} columns",
+            name,
+            names.len(),
+            declared.len()
+        );
+    }
+    Ok(declared.to_vec())
+}
+
+/// A recursive common table. The rows of the setup SELECT go into a queue, and each row taken
+/// out of the queue is a row of the table, which the recursive SELECTs are run on to queue more
+/// rows.
+pub struct RecursiveQuery<'a> {
+    name: String,
+    pub columns: Vec<String>,
+    pub column_types: Vec<(Option<Affinity>, Option<Collation>)>,
+    pub outer_width: usize,
+    pub outer_depth: usize,
+    setup: Rc<Plan<'a>>,
+    recursive: Vec<Rc<Plan<'a>>>,
+    /// Whether rows that were already queued are dropped, as with UNION instead of UNION ALL
+    distinct: bool,
+    collations: Vec<Collation>,
+    /// The row the recursive SELECTs read as the table
+    row: Rc<RefCell<Row>>,
+    keys: Vec<BoundExpr<'a>>,
+    orders: Vec<SortOrder>,
+    limit: Option<usize>,
+    offset: usize,
+    recursion_limit: usize,
+}
+
+impl<'a> RecursiveQuery<'a> {
+    /// The setup SELECT and the recursive SELECTs
+    pub fn plans(&self) -> Vec<&Plan<'a>> {
+        std::iter::once(&*self.setup)
+            .chain(self.recursive.iter().map(|plan| &**plan))
+            .collect()
+    }
+
+    /// The steps of EXPLAIN QUERY PLAN for computing the rows of the table
+    pub fn explain(&self, explainer: &mut Explainer) -> Vec<Step> {
+        let setup = self.setup.explain(explainer);
+        let mut recursive = vec![];
+        for plan in &self.recursive {
+            recursive.extend(plan.explain(explainer));
+        }
+        vec![
+            Step::with_children("SETUP", setup),
+            Step::with_children("RECURSIVE STEP", recursive),
+        ]
+    }
+
+    pub fn run(self: Rc<Self>, outer: &[Value]) -> Result<Rows<'a>> {
+        let mut rows = RecursiveRows {
+            queue: SortQueue::new(self.orders.clone()),
+            query: self.clone(),
+            outer: outer.to_vec(),
+            seen: HashSet::new(),
+            last: None,
+            recursions: 0,
+            skipped: 0,
+            produced: 0,
+        };
+        if self.limit != Some(0) {
+            for row in self.setup.clone().run(outer)? {
+                rows.add(row?)?;
+            }
+        }
+        Ok(Box::new(rows))
+    }
+}
+
+struct RecursiveRows<'a> {
+    query: Rc<RecursiveQuery<'a>>,
+    outer: Row,
+    queue: SortQueue,
+    seen: HashSet<Vec<KeyValue>>,
+    /// The row returned last, which hasn't been recursed on yet
+    last: Option<Row>,
+    recursions: usize,
+    skipped: usize,
+    produced: usize,
+}
+
+impl<'a> RecursiveRows<'a> {
+    fn advance(&mut self) -> Result<Option<Row>> {
+        if let Some(row) = self.last.take() {
+            self.recurse(row)?;
+        }
+        if matches!(self.query.limit, Some(limit) if self.produced >= limit) {
+            return Ok(None);
+        }
+        while let Some(row) = self.queue.pop() {
+            if self.skipped < self.query.offset {
+                self.skipped += 1;
+                self.recurse(row)?;
+                continue;
+            }
+            self.produced += 1;
+            self.last = Some(row.clone());
+            return Ok(Some(row));
+        }
+        Ok(None)
+    }
+
+    fn recurse(&mut self, row: Row) -> Result<()> {
+        self.recursions += 1;
+        if self.recursions > self.query.recursion_limit {
+            bail!(
+                "recursion limit of {} rows exceeded in {}",
+                self.query.recursion_limit,
+                self.query.name
+            );
+        }
+        *self.query.row.borrow_mut() = row;
+        let query = self.query.clone();
+        for plan in &query.recursive {
+            for row in plan.clone().run(&self.outer)? {
+                self.add(row?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, row: Row) -> Result<()> {
+        if self.query.distinct && !self.seen.insert(key_values(&row, &self.query.collations)) {
+            return Ok(());
+        }
+        let keys = project(&self.query.keys, &row)?;
+        self.queue.push(keys, row);
+        Ok(())
+    }
+}
+
+impl<'a> Iterator for RecursiveRows<'a> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance().transpose()
+    }
+}
@@ -0,0 +1,926 @@
+use super::printf::printf;
+use crate::value::{parse_numeric, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Milliseconds in a day
+const DAY: i64 = 86_400_000;
+
+/// Julian day of 1970-01-01, in milliseconds
+const UNIX_EPOCH_JD: i64 = 210_866_760_000_000;
+
+/// Largest julian day SQLite supports, the end of 9999-12-31, in milliseconds
+const MAX_JD: i64 = 464_269_060_799_999;
+
+/// A point in time, following SQLite's [date.c](https://www.sqlite.org/src/file/src/date.c):
+/// either a julian day in milliseconds, broken down date and time fields, or both
+#[derive(Debug, Clone, Default)]
+struct DateTime {
+    jd: i64,
+    year: i32,
+    month: i32,
+    day: i32,
+    hour: i32,
+    minute: i32,
+    second: f64,
+    /// Time zone offset in minutes
+    tz: i32,
+    valid_jd: bool,
+    valid_ymd: bool,
+    valid_hms: bool,
+    valid_tz: bool,
+    /// The time zone is known, so `utc` has nothing to do
+    tz_set: bool,
+    /// The value is a plain number held in `second`, that modifiers like `unixepoch` interpret
+    raw_seconds: bool,
+    /// Days past the end of the month that `floor` takes back, like 3 for February 31st
+    floor: i32,
+    /// Show milliseconds in the result
+    subsec: bool,
+    error: bool,
+}
+
+impl DateTime {
+    fn now() -> Self {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as i64);
+        DateTime {
+            jd: elapsed + UNIX_EPOCH_JD,
+            valid_jd: true,
+            ..DateTime::default()
+        }
+    }
+
+    fn from_number(value: f64) -> Self {
+        let mut date = DateTime {
+            second: value,
+            raw_seconds: true,
+            ..DateTime::default()
+        };
+        if (0.0..5373484.5).contains(&value) {
+            date.jd = (value * DAY as f64 + 0.5) as i64;
+            date.valid_jd = true;
+        }
+        date
+    }
+
+    /// Parses `YYYY-MM-DD [HH:MM[:SS[.SSS]]]`, `HH:MM[:SS[.SSS]]`, `now`, or a julian day number
+    fn parse(text: &str) -> Option<Self> {
+        let mut date = DateTime::default();
+        if date.parse_ymd(text) || date.parse_hms(text) {
+            Some(date)
+        } else if text.eq_ignore_ascii_case("now") {
+            Some(DateTime::now())
+        } else {
+            parse_real(text).map(DateTime::from_number)
+        }
+    }
+
+    fn parse_ymd(&mut self, text: &str) -> bool {
+        let (negative, text) = match text.strip_prefix('-') {
+            Some(text) => (true, text),
+            None => (false, text),
+        };
+        let fields = match digits(
+            text,
+            &[
+                (4, 0, 9999, Some(b'-')),
+                (2, 1, 12, Some(b'-')),
+                (2, 1, 31, None),
+            ],
+        ) {
+            Some(fields) => fields,
+            None => return false,
+        };
+        let rest = text[10..].trim_start_matches(|c: char| c.is_ascii_whitespace() || c == 'T');
+        if !self.parse_hms(rest) {
+            if !rest.is_empty() {
+                return false;
+            }
+            self.valid_hms = false;
+        }
+
+        self.valid_jd = false;
+        self.valid_ymd = true;
+        self.year = if negative { -fields[0] } else { fields[0] };
+        self.month = fields[1];
+        self.day = fields[2];
+        self.compute_floor();
+        if self.valid_tz {
+            self.compute_jd();
+        }
+        true
+    }
+
+    fn parse_hms(&mut self, text: &str) -> bool {
+        let fields = match digits(text, &[(2, 0, 24, Some(b':')), (2, 0, 59, None)]) {
+            Some(fields) => fields,
+            None => return false,
+        };
+        let mut rest = &text[5..];
+        let mut second = 0.0;
+        if let Some(after_colon) = rest.strip_prefix(':') {
+            second = match digits(after_colon, &[(2, 0, 59, None)]) {
+                Some(fields) => fields[0] as f64,
+                None => return false,
+            };
+            rest = &after_colon[2..];
+            if rest.starts_with('.') && rest[1..].starts_with(|c: char| c.is_ascii_digit()) {
+                let fraction: String = rest[1..].chars().take_while(char::is_ascii_digit).collect();
+                rest = &rest[1 + fraction.len()..];
+                second += format!("0.{}", fraction).parse::<f64>().unwrap_or(0.0);
+            }
+        }
+
+        self.valid_jd = false;
+        self.raw_seconds = false;
+        self.valid_hms = true;
+        self.hour = fields[0];
+        self.minute = fields[1];
+        self.second = second;
+        if !self.parse_timezone(rest) {
+            return false;
+        }
+        self.valid_tz = self.tz != 0;
+        true
+    }
+
+    /// Parses the `[+-]HH:MM` or `Z` that may follow a time
+    fn parse_timezone(&mut self, text: &str) -> bool {
+        let text = text.trim_start_matches(|c: char| c.is_ascii_whitespace());
+        self.tz = 0;
+        let rest = if let Some(rest) = text.strip_prefix(|c| c == 'Z' || c == 'z') {
+            rest
+        } else {
+            let sign = match text.chars().next() {
+                Some('-') => -1,
+                Some('+') => 1,
+                Some(_) => return false,
+                None => return true,
+            };
+            let fields = match digits(&text[1..], &[(2, 0, 14, Some(b':')), (2, 0, 59, None)]) {
+                Some(fields) => fields,
+                None => return false,
+            };
+            self.tz = sign * (fields[0] * 60 + fields[1]);
+            &text[6..]
+        };
+        self.tz_set = true;
+        rest.trim_start_matches(|c: char| c.is_ascii_whitespace())
+            .is_empty()
+    }
+
+    fn compute_jd(&mut self) {
+        if self.valid_jd {
+            return;
+        }
+        let (mut year, mut month, day) = if self.valid_ymd {
+            (self.year, self.month, self.day)
+        } else {
+            (2000, 1, 1)
+        };
+        if !(-4713..=9999).contains(&year) || self.raw_seconds {
+            *self = DateTime {
+                error: true,
+                ..DateTime::default()
+            };
+            return;
+        }
+        if month <= 2 {
+            year -= 1;
+            month += 12;
+        }
+        let a = year / 100;
+        let b = 2 - a + a / 4;
+        let x1 = 36525 * (year + 4716) / 100;
+        let x2 = 306001 * (month + 1) / 10000;
+        self.jd = (((x1 + x2 + day + b) as f64 - 1524.5) * DAY as f64) as i64;
+        self.valid_jd = true;
+        if self.valid_hms {
+            self.jd += self.hour as i64 * 3_600_000
+                + self.minute as i64 * 60_000
+                + (self.second * 1000.0 + 0.5) as i64;
+            if self.valid_tz {
+                self.jd -= self.tz as i64 * 60_000;
+                self.valid_ymd = false;
+                self.valid_hms = false;
+                self.valid_tz = false;
+            }
+        }
+    }
+
+    fn compute_ymd(&mut self) {
+        if self.valid_ymd {
+            return;
+        }
+        if !self.valid_jd {
+            self.year = 2000;
+            self.month = 1;
+            self.day = 1;
+        } else if !(0..=MAX_JD).contains(&self.jd) {
+            *self = DateTime {
+                error: true,
+                ..DateTime::default()
+            };
+            return;
+        } else {
+            let z = ((self.jd + DAY / 2) / DAY) as i32;
+            let a = ((z as f64 - 1867216.25) / 36524.25) as i32;
+            let a = z + 1 + a - a / 4;
+            let b = a + 1524;
+            let c = ((b as f64 - 122.1) / 365.25) as i32;
+            let d = (36525 * (c & 32767)) / 100;
+            let e = ((b - d) as f64 / 30.6001) as i32;
+            let x1 = (30.6001 * e as f64) as i32;
+            self.day = b - d - x1;
+            self.month = if e < 14 { e - 1 } else { e - 13 };
+            self.year = if self.month > 2 { c - 4716 } else { c - 4715 };
+        }
+        self.valid_ymd = true;
+    }
+
+    fn compute_hms(&mut self) {
+        if self.valid_hms {
+            return;
+        }
+        self.compute_jd();
+        let milliseconds = ((self.jd + DAY / 2) % DAY) as i32;
+        let seconds = milliseconds / 1000;
+        self.hour = seconds / 3600;
+        self.minute = seconds / 60 % 60;
+        self.second = (seconds % 60) as f64 + (milliseconds % 1000) as f64 / 1000.0;
+        self.raw_seconds = false;
+        self.valid_hms = true;
+    }
+
+    fn compute_ymd_hms(&mut self) {
+        self.compute_ymd();
+        self.compute_hms();
+    }
+
+    fn compute_floor(&mut self) {
+        let leap_year = self.year % 4 == 0 && (self.year % 100 != 0 || self.year % 400 == 0);
+        self.floor = if self.day <= 28 || (1 << self.month) & 0x15aa != 0 {
+            // Months with 31 days
+            0
+        } else if self.month != 2 {
+            (self.day == 31) as i32
+        } else if leap_year {
+            self.day - 29
+        } else {
+            self.day - 28
+        };
+    }
+
+    /// Brings the month back between 1 and 12, carrying into the year
+    fn normalize_month(&mut self) {
+        let years = if self.month > 0 {
+            (self.month - 1) / 12
+        } else {
+            (self.month - 12) / 12
+        };
+        self.year += years;
+        self.month -= years * 12;
+    }
+
+    fn clear_ymd_hms_tz(&mut self) {
+        self.valid_ymd = false;
+        self.valid_hms = false;
+        self.valid_tz = false;
+    }
+
+    /// Seconds since 1970-01-01
+    fn unix_seconds(&self) -> i64 {
+        self.jd / 1000 - UNIX_EPOCH_JD / 1000
+    }
+
+    /// Applies a modifier. `first` tells whether it directly follows the time value, which
+    /// `unixepoch`, `julianday` and `auto` require.
+    fn modify(&mut self, modifier: &str, first: bool) -> bool {
+        let modifier = modifier.to_ascii_lowercase();
+        match modifier.as_str() {
+            "auto" => {
+                if !first {
+                    return false;
+                }
+                if !self.raw_seconds || self.valid_jd {
+                    self.raw_seconds = false;
+                    true
+                } else if (-210_866_760_000.0..=253_402_300_799.0).contains(&self.second) {
+                    self.set_unix_seconds();
+                    true
+                } else {
+                    false
+                }
+            }
+            "julianday" => {
+                let valid = first && self.valid_jd && self.raw_seconds;
+                if valid {
+                    self.raw_seconds = false;
+                }
+                valid
+            }
+            "unixepoch" => {
+                let jd = self.second * 1000.0 + UNIX_EPOCH_JD as f64;
+                let valid = first && self.raw_seconds && (0.0..(MAX_JD + 1) as f64).contains(&jd);
+                if valid {
+                    self.set_unix_seconds();
+                }
+                valid
+            }
+            "subsec" | "subsecond" => {
+                self.subsec = true;
+                true
+            }
+            // A day past the end of the month rolls over into the next month, unless `floor`
+            // moves it back to the last day of the month
+            "ceiling" => {
+                self.compute_jd();
+                self.clear_ymd_hms_tz();
+                self.floor = 0;
+                true
+            }
+            "floor" => {
+                self.compute_jd();
+                self.jd -= self.floor as i64 * DAY;
+                self.clear_ymd_hms_tz();
+                true
+            }
+            "localtime" => {
+                self.compute_jd();
+                self.jd += local_offset(self);
+                self.clear_ymd_hms_tz();
+                true
+            }
+            "utc" => {
+                if !self.tz_set {
+                    // Guess the UTC time and correct it by how far its local time is off, a
+                    // few times since the offset can change between the guesses
+                    self.compute_jd();
+                    let local = self.jd;
+                    let mut guess = local;
+                    let mut error = 0;
+                    for _ in 0..4 {
+                        guess -= error;
+                        let guessed = DateTime {
+                            jd: guess,
+                            valid_jd: true,
+                            ..DateTime::default()
+                        };
+                        error = guess + local_offset(&guessed) - local;
+                        if error == 0 {
+                            break;
+                        }
+                    }
+                    *self = DateTime {
+                        jd: guess,
+                        valid_jd: true,
+                        tz_set: true,
+                        ..DateTime::default()
+                    };
+                }
+                true
+            }
+            _ => {
+                if let Some(weekday) = modifier.strip_prefix("weekday ") {
+                    self.weekday(weekday)
+                } else if let Some(unit) = modifier.strip_prefix("start of ") {
+                    self.start_of(unit)
+                } else if modifier.starts_with(|c: char| c == '+' || c == '-' || c.is_ascii_digit())
+                {
+                    self.add(&modifier)
+                } else {
+                    false
+                }
+            }
+        }
+    }
+
+    fn set_unix_seconds(&mut self) {
+        let jd = self.second * 1000.0 + UNIX_EPOCH_JD as f64;
+        self.clear_ymd_hms_tz();
+        self.jd = (jd + 0.5) as i64;
+        self.valid_jd = true;
+        self.raw_seconds = false;
+    }
+
+    /// `weekday N` advances to the next date whose day of the week is N, Sunday being 0
+    fn weekday(&mut self, weekday: &str) -> bool {
+        let weekday = match parse_real(weekday) {
+            Some(n) if n.fract() == 0.0 && (0.0..7.0).contains(&n) => n as i64,
+            _ => return false,
+        };
+        self.compute_ymd_hms();
+        self.valid_tz = false;
+        self.valid_jd = false;
+        self.compute_jd();
+        let mut day = ((self.jd + 129_600_000) / DAY) % 7;
+        if day > weekday {
+            day -= 7;
+        }
+        self.jd += (weekday - day) * DAY;
+        self.clear_ymd_hms_tz();
+        true
+    }
+
+    fn start_of(&mut self, unit: &str) -> bool {
+        if !self.valid_jd && !self.valid_ymd && !self.valid_hms {
+            return false;
+        }
+        self.compute_ymd();
+        self.valid_hms = true;
+        self.hour = 0;
+        self.minute = 0;
+        self.second = 0.0;
+        self.raw_seconds = false;
+        self.valid_tz = false;
+        self.valid_jd = false;
+        match unit {
+            "month" => self.day = 1,
+            "year" => {
+                self.month = 1;
+                self.day = 1;
+            }
+            "day" => {}
+            _ => return false,
+        }
+        true
+    }
+
+    /// `±NNN unit` adds an amount of days, hours, minutes, seconds, months or years,
+    /// `±HH:MM[:SS[.SSS]]` adds a time, and `±YYYY-MM-DD [HH:MM[:SS[.SSS]]]` adds years,
+    /// months, days and optionally a time
+    fn add(&mut self, modifier: &str) -> bool {
+        let negative = modifier.starts_with('-');
+        // The number ends before a ':', a space, or the '-' that follows the years
+        let end = modifier
+            .char_indices()
+            .skip(1)
+            .find(|&(i, c)| {
+                c == ':'
+                    || c.is_ascii_whitespace()
+                    || c == '-'
+                        && (i == 5 || i == 6)
+                        && digits(&modifier[1..], &[(i - 1, 0, 14712, None)]).is_some()
+            })
+            .map_or(modifier.len(), |(i, _)| i);
+        let amount = match parse_real(&modifier[..end]) {
+            Some(amount) => amount,
+            None => return false,
+        };
+
+        if modifier[end..].starts_with('-') {
+            if !modifier.starts_with(['+', '-']) {
+                return false;
+            }
+            let fields = match digits(
+                &modifier[end + 1..],
+                &[(2, 0, 11, Some(b'-')), (2, 0, 30, None)],
+            ) {
+                Some(fields) => fields,
+                None => return false,
+            };
+            let (years, months, days) = if negative {
+                (amount as i32, -fields[0], -fields[1])
+            } else {
+                (amount as i32, fields[0], fields[1])
+            };
+            self.compute_ymd_hms();
+            self.valid_jd = false;
+            self.year += years;
+            self.month += months;
+            self.normalize_month();
+            self.compute_floor();
+            self.compute_jd();
+            self.valid_ymd = false;
+            self.valid_hms = false;
+            self.jd += days as i64 * DAY;
+
+            // A time may follow, after a single space
+            let time = &modifier[end + 6..];
+            if time.is_empty() {
+                return true;
+            }
+            return time.starts_with(|c: char| c.is_ascii_whitespace())
+                && digits(&time[1..], &[(2, 0, 24, Some(b':')), (2, 0, 59, None)]).is_some()
+                && self.add_time(&time[1..], negative);
+        }
+
+        if modifier[end..].starts_with(':') {
+            let time = modifier.strip_prefix(['+', '-']).unwrap_or(modifier);
+            return self.add_time(time, negative);
+        }
+
+        let unit = modifier[end..].trim_start_matches(|c: char| c.is_ascii_whitespace());
+        if unit.len() < 3 || unit.len() > 10 {
+            return false;
+        }
+        let unit = unit.strip_suffix('s').unwrap_or(unit);
+        self.compute_jd();
+
+        // Milliseconds in one unit, and the largest amount that can be added
+        let (milliseconds, limit) = match unit {
+            "second" => (1000.0, 464_269_060_800.0),
+            "minute" => (60_000.0, 7_737_817_680.0),
+            "hour" => (3_600_000.0, 128_963_628.0),
+            "day" => (86_400_000.0, 5_373_485.0),
+            "month" => (2_592_000_000.0, 176_546.0),
+            "year" => (31_536_000_000.0, 14_713.0),
+            _ => return false,
+        };
+        if amount <= -limit || amount >= limit {
+            return false;
+        }
+
+        let mut amount = amount;
+        match unit {
+            "month" => {
+                self.compute_ymd_hms();
+                self.month += amount as i32;
+                self.normalize_month();
+                self.compute_floor();
+                self.valid_jd = false;
+                amount -= amount.trunc();
+            }
+            "year" => {
+                self.compute_ymd_hms();
+                self.year += amount as i32;
+                self.compute_floor();
+                self.valid_jd = false;
+                amount -= amount.trunc();
+            }
+            _ => self.floor = 0,
+        }
+        self.compute_jd();
+        let rounder = if amount < 0.0 { -0.5 } else { 0.5 };
+        self.jd += (amount * milliseconds + rounder) as i64;
+        self.clear_ymd_hms_tz();
+        true
+    }
+
+    /// Adds or subtracts a time of day given as `HH:MM[:SS[.SSS]]`
+    fn add_time(&mut self, time: &str, negative: bool) -> bool {
+        let mut offset = DateTime::default();
+        if !offset.parse_hms(time) {
+            return false;
+        }
+        offset.compute_jd();
+        offset.jd -= DAY / 2;
+        offset.jd -= offset.jd / DAY * DAY;
+        if negative {
+            offset.jd = -offset.jd;
+        }
+        self.compute_jd();
+        self.clear_ymd_hms_tz();
+        self.jd += offset.jd;
+        true
+    }
+
+    /// Days since the first of January of the same year
+    fn days_after_jan01(&self) -> i64 {
+        let mut jan01 = self.clone();
+        jan01.valid_jd = false;
+        jan01.month = 1;
+        jan01.day = 1;
+        jan01.compute_jd();
+        (self.jd - jan01.jd + DAY / 2) / DAY
+    }
+
+    fn days_after_monday(&self) -> i64 {
+        ((self.jd + DAY / 2) / DAY) % 7
+    }
+
+    fn days_after_sunday(&self) -> i64 {
+        ((self.jd + 129_600_000) / DAY) % 7
+    }
+
+    /// The Thursday of the same week, whose year is the ISO 8601 year of the week
+    fn iso_week_thursday(&self) -> DateTime {
+        let mut thursday = self.clone();
+        thursday.jd += (3 - self.days_after_monday()) * DAY;
+        thursday.valid_ymd = false;
+        thursday.compute_ymd();
+        thursday
+    }
+
+    /// `HH:MM:SS`, or `HH:MM:SS.SSS` after the `subsec` modifier
+    fn time_text(&self) -> String {
+        if self.subsec {
+            let milliseconds = (self.second * 1000.0) as i32;
+            format!(
+                "{:02}:{:02}:{:02}.{:03}",
+                self.hour,
+                self.minute,
+                milliseconds / 1000,
+                milliseconds % 1000
+            )
+        } else {
+            format!(
+                "{:02}:{:02}:{:02}",
+                self.hour, self.minute, self.second as i32
+            )
+        }
+    }
+
+    fn year_text(&self) -> String {
+        if self.year < 0 {
+            format!("-{:04}", -self.year % 10000)
+        } else {
+            format!("{:04}", self.year % 10000)
+        }
+    }
+}
+
+/// Reads the whole text as a number, like a julian day or an amount in a modifier
+fn parse_real(text: &str) -> Option<f64> {
+    parse_numeric(text).and_then(|value| value.to_real())
+}
+
+/// Reads fixed-width groups of digits. Each group is (width, min, max, separator) where the
+/// separator must follow the group.
+fn digits(text: &str, groups: &[(usize, i32, i32, Option<u8>)]) -> Option<Vec<i32>> {
+    let bytes = text.as_bytes();
+    let mut position = 0;
+    let mut values = vec![];
+    for &(width, min, max, separator) in groups {
+        let group = bytes.get(position..position + width)?;
+        if !group.iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let value = group
+            .iter()
+            .fold(0, |value, digit| value * 10 + (digit - b'0') as i32);
+        if value < min || value > max {
+            return None;
+        }
+        position += width;
+        if let Some(separator) = separator {
+            if bytes.get(position) != Some(&separator) {
+                return None;
+            }
+            position += 1;
+        }
+        values.push(value);
+    }
+    Some(values)
+}
+
+/// Reads the time value and applies the modifiers, returning None when either is invalid
+fn parse_args(args: &[Value]) -> Option<DateTime> {
+    let mut date = match args.first() {
+        None => DateTime::now(),
+        Some(Value::Null) => return None,
+        Some(Value::Integer(v)) => DateTime::from_number(*v as f64),
+        Some(Value::Real(v)) => DateTime::from_number(*v),
+        Some(v) => DateTime::parse(&v.to_string())?,
+    };
+
+    for (i, modifier) in args.iter().enumerate().skip(1) {
+        let modifier = modifier.to_text()?;
+        if !date.modify(&modifier, i == 1) {
+            return None;
+        }
+    }
+
+    date.compute_jd();
+    if date.error || !(0..=MAX_JD).contains(&date.jd) {
+        return None;
+    }
+    // Without modifiers, a day past the end of the month rolls over, like 2023-02-31 to 2023-03-03
+    if args.len() == 1 && date.valid_ymd && date.day > 28 {
+        date.valid_ymd = false;
+    }
+    Some(date)
+}
+
+pub fn julianday(args: &[Value]) -> Value {
+    match parse_args(args) {
+        Some(date) => Value::Real(date.jd as f64 / DAY as f64),
+        None => Value::Null,
+    }
+}
+
+pub fn unixepoch(args: &[Value]) -> Value {
+    match parse_args(args) {
+        Some(date) if date.subsec => Value::Real((date.jd - UNIX_EPOCH_JD) as f64 / 1000.0),
+        Some(date) => Value::Integer(date.unix_seconds()),
+        None => Value::Null,
+    }
+}
+
+pub fn datetime(args: &[Value]) -> Value {
+    match parse_args(args) {
+        Some(mut date) => {
+            date.compute_ymd_hms();
+            Value::Text(format!(
+                "{}-{:02}-{:02} {}",
+                date.year_text(),
+                date.month,
+                date.day,
+                date.time_text()
+            ))
+        }
+        None => Value::Null,
+    }
+}
+
+pub fn date(args: &[Value]) -> Value {
+    match parse_args(args) {
+        Some(mut date) => {
+            date.compute_ymd();
+            Value::Text(format!(
+                "{}-{:02}-{:02}",
+                date.year_text(),
+                date.month,
+                date.day
+            ))
+        }
+        None => Value::Null,
+    }
+}
+
+pub fn time(args: &[Value]) -> Value {
+    match parse_args(args) {
+        Some(mut date) => {
+            date.compute_hms();
+            Value::Text(date.time_text())
+        }
+        None => Value::Null,
+    }
+}
+
+/// `strftime(format, time, modifiers...)`. An unknown substitution makes the result NULL.
+pub fn strftime(args: &[Value]) -> Value {
+    let format = match args[0].to_text() {
+        Some(format) => format,
+        None => return Value::Null,
+    };
+    let mut date = match parse_args(&args[1..]) {
+        Some(date) => date,
+        None => return Value::Null,
+    };
+    date.compute_ymd_hms();
+
+    let mut output = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        let twelve_hour = match date.hour % 12 {
+            0 => 12,
+            hour => hour,
+        };
+        let text = match chars.next() {
+            Some('d') => format!("{:02}", date.day),
+            Some('e') => format!("{:2}", date.day),
+            Some('f') => printf("%06.3f", &[Value::Real(date.second.min(59.999))]),
+            Some('F') => format!("{}-{:02}-{:02}", date.year_text(), date.month, date.day),
+            Some('G') => format!("{:04}", date.iso_week_thursday().year),
+            Some('g') => format!("{:02}", date.iso_week_thursday().year % 100),
+            Some('H') => format!("{:02}", date.hour),
+            Some('I') => format!("{:02}", twelve_hour),
+            Some('j') => format!("{:03}", date.days_after_jan01() + 1),
+            Some('J') => printf("%.16g", &[Value::Real(date.jd as f64 / DAY as f64)]),
+            Some('k') => format!("{:2}", date.hour),
+            Some('l') => format!("{:2}", twelve_hour),
+            Some('m') => format!("{:02}", date.month),
+            Some('M') => format!("{:02}", date.minute),
+            Some('p') => if date.hour >= 12 { "PM" } else { "AM" }.to_string(),
+            Some('P') => if date.hour >= 12 { "pm" } else { "am" }.to_string(),
+            Some('R') => format!("{:02}:{:02}", date.hour, date.minute),
+            Some('s') => date.unix_seconds().to_string(),
+            Some('S') => format!("{:02}", date.second as i32),
+            Some('T') => format!(
+                "{:02}:{:02}:{:02}",
+                date.hour, date.minute, date.second as i32
+            ),
+            Some('u') => match date.days_after_sunday() {
+                0 => "7".to_string(),
+                day => day.to_string(),
+            },
+            Some('U') => format!(
+                "{:02}",
+                (date.days_after_jan01() - date.days_after_sunday() + 7) / 7
+            ),
+            Some('V') => format!("{:02}", date.iso_week_thursday().days_after_jan01() / 7 + 1),
+            Some('w') => date.days_after_sunday().to_string(),
+            Some('W') => format!(
+                "{:02}",
+                (date.days_after_jan01() - date.days_after_monday() + 7) / 7
+            ),
+            Some('Y') => format!("{:04}", date.year),
+            Some('%') => "%".to_string(),
+            _ => return Value::Null,
+        };
+        output.push_str(&text);
+    }
+
+    Value::Text(output)
+}
+
+/// Milliseconds to add to a UTC time to get the local time. Like SQLite, times outside of
+/// 1971-2037 use the offset of the same date in a year between 2000 and 2003 that is the same
+/// distance from a leap year.
+fn local_offset(date: &DateTime) -> i64 {
+    let mut utc = date.clone();
+    utc.compute_ymd_hms();
+    if utc.year < 1971 || utc.year >= 2038 {
+        utc.year = 2000 + utc.year % 4;
+    } else {
+        utc.second = (utc.second + 0.5).trunc();
+    }
+    utc.tz = 0;
+    utc.valid_jd = false;
+    utc.compute_jd();
+
+    timezone::utc_offset(utc.unix_seconds()) * 1000
+}
+
+/// Offsets of the local time zone, read from the compiled
+/// [TZif](https://www.rfc-editor.org/rfc/rfc8536) file named by `$TZ`, or `/etc/localtime`
+mod timezone {
+    use once_cell::sync::Lazy;
+    use std::convert::TryInto;
+
+    /// Transition times, each with the UTC offset in seconds that starts at that time, and the
+    /// offset in use before the first transition
+    struct TimeZone {
+        transitions: Vec<(i64, i64)>,
+        initial_offset: i64,
+    }
+
+    static LOCAL: Lazy<Option<TimeZone>> = Lazy::new(|| {
+        let path = match std::env::var("TZ") {
+            Ok(name) if !name.is_empty() => {
+                let name = name.trim_start_matches(':');
+                if name.starts_with('/') {
+                    name.to_string()
+                } else {
+                    format!("/usr/share/zoneinfo/{}", name)
+                }
+            }
+            _ => "/etc/localtime".to_string(),
+        };
+        parse(&std::fs::read(path).ok()?)
+    });
+
+    /// UTC offset in seconds of the local time zone at a time given in seconds since 1970.
+    /// Without time zone information, local time is UTC.
+    pub fn utc_offset(time: i64) -> i64 {
+        match &*LOCAL {
+            Some(zone) => zone
+                .transitions
+                .iter()
+                .take_while(|(start, _)| *start <= time)
+                .last()
+                .map_or(zone.initial_offset, |(_, offset)| *offset),
+            None => 0,
+        }
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    }
+
+    /// Reads the version 1 data block, whose 32 bit times cover the years SQLite asks about
+    fn parse(data: &[u8]) -> Option<TimeZone> {
+        if !data.starts_with(b"TZif") {
+            return None;
+        }
+        // The header counts are isutcnt, isstdcnt, leapcnt, timecnt, typecnt and charcnt
+        let count = |i: usize| read_u32(data, 20 + i * 4).map(|count| count as usize);
+        let (transition_count, type_count) = (count(3)?, count(4)?);
+
+        let times_start = 44;
+        let indices_start = times_start + transition_count * 4;
+        let types_start = indices_start + transition_count;
+        let offset_of_type = |index: usize| {
+            let start = types_start + index * 6;
+            read_u32(data, start).map(|offset| offset as i32 as i64)
+        };
+        if types_start + type_count * 6 > data.len() {
+            return None;
+        }
+
+        let mut transitions = vec![];
+        for i in 0..transition_count {
+            let time = read_u32(data, times_start + i * 4)? as i32 as i64;
+            let index = *data.get(indices_start + i)? as usize;
+            transitions.push((time, offset_of_type(index)?));
+        }
+
+        // Before the first transition, the first standard time type applies
+        let initial_offset = (0..type_count)
+            .find(|&index| data.get(types_start + index * 6 + 4) == Some(&0))
+            .map_or(Some(0), offset_of_type)?;
+
+        Some(TimeZone {
+            transitions,
+            initial_offset,
+        })
+    }
+}
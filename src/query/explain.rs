@@ -0,0 +1,227 @@
+//! EXPLAIN QUERY PLAN, which describes how a statement is run as a tree of steps, in the words
+//! SQLite uses for them
+
+use super::expr::{BoundExpr, Scope};
+use super::{Plan, QueryResult};
+use crate::ast::Select;
+use crate::database::Database;
+use crate::value::Value;
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// A step of a query plan, and the steps it is made of
+pub struct Step {
+    pub detail: String,
+    pub children: Vec<Step>,
+}
+
+impl Step {
+    pub fn new(detail: impl Into<String>) -> Self {
+        Step::with_children(detail, vec![])
+    }
+
+    pub fn with_children(detail: impl Into<String>, children: Vec<Step>) -> Self {
+        Step {
+            detail: detail.into(),
+            children,
+        }
+    }
+}
+
+/// Plans a SELECT statement without running it. Like SQLite, each row has the id of a step,
+/// the id of the step it belongs to (0 for none), an unused column and the description of the
+/// step.
+pub fn explain<'a>(db: &'a Database, select: &Select) -> Result<QueryResult<'a>> {
+    let plan = Plan::prepare(db, select, &Scope::default())?;
+    let mut explainer = Explainer::default();
+    explainer.number(&plan);
+    let steps = plan.explain(&mut explainer);
+
+    let mut rows = vec![];
+    add_rows(&steps, 0, &mut rows);
+    let columns = ["id", "parent", "notused", "detail"];
+    Ok(QueryResult {
+        columns: columns.iter().map(|column| column.to_string()).collect(),
+        rows: Box::new(rows.into_iter().map(Ok)),
+    })
+}
+
+fn add_rows(steps: &[Step], parent: i64, rows: &mut Vec<Vec<Value>>) {
+    for step in steps {
+        let id = rows.len() as i64 + 1;
+        rows.push(vec![
+            Value::Integer(id),
+            Value::Integer(parent),
+            Value::Integer(0),
+            Value::Text(step.detail.clone()),
+        ]);
+        add_rows(&step.children, id, rows);
+    }
+}
+
+/// Draws the steps of a query plan as a tree, from the rows `explain` returns, like the sqlite3
+/// shell does
+pub fn render_tree(rows: &[Vec<Value>]) -> Vec<String> {
+    fn render(rows: &[Vec<Value>], parent: &Value, prefix: &str, lines: &mut Vec<String>) {
+        let children: Vec<_> = rows.iter().filter(|row| &row[1] == parent).collect();
+        for (i, row) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let branch = if last { "`--" } else { "|--" };
+            lines.push(format!("{}{}{}", prefix, branch, row[3]));
+            let prefix = format!("{}{}", prefix, if last { "   " } else { "|  " });
+            render(rows, &row[0], &prefix, lines);
+        }
+    }
+
+    let mut lines = vec!["QUERY PLAN".to_string()];
+    render(rows, &Value::Integer(0), "", &mut lines);
+    lines
+}
+
+/// Numbers the queries of a statement, and remembers which subqueries were already described
+#[derive(Default)]
+pub struct Explainer {
+    /// Numbers of the queries, by their address
+    numbers: HashMap<usize, usize>,
+    last: usize,
+    explained: HashSet<usize>,
+}
+
+fn address(plan: &Plan) -> usize {
+    plan as *const Plan as usize
+}
+
+impl Explainer {
+    /// Numbers the queries of a statement in the order SQLite does, each one after those nested
+    /// in it, as they are written
+    fn number(&mut self, plan: &Plan) {
+        // ORDER BY terms that repeat a result column share its subqueries
+        if self.numbers.contains_key(&address(plan)) {
+            return;
+        }
+        for nested in plan.nested_plans() {
+            self.number(nested);
+        }
+        self.last += 1;
+        self.numbers.insert(address(plan), self.last);
+    }
+
+    /// A number for a query made up while planning, such as the one computing window functions
+    pub fn next_number(&mut self) -> usize {
+        self.last += 1;
+        self.last
+    }
+
+    /// Adds a step for each subquery of `exprs` that wasn't described yet
+    pub fn subqueries<'e, 'a: 'e>(
+        &mut self,
+        exprs: impl IntoIterator<Item = &'e BoundExpr<'a>>,
+        steps: &mut Vec<Step>,
+    ) {
+        let mut found = vec![];
+        for expr in exprs {
+            expr.subqueries(&mut found);
+        }
+        for expr in found {
+            let (subquery, kind) = match expr {
+                BoundExpr::Subquery(subquery) | BoundExpr::Exists(subquery) => (subquery, "SCALAR"),
+                BoundExpr::InSubquery { subquery, .. } => (subquery, "LIST"),
+                _ => continue,
+            };
+            if !self.explained.insert(Rc::as_ptr(subquery) as usize) {
+                continue;
+            }
+            let correlated = match subquery.is_correlated() {
+                true => "CORRELATED ",
+                false => "",
+            };
+            let plan = subquery.plan();
+            let number = self.numbers.get(&address(plan)).copied().unwrap_or(0);
+            let children = plan.explain(self);
+            steps.push(Step::with_children(
+                format!("{}{} SUBQUERY {}", correlated, kind, number),
+                children,
+            ));
+        }
+    }
+}
+
+impl<'a> Plan<'a> {
+    /// The queries nested in this one, in the order they are written in
+    fn nested_plans(&self) -> Vec<&Plan<'a>> {
+        if let Some(compound) = &self.compound {
+            return compound.plans();
+        }
+        let mut plans = vec![];
+        let mut exprs: Vec<&BoundExpr> = self.projection.iter().collect();
+        if let Some(tables) = &self.tables {
+            let (from, from_exprs) = tables.parts();
+            plans.extend(from);
+            exprs.extend(from_exprs);
+        }
+        exprs.extend(&self.filter);
+        if let Some(aggregate) = &self.aggregate {
+            exprs.extend(&aggregate.group_by);
+        }
+        exprs.extend(&self.having);
+        exprs.extend(&self.keys);
+
+        let mut found = vec![];
+        for expr in exprs {
+            expr.subqueries(&mut found);
+        }
+        for expr in found {
+            match expr {
+                BoundExpr::Subquery(subquery)
+                | BoundExpr::Exists(subquery)
+                | BoundExpr::InSubquery { subquery, .. } => plans.push(&**subquery.plan()),
+                _ => {}
+            }
+        }
+        plans
+    }
+
+    /// The steps of running the query
+    pub fn explain(&self, explainer: &mut Explainer) -> Vec<Step> {
+        if let Some(compound) = &self.compound {
+            return vec![compound.explain(explainer, !self.keys.is_empty())];
+        }
+
+        let mut steps = match &self.tables {
+            Some(tables) => tables.explain(explainer),
+            None => vec![Step::new("SCAN CONSTANT ROW")],
+        };
+        explainer.subqueries(&self.filter, &mut steps);
+        if let Some(aggregate) = &self.aggregate {
+            if !aggregate.group_by.is_empty() && !aggregate.grouped {
+                steps.push(Step::new("USE TEMP B-TREE FOR GROUP BY"));
+            }
+            explainer.subqueries(&aggregate.group_by, &mut steps);
+        }
+        explainer.subqueries(&self.having, &mut steps);
+
+        // Window functions are computed on the rows of the query above, in the order of the
+        // PARTITION BY and ORDER BY of their window
+        if let Some(windows) = &self.windows {
+            if windows.sorts() {
+                steps.push(Step::new("USE TEMP B-TREE FOR ORDER BY"));
+            }
+            let name = format!("(subquery-{})", explainer.next_number());
+            steps = vec![
+                Step::with_children(format!("CO-ROUTINE {}", name), steps),
+                Step::new(format!("SCAN {}", name)),
+            ];
+        }
+
+        explainer.subqueries(&self.projection, &mut steps);
+        explainer.subqueries(&self.keys, &mut steps);
+        if self.distinct {
+            steps.push(Step::new("USE TEMP B-TREE FOR DISTINCT"));
+        }
+        if !self.keys.is_empty() && !self.sorted {
+            steps.push(Step::new("USE TEMP B-TREE FOR ORDER BY"));
+        }
+        steps
+    }
+}
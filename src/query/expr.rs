@@ -0,0 +1,1602 @@
+use super::aggregate::{AggregateCall, AggregateFunction};
+use super::cte::CommonTable;
+use super::functions::ScalarFunction;
+use super::json;
+use super::subquery::Subquery;
+use super::window::{self, Window, WindowCall, WindowCallKind, WindowFunction};
+use super::Plan;
+use crate::ast::{
+    BinaryOperator, Expr, FunctionCall, Over, Select, UnaryOperator, WindowDefinition,
+};
+use crate::database::Database;
+use crate::value::{Affinity, Collation, Value};
+use anyhow::{anyhow, bail, Result};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+/// A column that expressions can refer to
+#[derive(Debug, Clone)]
+pub struct ScopeColumn {
+    /// Name of the table the column comes from
+    pub table: Option<String>,
+    pub name: String,
+    pub affinity: Affinity,
+    pub collation: Collation,
+    /// Hidden columns like the rowid can be referenced by name, but aren't part of `*`
+    pub hidden: bool,
+    /// Columns of the right table of a USING or NATURAL join that are also in the left table.
+    /// An unqualified name refers to the left table's column.
+    pub using: bool,
+    /// How many queries out the column comes from: 0 for the tables of the query itself, 1 for
+    /// those of the query a subquery is nested in, and so on
+    pub depth: usize,
+}
+
+/// The columns of the rows an expression is evaluated against. The rows of a subquery start
+/// with the row of the query it is nested in.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    pub columns: Vec<ScopeColumn>,
+    /// The tables named by the WITH clauses of the query and of those it is nested in
+    pub common_tables: Vec<Rc<CommonTable>>,
+    /// The views whose definitions the query is part of, which it can't refer to
+    pub views: Vec<String>,
+    /// The largest depth of the columns resolved so far, shared by the copies of the scope
+    outer_depth: Rc<Cell<usize>>,
+}
+
+impl Scope {
+    pub fn new(columns: Vec<ScopeColumn>) -> Self {
+        Scope {
+            columns,
+            common_tables: vec![],
+            views: vec![],
+            outer_depth: Rc::default(),
+        }
+    }
+
+    /// The scope of a subquery nested in a query with this scope, before the subquery's own
+    /// tables are added
+    pub fn nested(&self) -> Scope {
+        let columns = self
+            .columns
+            .iter()
+            .map(|column| ScopeColumn {
+                depth: column.depth + 1,
+                ..column.clone()
+            })
+            .collect();
+        Scope {
+            common_tables: self.common_tables.clone(),
+            views: self.views.clone(),
+            ..Scope::new(columns)
+        }
+    }
+
+    /// Finds the table a WITH clause named, the innermost one when several have the name
+    pub fn common_table(&self, name: &str) -> Option<&Rc<CommonTable>> {
+        self.common_tables
+            .iter()
+            .rev()
+            .find(|table| table.name.eq_ignore_ascii_case(name))
+    }
+
+    /// How many queries out the outermost column that was referred to comes from. Zero when
+    /// only the query's own tables were referred to.
+    pub fn outer_depth(&self) -> usize {
+        self.outer_depth.get()
+    }
+
+    /// Records a reference to the columns of a query that many queries out
+    pub fn refer_to_outer(&self, depth: usize) {
+        if depth > self.outer_depth.get() {
+            self.outer_depth.set(depth);
+        }
+    }
+
+    /// Finds the position of a column in the row
+    pub fn resolve(&self, table: Option<&str>, name: &str) -> Result<usize> {
+        let find = |name: &str| {
+            self.columns
+                .iter()
+                .enumerate()
+                .filter(|(_, column)| {
+                    column.name.eq_ignore_ascii_case(name)
+                        && match (table, &column.table) {
+                            (Some(table), Some(column_table)) => {
+                                table.eq_ignore_ascii_case(column_table)
+                            }
+                            (Some(_), None) => false,
+                            (None, _) => true,
+                        }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut matches = find(name);
+        // The rowid goes by several names, unless a real column has taken them
+        if matches.is_empty()
+            && (name.eq_ignore_ascii_case("oid") || name.eq_ignore_ascii_case("_rowid_"))
+        {
+            matches = find("rowid");
+        }
+        // The tables of a query hide those of the queries it is nested in
+        if let Some(depth) = matches.iter().map(|(_, column)| column.depth).min() {
+            matches.retain(|(_, column)| column.depth == depth);
+        }
+        if matches.len() > 1 {
+            matches.retain(|(_, column)| !column.hidden);
+        }
+        if matches.len() > 1 {
+            matches.retain(|(_, column)| !column.using);
+        }
+
+        match matches.as_slice() {
+            [(i, column)] => {
+                self.refer_to_outer(column.depth);
+                Ok(*i)
+            }
+            [] => match table {
+                Some(table) => bail!("no such column: {}.{}", table, name),
+                None => bail!("no such column: {}", name),
+            },
+            _ => bail!("ambiguous column name: {}", name),
+        }
+    }
+}
+
+/// An expression whose column references have been resolved to positions in a row
+#[derive(Debug, Clone)]
+pub enum BoundExpr<'a> {
+    Literal(Value),
+    Column(usize),
+    Unary(UnaryOperator, Box<BoundExpr<'a>>),
+    Binary(Box<BoundExpr<'a>>, BinaryOperator, Box<BoundExpr<'a>>),
+    Comparison {
+        left: Box<BoundExpr<'a>>,
+        op: BinaryOperator,
+        right: Box<BoundExpr<'a>>,
+        comparator: Comparator,
+    },
+    IsNull {
+        expr: Box<BoundExpr<'a>>,
+        negated: bool,
+    },
+    InList {
+        expr: Box<BoundExpr<'a>>,
+        list: Vec<(BoundExpr<'a>, Comparator)>,
+        negated: bool,
+    },
+    Like {
+        expr: Box<BoundExpr<'a>>,
+        pattern: Box<BoundExpr<'a>>,
+        escape: Option<Box<BoundExpr<'a>>>,
+        glob: bool,
+        negated: bool,
+    },
+    Function {
+        function: ScalarFunction,
+        args: Vec<BoundExpr<'a>>,
+        collation: Collation,
+    },
+    /// The first argument that isn't NULL
+    Coalesce(Vec<BoundExpr<'a>>),
+    /// The value of a window function call, until the position of the values of the calls in
+    /// the row is known
+    Window(usize),
+    Cast(Box<BoundExpr<'a>>, Affinity),
+    /// Each branch is a condition and its result. With an operand, the conditions are instead
+    /// values compared to the operand with the comparator of the branch.
+    Case {
+        operand: Option<Box<BoundExpr<'a>>>,
+        branches: Vec<(BoundExpr<'a>, BoundExpr<'a>, Comparator)>,
+        else_result: Option<Box<BoundExpr<'a>>>,
+    },
+    /// A scalar subquery
+    Subquery(Rc<Subquery<'a>>),
+    Exists(Rc<Subquery<'a>>),
+    InSubquery {
+        expr: Box<BoundExpr<'a>>,
+        subquery: Rc<Subquery<'a>>,
+        comparator: Comparator,
+        negated: bool,
+    },
+}
+
+/// How the two operands of a comparison are converted and compared, following
+/// [comparison affinity rules](https://www.sqlite.org/datatype3.html#type_conversions_prior_to_comparison)
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Comparator {
+    pub left_affinity: Option<Affinity>,
+    pub right_affinity: Option<Affinity>,
+    pub collation: Collation,
+}
+
+impl Comparator {
+    pub fn new(
+        left_affinity: Option<Affinity>,
+        right_affinity: Option<Affinity>,
+        collation: Collation,
+    ) -> Self {
+        let left_numeric = matches!(left_affinity, Some(affinity) if affinity.is_numeric());
+        let right_numeric = matches!(right_affinity, Some(affinity) if affinity.is_numeric());
+
+        let (left, right) = if left_numeric && !right_numeric {
+            (None, Some(Affinity::Numeric))
+        } else if right_numeric && !left_numeric {
+            (Some(Affinity::Numeric), None)
+        } else if left_affinity == Some(Affinity::Text) && right_affinity.is_none() {
+            (None, Some(Affinity::Text))
+        } else if right_affinity == Some(Affinity::Text) && left_affinity.is_none() {
+            (Some(Affinity::Text), None)
+        } else {
+            (None, None)
+        };
+
+        Comparator {
+            left_affinity: left,
+            right_affinity: right,
+            collation,
+        }
+    }
+
+    /// Compares two values, returning None when either of them is NULL
+    pub fn compare(&self, left: &Value, right: &Value) -> Option<Ordering> {
+        if left.is_null() || right.is_null() {
+            return None;
+        }
+
+        let convert = |value: &Value, affinity: Option<Affinity>| match affinity {
+            Some(affinity) => value.clone().apply_affinity(affinity),
+            None => value.clone(),
+        };
+        let left = convert(left, self.left_affinity);
+        let right = convert(right, self.right_affinity);
+
+        Some(left.compare(&right, self.collation))
+    }
+}
+
+/// Resolves column references in expressions against a scope. Subqueries are prepared against
+/// the database.
+pub struct Binder<'s, 'a> {
+    pub scope: &'s Scope,
+    db: &'a Database,
+    /// Aggregate calls found so far, when the expressions are evaluated against aggregated rows
+    aggregates: Option<&'s RefCell<Vec<AggregateCall<'a>>>>,
+    /// Whether the expressions are arguments of an aggregate call
+    in_aggregate: bool,
+    /// Window function calls found so far, and the windows of the WINDOW clause they can name,
+    /// when the expressions are evaluated once the windows are computed
+    windows: Option<WindowCalls<'s, 'a>>,
+}
+
+type WindowCalls<'s, 'a> = (
+    &'s RefCell<Vec<WindowCall<'a>>>,
+    &'s [(String, WindowDefinition)],
+);
+
+impl<'s, 'a> Binder<'s, 'a> {
+    pub fn new(db: &'a Database, scope: &'s Scope) -> Self {
+        Binder {
+            scope,
+            db,
+            aggregates: None,
+            in_aggregate: false,
+            windows: None,
+        }
+    }
+
+    /// A binder that collects aggregate calls. Each call is bound to the column following the
+    /// columns of the scope where the aggregated rows hold its value.
+    pub fn with_aggregates(
+        db: &'a Database,
+        scope: &'s Scope,
+        aggregates: &'s RefCell<Vec<AggregateCall<'a>>>,
+    ) -> Self {
+        Binder {
+            scope,
+            db,
+            aggregates: Some(aggregates),
+            in_aggregate: false,
+            windows: None,
+        }
+    }
+
+    /// A binder that also collects window function calls. Each call is bound to a placeholder
+    /// for its value, which [`BoundExpr::place_windows`] replaces with a column.
+    pub fn with_windows(
+        self,
+        calls: &'s RefCell<Vec<WindowCall<'a>>>,
+        definitions: &'s [(String, WindowDefinition)],
+    ) -> Self {
+        Binder {
+            windows: Some((calls, definitions)),
+            ..self
+        }
+    }
+
+    pub fn bind(&self, expr: &Expr) -> Result<BoundExpr<'a>> {
+        Ok(match expr {
+            Expr::Literal(value) => BoundExpr::Literal(value.clone()),
+            Expr::Column { table, name } => {
+                BoundExpr::Column(self.scope.resolve(table.as_deref(), name)?)
+            }
+            Expr::Unary(op, expr) => BoundExpr::Unary(*op, Box::new(self.bind(expr)?)),
+            Expr::Binary(left, op, right) if op.is_comparison() => BoundExpr::Comparison {
+                left: Box::new(self.bind(left)?),
+                op: *op,
+                right: Box::new(self.bind(right)?),
+                comparator: self.comparator(left, right),
+            },
+            Expr::Binary(left, op, right) => {
+                BoundExpr::Binary(Box::new(self.bind(left)?), *op, Box::new(self.bind(right)?))
+            }
+            Expr::IsNull { expr, negated } => BoundExpr::IsNull {
+                expr: Box::new(self.bind(expr)?),
+                negated: *negated,
+            },
+            // x BETWEEN y AND z is the same as x >= y AND x <= z
+            Expr::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let between = BoundExpr::Binary(
+                    Box::new(BoundExpr::Comparison {
+                        left: Box::new(self.bind(expr)?),
+                        op: BinaryOperator::GtEq,
+                        right: Box::new(self.bind(low)?),
+                        comparator: self.comparator(expr, low),
+                    }),
+                    BinaryOperator::And,
+                    Box::new(BoundExpr::Comparison {
+                        left: Box::new(self.bind(expr)?),
+                        op: BinaryOperator::LtEq,
+                        right: Box::new(self.bind(high)?),
+                        comparator: self.comparator(expr, high),
+                    }),
+                );
+                if *negated {
+                    BoundExpr::Unary(UnaryOperator::Not, Box::new(between))
+                } else {
+                    between
+                }
+            }
+            Expr::InList {
+                expr: left,
+                list,
+                negated,
+            } => BoundExpr::InList {
+                expr: Box::new(self.bind(left)?),
+                list: list
+                    .iter()
+                    .map(|item| Ok((self.bind(item)?, self.comparator(left, item))))
+                    .collect::<Result<_>>()?,
+                negated: *negated,
+            },
+            Expr::Like {
+                expr,
+                pattern,
+                escape,
+                glob,
+                negated,
+            } => BoundExpr::Like {
+                expr: Box::new(self.bind(expr)?),
+                pattern: Box::new(self.bind(pattern)?),
+                escape: match escape {
+                    Some(escape) => Some(Box::new(self.bind(escape)?)),
+                    None => None,
+                },
+                glob: *glob,
+                negated: *negated,
+            },
+            Expr::Function(function) => match (
+                &function.over,
+                AggregateFunction::from_name(&function.name, function.args.len()),
+            ) {
+                (Some(over), _) => self.bind_window(function, over)?,
+                (None, Some(aggregate)) => self.bind_aggregate(aggregate, function)?,
+                (None, None) => self.bind_function(function)?,
+            },
+            Expr::Cast { expr, type_name } => BoundExpr::Cast(
+                Box::new(self.bind(expr)?),
+                Affinity::from_type_name(type_name),
+            ),
+            Expr::Case {
+                operand,
+                branches,
+                else_result,
+            } => BoundExpr::Case {
+                operand: match operand {
+                    Some(operand) => Some(Box::new(self.bind(operand)?)),
+                    None => None,
+                },
+                branches: branches
+                    .iter()
+                    .map(|(when, then)| {
+                        let comparator = match operand {
+                            Some(operand) => self.comparator(operand, when),
+                            None => Comparator::new(None, None, Collation::Binary),
+                        };
+                        Ok((self.bind(when)?, self.bind(then)?, comparator))
+                    })
+                    .collect::<Result<_>>()?,
+                else_result: match else_result {
+                    Some(else_result) => Some(Box::new(self.bind(else_result)?)),
+                    None => None,
+                },
+            },
+            Expr::Collate(expr, collation) => {
+                if Collation::from_name(collation).is_none() {
+                    bail!("no such collation sequence: {}", collation);
+                }
+                self.bind(expr)?
+            }
+            Expr::Subquery(select) => BoundExpr::Subquery(self.bind_subquery(select, true)?),
+            Expr::Exists(select) => BoundExpr::Exists(self.bind_subquery(select, false)?),
+            Expr::InSelect {
+                expr: left,
+                select,
+                negated,
+            } => {
+                let subquery = self.bind_subquery(select, true)?;
+                let (collation, explicit) = self.collation(left);
+                let collation = if explicit || self.has_collation(left) {
+                    collation
+                } else {
+                    subquery.collation().unwrap_or(Collation::Binary)
+                };
+                BoundExpr::InSubquery {
+                    expr: Box::new(self.bind(left)?),
+                    comparator: Comparator::new(
+                        self.affinity(left),
+                        subquery.affinity(),
+                        collation,
+                    ),
+                    subquery,
+                    negated: *negated,
+                }
+            }
+        })
+    }
+
+    /// Prepares a subquery nested in the query this binder belongs to. Subqueries used as values
+    /// must return a single column.
+    fn bind_subquery(&self, select: &Select, single_column: bool) -> Result<Rc<Subquery<'a>>> {
+        let plan = Plan::prepare(self.db, select, self.scope)?;
+        if single_column && plan.columns.len() != 1 {
+            bail!(
+                "sub-select returns {} columns - expected 1",
+                plan.columns.len()
+            );
+        }
+        // A reference to the query this binder belongs to is one query out for the subquery
+        if plan.outer_depth > 1 {
+            self.scope.refer_to_outer(plan.outer_depth - 1);
+        }
+        Ok(Rc::new(Subquery::new(plan)))
+    }
+
+    fn bind_function(&self, call: &FunctionCall) -> Result<BoundExpr<'a>> {
+        let name = call.name.to_ascii_lowercase();
+        let function = ScalarFunction::from_name(&name);
+        if WindowFunction::from_name(&name).is_some() {
+            bail!("misuse of window function {}()", call.name);
+        }
+        if function.is_none() && !matches!(name.as_str(), "coalesce" | "ifnull" | "iif") {
+            bail!("no such function: {}", call.name);
+        }
+        if call.filter.is_some() {
+            bail!("FILTER may not be used with non-aggregate {}()", call.name);
+        }
+
+        let arg_count = call.args.len();
+        let accepted = match function {
+            Some(function) => function.accepts(arg_count),
+            None if name == "coalesce" => arg_count >= 2,
+            None if name == "ifnull" => arg_count == 2,
+            None => arg_count == 2 || arg_count == 3,
+        };
+        if !accepted || call.wildcard {
+            bail!("wrong number of arguments to function {}()", call.name);
+        }
+
+        let mut args = call
+            .args
+            .iter()
+            .map(|arg| self.bind(arg))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(match function {
+            Some(function) => BoundExpr::Function {
+                function,
+                args,
+                // The first argument with an explicit collation decides, then the first column
+                collation: call
+                    .args
+                    .iter()
+                    .map(|arg| self.collation(arg))
+                    .find(|(_, explicit)| *explicit)
+                    .or_else(|| call.args.first().map(|arg| self.collation(arg)))
+                    .map_or(Collation::Binary, |(collation, _)| collation),
+            },
+            // iif(X, Y, Z) is CASE WHEN X THEN Y ELSE Z END
+            None if name == "iif" => {
+                let else_result = if args.len() == 3 {
+                    args.pop().map(Box::new)
+                } else {
+                    None
+                };
+                let then = args.pop().unwrap_or(BoundExpr::Literal(Value::Null));
+                let when = args.pop().unwrap_or(BoundExpr::Literal(Value::Null));
+                BoundExpr::Case {
+                    operand: None,
+                    branches: vec![(when, then, Comparator::new(None, None, Collation::Binary))],
+                    else_result,
+                }
+            }
+            None => BoundExpr::Coalesce(args),
+        })
+    }
+
+    fn bind_aggregate(
+        &self,
+        function: AggregateFunction,
+        call: &FunctionCall,
+    ) -> Result<BoundExpr<'a>> {
+        let aggregates = match self.aggregates {
+            _ if self.in_aggregate => bail!("misuse of aggregate function {}()", call.name),
+            Some(aggregates) => aggregates,
+            None => bail!("misuse of aggregate: {}()", call.name),
+        };
+        if !function.accepts(&call.name, call.args.len()) {
+            bail!("wrong number of arguments to function {}()", call.name);
+        }
+        if call.distinct && call.args.len() != 1 {
+            bail!("DISTINCT aggregates must have exactly one argument");
+        }
+
+        // Aggregates can't be nested, so the arguments are bound without collecting them
+        let binder = Binder {
+            scope: self.scope,
+            db: self.db,
+            aggregates: None,
+            in_aggregate: true,
+            windows: None,
+        };
+        let aggregate = binder.aggregate_call(function, call)?;
+
+        let mut aggregates = aggregates.borrow_mut();
+        aggregates.push(aggregate);
+        Ok(BoundExpr::Column(
+            self.scope.columns.len() + aggregates.len() - 1,
+        ))
+    }
+
+    /// Binds the arguments of an aggregate call with this binder
+    fn aggregate_call(
+        &self,
+        function: AggregateFunction,
+        call: &FunctionCall,
+    ) -> Result<AggregateCall<'a>> {
+        Ok(AggregateCall {
+            function,
+            args: call
+                .args
+                .iter()
+                .map(|arg| self.bind(arg))
+                .collect::<Result<_>>()?,
+            distinct: call.distinct,
+            filter: match &call.filter {
+                Some(filter) => Some(self.bind(filter)?),
+                None => None,
+            },
+            collation: match call.args.first() {
+                Some(arg) => self.collation(arg).0,
+                None => Collation::Binary,
+            },
+        })
+    }
+
+    fn bind_window(&self, call: &FunctionCall, over: &Over) -> Result<BoundExpr<'a>> {
+        let (calls, definitions) = match self.windows {
+            Some(windows) => windows,
+            None => bail!("misuse of window function {}()", call.name),
+        };
+        // Window calls can't be nested, but their arguments and windows may call aggregate
+        // functions
+        let binder = Binder {
+            scope: self.scope,
+            db: self.db,
+            aggregates: self.aggregates,
+            in_aggregate: false,
+            windows: None,
+        };
+
+        let name = call.name.to_ascii_lowercase();
+        let arg_count = call.args.len();
+        let kind = match (
+            WindowFunction::from_name(&name),
+            AggregateFunction::from_name(&name, arg_count),
+        ) {
+            (Some(function), _) => {
+                if !function.accepts(arg_count) || call.wildcard || call.distinct {
+                    bail!("wrong number of arguments to function {}()", call.name);
+                }
+                if call.filter.is_some() {
+                    bail!("FILTER clause may only be used with aggregate window functions");
+                }
+                let args = call
+                    .args
+                    .iter()
+                    .map(|arg| binder.bind(arg))
+                    .collect::<Result<_>>()?;
+                WindowCallKind::Builtin(function, args)
+            }
+            (None, Some(function)) => {
+                if !function.accepts(&call.name, arg_count) {
+                    bail!("wrong number of arguments to function {}()", call.name);
+                }
+                if call.distinct {
+                    bail!("DISTINCT is not supported for window functions");
+                }
+                WindowCallKind::Aggregate(binder.aggregate_call(function, call)?)
+            }
+            (None, None) => {
+                if ScalarFunction::from_name(&name).is_some()
+                    || matches!(name.as_str(), "coalesce" | "ifnull" | "iif")
+                {
+                    bail!("{}() may not be used as a window function", call.name);
+                }
+                bail!("no such function: {}", call.name);
+            }
+        };
+        let definition = window::resolve(over, definitions)?;
+        let window = Window::prepare(self.db, &binder, &definition)?;
+
+        let mut calls = calls.borrow_mut();
+        calls.push(WindowCall { kind, window });
+        Ok(BoundExpr::Window(calls.len() - 1))
+    }
+
+    /// Affinity of an expression, which only column references have
+    pub fn affinity(&self, expr: &Expr) -> Option<Affinity> {
+        match expr {
+            Expr::Column { table, name } => self
+                .scope
+                .resolve(table.as_deref(), name)
+                .ok()
+                .map(|i| self.scope.columns[i].affinity)
+                .filter(|affinity| *affinity != Affinity::Blob),
+            Expr::Collate(expr, _) => self.affinity(expr),
+            Expr::Cast { type_name, .. } => Some(Affinity::from_type_name(type_name))
+                .filter(|affinity| *affinity != Affinity::Blob),
+            Expr::Subquery(select) => self
+                .bind_subquery(select, true)
+                .ok()
+                .and_then(|subquery| subquery.affinity()),
+            _ => None,
+        }
+    }
+
+    /// Collating sequence of an expression, and whether it was given explicitly with COLLATE
+    pub fn collation(&self, expr: &Expr) -> (Collation, bool) {
+        match expr {
+            Expr::Collate(_, name) => (
+                Collation::from_name(name).unwrap_or(Collation::Binary),
+                true,
+            ),
+            Expr::Column { table, name } => (
+                self.scope
+                    .resolve(table.as_deref(), name)
+                    .map(|i| self.scope.columns[i].collation)
+                    .unwrap_or(Collation::Binary),
+                false,
+            ),
+            Expr::Cast { expr, .. } => self.collation(expr),
+            _ => (Collation::Binary, false),
+        }
+    }
+
+    /// Whether an expression has a collating sequence of its own, from a column or COLLATE, as
+    /// opposed to the default one
+    pub fn has_collation(&self, expr: &Expr) -> bool {
+        match expr {
+            Expr::Collate(..) => true,
+            Expr::Column { table, name } => self.scope.resolve(table.as_deref(), name).is_ok(),
+            Expr::Cast { expr, .. } => self.has_collation(expr),
+            _ => false,
+        }
+    }
+
+    /// Builds the comparator for `left <op> right`. An explicit COLLATE on either side wins over
+    /// the collation of a column, and the left operand wins over the right one.
+    pub fn comparator(&self, left: &Expr, right: &Expr) -> Comparator {
+        let (left_collation, left_explicit) = self.collation(left);
+        let (right_collation, right_explicit) = self.collation(right);
+        let collation = if left_explicit || !right_explicit && self.has_collation(left) {
+            left_collation
+        } else {
+            right_collation
+        };
+
+        Comparator::new(self.affinity(left), self.affinity(right), collation)
+    }
+}
+
+impl<'a> BoundExpr<'a> {
+    pub fn eval(&self, row: &[Value]) -> Result<Value> {
+        Ok(match self {
+            BoundExpr::Literal(value) => value.clone(),
+            BoundExpr::Column(i) => row[*i].clone(),
+            BoundExpr::Unary(op, expr) => unary(*op, expr.eval(row)?),
+            BoundExpr::Binary(left, BinaryOperator::And, right) => match left.eval(row)?.truth() {
+                Some(false) => Value::Integer(0),
+                left => match (left, right.eval(row)?.truth()) {
+                    (_, Some(false)) => Value::Integer(0),
+                    (Some(true), Some(true)) => Value::Integer(1),
+                    _ => Value::Null,
+                },
+            },
+            BoundExpr::Binary(left, BinaryOperator::Or, right) => match left.eval(row)?.truth() {
+                Some(true) => Value::Integer(1),
+                left => match (left, right.eval(row)?.truth()) {
+                    (_, Some(true)) => Value::Integer(1),
+                    (Some(false), Some(false)) => Value::Integer(0),
+                    _ => Value::Null,
+                },
+            },
+            BoundExpr::Binary(left, op, right) => binary(left.eval(row)?, *op, right.eval(row)?)?,
+            BoundExpr::Comparison {
+                left,
+                op,
+                right,
+                comparator,
+            } => compare(&left.eval(row)?, *op, &right.eval(row)?, comparator),
+            BoundExpr::IsNull { expr, negated } => {
+                Value::Integer((expr.eval(row)?.is_null() != *negated) as i64)
+            }
+            BoundExpr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                let mut result = Some(false);
+                for (item, comparator) in list {
+                    match comparator.compare(&value, &item.eval(row)?) {
+                        Some(Ordering::Equal) => {
+                            result = Some(true);
+                            break;
+                        }
+                        Some(_) => {}
+                        None => result = None,
+                    }
+                }
+                if value.is_null() && !list.is_empty() {
+                    result = None;
+                }
+                match result {
+                    Some(found) => Value::Integer((found != *negated) as i64),
+                    None => Value::Null,
+                }
+            }
+            BoundExpr::Like {
+                expr,
+                pattern,
+                escape,
+                glob,
+                negated,
+            } => {
+                let value = expr.eval(row)?;
+                let pattern = pattern.eval(row)?;
+                let escape = match escape {
+                    Some(escape) => match escape.eval(row)? {
+                        Value::Null => return Ok(Value::Null),
+                        escape => {
+                            let escape = escape.to_string();
+                            let mut chars = escape.chars();
+                            match (chars.next(), chars.next()) {
+                                (Some(c), None) => Some(c),
+                                _ => bail!("ESCAPE expression must be a single character"),
+                            }
+                        }
+                    },
+                    None => None,
+                };
+
+                match (value, pattern) {
+                    (Value::Null, _) | (_, Value::Null) => Value::Null,
+                    (value, pattern) => {
+                        let matched = if *glob {
+                            glob_match(&pattern.to_string(), &value.to_string())
+                        } else {
+                            like_match(&pattern.to_string(), &value.to_string(), escape)
+                        };
+                        Value::Integer((matched != *negated) as i64)
+                    }
+                }
+            }
+            BoundExpr::Function {
+                function,
+                args,
+                collation,
+            } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(row))
+                    .collect::<Result<_>>()?;
+                function.call(args, *collation)?
+            }
+            BoundExpr::Window(_) => unreachable!("window calls are placed before evaluation"),
+            BoundExpr::Coalesce(args) => {
+                for arg in args {
+                    let value = arg.eval(row)?;
+                    if !value.is_null() {
+                        return Ok(value);
+                    }
+                }
+                Value::Null
+            }
+            BoundExpr::Cast(expr, affinity) => expr.eval(row)?.cast(*affinity),
+            BoundExpr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                let operand = match operand {
+                    Some(operand) => Some(operand.eval(row)?),
+                    None => None,
+                };
+                for (when, then, comparator) in branches {
+                    let when = when.eval(row)?;
+                    let matched = match &operand {
+                        Some(operand) => {
+                            comparator.compare(operand, &when) == Some(Ordering::Equal)
+                        }
+                        None => when.truth() == Some(true),
+                    };
+                    if matched {
+                        return then.eval(row);
+                    }
+                }
+                match else_result {
+                    Some(else_result) => else_result.eval(row)?,
+                    None => Value::Null,
+                }
+            }
+            BoundExpr::Subquery(subquery) => subquery.value(row)?,
+            BoundExpr::Exists(subquery) => Value::Integer(subquery.exists(row)? as i64),
+            BoundExpr::InSubquery {
+                expr,
+                subquery,
+                comparator,
+                negated,
+            } => match subquery.contains(row, &expr.eval(row)?, comparator)? {
+                Some(found) => Value::Integer((found != *negated) as i64),
+                None => Value::Null,
+            },
+        })
+    }
+
+    /// Whether the expression refers to a column at `start` or after it in the row. Correlated
+    /// subqueries may refer to any column.
+    pub fn uses_columns_from(&self, start: usize) -> bool {
+        self.uses_columns(&|i| i >= start)
+    }
+
+    /// Whether the expression refers to a column whose position `columns` holds for.
+    /// Correlated subqueries may refer to any column.
+    pub fn uses_columns(&self, columns: &dyn Fn(usize) -> bool) -> bool {
+        let any = |exprs: &[BoundExpr]| exprs.iter().any(|expr| expr.uses_columns(columns));
+        match self {
+            BoundExpr::Literal(_) => false,
+            BoundExpr::Column(i) => columns(*i),
+            BoundExpr::Window(_) => true,
+            BoundExpr::Unary(_, expr)
+            | BoundExpr::Cast(expr, _)
+            | BoundExpr::IsNull { expr, .. } => expr.uses_columns(columns),
+            BoundExpr::Binary(left, _, right) | BoundExpr::Comparison { left, right, .. } => {
+                left.uses_columns(columns) || right.uses_columns(columns)
+            }
+            BoundExpr::InList { expr, list, .. } => {
+                expr.uses_columns(columns)
+                    || list.iter().any(|(item, _)| item.uses_columns(columns))
+            }
+            BoundExpr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.uses_columns(columns)
+                    || pattern.uses_columns(columns)
+                    || matches!(escape, Some(escape) if escape.uses_columns(columns))
+            }
+            BoundExpr::Function { args, .. } | BoundExpr::Coalesce(args) => any(args),
+            BoundExpr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                matches!(operand, Some(operand) if operand.uses_columns(columns))
+                    || branches.iter().any(|(when, then, _)| {
+                        when.uses_columns(columns) || then.uses_columns(columns)
+                    })
+                    || matches!(else_result, Some(else_result) if else_result.uses_columns(columns))
+            }
+            BoundExpr::Subquery(subquery) | BoundExpr::Exists(subquery) => subquery.is_correlated(),
+            BoundExpr::InSubquery { expr, subquery, .. } => {
+                expr.uses_columns(columns) || subquery.is_correlated()
+            }
+        }
+    }
+
+    /// The expressions running a subquery within the expression, in the order they appear in it
+    pub fn subqueries<'e>(&'e self, found: &mut Vec<&'e BoundExpr<'a>>) {
+        let all = |exprs: &'e [BoundExpr<'a>], found: &mut Vec<&'e BoundExpr<'a>>| {
+            for expr in exprs {
+                expr.subqueries(found);
+            }
+        };
+        match self {
+            BoundExpr::Literal(_) | BoundExpr::Column(_) | BoundExpr::Window(_) => {}
+            BoundExpr::Unary(_, expr)
+            | BoundExpr::Cast(expr, _)
+            | BoundExpr::IsNull { expr, .. } => expr.subqueries(found),
+            BoundExpr::Binary(left, _, right) | BoundExpr::Comparison { left, right, .. } => {
+                left.subqueries(found);
+                right.subqueries(found);
+            }
+            BoundExpr::InList { expr, list, .. } => {
+                expr.subqueries(found);
+                for (item, _) in list {
+                    item.subqueries(found);
+                }
+            }
+            BoundExpr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.subqueries(found);
+                pattern.subqueries(found);
+                if let Some(escape) = escape {
+                    escape.subqueries(found);
+                }
+            }
+            BoundExpr::Function { args, .. } | BoundExpr::Coalesce(args) => all(args, found),
+            BoundExpr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                if let Some(operand) = operand {
+                    operand.subqueries(found);
+                }
+                for (when, then, _) in branches {
+                    when.subqueries(found);
+                    then.subqueries(found);
+                }
+                if let Some(else_result) = else_result {
+                    else_result.subqueries(found);
+                }
+            }
+            BoundExpr::Subquery(_) | BoundExpr::Exists(_) => found.push(self),
+            BoundExpr::InSubquery { expr, .. } => {
+                expr.subqueries(found);
+                found.push(self);
+            }
+        }
+    }
+
+    /// Replaces the placeholders of window function calls with the columns holding their values,
+    /// which the rows have from `start` on
+    pub fn place_windows(&mut self, start: usize) {
+        let place = |exprs: &mut [BoundExpr]| {
+            for expr in exprs {
+                expr.place_windows(start);
+            }
+        };
+        match self {
+            BoundExpr::Window(i) => *self = BoundExpr::Column(start + *i),
+            BoundExpr::Literal(_)
+            | BoundExpr::Column(_)
+            | BoundExpr::Subquery(_)
+            | BoundExpr::Exists(_) => {}
+            BoundExpr::Unary(_, expr)
+            | BoundExpr::Cast(expr, _)
+            | BoundExpr::IsNull { expr, .. }
+            | BoundExpr::InSubquery { expr, .. } => expr.place_windows(start),
+            BoundExpr::Binary(left, _, right) | BoundExpr::Comparison { left, right, .. } => {
+                left.place_windows(start);
+                right.place_windows(start);
+            }
+            BoundExpr::InList { expr, list, .. } => {
+                expr.place_windows(start);
+                for (item, _) in list {
+                    item.place_windows(start);
+                }
+            }
+            BoundExpr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.place_windows(start);
+                pattern.place_windows(start);
+                if let Some(escape) = escape {
+                    escape.place_windows(start);
+                }
+            }
+            BoundExpr::Function { args, .. } | BoundExpr::Coalesce(args) => place(args),
+            BoundExpr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                if let Some(operand) = operand {
+                    operand.place_windows(start);
+                }
+                for (when, then, _) in branches {
+                    when.place_windows(start);
+                    then.place_windows(start);
+                }
+                if let Some(else_result) = else_result {
+                    else_result.place_windows(start);
+                }
+            }
+        }
+    }
+
+    /// Moves the columns the expression refers to by `offset`, for expressions bound against a
+    /// single table that are evaluated against rows joining it after other tables
+    pub fn move_columns(&mut self, offset: usize) {
+        let move_all = |exprs: &mut [BoundExpr]| {
+            for expr in exprs {
+                expr.move_columns(offset);
+            }
+        };
+        match self {
+            BoundExpr::Column(column) => *column += offset,
+            BoundExpr::Literal(_)
+            | BoundExpr::Window(_)
+            | BoundExpr::Subquery(_)
+            | BoundExpr::Exists(_) => {}
+            BoundExpr::Unary(_, expr)
+            | BoundExpr::Cast(expr, _)
+            | BoundExpr::IsNull { expr, .. }
+            | BoundExpr::InSubquery { expr, .. } => expr.move_columns(offset),
+            BoundExpr::Binary(left, _, right) | BoundExpr::Comparison { left, right, .. } => {
+                left.move_columns(offset);
+                right.move_columns(offset);
+            }
+            BoundExpr::InList { expr, list, .. } => {
+                expr.move_columns(offset);
+                for (item, _) in list {
+                    item.move_columns(offset);
+                }
+            }
+            BoundExpr::Like {
+                expr,
+                pattern,
+                escape,
+                ..
+            } => {
+                expr.move_columns(offset);
+                pattern.move_columns(offset);
+                if let Some(escape) = escape {
+                    escape.move_columns(offset);
+                }
+            }
+            BoundExpr::Function { args, .. } | BoundExpr::Coalesce(args) => move_all(args),
+            BoundExpr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                if let Some(operand) = operand {
+                    operand.move_columns(offset);
+                }
+                for (when, then, _) in branches {
+                    when.move_columns(offset);
+                    then.move_columns(offset);
+                }
+                if let Some(else_result) = else_result {
+                    else_result.move_columns(offset);
+                }
+            }
+        }
+    }
+
+    /// Replaces the parts of the expression that are the same as `expr` with `with`, such as an
+    /// indexed expression with the column its value is read into. Returns whether there were
+    /// any.
+    pub fn replace(&mut self, expr: &BoundExpr, with: &BoundExpr<'a>) -> bool {
+        if self.same_as(expr) {
+            *self = with.clone();
+            return true;
+        }
+        let replace_all = |exprs: &mut [BoundExpr<'a>]| {
+            let mut replaced = false;
+            for item in exprs {
+                replaced |= item.replace(expr, with);
+            }
+            replaced
+        };
+        match self {
+            BoundExpr::Literal(_)
+            | BoundExpr::Column(_)
+            | BoundExpr::Window(_)
+            | BoundExpr::Subquery(_)
+            | BoundExpr::Exists(_) => false,
+            BoundExpr::Unary(_, inner)
+            | BoundExpr::Cast(inner, _)
+            | BoundExpr::IsNull { expr: inner, .. }
+            | BoundExpr::InSubquery { expr: inner, .. } => inner.replace(expr, with),
+            BoundExpr::Binary(left, _, right) | BoundExpr::Comparison { left, right, .. } => {
+                let left = left.replace(expr, with);
+                right.replace(expr, with) || left
+            }
+            BoundExpr::InList {
+                expr: inner, list, ..
+            } => {
+                let mut replaced = inner.replace(expr, with);
+                for (item, _) in list {
+                    replaced |= item.replace(expr, with);
+                }
+                replaced
+            }
+            BoundExpr::Like {
+                expr: inner,
+                pattern,
+                escape,
+                ..
+            } => {
+                let mut replaced = inner.replace(expr, with);
+                replaced |= pattern.replace(expr, with);
+                if let Some(escape) = escape {
+                    replaced |= escape.replace(expr, with);
+                }
+                replaced
+            }
+            BoundExpr::Function { args, .. } | BoundExpr::Coalesce(args) => replace_all(args),
+            BoundExpr::Case {
+                operand,
+                branches,
+                else_result,
+            } => {
+                let mut replaced = false;
+                if let Some(operand) = operand {
+                    replaced |= operand.replace(expr, with);
+                }
+                for (when, then, _) in branches {
+                    replaced |= when.replace(expr, with);
+                    replaced |= then.replace(expr, with);
+                }
+                if let Some(else_result) = else_result {
+                    replaced |= else_result.replace(expr, with);
+                }
+                replaced
+            }
+        }
+    }
+
+    /// Whether two expressions are written the same way, so that they always have the same value.
+    /// Expressions with subqueries or window function calls are never the same as another.
+    pub fn same_as(&self, other: &BoundExpr) -> bool {
+        let same_all = |left: &[BoundExpr], right: &[BoundExpr]| {
+            left.len() == right.len() && left.iter().zip(right).all(|(l, r)| l.same_as(r))
+        };
+        let same_option =
+            |left: &Option<Box<BoundExpr>>, right: &Option<Box<BoundExpr>>| match (left, right) {
+                (Some(left), Some(right)) => left.same_as(right),
+                (None, None) => true,
+                _ => false,
+            };
+        match (self, other) {
+            (BoundExpr::Literal(left), BoundExpr::Literal(right)) => left == right,
+            (BoundExpr::Column(left), BoundExpr::Column(right)) => left == right,
+            (BoundExpr::Unary(op, expr), BoundExpr::Unary(other_op, other_expr)) => {
+                op == other_op && expr.same_as(other_expr)
+            }
+            (
+                BoundExpr::Binary(left, op, right),
+                BoundExpr::Binary(other_left, other_op, other_right),
+            ) => op == other_op && left.same_as(other_left) && right.same_as(other_right),
+            (
+                BoundExpr::Comparison {
+                    left,
+                    op,
+                    right,
+                    comparator,
+                },
+                BoundExpr::Comparison {
+                    left: other_left,
+                    op: other_op,
+                    right: other_right,
+                    comparator: other_comparator,
+                },
+            ) => {
+                op == other_op
+                    && comparator == other_comparator
+                    && left.same_as(other_left)
+                    && right.same_as(other_right)
+            }
+            (
+                BoundExpr::IsNull { expr, negated },
+                BoundExpr::IsNull {
+                    expr: other_expr,
+                    negated: other_negated,
+                },
+            ) => negated == other_negated && expr.same_as(other_expr),
+            (
+                BoundExpr::InList {
+                    expr,
+                    list,
+                    negated,
+                },
+                BoundExpr::InList {
+                    expr: other_expr,
+                    list: other_list,
+                    negated: other_negated,
+                },
+            ) => {
+                negated == other_negated
+                    && expr.same_as(other_expr)
+                    && list.len() == other_list.len()
+                    && list.iter().zip(other_list).all(
+                        |((item, comparator), (other, other_comparator))| {
+                            comparator == other_comparator && item.same_as(other)
+                        },
+                    )
+            }
+            (
+                BoundExpr::Like {
+                    expr,
+                    pattern,
+                    escape,
+                    glob,
+                    negated,
+                },
+                BoundExpr::Like {
+                    expr: other_expr,
+                    pattern: other_pattern,
+                    escape: other_escape,
+                    glob: other_glob,
+                    negated: other_negated,
+                },
+            ) => {
+                glob == other_glob
+                    && negated == other_negated
+                    && expr.same_as(other_expr)
+                    && pattern.same_as(other_pattern)
+                    && same_option(escape, other_escape)
+            }
+            (
+                BoundExpr::Function {
+                    function,
+                    args,
+                    collation,
+                },
+                BoundExpr::Function {
+                    function: other_function,
+                    args: other_args,
+                    collation: other_collation,
+                },
+            ) => {
+                function == other_function
+                    && collation == other_collation
+                    && same_all(args, other_args)
+            }
+            (BoundExpr::Coalesce(args), BoundExpr::Coalesce(other_args)) => {
+                same_all(args, other_args)
+            }
+            (BoundExpr::Cast(expr, affinity), BoundExpr::Cast(other_expr, other_affinity)) => {
+                affinity == other_affinity && expr.same_as(other_expr)
+            }
+            (
+                BoundExpr::Case {
+                    operand,
+                    branches,
+                    else_result,
+                },
+                BoundExpr::Case {
+                    operand: other_operand,
+                    branches: other_branches,
+                    else_result: other_else_result,
+                },
+            ) => {
+                same_option(operand, other_operand)
+                    && same_option(else_result, other_else_result)
+                    && branches.len() == other_branches.len()
+                    && branches.iter().zip(other_branches).all(
+                        |((when, then, comparator), (other_when, other_then, other_comparator))| {
+                            comparator == other_comparator
+                                && when.same_as(other_when)
+                                && then.same_as(other_then)
+                        },
+                    )
+            }
+            _ => false,
+        }
+    }
+
+    /// Evaluates an expression that doesn't refer to any columns
+    pub fn eval_constant(&self) -> Result<Value> {
+        self.eval(&[])
+    }
+}
+
+fn compare(left: &Value, op: BinaryOperator, right: &Value, comparator: &Comparator) -> Value {
+    let ordering = match (op, left.is_null(), right.is_null()) {
+        (BinaryOperator::Is, true, true) | (BinaryOperator::IsNot, true, true) => {
+            Some(Ordering::Equal)
+        }
+        (BinaryOperator::Is, true, false)
+        | (BinaryOperator::Is, false, true)
+        | (BinaryOperator::IsNot, true, false)
+        | (BinaryOperator::IsNot, false, true) => Some(Ordering::Less),
+        _ => comparator.compare(left, right),
+    };
+
+    let ordering = match ordering {
+        Some(ordering) => ordering,
+        None => return Value::Null,
+    };
+    let result = match op {
+        BinaryOperator::Eq | BinaryOperator::Is => ordering == Ordering::Equal,
+        BinaryOperator::NotEq | BinaryOperator::IsNot => ordering != Ordering::Equal,
+        BinaryOperator::Lt => ordering == Ordering::Less,
+        BinaryOperator::LtEq => ordering != Ordering::Greater,
+        BinaryOperator::Gt => ordering == Ordering::Greater,
+        BinaryOperator::GtEq => ordering != Ordering::Less,
+        _ => unreachable!(),
+    };
+    Value::Integer(result as i64)
+}
+
+fn unary(op: UnaryOperator, value: Value) -> Value {
+    match (op, value) {
+        (_, Value::Null) => Value::Null,
+        (UnaryOperator::Plus, value) => value,
+        (UnaryOperator::Not, value) => match value.truth() {
+            Some(truth) => Value::Integer(!truth as i64),
+            None => Value::Null,
+        },
+        (UnaryOperator::BitNot, value) => Value::Integer(!value.to_integer().unwrap_or(0)),
+        (UnaryOperator::Negate, value) => value.negate(),
+    }
+}
+
+fn binary(left: Value, op: BinaryOperator, right: Value) -> Result<Value> {
+    if left.is_null() || right.is_null() {
+        return Ok(Value::Null);
+    }
+
+    Ok(match op {
+        BinaryOperator::Concat => Value::Text(format!("{}{}", left, right)),
+        BinaryOperator::Extract => json::arrow(&left, &right, true)?,
+        BinaryOperator::ExtractValue => json::arrow(&left, &right, false)?,
+        BinaryOperator::Add | BinaryOperator::Subtract | BinaryOperator::Multiply => {
+            arithmetic(left.to_number(), op, right.to_number())
+        }
+        BinaryOperator::Divide => match (left.to_number(), right.to_number()) {
+            (_, Value::Integer(0)) => Value::Null,
+            (Value::Integer(a), Value::Integer(b)) => match a.checked_div(b) {
+                Some(v) => Value::Integer(v),
+                None => Value::Real(a as f64 / b as f64),
+            },
+            (a, b) => {
+                let b = b.to_real().unwrap();
+                if b == 0.0 {
+                    Value::Null
+                } else {
+                    Value::Real(a.to_real().unwrap() / b)
+                }
+            }
+        },
+        BinaryOperator::Modulo => {
+            let (a, b) = (left.to_number(), right.to_number());
+            let is_real = matches!(a, Value::Real(_)) || matches!(b, Value::Real(_));
+            let (a, b) = (a.to_integer().unwrap(), b.to_integer().unwrap());
+            if b == 0 {
+                Value::Null
+            } else {
+                let remainder = a.checked_rem(b).unwrap_or(0);
+                if is_real {
+                    Value::Real(remainder as f64)
+                } else {
+                    Value::Integer(remainder)
+                }
+            }
+        }
+        BinaryOperator::BitAnd => {
+            Value::Integer(left.to_integer().unwrap() & right.to_integer().unwrap())
+        }
+        BinaryOperator::BitOr => {
+            Value::Integer(left.to_integer().unwrap() | right.to_integer().unwrap())
+        }
+        BinaryOperator::ShiftLeft => Value::Integer(shift_left(
+            left.to_integer().unwrap(),
+            right.to_integer().unwrap(),
+        )),
+        BinaryOperator::ShiftRight => Value::Integer(shift_left(
+            left.to_integer().unwrap(),
+            right.to_integer().unwrap().saturating_neg(),
+        )),
+        op => bail!("Unsupported operator: {:?}", op),
+    })
+}
+
+fn shift_left(value: i64, amount: i64) -> i64 {
+    if amount >= 64 {
+        0
+    } else if amount >= 0 {
+        value << amount
+    } else if amount <= -64 {
+        if value < 0 {
+            -1
+        } else {
+            0
+        }
+    } else {
+        value >> -amount
+    }
+}
+
+fn arithmetic(left: Value, op: BinaryOperator, right: Value) -> Value {
+    if let (Value::Integer(a), Value::Integer(b)) = (&left, &right) {
+        let result = match op {
+            BinaryOperator::Add => a.checked_add(*b),
+            BinaryOperator::Subtract => a.checked_sub(*b),
+            _ => a.checked_mul(*b),
+        };
+        if let Some(result) = result {
+            return Value::Integer(result);
+        }
+    }
+
+    let (a, b) = (left.to_real().unwrap(), right.to_real().unwrap());
+    Value::Real(match op {
+        BinaryOperator::Add => a + b,
+        BinaryOperator::Subtract => a - b,
+        _ => a * b,
+    })
+}
+
+/// Matches `text` against a LIKE pattern. `%` matches any sequence of characters and `_` matches
+/// any single character. ASCII characters are compared case insensitively.
+pub fn like_match(pattern: &str, text: &str, escape: Option<char>) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    like_match_from(&pattern, &text, escape)
+}
+
+fn like_match_from(pattern: &[char], text: &[char], escape: Option<char>) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        let c = pattern[p];
+        if Some(c) == escape {
+            p += 1;
+            if p >= pattern.len() || t >= text.len() || pattern[p] != text[t] {
+                return false;
+            }
+        } else if c == '%' {
+            while p < pattern.len() && pattern[p] == '%' {
+                p += 1;
+            }
+            if p == pattern.len() {
+                return true;
+            }
+            return (t..=text.len())
+                .any(|start| like_match_from(&pattern[p..], &text[start..], escape));
+        } else if c == '_' {
+            if t >= text.len() {
+                return false;
+            }
+        } else if t >= text.len() || !c.eq_ignore_ascii_case(&text[t]) {
+            return false;
+        }
+        p += 1;
+        t += 1;
+    }
+    t == text.len()
+}
+
+/// Matches `text` against a GLOB pattern, which uses Unix file globbing syntax and is case
+/// sensitive
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match_from(&pattern, &text)
+}
+
+fn glob_match_from(pattern: &[char], text: &[char]) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        match pattern[p] {
+            '*' => {
+                while p < pattern.len() && pattern[p] == '*' {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                return (t..=text.len())
+                    .any(|start| glob_match_from(&pattern[p..], &text[start..]));
+            }
+            '?' => {
+                if t >= text.len() {
+                    return false;
+                }
+            }
+            '[' => {
+                if t >= text.len() {
+                    return false;
+                }
+                let (matched, end) = match character_class(&pattern[p + 1..], text[t]) {
+                    Some(result) => result,
+                    None => return false,
+                };
+                if !matched {
+                    return false;
+                }
+                p += end + 1;
+            }
+            c => {
+                if t >= text.len() || c != text[t] {
+                    return false;
+                }
+            }
+        }
+        p += 1;
+        t += 1;
+    }
+    t == text.len()
+}
+
+/// Matches a character against a `[...]` class, returning whether it matched and the position
+/// of the closing bracket. Returns None when the class isn't closed.
+fn character_class(class: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let inverted = class.first() == Some(&'^');
+    if inverted {
+        i += 1;
+    }
+
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        if class[i] == ']' && !first {
+            return Some((matched != inverted, i));
+        }
+        first = false;
+        if i + 2 < class.len() && class[i + 1] == '-' && class[i + 2] != ']' {
+            if class[i] <= c && c <= class[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if class[i] == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Evaluates LIMIT and OFFSET expressions, which must be integers
+pub fn eval_integer(expr: &BoundExpr) -> Result<i64> {
+    match expr.eval_constant()?.apply_affinity(Affinity::Integer) {
+        Value::Integer(v) => Ok(v),
+        _ => Err(anyhow!("datatype mismatch")),
+    }
+}
@@ -0,0 +1,342 @@
+use super::datetime;
+use super::json;
+use super::printf::printf;
+use crate::value::{Collation, Value};
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+
+/// Scalar functions from SQLite's
+/// [core functions](https://www.sqlite.org/lang_corefunc.html). `coalesce()`, `ifnull()` and
+/// `iif()` only evaluate some of their arguments, so they are bound to other expressions instead.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScalarFunction {
+    Abs,
+    Char,
+    Date,
+    DateTime,
+    Hex,
+    Instr,
+    Json,
+    JsonArrayLength,
+    JsonExtract,
+    JsonType,
+    JsonValid,
+    JulianDay,
+    Length,
+    Lower,
+    LTrim,
+    Max,
+    Min,
+    NullIf,
+    Printf,
+    Quote,
+    Replace,
+    Round,
+    RTrim,
+    StrFTime,
+    Substr,
+    Time,
+    Trim,
+    TypeOf,
+    Unicode,
+    UnixEpoch,
+    Upper,
+}
+
+impl ScalarFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "abs" => ScalarFunction::Abs,
+            "char" => ScalarFunction::Char,
+            "date" => ScalarFunction::Date,
+            "datetime" => ScalarFunction::DateTime,
+            "hex" => ScalarFunction::Hex,
+            "instr" => ScalarFunction::Instr,
+            "json" => ScalarFunction::Json,
+            "json_array_length" => ScalarFunction::JsonArrayLength,
+            "json_extract" => ScalarFunction::JsonExtract,
+            "json_type" => ScalarFunction::JsonType,
+            "json_valid" => ScalarFunction::JsonValid,
+            "julianday" => ScalarFunction::JulianDay,
+            "length" => ScalarFunction::Length,
+            "lower" => ScalarFunction::Lower,
+            "ltrim" => ScalarFunction::LTrim,
+            "max" => ScalarFunction::Max,
+            "min" => ScalarFunction::Min,
+            "nullif" => ScalarFunction::NullIf,
+            "printf" | "format" => ScalarFunction::Printf,
+            "quote" => ScalarFunction::Quote,
+            "replace" => ScalarFunction::Replace,
+            "round" => ScalarFunction::Round,
+            "rtrim" => ScalarFunction::RTrim,
+            "strftime" => ScalarFunction::StrFTime,
+            "substr" | "substring" => ScalarFunction::Substr,
+            "time" => ScalarFunction::Time,
+            "trim" => ScalarFunction::Trim,
+            "typeof" => ScalarFunction::TypeOf,
+            "unicode" => ScalarFunction::Unicode,
+            "unixepoch" => ScalarFunction::UnixEpoch,
+            "upper" => ScalarFunction::Upper,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function accepts that many arguments
+    pub fn accepts(self, arg_count: usize) -> bool {
+        match self {
+            ScalarFunction::Char
+            | ScalarFunction::Date
+            | ScalarFunction::DateTime
+            | ScalarFunction::JsonExtract
+            | ScalarFunction::JulianDay
+            | ScalarFunction::Time
+            | ScalarFunction::UnixEpoch => true,
+            ScalarFunction::Printf | ScalarFunction::StrFTime => arg_count >= 1,
+            ScalarFunction::Max | ScalarFunction::Min => arg_count >= 2,
+            ScalarFunction::Round | ScalarFunction::Trim => arg_count == 1 || arg_count == 2,
+            ScalarFunction::LTrim | ScalarFunction::RTrim => arg_count == 1 || arg_count == 2,
+            ScalarFunction::JsonArrayLength
+            | ScalarFunction::JsonType
+            | ScalarFunction::JsonValid => arg_count == 1 || arg_count == 2,
+            ScalarFunction::Substr => arg_count == 2 || arg_count == 3,
+            ScalarFunction::Instr | ScalarFunction::NullIf => arg_count == 2,
+            ScalarFunction::Replace => arg_count == 3,
+            _ => arg_count == 1,
+        }
+    }
+
+    /// Calls the function. `collation` is the collating sequence of the arguments, used by the
+    /// functions that compare them.
+    pub fn call(self, args: Vec<Value>, collation: Collation) -> Result<Value> {
+        let mut args = args;
+        Ok(match self {
+            ScalarFunction::Abs => match &args[0] {
+                Value::Null => Value::Null,
+                Value::Integer(v) => match v.checked_abs() {
+                    Some(v) => Value::Integer(v),
+                    None => bail!("integer overflow"),
+                },
+                v => Value::Real(v.to_real().unwrap_or(0.0).abs()),
+            },
+            ScalarFunction::Char => Value::Text(
+                args.iter()
+                    .map(|arg| {
+                        let code = arg.to_integer().unwrap_or(0);
+                        u32::try_from(code)
+                            .ok()
+                            .and_then(std::char::from_u32)
+                            .unwrap_or('\u{fffd}')
+                    })
+                    .collect(),
+            ),
+            ScalarFunction::Date => datetime::date(&args),
+            ScalarFunction::DateTime => datetime::datetime(&args),
+            ScalarFunction::JulianDay => datetime::julianday(&args),
+            ScalarFunction::StrFTime => datetime::strftime(&args),
+            ScalarFunction::Time => datetime::time(&args),
+            ScalarFunction::UnixEpoch => datetime::unixepoch(&args),
+            ScalarFunction::Json => json::json(&args)?,
+            ScalarFunction::JsonArrayLength => json::array_length(&args)?,
+            ScalarFunction::JsonExtract => json::extract(&args)?,
+            ScalarFunction::JsonType => json::json_type(&args)?,
+            ScalarFunction::JsonValid => json::valid(&args)?,
+            ScalarFunction::Hex => {
+                let bytes = match args.swap_remove(0) {
+                    Value::Blob(bytes) => bytes,
+                    v => v.to_text().unwrap_or_default().into_bytes(),
+                };
+                Value::Text(bytes.iter().map(|byte| format!("{:02X}", byte)).collect())
+            }
+            ScalarFunction::Instr => match (&args[0], &args[1]) {
+                (Value::Null, _) | (_, Value::Null) => Value::Null,
+                (Value::Blob(haystack), Value::Blob(needle)) => Value::Integer(
+                    find_bytes(haystack, needle).map_or(0, |position| position as i64 + 1),
+                ),
+                (haystack, needle) => {
+                    let haystack = haystack.to_string();
+                    let needle = needle.to_string();
+                    Value::Integer(match haystack.find(&needle) {
+                        Some(position) => haystack[..position].chars().count() as i64 + 1,
+                        None => 0,
+                    })
+                }
+            },
+            ScalarFunction::Length => match &args[0] {
+                Value::Null => Value::Null,
+                Value::Blob(bytes) => Value::Integer(bytes.len() as i64),
+                // Text ends at the first NUL character
+                v => {
+                    Value::Integer(v.to_string().chars().take_while(|c| *c != '\0').count() as i64)
+                }
+            },
+            ScalarFunction::Lower => match args[0].to_text() {
+                Some(text) => Value::Text(text.to_ascii_lowercase()),
+                None => Value::Null,
+            },
+            ScalarFunction::Upper => match args[0].to_text() {
+                Some(text) => Value::Text(text.to_ascii_uppercase()),
+                None => Value::Null,
+            },
+            ScalarFunction::Trim | ScalarFunction::LTrim | ScalarFunction::RTrim => {
+                let characters = match args.get(1) {
+                    Some(characters) => match characters.to_text() {
+                        Some(characters) => characters,
+                        None => return Ok(Value::Null),
+                    },
+                    None => " ".to_string(),
+                };
+                let text = match args[0].to_text() {
+                    Some(text) => text,
+                    None => return Ok(Value::Null),
+                };
+                let is_trimmed = |c: char| characters.contains(c);
+                Value::Text(
+                    match self {
+                        ScalarFunction::LTrim => text.trim_start_matches(is_trimmed),
+                        ScalarFunction::RTrim => text.trim_end_matches(is_trimmed),
+                        _ => text.trim_matches(is_trimmed),
+                    }
+                    .to_string(),
+                )
+            }
+            ScalarFunction::Max | ScalarFunction::Min => {
+                if args.iter().any(Value::is_null) {
+                    return Ok(Value::Null);
+                }
+                let wanted = match self {
+                    ScalarFunction::Max => Ordering::Greater,
+                    _ => Ordering::Less,
+                };
+                let mut args = args.into_iter();
+                let first = args.next().unwrap_or(Value::Null);
+                args.fold(first, |best, arg| {
+                    if arg.compare(&best, collation) == wanted {
+                        arg
+                    } else {
+                        best
+                    }
+                })
+            }
+            ScalarFunction::NullIf => {
+                if args[0].compare(&args[1], collation) == Ordering::Equal {
+                    Value::Null
+                } else {
+                    args.swap_remove(0)
+                }
+            }
+            ScalarFunction::Printf => match args[0].to_text() {
+                Some(format) => Value::Text(printf(&format, &args[1..])),
+                None => Value::Null,
+            },
+            ScalarFunction::Quote => Value::Text(args[0].quote()),
+            ScalarFunction::Replace => match (&args[0], &args[1], &args[2]) {
+                (Value::Null, _, _) | (_, Value::Null, _) | (_, _, Value::Null) => Value::Null,
+                (text, pattern, replacement) => {
+                    let text = text.to_string();
+                    let pattern = pattern.to_string();
+                    if pattern.is_empty() {
+                        Value::Text(text)
+                    } else {
+                        Value::Text(text.replace(&pattern, &replacement.to_string()))
+                    }
+                }
+            },
+            ScalarFunction::Round => {
+                let digits = match args.get(1) {
+                    Some(Value::Null) => return Ok(Value::Null),
+                    Some(digits) => digits.to_integer().unwrap_or(0).clamp(0, 30),
+                    None => 0,
+                };
+                let value = match args[0].to_real() {
+                    Some(value) => value,
+                    None => return Ok(Value::Null),
+                };
+                Value::Real(round(value, digits as usize))
+            }
+            ScalarFunction::Substr => substr(&args),
+            ScalarFunction::TypeOf => Value::Text(args[0].type_name().to_string()),
+            ScalarFunction::Unicode => match args[0].to_text().and_then(|text| text.chars().next())
+            {
+                Some(c) => Value::Integer(c as i64),
+                None => Value::Null,
+            },
+        })
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Rounds like SQLite, which formats the value with the given number of digits after the
+/// decimal point and reads the result back
+fn round(value: f64, digits: usize) -> f64 {
+    // Values this large have no fractional part
+    if value.abs() > 4503599627370496.0 {
+        value
+    } else if digits == 0 {
+        (value + if value < 0.0 { -0.5 } else { 0.5 }) as i64 as f64
+    } else {
+        let text = printf("%.*f", &[Value::Integer(digits as i64), Value::Real(value)]);
+        text.parse().unwrap_or(value)
+    }
+}
+
+/// `substr(X, Y, Z)`: Z characters (or bytes of a blob) of X starting at position Y. Positions
+/// start at 1, and a negative Y counts from the end. A negative Z takes the characters before
+/// position Y instead.
+fn substr(args: &[Value]) -> Value {
+    if args.iter().any(Value::is_null) {
+        return Value::Null;
+    }
+    let length = match &args[0] {
+        Value::Blob(bytes) => bytes.len(),
+        v => v.to_string().chars().count(),
+    } as i64;
+
+    let mut start = args[1].to_integer().unwrap_or(0);
+    let (mut count, negative) = match args.get(2) {
+        Some(count) => {
+            let count = count.to_integer().unwrap_or(0);
+            (count.saturating_abs(), count < 0)
+        }
+        None => (i64::MAX / 2, false),
+    };
+
+    if start < 0 {
+        start += length;
+        if start < 0 {
+            count += start;
+            if count < 0 {
+                count = 0;
+            }
+            start = 0;
+        }
+    } else if start > 0 {
+        start -= 1;
+    } else if count > 0 {
+        count -= 1;
+    }
+    if negative {
+        start -= count;
+        if start < 0 {
+            count += start;
+            start = 0;
+        }
+    }
+    if start + count > length {
+        count = (length - start).max(0);
+    }
+    let (start, count) = (start.max(0) as usize, count.max(0) as usize);
+
+    match &args[0] {
+        Value::Blob(bytes) => Value::Blob(bytes.iter().skip(start).take(count).copied().collect()),
+        v => Value::Text(v.to_string().chars().skip(start).take(count).collect()),
+    }
+}
@@ -0,0 +1,1838 @@
+use super::aggregate::{key_values, KeyValue};
+use super::cte::{self, RecursiveQuery, Reference};
+use super::explain::{Explainer, Step};
+use super::expr::{Binder, BoundExpr, Comparator, Scope, ScopeColumn};
+use super::planner::{
+    self, covering_slots, mark_columns, needed_tables, table_info, Access, BoundTerms, OrCondition,
+    Planner, TableInfo,
+};
+use super::sort::SortOrder;
+use super::subquery::Subquery;
+use super::{generate_columns, index_row, json, table_row, table_scope, Plan, Row, Rows};
+use crate::ast::{BinaryOperator, FromClause, JoinConstraint, JoinOperator, TableRef, TableSource};
+use crate::btree::{
+    scan_index_back_from, scan_index_from, scan_table, scan_table_back_from, scan_table_from,
+    scan_table_reverse,
+};
+use crate::database::Database;
+use crate::record::parse_record;
+use crate::schema::{Schema, TableDefinition};
+use crate::value::{Affinity, Collation, Value};
+use anyhow::{bail, Result};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// The tables of a FROM clause, joined one after the other in the order the planner chose
+pub struct Tables<'a> {
+    /// Number of values in a joined row
+    width: usize,
+    tables: Vec<Rc<JoinedTable<'a>>>,
+    /// The expressions of the covering indexes the tables are read with, and the columns that
+    /// the rows have their values in instead
+    substitutions: Vec<(BoundExpr<'a>, BoundExpr<'a>)>,
+}
+
+/// A table of the FROM clause, read again for each row of the tables joined before it
+pub struct JoinedTable<'a> {
+    /// Name or alias of the table
+    pub name: String,
+    pub source: Source<'a>,
+    /// Position of the table's first column in the row. The columns of each table keep their
+    /// place in the scope, whatever the order the tables are joined in.
+    pub start: usize,
+    /// Number of columns the table adds to a row
+    pub width: usize,
+    /// Whether rows without a match are kept, with NULLs for this table, as in a LEFT JOIN
+    pub outer: bool,
+    /// Whether the table is on the right of a CROSS JOIN, which keeps the tables to its left
+    /// before it
+    pub cross: bool,
+    /// The ON clause, and the equalities of USING or NATURAL
+    pub condition: Option<BoundExpr<'a>>,
+    /// The terms of the WHERE clause that only depend on this table and those joined before
+    /// it, so that rows are dropped as early as possible
+    pub filter: Option<BoundExpr<'a>>,
+}
+
+pub enum Source<'a> {
+    Table {
+        db: &'a Database,
+        schema: &'a Schema,
+        definition: Rc<TableDefinition>,
+        /// The VIRTUAL generated columns, computed for the rows read from the table
+        generated: Rc<Generated<'a>>,
+        lookup: Option<Lookup<'a>>,
+        /// Whether the rows are read in descending order of their rowid, or of the key of the
+        /// index the lookup reads
+        reverse: bool,
+    },
+    /// `json_each()` or `json_tree()`. The arguments may refer to the tables to the left.
+    Function {
+        recursive: bool,
+        args: Vec<BoundExpr<'a>>,
+    },
+    /// A subquery or common table. Its rows are kept when it is read again for every row to its
+    /// left, unless it refers to the enclosing query.
+    Subquery {
+        query: Derived<'a>,
+        cache: Option<RefCell<Option<Rc<Vec<Row>>>>>,
+    },
+    /// A recursive common table inside its own definition, which holds one row at a time
+    Current(Rc<RefCell<Row>>),
+}
+
+/// Positions of the VIRTUAL generated columns of a table and their expressions, bound against
+/// its row
+type Generated<'a> = Vec<(usize, BoundExpr<'a>)>;
+
+/// A query whose rows are read like those of a table
+pub enum Derived<'a> {
+    Select(Rc<Plan<'a>>),
+    Recursive(Rc<RecursiveQuery<'a>>),
+}
+
+impl<'a> Derived<'a> {
+    fn run(&self, left: &[Value]) -> Result<Rows<'a>> {
+        match self {
+            Derived::Select(plan) => plan.clone().run(&left[..plan.outer_width]),
+            Derived::Recursive(query) => query.clone().run(&left[..query.outer_width]),
+        }
+    }
+}
+
+/// Finds the rows of a table that may satisfy equalities, instead of scanning all of them
+pub enum Lookup<'a> {
+    /// The rowid equals the probe, evaluated against the row of the tables joined before, or
+    /// one of its values
+    Rowid(Probe<'a>),
+    /// The rowid is within the bounds
+    RowidRange(Bounds<'a>),
+    /// The first columns of an index equal the probes, and the next one may be within bounds.
+    /// Without either, every entry of the index is read, so that rows come out in the order of
+    /// the index.
+    Index {
+        /// Name of the index, or None for the b-tree of a WITHOUT ROWID table
+        name: Option<String>,
+        root_page: u32,
+        /// Positions in the table's row of the values of an index record, which the rowid
+        /// follows unless the table is WITHOUT ROWID
+        keys: Vec<usize>,
+        /// Names of the columns the probes are compared to
+        columns: Vec<String>,
+        probes: Vec<Probe<'a>>,
+        range: Option<Box<Bounds<'a>>>,
+        /// Whether the index covers the columns the query uses, so that rows are made of its
+        /// records instead of being read from the table
+        covering: bool,
+        /// When covering, the positions in the records of the values of the expressions the
+        /// query reads from the index, and those of the columns of the row they're put in
+        slots: Vec<(usize, usize)>,
+        /// When covering, the columns the WHERE clause of the index equals to constants
+        constants: Vec<(usize, Value)>,
+    },
+    /// An index on the columns the probes are compared to, built in memory the first time the
+    /// table is read, for a table that has none
+    Automatic {
+        /// Positions of the columns in the table's row, and their names
+        columns: Vec<usize>,
+        names: Vec<String>,
+        probes: Vec<Probe<'a>>,
+        entries: RefCell<Option<Rc<AutomaticIndex>>>,
+    },
+    /// The rows that any of the lookups finds that meet the condition it is for, each of them
+    /// once, for conditions joined with OR
+    Or {
+        /// Position of the table's first column in the row
+        start: usize,
+        lookups: Vec<(Lookup<'a>, Option<BoundExpr<'a>>)>,
+    },
+}
+
+impl<'a> Lookup<'a> {
+    /// The expressions the lookup evaluates
+    fn exprs(&self) -> Vec<&BoundExpr<'a>> {
+        match self {
+            Lookup::Rowid(probe) => probe.operand.exprs(),
+            Lookup::RowidRange(range) => range.probes().collect(),
+            Lookup::Index { probes, range, .. } => probes
+                .iter()
+                .flat_map(|probe| probe.operand.exprs())
+                .chain(range.iter().flat_map(|range| range.probes()))
+                .collect(),
+            Lookup::Automatic { probes, .. } => probes
+                .iter()
+                .flat_map(|probe| probe.operand.exprs())
+                .collect(),
+            Lookup::Or { lookups, .. } => lookups
+                .iter()
+                .flat_map(|(lookup, condition)| {
+                    let mut exprs = lookup.exprs();
+                    exprs.extend(condition);
+                    exprs
+                })
+                .collect(),
+        }
+    }
+
+    fn exprs_mut(&mut self) -> Vec<&mut BoundExpr<'a>> {
+        match self {
+            Lookup::Rowid(probe) => probe.operand.exprs_mut(),
+            Lookup::RowidRange(range) => range.probes_mut(),
+            Lookup::Index { probes, range, .. } => probes
+                .iter_mut()
+                .flat_map(|probe| probe.operand.exprs_mut())
+                .chain(range.iter_mut().flat_map(|range| range.probes_mut()))
+                .collect(),
+            Lookup::Automatic { probes, .. } => probes
+                .iter_mut()
+                .flat_map(|probe| probe.operand.exprs_mut())
+                .collect(),
+            Lookup::Or { lookups, .. } => lookups
+                .iter_mut()
+                .flat_map(|(lookup, condition)| {
+                    let mut exprs = lookup.exprs_mut();
+                    exprs.extend(condition);
+                    exprs
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The rows of a table by the values of the columns of an automatic index
+type AutomaticIndex = HashMap<Vec<KeyValue>, Vec<Row>>;
+
+/// What an indexed column is compared to
+pub struct Probe<'a> {
+    operand: Operand<'a>,
+    /// Affinity the comparison applies to the value
+    affinity: Option<Affinity>,
+    collation: Collation,
+    /// Whether the index keeps the column in descending order
+    descending: bool,
+}
+
+impl<'a> Probe<'a> {
+    /// The values the column may equal, with the affinity applied, without duplicates and in
+    /// the order of the index. NULLs are left out, since no column equals them.
+    fn values(&self, left: &[Value]) -> Result<Vec<Value>> {
+        let mut values = vec![];
+        for value in self.operand.eval(left)? {
+            if value.is_null() {
+                continue;
+            }
+            values.push(match self.affinity {
+                Some(affinity) => value.apply_affinity(affinity),
+                None => value,
+            });
+        }
+        values.sort_by(|a, b| a.compare(b, self.collation));
+        values.dedup_by(|a, b| a.compare(b, self.collation) == Ordering::Equal);
+        if self.descending {
+            values.reverse();
+        }
+        Ok(values)
+    }
+
+    /// The value of a probe with a single one, or None when it's NULL
+    fn value(&self, left: &[Value]) -> Result<Option<Value>> {
+        Ok(self.values(left)?.pop())
+    }
+}
+
+/// The value, or values, that an equality compares a column to
+#[derive(Clone)]
+pub enum Operand<'a> {
+    Value(BoundExpr<'a>),
+    /// The items of an IN list, or the values of equalities on the same column joined with OR,
+    /// with the affinity each comparison applies to them
+    List(Vec<(BoundExpr<'a>, Option<Affinity>)>),
+    /// An IN subquery that doesn't refer to the enclosing query
+    Subquery(Rc<Subquery<'a>>),
+}
+
+impl<'a> Operand<'a> {
+    /// Whether the column may equal more than one value
+    pub fn is_list(&self) -> bool {
+        !matches!(self, Operand::Value(_))
+    }
+
+    /// Number of values, which SQLite guesses to be 25 for a subquery
+    pub fn count(&self) -> f64 {
+        match self {
+            Operand::Value(_) => 1.0,
+            Operand::List(exprs) => exprs.len() as f64,
+            Operand::Subquery(_) => 25.0,
+        }
+    }
+
+    fn exprs(&self) -> Vec<&BoundExpr<'a>> {
+        match self {
+            Operand::Value(expr) => vec![expr],
+            Operand::List(items) => items.iter().map(|(expr, _)| expr).collect(),
+            Operand::Subquery(_) => vec![],
+        }
+    }
+
+    fn exprs_mut(&mut self) -> Vec<&mut BoundExpr<'a>> {
+        match self {
+            Operand::Value(expr) => vec![expr],
+            Operand::List(items) => items.iter_mut().map(|(expr, _)| expr).collect(),
+            Operand::Subquery(_) => vec![],
+        }
+    }
+
+    fn eval(&self, left: &[Value]) -> Result<Vec<Value>> {
+        match self {
+            Operand::Value(expr) => Ok(vec![expr.eval(left)?]),
+            Operand::List(items) => items
+                .iter()
+                .map(|(expr, affinity)| {
+                    let value = expr.eval(left)?;
+                    Ok(match affinity {
+                        Some(affinity) => value.apply_affinity(*affinity),
+                        None => value,
+                    })
+                })
+                .collect(),
+            Operand::Subquery(subquery) => Ok(subquery.values(left)?.to_vec()),
+        }
+    }
+}
+
+/// The values a column is compared to by the terms that bound it. Both bounds are inclusive:
+/// the rows just outside of strict bounds are left to the terms to drop.
+pub struct Bounds<'a> {
+    /// Name of the column, for EXPLAIN QUERY PLAN
+    column: String,
+    lower: Option<Probe<'a>>,
+    upper: Option<Probe<'a>>,
+    /// Whether the blobs of the column are read too, since the upper bound of a LIKE pattern
+    /// is a string, which they sort after, while their bytes may match the pattern
+    blobs: bool,
+}
+
+impl<'a> Bounds<'a> {
+    fn probes(&self) -> impl Iterator<Item = &BoundExpr<'a>> {
+        self.lower
+            .iter()
+            .chain(&self.upper)
+            .flat_map(|probe| probe.operand.exprs())
+    }
+
+    fn probes_mut(&mut self) -> Vec<&mut BoundExpr<'a>> {
+        self.lower
+            .iter_mut()
+            .chain(&mut self.upper)
+            .flat_map(|probe| probe.operand.exprs_mut())
+            .collect()
+    }
+}
+
+/// Binds the FROM clause, returning the scope of the joined tables, which follow the columns of
+/// the `outer` scope of the enclosing query. The tables are joined in the order they're written
+/// in until `Tables::plan` is called.
+pub fn prepare<'a>(
+    db: &'a Database,
+    from: &FromClause,
+    outer: &Scope,
+) -> Result<(Scope, Tables<'a>)> {
+    let mut scope = outer.nested();
+    let mut tables = vec![];
+
+    let first = std::iter::once((JoinOperator::Inner, false, &from.table, None));
+    let joins = from.joins.iter().map(|join| {
+        (
+            join.operator,
+            join.natural,
+            &join.table,
+            join.constraint.as_ref(),
+        )
+    });
+    for (operator, natural, table, constraint) in first.chain(joins) {
+        if tables.len() == 64 {
+            bail!("at most 64 tables in a join");
+        }
+        let offset = scope.columns.len();
+        let (source, columns) = table_source(db, table, &scope, outer)?;
+        let width = columns.len();
+        let left = scope.clone();
+        scope.columns.extend(columns);
+
+        if natural && constraint.is_some() {
+            bail!("a NATURAL join may not have an ON or USING clause");
+        }
+        let using = match constraint {
+            Some(JoinConstraint::Using(names)) => names.clone(),
+            _ if natural => scope.columns[offset..]
+                .iter()
+                .filter(|column| !column.hidden)
+                .filter(|column| {
+                    left.columns.iter().any(|left_column| {
+                        !left_column.hidden
+                            && left_column.depth == 0
+                            && left_column.name.eq_ignore_ascii_case(&column.name)
+                    })
+                })
+                .map(|column| column.name.clone())
+                .collect(),
+            _ => vec![],
+        };
+
+        let mut conditions = vec![];
+        for name in &using {
+            let find = |columns: &[ScopeColumn]| {
+                columns.iter().position(|column| {
+                    !column.hidden && column.depth == 0 && column.name.eq_ignore_ascii_case(name)
+                })
+            };
+            let (left_column, right_column) =
+                match (find(&left.columns), find(&scope.columns[offset..])) {
+                    (Some(left_column), Some(right_column)) => (left_column, offset + right_column),
+                    _ => bail!(
+                        "cannot join using column {} - column not present in both tables",
+                        name
+                    ),
+                };
+            scope.columns[right_column].using = true;
+
+            let affinity =
+                |i: usize| Some(scope.columns[i].affinity).filter(|a| *a != Affinity::Blob);
+            let collation = scope.columns[left_column].collation;
+            let comparator =
+                Comparator::new(affinity(left_column), affinity(right_column), collation);
+            conditions.push(BoundExpr::Comparison {
+                left: Box::new(BoundExpr::Column(left_column)),
+                op: BinaryOperator::Eq,
+                right: Box::new(BoundExpr::Column(right_column)),
+                comparator,
+            });
+        }
+
+        if let Some(JoinConstraint::On(on)) = constraint {
+            conditions.push(Binder::new(db, &scope).bind(on)?);
+        }
+
+        let condition = conditions.into_iter().reduce(|left, right| {
+            BoundExpr::Binary(Box::new(left), BinaryOperator::And, Box::new(right))
+        });
+        tables.push(Rc::new(JoinedTable {
+            name: table.name().to_string(),
+            source,
+            start: offset,
+            width,
+            outer: operator == JoinOperator::Left,
+            cross: operator == JoinOperator::Cross,
+            condition,
+            filter: None,
+        }));
+    }
+
+    let tables = Tables {
+        width: scope.columns.len(),
+        tables,
+        substitutions: vec![],
+    };
+    Ok((scope, tables))
+}
+
+impl<'a> Tables<'a> {
+    /// Joins the tables for a row of the enclosing query, which the joined rows start with
+    pub fn rows(&self, outer: &[Value]) -> Result<Rows<'a>> {
+        let mut row = outer.to_vec();
+        row.resize(self.width, Value::Null);
+        let mut rows: Rows<'a> = Box::new(std::iter::once(Ok(row)));
+        for table in &self.tables {
+            rows = Box::new(NestedLoop {
+                outer: rows,
+                table: table.clone(),
+                current: None,
+            });
+        }
+        Ok(rows)
+    }
+
+    /// Chooses the order the tables are joined in and how the rows of each one are found, from
+    /// the equalities and ranges of the WHERE and ON clauses, and moves the terms of the WHERE clause to
+    /// the first table whose rows have all the columns they use. Inner joins may be reordered,
+    /// since they keep the same rows whatever the order. `keys` and `orders` are the terms the
+    /// rows are sorted by, in either direction when `grouping`; returns whether the rows already
+    /// come out in that order. `exprs` and `unindexed` are the other expressions the query
+    /// evaluates against the joined rows, the latter never taking the values of the expressions
+    /// an index has from it.
+    pub fn plan(
+        &mut self,
+        filter: Option<BoundExpr<'a>>,
+        keys: &[BoundExpr<'a>],
+        orders: &[SortOrder],
+        grouping: bool,
+        exprs: &[&BoundExpr<'a>],
+        unindexed: &[&BoundExpr<'a>],
+    ) -> Result<bool> {
+        let mut tables: Vec<JoinedTable<'a>> = std::mem::take(&mut self.tables)
+            .into_iter()
+            .map(|table| Rc::try_unwrap(table).ok().expect("tables are planned once"))
+            .collect();
+
+        let mut terms = vec![];
+        if let Some(filter) = filter {
+            split_conjuncts(filter, &mut terms);
+        }
+        // The ON clauses of inner joins are the same as the WHERE clause
+        let reorderable = tables.len() > 1 && tables.iter().all(|table| !table.outer);
+        if reorderable {
+            for table in &mut tables {
+                if let Some(condition) = table.condition.take() {
+                    split_conjuncts(condition, &mut terms);
+                }
+            }
+        }
+
+        let ranges: Vec<_> = tables
+            .iter()
+            .map(|table| table.start..table.start + table.width)
+            .collect();
+        let needs = |expr: &BoundExpr| needed_tables(expr, &ranges);
+        let term_needs: Vec<_> = terms.iter().map(needs).collect();
+
+        let mut infos = vec![];
+        for (i, table) in tables.iter().enumerate() {
+            // WHERE can't narrow down the rows of the right table of a LEFT JOIN, since rows
+            // that match nothing are kept
+            let mut lookup_terms = vec![];
+            if !table.outer {
+                lookup_terms.extend(&terms);
+            }
+            if let Some(condition) = &table.condition {
+                conjunct_refs(condition, &mut lookup_terms);
+            }
+            infos.push(table_info(table, i, &ranges, &lookup_terms)?);
+        }
+
+        // An index can be read instead of its table when it has every column the query uses
+        let table_exprs = tables.iter().flat_map(|table| table.exprs());
+        let read: Vec<_> = exprs
+            .iter()
+            .copied()
+            .chain(&terms)
+            .chain(table_exprs)
+            .collect();
+        let mut used = vec![false; self.width];
+        let mut any_used = false;
+        for expr in read.iter().chain(unindexed) {
+            any_used |= mark_columns(expr, &mut used);
+        }
+        for (info, table) in infos.iter_mut().zip(&tables) {
+            // Like SQLite, take a generated column to use every column of its table
+            let generated = match &table.source {
+                Source::Table { definition, .. } => {
+                    definition
+                        .columns
+                        .iter()
+                        .enumerate()
+                        .any(|(column, definition)| {
+                            definition.generated.is_some() && used[table.start + column]
+                        })
+                }
+                _ => false,
+            };
+            for index in &mut info.indexes {
+                let slots = match any_used || generated {
+                    true => None,
+                    false => covering_slots(
+                        index,
+                        info.rowid,
+                        &info.expressions,
+                        table,
+                        &read,
+                        unindexed,
+                        &used,
+                    ),
+                };
+                index.covering = index.primary || slots.is_some();
+                index.slots = slots.unwrap_or_default();
+            }
+        }
+
+        let plan = Planner {
+            tables: &tables,
+            infos: &infos,
+            term_needs: &term_needs,
+            keys,
+            orders,
+            grouping,
+        };
+        let order = match reorderable {
+            true => {
+                let required: Vec<_> = infos.iter().map(|info| info.required).collect();
+                planner::best_order(tables.len(), &required, |order| {
+                    Some(plan.best_accesses(order).0)
+                })
+            }
+            false => (0..tables.len()).collect(),
+        };
+        let (_, accesses, directions) = plan.best_accesses(&order);
+        let sorted = directions.is_some();
+        let reversed = by_table(&order, directions.unwrap_or_default(), false);
+
+        let accesses = by_table(&order, accesses, Access::Scan);
+
+        // The rows read from a covering index have the values of the expressions it has in
+        // place of columns the query doesn't use
+        self.substitutions = infos
+            .iter()
+            .zip(&accesses)
+            .zip(&tables)
+            .flat_map(|((info, access), table)| match access {
+                Access::Index { index, .. } => info.indexes[*index]
+                    .slots
+                    .iter()
+                    .map(|&(j, column)| {
+                        let column = BoundExpr::Column(table.start + column);
+                        (info.expressions[j].clone(), column)
+                    })
+                    .collect(),
+                _ => vec![],
+            })
+            .collect();
+
+        let mut lookups: Vec<_> = infos
+            .into_iter()
+            .zip(accesses)
+            .zip(by_table(&order, joined_before(&order), 0))
+            .zip(&tables)
+            .map(|(((info, access), joined), table)| lookup(info, access, joined, table))
+            .collect();
+        let mut planned = vec![];
+        let mut tables: Vec<_> = tables.into_iter().map(Some).collect();
+        let mut joined = 0u64;
+        for &i in &order {
+            let mut table = tables[i].take().unwrap();
+            if let Source::Table {
+                lookup, reverse, ..
+            } = &mut table.source
+            {
+                *lookup = lookups[i].take();
+                *reverse = reversed[i];
+            }
+
+            // Correlated subqueries may use any column, so they wait for the last table
+            joined |= 1 << i;
+            let (here, rest) = terms
+                .into_iter()
+                .zip(term_needs.iter().copied())
+                .partition::<Vec<_>, _>(|(_, needs)| needs & !joined == 0);
+            terms = rest.iter().map(|(term, _)| term.clone()).collect();
+            table.filter = here
+                .into_iter()
+                .map(|(term, _)| term)
+                .reduce(|left, right| {
+                    BoundExpr::Binary(Box::new(left), BinaryOperator::And, Box::new(right))
+                });
+            for expr in table.exprs_mut() {
+                self.substitute(expr);
+            }
+            planned.push(Rc::new(table));
+        }
+        self.tables = planned;
+        Ok(sorted)
+    }
+
+    /// Replaces the parts of an expression evaluated against the joined rows that are read
+    /// from covering indexes with the columns holding their values
+    pub fn substitute(&self, expr: &mut BoundExpr<'a>) {
+        for (indexed, column) in &self.substitutions {
+            expr.replace(indexed, column);
+        }
+    }
+
+    /// The steps of EXPLAIN QUERY PLAN for reading the tables, each followed by the subqueries
+    /// it runs
+    pub fn explain(&self, explainer: &mut Explainer) -> Vec<Step> {
+        let mut steps = vec![];
+        for table in &self.tables {
+            let name = &table.name;
+            let mut step = match &table.source {
+                Source::Table {
+                    lookup, definition, ..
+                } => lookup_step(name, lookup.as_ref(), definition, table.outer, &mut steps),
+                Source::Function { args, .. } => Step::new(format!(
+                    "SCAN {} VIRTUAL TABLE INDEX {}:",
+                    name,
+                    (1 << args.len()) - 1
+                )),
+                Source::Subquery { query, cache } => {
+                    let children = match query {
+                        Derived::Select(plan) => plan.explain(explainer),
+                        Derived::Recursive(query) => query.explain(explainer),
+                    };
+                    let kind = match cache {
+                        Some(_) => "MATERIALIZE",
+                        None => "CO-ROUTINE",
+                    };
+                    steps.push(Step::with_children(format!("{} {}", kind, name), children));
+                    Step::new(format!("SCAN {}", name))
+                }
+                Source::Current(_) => Step::new(format!("SCAN {}", name)),
+            };
+            if table.outer && !matches!(&table.source, Source::Table { .. }) {
+                step.detail.push_str(" LEFT-JOIN");
+            }
+            steps.push(step);
+            explainer.subqueries(table.exprs(), &mut steps);
+        }
+        steps
+    }
+
+    /// The queries the FROM clause reads, and the expressions it evaluates, in the order they
+    /// are written in
+    pub fn parts(&self) -> (Vec<&Plan<'a>>, Vec<&BoundExpr<'a>>) {
+        let mut tables: Vec<_> = self.tables.iter().collect();
+        tables.sort_by_key(|table| table.start);
+        let mut plans = vec![];
+        let mut exprs = vec![];
+        for table in &tables {
+            match &table.source {
+                Source::Subquery {
+                    query: Derived::Select(plan),
+                    ..
+                } => plans.push(&**plan),
+                Source::Subquery {
+                    query: Derived::Recursive(query),
+                    ..
+                } => plans.extend(query.plans()),
+                _ => {}
+            }
+        }
+        for table in &tables {
+            exprs.extend(table.exprs());
+        }
+        (plans, exprs)
+    }
+}
+
+impl<'a> JoinedTable<'a> {
+    /// The expressions evaluated for each row the table is read for
+    fn exprs(&self) -> Vec<&BoundExpr<'a>> {
+        let mut exprs = vec![];
+        match &self.source {
+            Source::Function { args, .. } => exprs.extend(args),
+            Source::Table {
+                lookup: Some(lookup),
+                ..
+            } => exprs.extend(lookup.exprs()),
+            _ => {}
+        }
+        exprs.extend(&self.condition);
+        exprs.extend(&self.filter);
+        exprs
+    }
+
+    fn exprs_mut(&mut self) -> Vec<&mut BoundExpr<'a>> {
+        let mut exprs = vec![];
+        match &mut self.source {
+            Source::Function { args, .. } => exprs.extend(args),
+            Source::Table {
+                lookup: Some(lookup),
+                ..
+            } => exprs.extend(lookup.exprs_mut()),
+            _ => {}
+        }
+        exprs.extend(&mut self.condition);
+        exprs.extend(&mut self.filter);
+        exprs
+    }
+}
+
+/// How EXPLAIN QUERY PLAN shows reading a table with a lookup, adding the steps that come
+/// before it to `steps`. The searches of a MULTI-INDEX OR are the ones marked as reading the
+/// right table of a LEFT JOIN.
+fn lookup_step(
+    name: &str,
+    lookup: Option<&Lookup>,
+    definition: &TableDefinition,
+    outer: bool,
+    steps: &mut Vec<Step>,
+) -> Step {
+    let detail = match lookup {
+        None => format!("SCAN {}", name),
+        Some(Lookup::Rowid(_)) => format!("SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", name),
+        Some(Lookup::RowidRange(range)) => format!(
+            "SEARCH {} USING INTEGER PRIMARY KEY ({})",
+            name,
+            constraint_list(&[], Some(range))
+        ),
+        Some(Lookup::Index {
+            name: index,
+            columns,
+            range,
+            covering,
+            ..
+        }) => {
+            let using = match (index, covering) {
+                (Some(index), true) => format!("COVERING INDEX {}", index),
+                (Some(index), false) => format!("INDEX {}", index),
+                (None, _) => "PRIMARY KEY".to_string(),
+            };
+            match (columns.is_empty() && range.is_none(), index) {
+                // Reading all of the b-tree of a WITHOUT ROWID table is reading the table
+                (true, None) => format!("SCAN {}", name),
+                (true, Some(_)) => format!("SCAN {} USING {}", name, using),
+                (false, _) => format!(
+                    "SEARCH {} USING {} ({})",
+                    name,
+                    using,
+                    constraint_list(columns, range.as_deref())
+                ),
+            }
+        }
+        Some(Lookup::Automatic {
+            columns: positions,
+            names,
+            ..
+        }) => {
+            // SQLite doesn't filter lookups of text alone
+            let columns = constraint_list(names, None);
+            if positions.iter().any(|&column| {
+                definition.columns.get(column).map(|column| column.affinity) != Some(Affinity::Text)
+            }) {
+                steps.push(Step::new(format!("BLOOM FILTER ON {} ({})", name, columns)));
+            }
+            format!(
+                "SEARCH {} USING AUTOMATIC COVERING INDEX ({})",
+                name, columns
+            )
+        }
+        Some(Lookup::Or { lookups, .. }) => {
+            let children = lookups
+                .iter()
+                .enumerate()
+                .map(|(n, (lookup, _))| {
+                    let mut steps = vec![];
+                    let step = lookup_step(name, Some(lookup), definition, outer, &mut steps);
+                    steps.push(step);
+                    Step::with_children(format!("INDEX {}", n + 1), steps)
+                })
+                .collect();
+            return Step::with_children("MULTI-INDEX OR", children);
+        }
+    };
+    match outer {
+        true => Step::new(format!("{} LEFT-JOIN", detail)),
+        false => Step::new(detail),
+    }
+}
+
+/// The equalities and bounds of a lookup, as EXPLAIN QUERY PLAN shows them
+fn constraint_list(columns: &[String], range: Option<&Bounds>) -> String {
+    let mut constraints: Vec<_> = columns
+        .iter()
+        .map(|column| format!("{}=?", column))
+        .collect();
+    if let Some(range) = range {
+        if range.lower.is_some() {
+            constraints.push(format!("{}>?", range.column));
+        }
+        if range.upper.is_some() {
+            constraints.push(format!("{}<?", range.column));
+        }
+    }
+    constraints.join(" AND ")
+}
+
+/// Puts what was chosen for the tables in join `order` back in the order the tables are written
+/// in
+fn by_table<T: Clone>(order: &[usize], chosen: Vec<T>, default: T) -> Vec<T> {
+    let mut by_table = vec![default; order.len()];
+    for (&i, chosen) in order.iter().zip(chosen) {
+        by_table[i] = chosen;
+    }
+    by_table
+}
+
+/// The tables joined before each one of those joined in `order`, one bit each
+fn joined_before(order: &[usize]) -> Vec<u64> {
+    let mut joined = 0;
+    order
+        .iter()
+        .map(|&i| {
+            let before = joined;
+            joined |= 1 << i;
+            before
+        })
+        .collect()
+}
+
+/// The lookup a table is read with, for the way the planner chose, after the `joined` tables
+fn lookup<'a>(
+    info: TableInfo<'_, 'a>,
+    access: Access,
+    joined: u64,
+    table: &JoinedTable,
+) -> Option<Lookup<'a>> {
+    let column_name = |column: usize| match &table.source {
+        Source::Table { definition, .. } if column < definition.columns.len() => {
+            definition.columns[column].name.clone()
+        }
+        Source::Table { .. } if column >= table.width => "<expr>".to_string(),
+        _ => "rowid".to_string(),
+    };
+    let mut equalities: Vec<_> = info.equalities.into_iter().map(Some).collect();
+    let mut take = |e: usize| equalities[e].take().unwrap();
+    let mut bounds: Vec<_> = info.bounds.into_iter().map(Some).collect();
+    let mut bounds = |terms: BoundTerms, column: String, collation, descending| {
+        let mut probe = |b: Option<usize>| {
+            b.map(|b| {
+                let bound = bounds[b].take().unwrap();
+                (
+                    Probe {
+                        operand: Operand::Value(bound.probe),
+                        affinity: bound.probe_affinity,
+                        collation,
+                        descending,
+                    },
+                    bound.pattern,
+                )
+            })
+        };
+        let lower = probe(terms.lower);
+        let upper = probe(terms.upper);
+        Bounds {
+            column,
+            blobs: matches!(upper, Some((_, true))),
+            lower: lower.map(|(probe, _)| probe),
+            upper: upper.map(|(probe, _)| probe),
+        }
+    };
+    match access {
+        Access::Scan => None,
+        Access::Rowid(e) => Some(Lookup::Rowid(Probe {
+            operand: take(e).probe,
+            affinity: Some(Affinity::Numeric),
+            collation: Collation::Binary,
+            descending: false,
+        })),
+        Access::RowidRange(terms) => Some(Lookup::RowidRange(bounds(
+            terms,
+            "rowid".to_string(),
+            Collation::Binary,
+            false,
+        ))),
+        Access::Index {
+            index,
+            equalities,
+            bounds: terms,
+        } => {
+            let index = &info.indexes[index];
+            let mut columns = vec![];
+            let mut probes = vec![];
+            let count = equalities.len();
+            for (e, &(column, collation, descending)) in equalities.into_iter().zip(&index.columns)
+            {
+                let equality = take(e);
+                columns.push(column_name(column));
+                probes.push(Probe {
+                    operand: equality.probe,
+                    affinity: equality.probe_affinity,
+                    collation,
+                    descending,
+                });
+            }
+            let range = match (terms.count(), index.columns.get(count)) {
+                (0, _) => None,
+                (_, Some(&(column, collation, descending))) => Some(Box::new(bounds(
+                    terms,
+                    column_name(column),
+                    collation,
+                    descending,
+                ))),
+                (_, None) => Some(Box::new(bounds(
+                    terms,
+                    "rowid".to_string(),
+                    Collation::Binary,
+                    false,
+                ))),
+            };
+            // The values of the expressions the query reads from the index
+            let slots = index
+                .columns
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &(column, _, _))| {
+                    let j = column.checked_sub(table.width)?;
+                    let &(_, slot) = index.slots.iter().find(|&&(other, _)| other == j)?;
+                    Some((i, slot))
+                })
+                .collect();
+            // The records of a WITHOUT ROWID table have every column after the key
+            let keys = match (index.primary, &table.source) {
+                (true, Source::Table { definition, .. }) => definition.stored_columns.clone(),
+                _ => index.columns.iter().map(|&(column, _, _)| column).collect(),
+            };
+            Some(Lookup::Index {
+                name: match index.primary {
+                    true => None,
+                    false => Some(index.schema.name.clone()),
+                },
+                root_page: index.schema.root_page,
+                keys,
+                columns,
+                probes,
+                range,
+                covering: index.covering,
+                slots,
+                constants: index.constants.clone(),
+            })
+        }
+        Access::Automatic(automatic) => {
+            let mut columns = vec![];
+            let mut names = vec![];
+            let mut probes = vec![];
+            for e in automatic {
+                let equality = take(e);
+                columns.push(equality.column);
+                names.push(column_name(equality.column));
+                probes.push(Probe {
+                    operand: equality.probe,
+                    affinity: equality.probe_affinity,
+                    collation: equality.collation,
+                    descending: false,
+                });
+            }
+            Some(Lookup::Automatic {
+                columns,
+                names,
+                probes,
+                entries: RefCell::new(None),
+            })
+        }
+        // The terms of each condition that refer to tables joined later are left to the term
+        // itself
+        Access::Or(o, accesses) => {
+            let conditions = info.ors.into_iter().nth(o).unwrap();
+            let lookups = conditions
+                .into_iter()
+                .zip(accesses)
+                .map(|(OrCondition { terms, info }, access)| {
+                    let condition = terms
+                        .into_iter()
+                        .filter(|(_, needs)| needs & !joined == 0)
+                        .map(|(term, _)| term)
+                        .reduce(|left, right| {
+                            BoundExpr::Binary(Box::new(left), BinaryOperator::And, Box::new(right))
+                        });
+                    (lookup(info, access, joined, table).unwrap(), condition)
+                })
+                .collect();
+            Some(Lookup::Or {
+                start: table.start,
+                lookups,
+            })
+        }
+    }
+}
+
+/// Binds the expressions of a table's VIRTUAL generated columns, ordered so that each comes
+/// after the generated columns it refers to
+fn generated_columns<'a>(db: &'a Database, definition: &TableDefinition) -> Result<Generated<'a>> {
+    let scope = table_scope(definition, &definition.name);
+    let binder = Binder::new(db, &scope);
+    let mut pending = vec![];
+    for (i, column) in definition.columns.iter().enumerate() {
+        if let Some((expr, false)) = &column.generated {
+            pending.push((i, binder.bind(expr)?));
+        }
+    }
+
+    let mut generated = vec![];
+    while !pending.is_empty() {
+        let is_pending = |column| pending.iter().any(|&(i, _)| i == column);
+        match pending
+            .iter()
+            .position(|(_, expr)| !expr.uses_columns(&is_pending))
+        {
+            Some(ready) => generated.push(pending.remove(ready)),
+            None => bail!(
+                "generated column loop on \"{}\"",
+                definition.columns[pending[0].0].name
+            ),
+        }
+    }
+    Ok(generated)
+}
+
+/// The source of a table in the FROM clause and its columns. The arguments of a table-valued
+/// function are bound against the tables to its left, and a subquery against the `outer` scope
+/// of the enclosing query.
+fn table_source<'a>(
+    db: &'a Database,
+    table: &TableRef,
+    left: &Scope,
+    outer: &Scope,
+) -> Result<(Source<'a>, Vec<ScopeColumn>)> {
+    if let TableSource::Table(name) = &table.source {
+        if let Some(common_table) = left.common_table(name) {
+            let name = Some(table.name().to_string());
+            return match cte::prepare(db, common_table, outer)? {
+                Reference::Select(plan) => {
+                    let columns = derived_columns(name, &plan.columns, &plan.column_types);
+                    let outer_depth = plan.outer_depth;
+                    let query = Derived::Select(Rc::new(*plan));
+                    Ok((derived_source(query, outer_depth, left, outer), columns))
+                }
+                Reference::Recursive(query) => {
+                    let columns = derived_columns(name, &query.columns, &query.column_types);
+                    let outer_depth = query.outer_depth;
+                    let query = Derived::Recursive(Rc::new(*query));
+                    Ok((derived_source(query, outer_depth, left, outer), columns))
+                }
+                Reference::Current { row, columns } => {
+                    let columns = columns
+                        .into_iter()
+                        .map(|column| ScopeColumn {
+                            table: name.clone(),
+                            ..column
+                        })
+                        .collect();
+                    Ok((Source::Current(row), columns))
+                }
+            };
+        }
+    }
+
+    match &table.source {
+        TableSource::Table(name) => {
+            if let Some(view) = db.view(name) {
+                // A view is a subquery that sees neither the enclosing query nor its CTEs, and
+                // that can't refer to itself, even through other views
+                if left
+                    .views
+                    .iter()
+                    .any(|other| other.eq_ignore_ascii_case(&view.name))
+                {
+                    bail!("view {} is circularly defined", view.name);
+                }
+                let definition = view.view_definition()?;
+                let mut scope = Scope::default();
+                scope.views = left.views.clone();
+                scope.views.push(view.name.clone());
+                let plan = Plan::prepare(db, &definition.select, &scope)?;
+                let names = if definition.columns.is_empty() {
+                    &plan.columns
+                } else if definition.columns.len() == plan.columns.len() {
+                    &definition.columns
+                } else {
+                    bail!(
+                        "expected {} columns for '{}' but got {}",
+                        definition.columns.len(),
+                        view.name,
+                        plan.columns.len()
+                    );
+                };
+                let name = Some(table.name().to_string());
+                let columns = derived_columns(name, names, &plan.column_types);
+                let query = Derived::Select(Rc::new(plan));
+                return Ok((derived_source(query, 0, left, outer), columns));
+            }
+
+            let schema = db.table(name)?;
+            let definition = schema.table_definition()?;
+            let scope = table_scope(&definition, table.name());
+            let generated = generated_columns(db, &definition)?;
+            let source = Source::Table {
+                db,
+                schema,
+                definition: Rc::new(definition),
+                generated: Rc::new(generated),
+                lookup: None,
+                reverse: false,
+            };
+            Ok((source, scope.columns))
+        }
+        TableSource::Function { name, args } => {
+            let recursive = match name.to_ascii_lowercase().as_str() {
+                "json_each" => false,
+                "json_tree" => true,
+                _ => match db.table(name) {
+                    Ok(_) => bail!("'{}' is not a function", name),
+                    Err(_) if db.view(name).is_some() => bail!("'{}' is not a function", name),
+                    Err(e) => return Err(e),
+                },
+            };
+            if args.len() > 2 {
+                bail!("too many arguments on {}() - max 2", name);
+            }
+
+            let binder = Binder::new(db, left);
+            let args = args
+                .iter()
+                .map(|arg| binder.bind(arg))
+                .collect::<Result<Vec<_>>>()?;
+
+            let mut columns: Vec<_> = json::EACH_COLUMNS
+                .iter()
+                .map(|&(name, hidden)| ScopeColumn {
+                    table: Some(table.name().to_string()),
+                    name: name.to_string(),
+                    affinity: Affinity::Blob,
+                    collation: Collation::Binary,
+                    hidden,
+                    using: false,
+                    depth: 0,
+                })
+                .collect();
+            columns.push(ScopeColumn {
+                table: Some(table.name().to_string()),
+                name: "rowid".to_string(),
+                affinity: Affinity::Integer,
+                collation: Collation::Binary,
+                hidden: true,
+                using: false,
+                depth: 0,
+            });
+            Ok((Source::Function { recursive, args }, columns))
+        }
+        TableSource::Subquery(select) => {
+            let plan = Plan::prepare(db, select, outer)?;
+            let columns = derived_columns(table.alias.clone(), &plan.columns, &plan.column_types);
+            let outer_depth = plan.outer_depth;
+            let query = Derived::Select(Rc::new(plan));
+            Ok((derived_source(query, outer_depth, left, outer), columns))
+        }
+    }
+}
+
+/// The columns of a subquery or common table
+fn derived_columns(
+    table: Option<String>,
+    names: &[String],
+    types: &[(Option<Affinity>, Option<Collation>)],
+) -> Vec<ScopeColumn> {
+    let mut unique_names: Vec<String> = vec![];
+    let mut columns = vec![];
+    for (name, (affinity, collation)) in names.iter().zip(types) {
+        // Duplicate names get a numeric suffix, so that they can be told apart
+        let mut unique = name.clone();
+        let mut suffix = 0;
+        while unique_names
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(&unique))
+        {
+            suffix += 1;
+            unique = format!("{}:{}", name, suffix);
+        }
+        unique_names.push(unique.clone());
+        columns.push(ScopeColumn {
+            table: table.clone(),
+            name: unique,
+            affinity: affinity.unwrap_or(Affinity::Blob),
+            collation: collation.unwrap_or(Collation::Binary),
+            hidden: false,
+            using: false,
+            depth: 0,
+        });
+    }
+    columns
+}
+
+fn derived_source<'a>(
+    query: Derived<'a>,
+    outer_depth: usize,
+    left: &Scope,
+    outer: &Scope,
+) -> Source<'a> {
+    // The query's references to the enclosing query are references of this query
+    left.refer_to_outer(outer_depth);
+    // Nothing to the left means the query is only read once for each run
+    let cache = if left.columns.len() > outer.columns.len() && outer_depth == 0 {
+        Some(RefCell::new(None))
+    } else {
+        None
+    };
+    Source::Subquery { query, cache }
+}
+
+pub fn split_conjuncts<'a>(expr: BoundExpr<'a>, terms: &mut Vec<BoundExpr<'a>>) {
+    match expr {
+        BoundExpr::Binary(left, BinaryOperator::And, right) => {
+            split_conjuncts(*left, terms);
+            split_conjuncts(*right, terms);
+        }
+        expr => terms.push(expr),
+    }
+}
+
+/// The terms of a condition that are joined with AND
+pub fn conjunct_refs<'e, 'a>(expr: &'e BoundExpr<'a>, terms: &mut Vec<&'e BoundExpr<'a>>) {
+    match expr {
+        BoundExpr::Binary(left, BinaryOperator::And, right) => {
+            conjunct_refs(left, terms);
+            conjunct_refs(right, terms);
+        }
+        expr => terms.push(expr),
+    }
+}
+
+impl<'a> Source<'a> {
+    /// The rows of the source for a row of the tables joined before it
+    fn rows(&self, left: &[Value]) -> Result<Rows<'a>> {
+        match self {
+            Source::Function { recursive, args } => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(left))
+                    .collect::<Result<Vec<_>>>()?;
+                let rows = json::each(&args, *recursive)?.into_iter().enumerate().map(
+                    |(rowid, mut row)| {
+                        row.push(Value::Integer(rowid as i64));
+                        Ok(row)
+                    },
+                );
+                Ok(Box::new(rows))
+            }
+            Source::Subquery { query, cache: None } => query.run(left),
+            Source::Subquery {
+                query,
+                cache: Some(cache),
+            } => {
+                let cached = cache.borrow().clone();
+                let rows = match cached {
+                    Some(rows) => rows,
+                    None => {
+                        let rows = query.run(left)?.collect::<Result<Vec<_>>>()?;
+                        let rows = Rc::new(rows);
+                        *cache.borrow_mut() = Some(rows.clone());
+                        rows
+                    }
+                };
+                Ok(Box::new((0..rows.len()).map(move |i| Ok(rows[i].clone()))))
+            }
+            Source::Current(row) => Ok(Box::new(std::iter::once(Ok(row.borrow().clone())))),
+            Source::Table {
+                db,
+                schema,
+                definition,
+                generated,
+                lookup: None,
+                reverse,
+            } => {
+                let scan = match reverse {
+                    true => scan_table_reverse(db, schema.root_page)?,
+                    false => scan_table(db, schema.root_page)?,
+                };
+                let definition = definition.clone();
+                let generated = generated.clone();
+                let rows = scan.map(move |row| {
+                    let (rowid, payload) = row?;
+                    table_row(&definition, &generated, rowid, &payload)
+                });
+                Ok(Box::new(rows))
+            }
+            Source::Table {
+                db,
+                schema,
+                definition,
+                generated,
+                lookup: Some(lookup),
+                reverse,
+            } => lookup_rows(db, schema, definition, generated, lookup, *reverse, left),
+        }
+    }
+}
+
+/// The rows of a table that a lookup finds for a row of the tables joined before it
+fn lookup_rows<'a>(
+    db: &'a Database,
+    schema: &Schema,
+    definition: &Rc<TableDefinition>,
+    generated: &Rc<Generated<'a>>,
+    lookup: &Lookup<'a>,
+    reverse: bool,
+    left: &[Value],
+) -> Result<Rows<'a>> {
+    match lookup {
+        Lookup::Rowid(probe) => {
+            let mut rowids: Vec<_> = probe
+                .values(left)?
+                .into_iter()
+                .filter_map(|value| match value {
+                    Value::Integer(rowid) => Some(rowid),
+                    Value::Real(v) if v.fract() == 0.0 && v.abs() < 9.2e18 => Some(v as i64),
+                    _ => None,
+                })
+                .collect();
+            rowids.sort_unstable();
+            rowids.dedup();
+            if reverse {
+                rowids.reverse();
+            }
+            let rowids = rowids.into_iter().map(Ok);
+            Ok(find_rows(
+                db,
+                schema.root_page,
+                definition,
+                generated,
+                rowids,
+            ))
+        }
+        Lookup::RowidRange(range) => {
+            let (lower, upper) = match rowid_range(range, left)? {
+                Some(range) => range,
+                None => return Ok(Box::new(std::iter::empty())),
+            };
+            let within = move |row: &Result<(i64, Cow<[u8]>)>| match row {
+                Ok((rowid, _)) => (lower..=upper).contains(rowid),
+                Err(_) => true,
+            };
+            let scan: Box<dyn Iterator<Item = _>> = match reverse {
+                true => {
+                    Box::new(scan_table_back_from(db, schema.root_page, upper)?.take_while(within))
+                }
+                false => Box::new(scan_table_from(db, schema.root_page, lower)?.take_while(within)),
+            };
+            let definition = definition.clone();
+            let generated = generated.clone();
+            let rows = scan.map(move |row| {
+                let (rowid, payload) = row?;
+                table_row(&definition, &generated, rowid, &payload)
+            });
+            Ok(Box::new(rows))
+        }
+        Lookup::Index {
+            name,
+            root_page,
+            keys,
+            probes,
+            range,
+            covering,
+            slots,
+            constants,
+            ..
+        } => {
+            let mut orders: Vec<_> = probes
+                .iter()
+                .map(|probe| (probe.collation, probe.descending))
+                .collect();
+
+            // The entries from the one the scan starts at to the one it stops at, after the
+            // values of the probes, both in the order of the index, which is that of the bounds
+            // unless the column is in descending order
+            let mut spans = vec![(vec![], vec![])];
+            if let Some(range) = range {
+                let bound = |probe: &Option<Probe>| -> Result<Option<Vec<Value>>> {
+                    match probe {
+                        Some(probe) => Ok(probe.value(left)?.map(|value| vec![value])),
+                        None => Ok(Some(vec![])),
+                    }
+                };
+                let (lower, upper) = match (bound(&range.lower)?, bound(&range.upper)?) {
+                    (Some(lower), Some(upper)) => (lower, upper),
+                    _ => return Ok(Box::new(std::iter::empty())),
+                };
+                let probe = range.lower.as_ref().or(range.upper.as_ref()).unwrap();
+                orders.push((probe.collation, probe.descending));
+                let span = |lower, upper| match probe.descending {
+                    true => (upper, lower),
+                    false => (lower, upper),
+                };
+                spans = vec![span(lower, upper)];
+                // Blobs sort after every string
+                if range.blobs {
+                    let blobs = span(vec![Value::Blob(vec![])], vec![]);
+                    match probe.descending {
+                        true => spans.insert(0, blobs),
+                        false => spans.push(blobs),
+                    }
+                }
+            }
+            let mut ranges = vec![];
+            for key in probe_keys(probes, left)? {
+                for (start, stop) in &spans {
+                    ranges.push(([&key[..], start].concat(), [&key[..], stop].concat()));
+                }
+            }
+            if reverse {
+                ranges.reverse();
+            }
+
+            let mut entries: Box<dyn Iterator<Item = _>> = Box::new(std::iter::empty());
+            for (start, stop) in ranges {
+                let scan = index_range(db, *root_page, start, stop, orders.clone(), reverse)?;
+                entries = Box::new(entries.chain(scan));
+            }
+            // The records of a WITHOUT ROWID table's own b-tree have all but the VIRTUAL columns
+            if *covering {
+                let definition = definition.clone();
+                let keys = keys.clone();
+                let slots = slots.clone();
+                let constants = constants.clone();
+                let generated = match name {
+                    Some(_) => Rc::new(vec![]),
+                    None => generated.clone(),
+                };
+                return Ok(Box::new(entries.map(move |entry| {
+                    let mut row = index_row(&definition, &keys, &slots, &entry?)?;
+                    for (column, value) in &constants {
+                        row[*column] = value.clone();
+                    }
+                    generate_columns(&definition, &generated, row)
+                })));
+            }
+            // The entries of a WITHOUT ROWID table's index end with its PRIMARY KEY instead
+            if let Some(primary_key) = &definition.primary_key {
+                let definition = definition.clone();
+                let generated = generated.clone();
+                let keys = keys.clone();
+                let columns: Vec<_> = primary_key.iter().map(|&(column, _, _)| column).collect();
+                let root_page = schema.root_page;
+                let rows = entries.filter_map(move |entry| {
+                    let find = || {
+                        let row = index_row(&definition, &keys, &[], &entry?)?;
+                        let key = columns.iter().map(|&column| row[column].clone()).collect();
+                        find_key_row(db, root_page, &definition, &generated, key)
+                    };
+                    find().transpose()
+                });
+                return Ok(Box::new(rows));
+            }
+            let key_count = keys.len() + 1;
+            let rowids = entries.filter_map(move |entry| match entry {
+                Ok(entry) => entry_rowid(&entry, key_count).transpose(),
+                Err(e) => Some(Err(e)),
+            });
+            Ok(find_rows(
+                db,
+                schema.root_page,
+                definition,
+                generated,
+                rowids,
+            ))
+        }
+        Lookup::Automatic {
+            columns,
+            probes,
+            entries,
+            ..
+        } => {
+            let keys = match probe_keys(probes, left)?.pop() {
+                Some(keys) => keys,
+                None => return Ok(Box::new(std::iter::empty())),
+            };
+            let collations: Vec<_> = probes.iter().map(|probe| probe.collation).collect();
+            let built = entries.borrow().clone();
+            let entries = match built {
+                Some(entries) => entries,
+                None => {
+                    let mut index: HashMap<_, Vec<Row>> = HashMap::new();
+                    for row in scan_table(db, schema.root_page)? {
+                        let (rowid, payload) = row?;
+                        let row = table_row(definition, generated, rowid, &payload)?;
+                        let values: Vec<_> =
+                            columns.iter().map(|&column| row[column].clone()).collect();
+                        // NULL never equals anything
+                        if values.iter().any(|value| value.is_null()) {
+                            continue;
+                        }
+                        let key = key_values(&values, &collations);
+                        index.entry(key).or_default().push(row);
+                    }
+                    let index = Rc::new(index);
+                    *entries.borrow_mut() = Some(index.clone());
+                    index
+                }
+            };
+            let rows = match entries.get(&key_values(&keys, &collations)) {
+                Some(rows) => rows.clone(),
+                None => vec![],
+            };
+            Ok(Box::new(rows.into_iter().map(Ok)))
+        }
+        Lookup::Or { start, lookups } => {
+            let mut rows: Rows<'a> = Box::new(std::iter::empty());
+            for (lookup, condition) in lookups {
+                let found = lookup_rows(db, schema, definition, generated, lookup, false, left)?;
+                let condition = condition.clone();
+                let mut joined = left.to_vec();
+                let start = *start;
+                let found = found.filter_map(move |row| {
+                    let row = match row {
+                        Ok(row) => row,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    for (slot, value) in joined[start..].iter_mut().zip(&row) {
+                        *slot = value.clone();
+                    }
+                    match condition.as_ref().map(|condition| condition.eval(&joined)) {
+                        Some(Ok(value)) if value.truth() != Some(true) => None,
+                        Some(Err(e)) => Some(Err(e)),
+                        _ => Some(Ok(row)),
+                    }
+                });
+                rows = Box::new(rows.chain(found));
+            }
+            // A row may meet more than one of the conditions. Its rowid tells it apart, or the
+            // PRIMARY KEY of a WITHOUT ROWID table.
+            let (columns, collations): (Vec<_>, Vec<_>) = match &definition.primary_key {
+                Some(primary_key) => primary_key
+                    .iter()
+                    .map(|&(column, collation, _)| (column, collation))
+                    .unzip(),
+                None => (vec![definition.columns.len()], vec![Collation::Binary]),
+            };
+            let mut found = HashSet::new();
+            Ok(Box::new(rows.filter(move |row| match row {
+                Ok(row) => {
+                    let key: Vec<_> = columns.iter().map(|&column| row[column].clone()).collect();
+                    found.insert(key_values(&key, &collations))
+                }
+                Err(_) => true,
+            })))
+        }
+    }
+}
+
+/// The keys a lookup searches an index for: every combination of the values of its probes, in
+/// the order of the index. There are none when a probe has no values.
+fn probe_keys(probes: &[Probe], left: &[Value]) -> Result<Vec<Vec<Value>>> {
+    let mut keys = vec![vec![]];
+    for probe in probes {
+        let values = probe.values(left)?;
+        keys = keys
+            .iter()
+            .flat_map(|key: &Vec<Value>| {
+                values.iter().map(move |value| {
+                    let mut key = key.clone();
+                    key.push(value.clone());
+                    key
+                })
+            })
+            .collect();
+    }
+    Ok(keys)
+}
+
+/// The rowids from the lower bound of a range to its upper bound, or None when no rowid is in
+/// it
+fn rowid_range(range: &Bounds, left: &[Value]) -> Result<Option<(i64, i64)>> {
+    let (mut lower, mut upper) = (i64::MIN, i64::MAX);
+    if let Some(probe) = &range.lower {
+        match probe.value(left)? {
+            Some(Value::Integer(value)) => lower = value,
+            Some(Value::Real(value)) => lower = value.floor() as i64,
+            // Strings and blobs are greater than any number
+            _ => return Ok(None),
+        }
+    }
+    if let Some(probe) = &range.upper {
+        match probe.value(left)? {
+            Some(Value::Integer(value)) => upper = value,
+            Some(Value::Real(value)) => upper = value.ceil() as i64,
+            Some(_) => {}
+            None => return Ok(None),
+        }
+    }
+    Ok(Some((lower, upper)))
+}
+
+/// Compares the first values of an index entry to a key, in the order of the index
+fn compare_entry(entry: &[u8], key: &[Value], orders: &[(Collation, bool)]) -> Result<Ordering> {
+    let values = parse_record(entry, key.len())?;
+    for ((value, key), &(collation, descending)) in values.into_iter().zip(key).zip(orders) {
+        let ordering = Value::from(value).compare(key, collation);
+        let ordering = match descending {
+            true => ordering.reverse(),
+            false => ordering,
+        };
+        if ordering != Ordering::Equal {
+            return Ok(ordering);
+        }
+    }
+    Ok(Ordering::Equal)
+}
+
+/// The entries of an index from the first one that doesn't sort before `start` to the last one
+/// that doesn't sort after `stop`, comparing as many values as the keys have, or the other way
+/// round when `reverse`
+fn index_range<'a>(
+    db: &'a Database,
+    root_page: u32,
+    start: Vec<Value>,
+    stop: Vec<Value>,
+    orders: Vec<(Collation, bool)>,
+    reverse: bool,
+) -> Result<impl Iterator<Item = Result<Cow<'a, [u8]>>> + 'a> {
+    let (mut entries, end, past) = match reverse {
+        false => {
+            let entries = scan_index_from(db, root_page, |entry| {
+                Ok(compare_entry(entry, &start, &orders)? == Ordering::Less)
+            })?;
+            (entries, stop, Ordering::Greater)
+        }
+        true => {
+            let entries = scan_index_back_from(db, root_page, |entry| {
+                Ok(compare_entry(entry, &stop, &orders)? == Ordering::Greater)
+            })?;
+            (entries, start, Ordering::Less)
+        }
+    };
+    // Entries are read one at a time, so that stopping early skips the others
+    let mut done = false;
+    Ok(std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let entry = match entries.next()? {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+        match compare_entry(&entry, &end, &orders) {
+            Ok(ordering) if ordering == past => {
+                done = true;
+                None
+            }
+            Ok(_) => Some(Ok(entry)),
+            Err(e) => Some(Err(e)),
+        }
+    }))
+}
+
+/// The rowid an index entry ends with
+fn entry_rowid(entry: &[u8], key_count: usize) -> Result<Option<i64>> {
+    let record = parse_record(entry, key_count)?;
+    Ok(match Value::from(record[key_count - 1]) {
+        Value::Integer(rowid) => Some(rowid),
+        _ => None,
+    })
+}
+
+/// Reads the rows of a table with the given rowids, skipping those that don't exist
+fn find_rows<'a>(
+    db: &'a Database,
+    root_page: u32,
+    definition: &Rc<TableDefinition>,
+    generated: &Rc<Generated<'a>>,
+    rowids: impl Iterator<Item = Result<i64>> + 'a,
+) -> Rows<'a> {
+    let definition = definition.clone();
+    let generated = generated.clone();
+    Box::new(rowids.filter_map(move |rowid| match rowid {
+        Ok(rowid) => find_row(db, root_page, &definition, &generated, rowid).transpose(),
+        Err(e) => Some(Err(e)),
+    }))
+}
+
+fn find_row(
+    db: &Database,
+    root_page: u32,
+    definition: &TableDefinition,
+    generated: &[(usize, BoundExpr)],
+    rowid: i64,
+) -> Result<Option<Row>> {
+    match scan_table_from(db, root_page, rowid)?.next() {
+        Some(row) => {
+            let (found, payload) = row?;
+            if found != rowid {
+                return Ok(None);
+            }
+            Ok(Some(table_row(definition, generated, rowid, &payload)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reads the row of a WITHOUT ROWID table whose PRIMARY KEY columns have the values of `key`
+fn find_key_row(
+    db: &Database,
+    root_page: u32,
+    definition: &TableDefinition,
+    generated: &[(usize, BoundExpr)],
+    key: Vec<Value>,
+) -> Result<Option<Row>> {
+    let orders = match &definition.primary_key {
+        Some(primary_key) => primary_key
+            .iter()
+            .map(|&(_, collation, descending)| (collation, descending))
+            .collect(),
+        None => vec![],
+    };
+    match index_range(db, root_page, key.clone(), key, orders, false)?.next() {
+        Some(record) => Ok(Some(table_row(definition, generated, 0, &record?)?)),
+        None => Ok(None),
+    }
+}
+
+/// Joins each row of `outer` with the rows of `table` that satisfy the join condition
+struct NestedLoop<'a> {
+    outer: Rows<'a>,
+    table: Rc<JoinedTable<'a>>,
+    /// The current outer row, the rows of the table for it, and whether any of them matched
+    current: Option<(Row, Rows<'a>, bool)>,
+}
+
+impl<'a> NestedLoop<'a> {
+    fn advance(&mut self) -> Result<Option<Row>> {
+        loop {
+            if let Some((outer, inner, matched)) = &mut self.current {
+                match inner.next() {
+                    Some(row) => {
+                        let row = row?;
+                        // A table that the row is made of alone fills it
+                        let joined = if row.len() == outer.len() {
+                            row
+                        } else {
+                            let mut joined = outer.clone();
+                            let start = self.table.start;
+                            for (slot, value) in joined[start..].iter_mut().zip(row) {
+                                *slot = value;
+                            }
+                            joined
+                        };
+                        let accepted = match &self.table.condition {
+                            Some(condition) => condition.eval(&joined)?.truth() == Some(true),
+                            None => true,
+                        };
+                        if accepted {
+                            *matched = true;
+                            if self.is_kept(&joined)? {
+                                return Ok(Some(joined));
+                            }
+                        }
+                    }
+                    None => {
+                        // The outer row has NULLs in the place of the table's columns
+                        let (outer, _, matched) = self.current.take().unwrap();
+                        if self.table.outer && !matched && self.is_kept(&outer)? {
+                            return Ok(Some(outer));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let outer = match self.outer.next() {
+                Some(row) => row?,
+                None => return Ok(None),
+            };
+            let inner = self.table.source.rows(&outer)?;
+            self.current = Some((outer, inner, false));
+        }
+    }
+
+    fn is_kept(&self, row: &[Value]) -> Result<bool> {
+        Ok(match &self.table.filter {
+            Some(filter) => filter.eval(row)?.truth() == Some(true),
+            None => true,
+        })
+    }
+}
+
+impl<'a> Iterator for NestedLoop<'a> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.advance().transpose()
+    }
+}
@@ -0,0 +1,1235 @@
+use super::Row;
+use crate::value::Value;
+use anyhow::{bail, Result};
+use std::fmt::Write;
+
+/// Deepest nesting of arrays and objects a document may have
+const MAX_DEPTH: usize = 1000;
+
+/// How a string literal was escaped, which decides how much work it takes to turn it into
+/// canonical JSON or into SQL text
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Escapes {
+    None,
+    /// Only the escapes of [RFC 8259](https://www.rfc-editor.org/rfc/rfc8259)
+    Json,
+    /// JSON5 escapes, control characters or `"` in a single-quoted string
+    Json5,
+}
+
+/// A parsed JSON value. Numbers and strings keep the text they were written with, like
+/// SQLite's [JSONB](https://www.sqlite.org/jsonb.html) does, so that `json_each()` reports the
+/// same element ids.
+#[derive(Debug, Clone)]
+enum Json {
+    Null,
+    True,
+    False,
+    /// Decimal or hexadecimal integer, without a leading `+`
+    Integer(String),
+    /// Floating point number, without a leading `+`. Infinity is held as `9e999`.
+    Real(String),
+    /// The contents of a string literal, between its quotes
+    Text(String, Escapes),
+    Array(Vec<Json>),
+    Object(Vec<(Json, Json)>),
+}
+
+/// A child of an array or object, with its position in the JSONB encoding of the document
+struct Member<'a> {
+    key: Key<'a>,
+    /// Offset of the element, or of its label for object members
+    id: usize,
+    /// Offset of the value
+    offset: usize,
+    value: &'a Json,
+}
+
+enum Key<'a> {
+    Index(usize),
+    Label(&'a Json),
+}
+
+/// The value a path leads to
+struct Found<'a> {
+    node: &'a Json,
+    offset: usize,
+    id: usize,
+}
+
+enum LookupError {
+    NotFound,
+    BadPath,
+}
+
+/// Size of a JSONB element header for a payload of that many bytes
+fn header_size(payload: usize) -> usize {
+    match payload {
+        0..=11 => 1,
+        12..=255 => 2,
+        256..=65535 => 3,
+        _ => 5,
+    }
+}
+
+impl Json {
+    fn payload_size(&self) -> usize {
+        match self {
+            Json::Null | Json::True | Json::False => 0,
+            Json::Integer(text) | Json::Real(text) | Json::Text(text, _) => text.len(),
+            Json::Array(items) => items.iter().map(Json::size).sum(),
+            Json::Object(members) => members.iter().map(|(k, v)| k.size() + v.size()).sum(),
+        }
+    }
+
+    /// Size of the JSONB encoding of the value
+    fn size(&self) -> usize {
+        let payload = self.payload_size();
+        header_size(payload) + payload
+    }
+
+    /// The children of an array or object at `offset`
+    fn members(&self, offset: usize) -> Vec<Member<'_>> {
+        let mut position = offset + header_size(self.payload_size());
+        let mut members = vec![];
+        match self {
+            Json::Array(items) => {
+                for (index, value) in items.iter().enumerate() {
+                    members.push(Member {
+                        key: Key::Index(index),
+                        id: position,
+                        offset: position,
+                        value,
+                    });
+                    position += value.size();
+                }
+            }
+            Json::Object(items) => {
+                for (label, value) in items {
+                    members.push(Member {
+                        key: Key::Label(label),
+                        id: position,
+                        offset: position + label.size(),
+                        value,
+                    });
+                    position += label.size() + value.size();
+                }
+            }
+            _ => {}
+        }
+        members
+    }
+
+    fn is_container(&self) -> bool {
+        matches!(self, Json::Array(_) | Json::Object(_))
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::True => "true",
+            Json::False => "false",
+            Json::Integer(_) => "integer",
+            Json::Real(_) => "real",
+            Json::Text(..) => "text",
+            Json::Array(_) => "array",
+            Json::Object(_) => "object",
+        }
+    }
+
+    /// Minified, canonical JSON text, with JSON5 extensions translated
+    fn render(&self, out: &mut String) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::True => out.push_str("true"),
+            Json::False => out.push_str("false"),
+            Json::Integer(text) if is_hex(text) => {
+                if text.starts_with('-') {
+                    out.push('-');
+                }
+                match hex_value(text) {
+                    Some(value) => write!(out, "{}", value).unwrap(),
+                    None => out.push_str("9.0e999"),
+                }
+            }
+            Json::Integer(text) => out.push_str(text),
+            Json::Real(text) => out.push_str(&canonical_real(text)),
+            Json::Text(text, Escapes::Json5) => {
+                out.push('"');
+                render_json5_text(text, out);
+                out.push('"');
+            }
+            Json::Text(text, _) => {
+                out.push('"');
+                out.push_str(text);
+                out.push('"');
+            }
+            Json::Array(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    item.render(out);
+                }
+                out.push(']');
+            }
+            Json::Object(members) => {
+                out.push('{');
+                for (i, (label, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    label.render(out);
+                    out.push(':');
+                    value.render(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut out = String::new();
+        self.render(&mut out);
+        out
+    }
+
+    /// The SQL value of a JSON value: arrays and objects become JSON text
+    fn to_value(&self) -> Result<Value> {
+        Ok(match self {
+            Json::Null => Value::Null,
+            Json::True => Value::Integer(1),
+            Json::False => Value::Integer(0),
+            Json::Integer(text) => {
+                let (negative, digits) = match text.strip_prefix('-') {
+                    Some(digits) => (true, digits),
+                    None => (false, text.as_str()),
+                };
+                let hex = is_hex(text);
+                let magnitude = if hex {
+                    match hex_value(text) {
+                        Some(value) => Some(value),
+                        None => bail!("malformed JSON"),
+                    }
+                } else {
+                    digits.parse::<u64>().ok()
+                };
+                match magnitude {
+                    Some(m) if m <= i64::MAX as u64 => {
+                        Value::Integer(if negative { -(m as i64) } else { m as i64 })
+                    }
+                    Some(m) if negative && m == 1 << 63 && !hex => Value::Integer(i64::MIN),
+                    Some(m) => Value::Real(if negative { -(m as f64) } else { m as f64 }),
+                    None => Value::Real(text.parse::<f64>().unwrap_or(0.0)),
+                }
+            }
+            Json::Real(text) => {
+                let value = canonical_real(text).parse::<f64>().unwrap_or(0.0);
+                // Negative zero shows as 0.0
+                Value::Real(if value == 0.0 { 0.0 } else { value })
+            }
+            Json::Text(text, Escapes::None) => Value::Text(text.clone()),
+            Json::Text(text, _) => Value::Text(unescape(text)),
+            Json::Array(_) | Json::Object(_) => Value::Text(self.to_json()),
+        })
+    }
+
+    /// The text of a label or string
+    fn text(&self) -> String {
+        match self {
+            Json::Text(text, Escapes::None) => text.clone(),
+            Json::Text(text, _) => unescape(text),
+            _ => String::new(),
+        }
+    }
+
+    /// Follows a path, the part after the leading `$`, like SQLite's `jsonLookupStep()`
+    fn lookup<'a>(
+        &'a self,
+        offset: usize,
+        id: usize,
+        path: &str,
+    ) -> std::result::Result<Found<'a>, LookupError> {
+        if path.is_empty() {
+            return Ok(Found {
+                node: self,
+                offset,
+                id,
+            });
+        }
+        let bytes = path.as_bytes();
+        if let Some(rest) = path.strip_prefix('.') {
+            let (label, rest) = if let Some(quoted) = rest.strip_prefix('"') {
+                let quoted_bytes = quoted.as_bytes();
+                let mut i = 0;
+                while i < quoted_bytes.len() && quoted_bytes[i] != b'"' {
+                    if quoted_bytes[i] == b'\\' && i + 1 < quoted_bytes.len() {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if i >= quoted_bytes.len() {
+                    return Err(LookupError::BadPath);
+                }
+                (unescape(&quoted[..i]), &quoted[i + 1..])
+            } else {
+                let end = rest.find(['.', '[']).unwrap_or(rest.len());
+                if end == 0 {
+                    return Err(LookupError::BadPath);
+                }
+                (rest[..end].to_string(), &rest[end..])
+            };
+            if !matches!(self, Json::Object(_)) {
+                return Err(LookupError::NotFound);
+            }
+            for member in self.members(offset) {
+                if let Key::Label(key) = member.key {
+                    if key.text() == label {
+                        return member.value.lookup(member.offset, member.id, rest);
+                    }
+                }
+            }
+            Err(LookupError::NotFound)
+        } else if bytes[0] == b'[' {
+            let items = match self {
+                Json::Array(items) => items,
+                _ => return Err(LookupError::NotFound),
+            };
+            let digit = |i: usize| matches!(bytes.get(i), Some(c) if c.is_ascii_digit());
+            let mut i = 1;
+            let mut index = 0usize;
+            while digit(i) {
+                index = index
+                    .saturating_mul(10)
+                    .saturating_add((bytes[i] - b'0') as usize);
+                i += 1;
+            }
+            if i < 2 || bytes.get(i) != Some(&b']') {
+                if bytes.get(1) != Some(&b'#') {
+                    return Err(LookupError::BadPath);
+                }
+                index = items.len();
+                i = 2;
+                if bytes.get(2) == Some(&b'-') && digit(3) {
+                    let mut back = 0usize;
+                    i = 3;
+                    while digit(i) {
+                        back = back
+                            .saturating_mul(10)
+                            .saturating_add((bytes[i] - b'0') as usize);
+                        i += 1;
+                    }
+                    if back > index {
+                        return Err(LookupError::NotFound);
+                    }
+                    index -= back;
+                }
+                if bytes.get(i) != Some(&b']') {
+                    return Err(LookupError::BadPath);
+                }
+            }
+            match self.members(offset).into_iter().nth(index) {
+                Some(member) => member
+                    .value
+                    .lookup(member.offset, member.id, &path[i + 1..]),
+                None => Err(LookupError::NotFound),
+            }
+        } else {
+            Err(LookupError::BadPath)
+        }
+    }
+}
+
+fn is_hex(text: &str) -> bool {
+    matches!(
+        text.strip_prefix('-').unwrap_or(text).as_bytes(),
+        [b'0', b'x', ..] | [b'0', b'X', ..]
+    )
+}
+
+/// The magnitude of a hexadecimal integer literal, or None when it doesn't fit in 64 bits
+fn hex_value(text: &str) -> Option<u64> {
+    let digits = &text.strip_prefix('-').unwrap_or(text)[2..];
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Adds the zeros JSON5 lets a number leave out around its decimal point, as in `.5` or `5.`
+fn canonical_real(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    let digits = match text.strip_prefix('-') {
+        Some(digits) => {
+            out.push('-');
+            digits
+        }
+        None => text,
+    };
+    if digits.starts_with('.') {
+        out.push('0');
+    }
+    let bytes = digits.as_bytes();
+    for (i, &byte) in bytes.iter().enumerate() {
+        out.push(byte as char);
+        if byte == b'.' && !matches!(bytes.get(i + 1), Some(c) if c.is_ascii_digit()) {
+            out.push('0');
+        }
+    }
+    out
+}
+
+fn render_control_character(c: char, out: &mut String) {
+    match c {
+        '\u{8}' => out.push_str("\\b"),
+        '\t' => out.push_str("\\t"),
+        '\n' => out.push_str("\\n"),
+        '\u{c}' => out.push_str("\\f"),
+        '\r' => out.push_str("\\r"),
+        c => write!(out, "\\u{:04x}", c as u32).unwrap(),
+    }
+}
+
+/// Translates the JSON5 escapes and raw characters of a string into RFC 8259 ones
+fn render_json5_text(text: &str, out: &mut String) {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => match chars.next() {
+                Some('\'') => out.push('\''),
+                Some('v') => out.push_str("\\u000b"),
+                Some('0') => out.push_str("\\u0000"),
+                Some('x') => {
+                    out.push_str("\\u00");
+                    out.extend(chars.by_ref().take(2));
+                }
+                Some('\r') => {
+                    chars.next_if_eq(&'\n');
+                }
+                Some('\n') | Some('\u{2028}') | Some('\u{2029}') => {}
+                Some(c) => {
+                    out.push('\\');
+                    out.push(c);
+                }
+                None => out.push('\\'),
+            },
+            c if (c as u32) < 0x20 => render_control_character(c, out),
+            c => out.push(c),
+        }
+    }
+}
+
+fn hex_digits(chars: &mut std::iter::Peekable<std::str::Chars>, count: usize) -> Option<u32> {
+    let mut value = 0;
+    for _ in 0..count {
+        value = value * 16 + chars.peek()?.to_digit(16)?;
+        chars.next();
+    }
+    Some(value)
+}
+
+/// The text of a string literal with its escapes resolved
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('u') => {
+                let mut code = hex_digits(&mut chars, 4).unwrap_or(0);
+                if (0xd800..0xdc00).contains(&code) {
+                    let mut lookahead = chars.clone();
+                    if lookahead.next() == Some('\\') && lookahead.next() == Some('u') {
+                        if let Some(low) = hex_digits(&mut lookahead, 4) {
+                            if (0xdc00..0xe000).contains(&low) {
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                                chars = lookahead;
+                            }
+                        }
+                    }
+                }
+                out.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+            }
+            Some('x') => {
+                let code = hex_digits(&mut chars, 2).unwrap_or(0);
+                out.push(std::char::from_u32(code).unwrap_or('\u{fffd}'));
+            }
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('t') => out.push('\t'),
+            Some('v') => out.push('\u{b}'),
+            Some('0') => out.push('\0'),
+            Some('\r') => {
+                chars.next_if_eq(&'\n');
+            }
+            Some('\n') | Some('\u{2028}') | Some('\u{2029}') => {}
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// A parser for JSON text, accepting the [JSON5](https://spec.json5.org) extensions that SQLite
+/// does
+struct Parser<'a> {
+    text: &'a [u8],
+    position: usize,
+    depth: usize,
+    /// Whether any JSON5 extension was used
+    json5: bool,
+}
+
+impl<'a> Parser<'a> {
+    fn at(&self, position: usize) -> u8 {
+        self.text.get(position).copied().unwrap_or(0)
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        if self.at(self.position) == byte {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn starts_with_ignore_case(&self, position: usize, word: &str) -> bool {
+        self.text
+            .get(position..position + word.len())
+            .map(|text| text.eq_ignore_ascii_case(word.as_bytes()))
+            .unwrap_or(false)
+    }
+
+    /// Length of the JSON5 whitespace or comment at `position`, which RFC 8259 doesn't allow
+    fn json5_whitespace(&self, position: usize) -> usize {
+        let text = &self.text[position.min(self.text.len())..];
+        match text {
+            [b'\x0b', ..] | [b'\x0c', ..] => 1,
+            [b'/', b'*', rest @ ..] => match rest.windows(2).position(|w| w == b"*/") {
+                Some(end) => end + 4,
+                None => 0,
+            },
+            [b'/', b'/', rest @ ..] => {
+                let mut end = 0;
+                while end < rest.len()
+                    && rest[end] != b'\n'
+                    && rest[end] != b'\r'
+                    && !rest[end..].starts_with("\u{2028}".as_bytes())
+                    && !rest[end..].starts_with("\u{2029}".as_bytes())
+                {
+                    end += 1;
+                }
+                end + 2
+            }
+            [0xc2, 0xa0, ..] => 2,
+            [0xe1, 0x9a, 0x80, ..]
+            | [0xe2, 0x81, 0x9f, ..]
+            | [0xe3, 0x80, 0x80, ..]
+            | [0xef, 0xbb, 0xbf, ..] => 3,
+            [0xe2, 0x80, c, ..] if (0x80..=0x8a).contains(c) || matches!(c, 0xa8 | 0xa9 | 0xaf) => {
+                3
+            }
+            _ => 0,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        loop {
+            match self.at(self.position) {
+                b' ' | b'\t' | b'\n' | b'\r' => self.position += 1,
+                _ => match self.json5_whitespace(self.position) {
+                    0 => return,
+                    n => {
+                        self.position += n;
+                        self.json5 = true;
+                    }
+                },
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        let start = self.position;
+        let word = |word: &str| {
+            self.text[start..].starts_with(word.as_bytes())
+                && !self.at(start + word.len()).is_ascii_alphanumeric()
+        };
+        let literal = if word("null") {
+            Some((Json::Null, 4))
+        } else if word("true") {
+            Some((Json::True, 4))
+        } else if word("false") {
+            Some((Json::False, 5))
+        } else {
+            None
+        };
+        if let Some((value, length)) = literal {
+            self.position += length;
+            return Some(value);
+        }
+        match self.at(start) {
+            b'{' => self.object(),
+            b'[' => self.array(),
+            b'"' | b'\'' => self.string(),
+            b'-' | b'+' | b'.' | b'0'..=b'9' => self.number(),
+            _ => {
+                const NAMES: [(&str, bool); 5] = [
+                    ("inf", true),
+                    ("infinity", true),
+                    ("nan", false),
+                    ("qnan", false),
+                    ("snan", false),
+                ];
+                for &(name, infinity) in NAMES.iter() {
+                    if self.starts_with_ignore_case(start, name)
+                        && !self.at(start + name.len()).is_ascii_alphanumeric()
+                    {
+                        self.position += name.len();
+                        self.json5 = true;
+                        return Some(if infinity {
+                            Json::Real("9e999".to_string())
+                        } else {
+                            Json::Null
+                        });
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    fn enter(&mut self) -> Option<()> {
+        self.position += 1;
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            None
+        } else {
+            Some(())
+        }
+    }
+
+    fn array(&mut self) -> Option<Json> {
+        self.enter()?;
+        let mut items = vec![];
+        self.skip_whitespace();
+        if !self.eat(b']') {
+            loop {
+                items.push(self.value()?);
+                self.skip_whitespace();
+                if self.eat(b']') {
+                    break;
+                }
+                if !self.eat(b',') {
+                    return None;
+                }
+                self.skip_whitespace();
+                if self.eat(b']') {
+                    self.json5 = true;
+                    break;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(Json::Array(items))
+    }
+
+    fn object(&mut self) -> Option<Json> {
+        self.enter()?;
+        let mut members = vec![];
+        self.skip_whitespace();
+        if !self.eat(b'}') {
+            loop {
+                let label = self.label()?;
+                self.skip_whitespace();
+                if !self.eat(b':') {
+                    return None;
+                }
+                members.push((label, self.value()?));
+                self.skip_whitespace();
+                if self.eat(b'}') {
+                    break;
+                }
+                if !self.eat(b',') {
+                    return None;
+                }
+                self.skip_whitespace();
+                if self.eat(b'}') {
+                    self.json5 = true;
+                    break;
+                }
+            }
+        }
+        self.depth -= 1;
+        Some(Json::Object(members))
+    }
+
+    /// An object label: a string, or a JSON5 identifier
+    fn label(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        let start = self.position;
+        let c = self.at(start);
+        if c == b'"' || c == b'\'' {
+            return self.string();
+        }
+        let identifier = |c: u8| c.is_ascii_alphabetic() || c == b'_' || c == b'$' || c >= 0x80;
+        if !identifier(c) || self.json5_whitespace(start) > 0 {
+            return None;
+        }
+        let mut end = start + 1;
+        while (identifier(self.at(end)) || self.at(end).is_ascii_digit())
+            && self.json5_whitespace(end) == 0
+        {
+            end += 1;
+        }
+        self.position = end;
+        self.json5 = true;
+        let text = std::str::from_utf8(&self.text[start..end]).ok()?;
+        Some(Json::Text(text.to_string(), Escapes::None))
+    }
+
+    fn string(&mut self) -> Option<Json> {
+        let quote = self.at(self.position);
+        if quote == b'\'' {
+            self.json5 = true;
+        }
+        let start = self.position + 1;
+        let hex = |from: usize, count: usize| {
+            (from..from + count).all(|i| self.at(i).is_ascii_hexdigit())
+        };
+        let mut escapes = Escapes::None;
+        let mut end = start;
+        loop {
+            let c = *self.text.get(end)?;
+            if c == quote {
+                break;
+            }
+            match c {
+                b'\\' => {
+                    end += 1;
+                    let c = self.at(end);
+                    if matches!(c, b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't')
+                        || c == b'u' && hex(end + 1, 4)
+                    {
+                        if escapes == Escapes::None {
+                            escapes = Escapes::Json;
+                        }
+                    } else if matches!(c, b'\'' | b'0' | b'v' | b'\n')
+                        || c == 0xe2
+                            && self.at(end + 1) == 0x80
+                            && matches!(self.at(end + 2), 0xa8 | 0xa9)
+                        || c == b'x' && hex(end + 1, 2)
+                    {
+                        escapes = Escapes::Json5;
+                    } else if c == b'\r' {
+                        if self.at(end + 1) == b'\n' {
+                            end += 1;
+                        }
+                        escapes = Escapes::Json5;
+                    } else {
+                        return None;
+                    }
+                }
+                0 => return None,
+                c if c < 0x20 => escapes = Escapes::Json5,
+                b'"' => escapes = Escapes::Json5,
+                _ => {}
+            }
+            end += 1;
+        }
+        if escapes == Escapes::Json5 {
+            self.json5 = true;
+        }
+        self.position = end + 1;
+        let text = std::str::from_utf8(&self.text[start..end]).ok()?;
+        Some(Json::Text(text.to_string(), escapes))
+    }
+
+    /// A number, following the rules of SQLite's `jsonTranslateTextToBlob()`
+    fn number(&mut self) -> Option<Json> {
+        let start = self.position;
+        let first = self.at(start);
+        let signed = first == b'-' || first == b'+';
+        if first == b'+' {
+            self.json5 = true;
+        }
+        let mut real = false;
+        let mut hex = false;
+        let mut end = start + 1;
+        if first == b'.' {
+            if !self.at(start + 1).is_ascii_digit() {
+                return None;
+            }
+            self.json5 = true;
+            real = true;
+        } else if signed && !self.at(start + 1).is_ascii_digit() {
+            if self.starts_with_ignore_case(start + 1, "inf") {
+                self.json5 = true;
+                let length = if self.starts_with_ignore_case(start + 4, "inity") {
+                    9
+                } else {
+                    4
+                };
+                self.position = start + length;
+                let text = if first == b'-' { "-9e999" } else { "9e999" };
+                return Some(Json::Real(text.to_string()));
+            }
+            if self.at(start + 1) != b'.' {
+                return None;
+            }
+            self.json5 = true;
+        } else {
+            let zero = if signed { start + 1 } else { start };
+            if self.at(zero) == b'0' {
+                if matches!(self.at(zero + 1), b'x' | b'X') && self.at(zero + 2).is_ascii_hexdigit()
+                {
+                    self.json5 = true;
+                    hex = true;
+                    end = zero + 3;
+                    while self.at(end).is_ascii_hexdigit() {
+                        end += 1;
+                    }
+                } else if self.at(zero + 1).is_ascii_digit() {
+                    return None;
+                }
+            }
+        }
+        if !hex {
+            let mut exponent = false;
+            loop {
+                let c = self.at(end);
+                if c.is_ascii_digit() {
+                    end += 1;
+                } else if c == b'.' {
+                    if real {
+                        return None;
+                    }
+                    real = true;
+                    end += 1;
+                } else if c == b'e' || c == b'E' {
+                    if self.at(end - 1) < b'0' {
+                        if self.at(end - 1) == b'.'
+                            && end >= start + 2
+                            && self.at(end - 2).is_ascii_digit()
+                        {
+                            self.json5 = true;
+                        } else {
+                            return None;
+                        }
+                    }
+                    if exponent {
+                        return None;
+                    }
+                    real = true;
+                    exponent = true;
+                    if matches!(self.at(end + 1), b'+' | b'-') {
+                        end += 1;
+                    }
+                    if !self.at(end + 1).is_ascii_digit() {
+                        return None;
+                    }
+                    end += 1;
+                } else {
+                    break;
+                }
+            }
+            if self.at(end - 1) < b'0' {
+                if self.at(end - 1) == b'.' && end >= start + 2 && self.at(end - 2).is_ascii_digit()
+                {
+                    self.json5 = true;
+                } else {
+                    return None;
+                }
+            }
+        }
+        self.position = end;
+        let from = if first == b'+' { start + 1 } else { start };
+        let text = std::str::from_utf8(&self.text[from..end]).ok()?.to_string();
+        Some(if real {
+            Json::Real(text)
+        } else {
+            Json::Integer(text)
+        })
+    }
+}
+
+/// Parses a JSON document, returning the value and whether it used JSON5 extensions
+fn parse(text: &str) -> Option<(Json, bool)> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        position: 0,
+        depth: 0,
+        json5: false,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position < parser.text.len() {
+        return None;
+    }
+    Some((value, parser.json5))
+}
+
+/// Parses a function argument, which is None for NULL
+fn parse_arg(arg: &Value) -> Result<Option<Json>> {
+    let text = match arg.to_text() {
+        Some(text) => text,
+        None => return Ok(None),
+    };
+    match parse(&text) {
+        Some((json, _)) => Ok(Some(json)),
+        None => bail!("malformed JSON"),
+    }
+}
+
+fn bad_path(path: &str) -> anyhow::Error {
+    anyhow::anyhow!("bad JSON path: '{}'", path.replace('\'', "''"))
+}
+
+/// Follows a path from the root of a document. Paths that lead nowhere give None.
+fn lookup<'a>(json: &'a Json, path: &str) -> Result<Option<Found<'a>>> {
+    let rest = match path.strip_prefix('$') {
+        Some(rest) => rest,
+        None => return Err(bad_path(path)),
+    };
+    match json.lookup(0, 0, rest) {
+        Ok(found) => Ok(Some(found)),
+        Err(LookupError::NotFound) => Ok(None),
+        Err(LookupError::BadPath) => Err(bad_path(path)),
+    }
+}
+
+/// `json(X)`
+pub fn json(args: &[Value]) -> Result<Value> {
+    Ok(match parse_arg(&args[0])? {
+        Some(json) => Value::Text(json.to_json()),
+        None => Value::Null,
+    })
+}
+
+/// `json_extract(X, P1, P2, ...)`. A single path gives the SQL value it leads to, several give
+/// a JSON array of the values.
+pub fn extract(args: &[Value]) -> Result<Value> {
+    if args.len() < 2 {
+        return Ok(Value::Null);
+    }
+    let json = match parse_arg(&args[0])? {
+        Some(json) => json,
+        None => return Ok(Value::Null),
+    };
+    let mut results = vec![];
+    for path in &args[1..] {
+        let path = match path.to_text() {
+            Some(path) => path,
+            None => return Ok(Value::Null),
+        };
+        results.push(lookup(&json, &path)?.map(|found| found.node));
+    }
+    if let [result] = results.as_slice() {
+        return match result {
+            Some(node) => node.to_value(),
+            None => Ok(Value::Null),
+        };
+    }
+    let mut out = String::from("[");
+    for (i, result) in results.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        match result {
+            Some(node) => node.render(&mut out),
+            None => out.push_str("null"),
+        }
+    }
+    out.push(']');
+    Ok(Value::Text(out))
+}
+
+/// `X -> P` (as JSON text) and `X ->> P` (as an SQL value). Besides full paths, P may be an
+/// array index or an object label.
+pub fn arrow(json: &Value, path: &Value, as_json: bool) -> Result<Value> {
+    let json = match parse_arg(json)? {
+        Some(json) => json,
+        None => return Ok(Value::Null),
+    };
+    let text = match path.to_text() {
+        Some(text) => text,
+        None => return Ok(Value::Null),
+    };
+    // The rest of the path, after the `$`
+    let steps = match path {
+        Value::Integer(i) if *i < 0 => format!("[#{}]", i),
+        Value::Integer(i) => format!("[{}]", i),
+        _ => {
+            if let Some(steps) = text.strip_prefix('$') {
+                steps.to_string()
+            } else if text.starts_with('[') && text.len() >= 3 && text.ends_with(']') {
+                text.clone()
+            } else if text.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'_') {
+                format!(".{}", text)
+            } else {
+                format!(".\"{}\"", text)
+            }
+        }
+    };
+    Ok(match json.lookup(0, 0, &steps) {
+        Ok(found) if as_json => Value::Text(found.node.to_json()),
+        Ok(found) => found.node.to_value()?,
+        Err(LookupError::NotFound) => Value::Null,
+        Err(LookupError::BadPath) => return Err(bad_path(&text)),
+    })
+}
+
+/// Finds the value at the optional path argument, for the functions that take one
+fn lookup_arg<'a>(json: &'a Json, path: Option<&Value>) -> Result<Option<&'a Json>> {
+    match path {
+        None => Ok(Some(json)),
+        Some(path) => match path.to_text() {
+            Some(path) => Ok(lookup(json, &path)?.map(|found| found.node)),
+            None => Ok(None),
+        },
+    }
+}
+
+/// `json_type(X, P)`
+pub fn json_type(args: &[Value]) -> Result<Value> {
+    let json = match parse_arg(&args[0])? {
+        Some(json) => json,
+        None => return Ok(Value::Null),
+    };
+    Ok(match lookup_arg(&json, args.get(1))? {
+        Some(node) => Value::Text(node.type_name().to_string()),
+        None => Value::Null,
+    })
+}
+
+/// `json_array_length(X, P)`
+pub fn array_length(args: &[Value]) -> Result<Value> {
+    let json = match parse_arg(&args[0])? {
+        Some(json) => json,
+        None => return Ok(Value::Null),
+    };
+    Ok(match lookup_arg(&json, args.get(1))? {
+        Some(Json::Array(items)) => Value::Integer(items.len() as i64),
+        Some(_) => Value::Integer(0),
+        None => Value::Null,
+    })
+}
+
+/// `json_valid(X, FLAGS)`. Flag 1 accepts RFC 8259 JSON and flag 2 JSON5; the JSONB flags 4
+/// and 8 don't apply, since blobs are read as text.
+pub fn valid(args: &[Value]) -> Result<Value> {
+    let flags = match args.get(1) {
+        Some(flags) => match flags.to_integer() {
+            Some(flags @ 1..=15) => flags,
+            _ => bail!("FLAGS parameter to json_valid() must be between 1 and 15"),
+        },
+        None => 1,
+    };
+    let text = match args[0].to_text() {
+        Some(text) => text,
+        None => return Ok(Value::Null),
+    };
+    let valid = match parse(&text) {
+        Some((_, json5)) => flags & 2 != 0 || flags & 1 != 0 && !json5,
+        None => false,
+    };
+    Ok(Value::Integer(valid as i64))
+}
+
+/// Rows of the `json_each(X, P)` and `json_tree(X, P)` table-valued functions: the children of
+/// the value at P, or with `recursive` that value and all of its descendants. The columns are
+/// those of [`EACH_COLUMNS`].
+pub fn each(args: &[Value], recursive: bool) -> Result<Vec<Row>> {
+    let text = match args.first().and_then(Value::to_text) {
+        Some(text) => text,
+        None => return Ok(vec![]),
+    };
+    let root_path = match args.get(1) {
+        Some(path) => match path.to_text() {
+            Some(path) => path,
+            None => return Ok(vec![]),
+        },
+        None => "$".to_string(),
+    };
+    let json = match parse(&text) {
+        Some((json, _)) => json,
+        None => bail!("malformed JSON"),
+    };
+    let root = match lookup(&json, &root_path)? {
+        Some(root) => root,
+        None => return Ok(vec![]),
+    };
+    let mut each = Each {
+        json: Value::Text(text.clone()),
+        root: Value::Text(root_path.clone()),
+        rows: vec![],
+    };
+    if recursive {
+        let (key, path) = tree_root(&json, &root_path, root.id);
+        each.push(key, root.node, root.id, None, &root_path, &path)?;
+        each.descend(root.node, root.offset, root.id, &root_path)?;
+    } else if root.node.is_container() {
+        for member in root.node.members(root.offset) {
+            let fullkey = format!("{}{}", root_path, member.path_step());
+            each.push(
+                member.key_value(),
+                member.value,
+                member.id,
+                None,
+                &fullkey,
+                &root_path,
+            )?;
+        }
+    } else {
+        each.push(
+            Value::Null,
+            root.node,
+            root.id,
+            None,
+            &root_path,
+            &root_path,
+        )?;
+    }
+    Ok(each.rows)
+}
+
+/// Columns of `json_each()` and `json_tree()`, and whether they are hidden
+pub const EACH_COLUMNS: [(&str, bool); 10] = [
+    ("key", false),
+    ("value", false),
+    ("type", false),
+    ("atom", false),
+    ("id", false),
+    ("parent", false),
+    ("fullkey", false),
+    ("path", false),
+    ("json", true),
+    ("root", true),
+];
+
+impl<'a> Member<'a> {
+    fn key_value(&self) -> Value {
+        match self.key {
+            Key::Index(index) => Value::Integer(index as i64),
+            Key::Label(label) => Value::Text(label.text()),
+        }
+    }
+
+    /// The step that leads from the container to this member in a path
+    fn path_step(&self) -> String {
+        match self.key {
+            Key::Index(index) => format!("[{}]", index),
+            Key::Label(Json::Text(label, _)) => {
+                let plain = matches!(label.bytes().next(), Some(c) if c.is_ascii_alphabetic())
+                    && label.bytes().all(|c| c.is_ascii_alphanumeric());
+                if plain {
+                    format!(".{}", label)
+                } else {
+                    format!(".\"{}\"", label)
+                }
+            }
+            Key::Label(_) => String::new(),
+        }
+    }
+}
+
+struct Each {
+    json: Value,
+    root: Value,
+    rows: Vec<Row>,
+}
+
+impl Each {
+    fn push(
+        &mut self,
+        key: Value,
+        node: &Json,
+        id: usize,
+        parent: Option<usize>,
+        fullkey: &str,
+        path: &str,
+    ) -> Result<()> {
+        let value = node.to_value()?;
+        let atom = if node.is_container() {
+            Value::Null
+        } else {
+            value.clone()
+        };
+        self.rows.push(vec![
+            key,
+            value,
+            Value::Text(node.type_name().to_string()),
+            atom,
+            Value::Integer(id as i64),
+            parent.map_or(Value::Null, |parent| Value::Integer(parent as i64)),
+            Value::Text(fullkey.to_string()),
+            Value::Text(path.to_string()),
+            self.json.clone(),
+            self.root.clone(),
+        ]);
+        Ok(())
+    }
+
+    fn descend(&mut self, node: &Json, offset: usize, id: usize, fullkey: &str) -> Result<()> {
+        for member in node.members(offset) {
+            let child_fullkey = format!("{}{}", fullkey, member.path_step());
+            self.push(
+                member.key_value(),
+                member.value,
+                member.id,
+                Some(id),
+                &child_fullkey,
+                fullkey,
+            )?;
+            self.descend(member.value, member.offset, member.id, &child_fullkey)?;
+        }
+        Ok(())
+    }
+}
+
+/// The key and path of the first row of `json_tree()`, taken from the root path: the path is
+/// the longest prefix that leads to the container holding the root, and the key is the rest.
+fn tree_root(json: &Json, root_path: &str, id: usize) -> (Value, String) {
+    let bytes = root_path.as_bytes();
+    let mut n = bytes.len();
+    if n <= 1 {
+        return (Value::Null, root_path.to_string());
+    }
+    while n > 1 {
+        n -= 1;
+        if bytes[n] == b'[' || bytes[n] == b'.' {
+            let holds_root = match lookup(json, &root_path[..n]) {
+                Ok(Some(found)) => found
+                    .node
+                    .members(found.offset)
+                    .first()
+                    .map(|member| member.id == id)
+                    .unwrap_or(false),
+                _ => false,
+            };
+            if holds_root {
+                break;
+            }
+        }
+    }
+    let step = &root_path[n..];
+    let key = if let Some(index) = step.strip_prefix('[') {
+        let digits: String = index
+            .chars()
+            .enumerate()
+            .take_while(|&(i, c)| c.is_ascii_digit() || i == 0 && c == '-')
+            .map(|(_, c)| c)
+            .collect();
+        Value::Integer(digits.parse().unwrap_or(0))
+    } else if let Some(quoted) = step.strip_prefix(".\"") {
+        Value::Text(quoted.strip_suffix('"').unwrap_or(quoted).to_string())
+    } else {
+        Value::Text(step.get(1..).unwrap_or_default().to_string())
+    };
+    (key, root_path[..n].to_string())
+}
@@ -0,0 +1,750 @@
+mod aggregate;
+mod compound;
+mod cte;
+mod datetime;
+mod explain;
+pub mod expr;
+mod functions;
+mod join;
+mod json;
+mod planner;
+mod printf;
+mod sort;
+mod subquery;
+mod window;
+
+use crate::ast::{Expr, Limit, ResultColumn, Select};
+use crate::database::Database;
+use crate::record::parse_record;
+use crate::schema::TableDefinition;
+use crate::value::{Affinity, Collation, Value};
+use aggregate::{key_values, Aggregate, KeyValue};
+use anyhow::{bail, Result};
+use compound::Compound;
+use expr::{eval_integer, Binder, BoundExpr, Scope, ScopeColumn};
+use join::Tables;
+use sort::{sort_rows, SortOrder};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::rc::Rc;
+use window::Windows;
+
+pub use explain::{explain, render_tree};
+
+pub type Row = Vec<Value>;
+pub type Rows<'a> = Box<dyn Iterator<Item = Result<Row>> + 'a>;
+
+/// Names of the output columns, and the rows a query produces. Rows are produced lazily, so
+/// stopping early avoids reading the rest of the table.
+pub struct QueryResult<'a> {
+    pub columns: Vec<String>,
+    pub rows: Rows<'a>,
+}
+
+/// Runs a SELECT statement
+pub fn select<'a>(db: &'a Database, select: &Select) -> Result<QueryResult<'a>> {
+    let plan = Plan::prepare(db, select, &Scope::default())?;
+    let columns = plan.columns.clone();
+    let rows = Rc::new(plan).run(&[])?;
+    Ok(QueryResult { columns, rows })
+}
+
+/// Names of the columns a SELECT statement produces, without running it
+pub fn columns(db: &Database, select: &Select) -> Result<Vec<String>> {
+    Ok(Plan::prepare(db, select, &Scope::default())?.columns)
+}
+
+/// Columns of a table, followed by its rowid unless it's a WITHOUT ROWID table. `name` is the
+/// table's name or alias.
+fn table_scope(definition: &TableDefinition, name: &str) -> Scope {
+    let mut columns: Vec<_> = definition
+        .columns
+        .iter()
+        .map(|column| ScopeColumn {
+            table: Some(name.to_string()),
+            name: column.name.clone(),
+            affinity: column.affinity,
+            collation: column.collation,
+            hidden: false,
+            using: false,
+            depth: 0,
+        })
+        .collect();
+
+    if definition.primary_key.is_none() {
+        columns.push(ScopeColumn {
+            table: Some(name.to_string()),
+            name: "rowid".to_string(),
+            affinity: Affinity::Integer,
+            collation: Collation::Binary,
+            hidden: true,
+            using: false,
+            depth: 0,
+        });
+    }
+
+    Scope::new(columns)
+}
+
+/// Decodes the record of a table row, laid out like `table_scope`. The rowid of a WITHOUT
+/// ROWID table's row is ignored. Columns that were added after the row was stored have their
+/// default value, and VIRTUAL generated columns are computed with `generated`.
+fn table_row(
+    definition: &TableDefinition,
+    generated: &[(usize, BoundExpr)],
+    rowid: i64,
+    payload: &[u8],
+) -> Result<Row> {
+    let record = parse_record(payload, definition.columns.len())?;
+
+    let mut row = vec![Value::Null; definition.columns.len()];
+    for (value, &column) in record.iter().zip(&definition.stored_columns) {
+        row[column] = match (Value::from(*value), definition.columns[column].affinity) {
+            // REAL values without a fractional part may be stored as integers
+            (Value::Integer(v), Affinity::Real) => Value::Real(v as f64),
+            (value, _) => value,
+        };
+    }
+    for &column in &definition.stored_columns[record.len()..] {
+        row[column] = definition.columns[column].default.clone();
+    }
+
+    if definition.primary_key.is_none() {
+        if let Some(alias) = definition.rowid_alias {
+            row[alias] = Value::Integer(rowid);
+        }
+        row.push(Value::Integer(rowid));
+    }
+
+    generate_columns(definition, generated, row)
+}
+
+/// Computes the VIRTUAL generated columns of a table row, with their expressions bound against
+/// the row in an order where each only refers to the generated columns before it
+fn generate_columns(
+    definition: &TableDefinition,
+    generated: &[(usize, BoundExpr)],
+    mut row: Row,
+) -> Result<Row> {
+    for (column, expr) in generated {
+        row[*column] = expr
+            .eval(&row)?
+            .apply_affinity(definition.columns[*column].affinity);
+    }
+    Ok(row)
+}
+
+/// Makes a table row out of the entry of an index that has every column the query uses, the
+/// index columns being at `columns` in the row. The values of the index's expressions that
+/// `slots` pairs with columns the query doesn't use go in those, as they are. The other
+/// columns are left NULL. The entries of a WITHOUT ROWID table's indexes have no rowid, and
+/// the records of its own b-tree may miss columns that were added later.
+fn index_row(
+    definition: &TableDefinition,
+    columns: &[usize],
+    slots: &[(usize, usize)],
+    entry: &[u8],
+) -> Result<Row> {
+    let has_rowid = definition.primary_key.is_none();
+    let record = parse_record(entry, columns.len() + has_rowid as usize)?;
+    let mut row = vec![Value::Null; definition.columns.len() + has_rowid as usize];
+    // The values of expressions an index has instead of columns are only in the row by `slots`
+    for (i, &column) in columns.iter().enumerate() {
+        if column >= definition.columns.len() {
+            continue;
+        }
+        row[column] = match record.get(i) {
+            Some(value) => match (Value::from(*value), definition.columns[column].affinity) {
+                (Value::Integer(v), Affinity::Real) => Value::Real(v as f64),
+                (value, _) => value,
+            },
+            None => definition.columns[column].default.clone(),
+        };
+    }
+    for &(i, column) in slots {
+        row[column] = Value::from(record[i]);
+    }
+    if has_rowid {
+        let rowid = Value::from(record[columns.len()]);
+        if let Some(alias) = definition.rowid_alias {
+            row[alias] = rowid.clone();
+        }
+        row[definition.columns.len()] = rowid;
+    }
+    Ok(row)
+}
+
+/// A SELECT statement bound against the database. A plan can be run several times, like a
+/// correlated subquery is for every row of the query it is nested in.
+pub struct Plan<'a> {
+    /// Names of the result columns
+    pub columns: Vec<String>,
+    /// Affinity of the result columns, and the collating sequence of those that have one of
+    /// their own, which subqueries pass on to the expressions using them
+    pub column_types: Vec<(Option<Affinity>, Option<Collation>)>,
+    /// Number of values at the start of each row that come from the row of the enclosing query
+    pub outer_width: usize,
+    /// How many queries out the outermost column the statement refers to comes from. Zero
+    /// when it only refers to its own tables.
+    pub outer_depth: usize,
+    /// The result columns, with `*` expanded, which ORDER BY terms of a compound SELECT are
+    /// matched against
+    pub result_columns: Vec<ResultColumn>,
+    tables: Option<Tables<'a>>,
+    filter: Option<BoundExpr<'a>>,
+    aggregate: Option<Aggregate<'a>>,
+    having: Option<BoundExpr<'a>>,
+    windows: Option<Windows<'a>>,
+    projection: Vec<BoundExpr<'a>>,
+    /// Whether rows equal to an earlier one are dropped, as with SELECT DISTINCT
+    distinct: bool,
+    /// ORDER BY terms
+    keys: Vec<BoundExpr<'a>>,
+    orders: Vec<SortOrder>,
+    /// Whether the tables are read in the order of the ORDER BY terms, which then need no
+    /// sorting
+    sorted: bool,
+    limit: Option<usize>,
+    offset: usize,
+    /// The SELECTs of a compound SELECT, whose rows the ORDER BY terms and the result columns
+    /// are evaluated against instead
+    compound: Option<Compound<'a>>,
+}
+
+impl<'a> Plan<'a> {
+    /// Binds a SELECT statement. `outer` is the scope of the query the statement is nested in,
+    /// which is empty for a top-level statement.
+    pub fn prepare(db: &'a Database, select: &Select, outer: &Scope) -> Result<Self> {
+        let with_scope;
+        let outer = match &select.with {
+            Some(with) => {
+                with_scope = cte::define(with, outer)?;
+                &with_scope
+            }
+            None => outer,
+        };
+        if !select.compound.is_empty() {
+            return compound::prepare(db, select, outer);
+        }
+
+        let (scope, mut tables) = match &select.from {
+            Some(from) => {
+                let (scope, tables) = join::prepare(db, from, outer)?;
+                (scope, Some(tables))
+            }
+            None => (outer.nested(), None),
+        };
+
+        // Result columns may call aggregate functions, which turn the query into an aggregate
+        // query whose rows are the groups. HAVING and ORDER BY can then call them too.
+        let aggregates = RefCell::new(vec![]);
+        let binder = Binder::with_aggregates(db, &scope, &aggregates);
+        let plain = Binder::new(db, &scope);
+        // Window functions can be called from the result columns and ORDER BY
+        let window_calls = RefCell::new(vec![]);
+        let window_binder = Binder::with_aggregates(db, &scope, &aggregates)
+            .with_windows(&window_calls, &select.windows);
+
+        let expanded = expand_result_columns(&select.columns, &scope, tables.is_some())?;
+        let mut columns = vec![];
+        let mut column_types = vec![];
+        let mut projection = vec![];
+        for (result_column, position) in &expanded {
+            match (result_column, position) {
+                (ResultColumn::Expr { .. }, Some(i)) => {
+                    let column = &scope.columns[*i];
+                    columns.push(column.name.clone());
+                    projection.push(BoundExpr::Column(*i));
+                    let affinity = Some(column.affinity).filter(|a| *a != Affinity::Blob);
+                    column_types.push((affinity, Some(column.collation)));
+                }
+                (ResultColumn::Expr { expr, alias, text }, None) => {
+                    // Columns are named after their alias, the column they refer to, or the
+                    // text of their expression
+                    let name = match (alias, expr) {
+                        (Some(alias), _) => alias.clone(),
+                        (None, Expr::Column { table, name }) => {
+                            match scope.resolve(table.as_deref(), name) {
+                                Ok(i) => scope.columns[i].name.clone(),
+                                Err(_) => text.clone(),
+                            }
+                        }
+                        (None, _) => text.clone(),
+                    };
+                    columns.push(name);
+                    projection.push(window_binder.bind(expr)?);
+                    let collation = match plain.collation(expr) {
+                        (collation, true) => Some(collation),
+                        (collation, false) if plain.has_collation(expr) => Some(collation),
+                        _ => None,
+                    };
+                    column_types.push((plain.affinity(expr), collation));
+                }
+                (ResultColumn::Star, _) | (ResultColumn::TableStar(_), _) => unreachable!(),
+            }
+        }
+        let result_columns: Vec<_> = expanded.into_iter().map(|(column, _)| column).collect();
+
+        let mut filter = match &select.where_clause {
+            Some(expr) => Some(plain.bind(expr)?),
+            None => None,
+        };
+
+        let mut group_by = vec![];
+        let mut group_collations = vec![];
+        let group_aggregates = RefCell::new(vec![]);
+        let group_binder = Binder::with_aggregates(db, &scope, &group_aggregates);
+        for (i, term) in select.group_by.iter().enumerate() {
+            let expr = match resolve_ordering_term(term, &result_columns, i, "GROUP")? {
+                // Column names take precedence over aliases in GROUP BY
+                Some(_) if matches!(term, Expr::Column { name, .. } if scope.resolve(None, name).is_ok()) => {
+                    term
+                }
+                Some(i) => match &result_columns[i] {
+                    ResultColumn::Expr { expr, .. } => expr,
+                    _ => term,
+                },
+                None => term,
+            };
+            group_by.push(group_binder.bind(expr)?);
+            group_collations.push(plain.collation(expr).0);
+        }
+        if !group_aggregates.borrow().is_empty() {
+            bail!("aggregate functions are not allowed in the GROUP BY clause");
+        }
+
+        let is_aggregate = !aggregates.borrow().is_empty() || !group_by.is_empty();
+        if select.having.is_some() && !is_aggregate {
+            bail!("HAVING clause on a non-aggregate query");
+        }
+        // Only the result columns and GROUP BY make a query an aggregate query
+        let binder = if is_aggregate { &binder } else { &plain };
+
+        let mut having = match &select.having {
+            Some(expr) => Some(binder.bind(expr)?),
+            None => None,
+        };
+
+        let order_binder = match is_aggregate {
+            true => Binder::with_aggregates(db, &scope, &aggregates),
+            false => Binder::new(db, &scope),
+        }
+        .with_windows(&window_calls, &select.windows);
+        let mut orders = vec![];
+        let mut keys = vec![];
+        for term in &select.order_by {
+            let (expr, collation) =
+                match resolve_ordering_term(&term.expr, &result_columns, keys.len(), "ORDER")? {
+                    Some(i) => (
+                        projection[i].clone(),
+                        result_collation(binder, &result_columns[i]),
+                    ),
+                    None => (
+                        order_binder.bind(&term.expr)?,
+                        binder.collation(&term.expr).0,
+                    ),
+                };
+            orders.push(SortOrder::new(term.descending, term.nulls_first, collation));
+            keys.push(expr);
+        }
+
+        // The values of window calls follow the values of aggregate calls in the row
+        let width = scope.columns.len() + aggregates.borrow().len();
+        for expr in projection.iter_mut().chain(&mut keys) {
+            expr.place_windows(width);
+        }
+        let window_calls = window_calls.into_inner();
+        let mut windows = match window_calls.is_empty() {
+            true => None,
+            false => Some(Windows {
+                calls: window_calls,
+                width,
+            }),
+        };
+
+        // Groups are sorted in the order ORDER BY asks for when it starts with the GROUP BY
+        // terms, in whichever direction, and else in ascending order
+        let mut group_orders: Vec<_> = group_collations
+            .iter()
+            .map(|collation| SortOrder::new(false, None, *collation))
+            .collect();
+        let ordered_groups = is_aggregate
+            && windows.is_none()
+            && keys.len() <= group_by.len()
+            && keys.iter().zip(&orders).enumerate().all(|(i, (key, order))| {
+                matches!((key, &group_by[i]), (BoundExpr::Column(a), BoundExpr::Column(b)) if a == b)
+                    && order.collation == group_collations[i]
+            });
+        if ordered_groups {
+            group_orders[..orders.len()].clone_from_slice(&orders);
+        }
+
+        // The planner chooses how to read the tables, knowing which rows the WHERE clause keeps
+        // and the order rows are needed in: that of GROUP BY for aggregate queries, and else
+        // that of ORDER BY. Window functions sort the rows themselves.
+        let mut sorted = ordered_groups;
+        let mut grouped = false;
+        if let Some(tables) = &mut tables {
+            let filter = filter.take();
+            // The columns these read are those an index must have to be read instead of its
+            // table. Like SQLite, the result columns and ORDER BY terms of aggregate and window
+            // queries read the columns of the expressions an index has, rather than the index.
+            let computed = is_aggregate || windows.is_some();
+            let calls = aggregates.borrow();
+            let call_exprs = calls
+                .iter()
+                .flat_map(|call| call.args.iter().chain(&call.filter));
+            let window_exprs = windows
+                .iter()
+                .flat_map(|windows| &windows.calls)
+                .flat_map(|call| call.exprs());
+            let outputs = projection.iter().chain(&keys);
+            let others = group_by
+                .iter()
+                .chain(&having)
+                .chain(call_exprs)
+                .chain(window_exprs);
+            let (exprs, unindexed): (Vec<&BoundExpr>, Vec<&BoundExpr>) = match computed {
+                true => (others.collect(), outputs.collect()),
+                false => (outputs.chain(others).collect(), vec![]),
+            };
+            if windows.is_some() || (is_aggregate && group_by.is_empty()) {
+                tables.plan(filter, &[], &[], false, &exprs, &unindexed)?;
+            } else if is_aggregate {
+                // Unless ORDER BY needs them in some order, groups can come in any
+                let grouping = keys.is_empty() || !ordered_groups;
+                grouped = tables.plan(
+                    filter,
+                    &group_by,
+                    &group_orders,
+                    grouping,
+                    &exprs,
+                    &unindexed,
+                )?;
+            } else {
+                sorted = tables.plan(filter, &keys, &orders, false, &exprs, &unindexed)?;
+            }
+            drop(calls);
+
+            // The values of the expressions an index has are read from it when it's read
+            // instead of its table
+            let mut calls = aggregates.borrow_mut();
+            let call_exprs = calls
+                .iter_mut()
+                .flat_map(|call| call.args.iter_mut().chain(&mut call.filter));
+            let window_exprs = windows
+                .iter_mut()
+                .flat_map(|windows| &mut windows.calls)
+                .flat_map(|call| call.exprs_mut());
+            let outputs = projection.iter_mut().chain(&mut keys).filter(|_| !computed);
+            for expr in outputs
+                .chain(&mut group_by)
+                .chain(&mut having)
+                .chain(call_exprs)
+                .chain(window_exprs)
+            {
+                tables.substitute(expr);
+            }
+        }
+
+        let aggregate = if is_aggregate {
+            Some(Aggregate {
+                grouped,
+                group_by,
+                collations: group_collations,
+                orders: group_orders,
+                calls: aggregates.into_inner(),
+                width: scope.columns.len(),
+            })
+        } else {
+            None
+        };
+
+        let (limit, offset) = prepare_limit(db, select.limit.as_ref())?;
+
+        Ok(Plan {
+            columns,
+            column_types,
+            outer_width: outer.columns.len(),
+            outer_depth: scope.outer_depth(),
+            result_columns,
+            tables,
+            filter,
+            aggregate,
+            having,
+            windows,
+            projection,
+            distinct: select.distinct,
+            keys,
+            orders,
+            sorted,
+            limit,
+            offset,
+            compound: None,
+        })
+    }
+
+    /// Applies the WHERE clause, GROUP BY, HAVING, window functions, ORDER BY, LIMIT and OFFSET
+    /// to the rows of the FROM clause and evaluates the result columns. `outer` is the row of
+    /// the enclosing query.
+    pub fn run(self: Rc<Self>, outer: &[Value]) -> Result<Rows<'a>> {
+        if self.limit == Some(0) {
+            return Ok(Box::new(std::iter::empty()));
+        }
+
+        let rows = match (&self.compound, &self.tables) {
+            (Some(compound), _) => compound.rows(outer)?,
+            (None, Some(tables)) => tables.rows(outer)?,
+            (None, None) => Box::new(std::iter::once(Ok(outer.to_vec()))),
+        };
+        let mut rows = filter_rows(rows, self.clone(), |plan| plan.filter.as_ref());
+        if let Some(aggregate) = &self.aggregate {
+            rows = filter_rows(aggregate.run(rows)?, self.clone(), |plan| {
+                plan.having.as_ref()
+            });
+        }
+        if let Some(windows) = &self.windows {
+            rows = windows.run(rows)?;
+        }
+
+        let (limit, offset) = (self.limit, self.offset);
+        let mut distinct = match self.distinct {
+            true => Some(Distinct::new(&self.column_types)),
+            false => None,
+        };
+        if self.keys.is_empty() || self.sorted {
+            let rows = rows
+                .map(move |row| project(&self.projection, &row?))
+                .filter(move |row| match (&mut distinct, row) {
+                    (Some(distinct), Ok(row)) => distinct.is_new(row),
+                    _ => true,
+                })
+                .skip(offset);
+            return Ok(match limit {
+                Some(limit) => Box::new(rows.take(limit)),
+                None => Box::new(rows),
+            });
+        }
+
+        let rows = rows
+            .map(|row| {
+                let row = row?;
+                let keys = project(&self.keys, &row)?;
+                Ok((keys, project(&self.projection, &row)?))
+            })
+            .filter(move |row| match (&mut distinct, row) {
+                (Some(distinct), Ok((_, row))) => distinct.is_new(row),
+                _ => true,
+            });
+        let sorted = sort_rows(
+            rows,
+            self.orders.clone(),
+            limit.map(|limit| limit.saturating_add(offset)),
+        )?;
+
+        Ok(Box::new(sorted.into_iter().skip(offset).map(Ok)))
+    }
+}
+
+/// Remembers the rows seen so far, compared with the collating sequences of their columns
+struct Distinct {
+    seen: HashSet<Vec<KeyValue>>,
+    collations: Vec<Collation>,
+}
+
+impl Distinct {
+    fn new(column_types: &[(Option<Affinity>, Option<Collation>)]) -> Self {
+        Distinct {
+            seen: HashSet::new(),
+            collations: column_types
+                .iter()
+                .map(|(_, collation)| collation.unwrap_or(Collation::Binary))
+                .collect(),
+        }
+    }
+
+    fn is_new(&mut self, row: &[Value]) -> bool {
+        self.seen.insert(key_values(row, &self.collations))
+    }
+}
+
+/// The result columns with `*` and `table.*` replaced by the columns they stand for, along with
+/// the positions of those columns in the row. `*` leaves out the columns of the right table of
+/// a USING or NATURAL join that the left table has too.
+fn expand_result_columns(
+    columns: &[ResultColumn],
+    scope: &Scope,
+    has_tables: bool,
+) -> Result<Vec<(ResultColumn, Option<usize>)>> {
+    let mut expanded = vec![];
+    for column in columns {
+        let table = match column {
+            ResultColumn::Expr { .. } => {
+                expanded.push((column.clone(), None));
+                continue;
+            }
+            _ if !has_tables => bail!("no tables specified"),
+            ResultColumn::Star => None,
+            ResultColumn::TableStar(table) => Some(table),
+        };
+
+        let start = expanded.len();
+        for (i, column) in scope.columns.iter().enumerate() {
+            let included = match table {
+                None => !column.using,
+                Some(table) => {
+                    matches!(&column.table, Some(name) if name.eq_ignore_ascii_case(table))
+                }
+            };
+            if column.depth == 0 && !column.hidden && included {
+                let star_column = ResultColumn::Expr {
+                    expr: Expr::Column {
+                        table: column.table.clone(),
+                        name: column.name.clone(),
+                    },
+                    alias: None,
+                    text: column.name.clone(),
+                };
+                expanded.push((star_column, Some(i)));
+            }
+        }
+        if let (Some(table), true) = (table, expanded.len() == start) {
+            bail!("no such table: {}", table);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Evaluates LIMIT and OFFSET, which can't refer to any column
+fn prepare_limit(db: &Database, limit: Option<&Limit>) -> Result<(Option<usize>, usize)> {
+    let limit = match limit {
+        Some(limit) => limit,
+        None => return Ok((None, 0)),
+    };
+    let empty_scope = Scope::default();
+    let constant = Binder::new(db, &empty_scope);
+    let offset = match &limit.offset {
+        Some(offset) => eval_integer(&constant.bind(offset)?)?.max(0) as usize,
+        None => 0,
+    };
+    let limit = eval_integer(&constant.bind(&limit.limit)?)?;
+    // A negative limit means there is no limit
+    Ok((usize::try_from(limit).ok(), offset))
+}
+
+/// Keeps the rows for which a condition of the plan is true
+fn filter_rows<'a>(
+    rows: Rows<'a>,
+    plan: Rc<Plan<'a>>,
+    condition: for<'p> fn(&'p Plan<'a>) -> Option<&'p BoundExpr<'a>>,
+) -> Rows<'a> {
+    if condition(&plan).is_none() {
+        return rows;
+    }
+    Box::new(rows.filter_map(move |row| {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        match condition(&plan).unwrap().eval(&row) {
+            Ok(value) if value.truth() == Some(true) => Some(Ok(row)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }))
+}
+
+fn project(exprs: &[BoundExpr], row: &[Value]) -> Result<Row> {
+    exprs.iter().map(|expr| expr.eval(row)).collect()
+}
+
+/// ORDER BY and GROUP BY terms can refer to result columns by number, or by alias
+fn resolve_ordering_term(
+    expr: &Expr,
+    columns: &[ResultColumn],
+    term: usize,
+    clause: &str,
+) -> Result<Option<usize>> {
+    match expr {
+        Expr::Literal(Value::Integer(n)) => {
+            if *n < 1 || *n as usize > columns.len() {
+                bail!(
+                    "{} {} BY term out of range - should be between 1 and {}",
+                    ordinal(term + 1),
+                    clause,
+                    columns.len()
+                );
+            }
+            Ok(Some(*n as usize - 1))
+        }
+        Expr::Column { table: None, name } => Ok(columns.iter().position(|column| {
+            matches!(column, ResultColumn::Expr { alias: Some(alias), .. } if alias.eq_ignore_ascii_case(name))
+        })),
+        _ => Ok(None),
+    }
+}
+
+/// ORDER BY terms of a compound SELECT refer to result columns by number, by the alias or name
+/// of a column of any of the SELECTs, or by repeating the column's expression. A term may add
+/// a COLLATE clause, which is returned along with the column.
+fn resolve_compound_ordering_term(
+    expr: &Expr,
+    selects: &[&[ResultColumn]],
+    term: usize,
+) -> Result<(usize, Option<Collation>)> {
+    if let Expr::Collate(expr, name) = expr {
+        let collation = match Collation::from_name(name) {
+            Some(collation) => collation,
+            None => bail!("no such collation sequence: {}", name),
+        };
+        let (column, _) = resolve_compound_ordering_term(expr, selects, term)?;
+        return Ok((column, Some(collation)));
+    }
+
+    if let Some(i) = resolve_ordering_term(expr, selects[0], term, "ORDER")? {
+        return Ok((i, None));
+    }
+    for columns in selects {
+        let position = columns.iter().position(|column| match (column, expr) {
+            (
+                ResultColumn::Expr {
+                    expr: Expr::Column { name, .. },
+                    alias: None,
+                    ..
+                },
+                Expr::Column { table: None, name: term_name },
+            ) => name.eq_ignore_ascii_case(term_name),
+            (ResultColumn::Expr { expr: column_expr, alias, .. }, _) => {
+                column_expr == expr
+                    || matches!((alias, expr), (Some(alias), Expr::Column { table: None, name }) if alias.eq_ignore_ascii_case(name))
+            }
+            _ => false,
+        });
+        if let Some(i) = position {
+            return Ok((i, None));
+        }
+    }
+    bail!(
+        "{} ORDER BY term does not match any column in the result set",
+        ordinal(term + 1)
+    )
+}
+
+fn result_collation(binder: &Binder, column: &ResultColumn) -> Collation {
+    match column {
+        ResultColumn::Expr { expr, .. } => binder.collation(expr).0,
+        _ => Collation::Binary,
+    }
+}
+
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{}{}", n, suffix)
+}
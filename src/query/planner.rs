@@ -0,0 +1,1622 @@
+//! The planner, which chooses the order tables are joined in and how each of them is read, from
+//! what it gathers about their indexes and the terms of the WHERE and ON clauses. It compares
+//! estimates of how many rows the ways of reading a table produce and what they cost. The
+//! figures follow those of [SQLite's query planner](https://www.sqlite.org/queryplanner-ng.html),
+//! so that both pick the same plans. Costs are in units of rows visited.
+
+use super::expr::{Binder, BoundExpr};
+use super::join::{conjunct_refs, split_conjuncts, Derived, JoinedTable, Operand, Source};
+use super::sort::SortOrder;
+use super::table_scope;
+use crate::ast::{BinaryOperator, Expr};
+use crate::database::Database;
+use crate::schema::{Schema, TableDefinition};
+use crate::statistics::IndexStatistics;
+use crate::value::{Affinity, Collation, Value};
+use anyhow::Result;
+use std::cell::RefCell;
+use std::ops::Range;
+
+/// Rows a table is assumed to have, as SQLite does when it wasn't analyzed
+pub const DEFAULT_ROWS: f64 = 1048576.0;
+
+/// Rows assumed to share the values of the first 1, 2, ... columns of an index, and 5 for
+/// each column after those
+const DEFAULT_INDEX_ROWS: [f64; 5] = [10.0, 9.0, 8.0, 7.0, 6.0];
+
+/// Rows a table-valued function is assumed to produce
+pub const FUNCTION_ROWS: f64 = 25.0;
+
+/// Rows an automatic index is assumed to find for each lookup
+const AUTOMATIC_INDEX_ROWS: f64 = 20.0;
+
+/// The estimates for one way of reading a table
+#[derive(Debug, Copy, Clone)]
+pub struct Estimate {
+    /// Rows produced each time the table is read
+    pub rows: f64,
+    /// Cost of reading the table once
+    pub cost: f64,
+    /// Cost paid once before the table is first read
+    pub setup: f64,
+}
+
+/// Estimated number of rows of a table
+pub fn table_rows(db: &Database, schema: &Schema) -> f64 {
+    db.statistics
+        .table_rows(&schema.name)
+        .unwrap_or(DEFAULT_ROWS)
+}
+
+/// Estimated number of rows whose first `columns` index columns have the same values, from the
+/// statistics of the index when it was analyzed. `first` is the value the first column is
+/// compared to when it is a constant, which samples of the index may have.
+pub fn index_rows(
+    statistics: Option<&IndexStatistics>,
+    columns: usize,
+    total_columns: usize,
+    unique: bool,
+    first: Option<(&Value, Collation)>,
+) -> f64 {
+    if unique && columns == total_columns {
+        return 1.0;
+    }
+    if let Some(statistics) = statistics {
+        if let (1, Some((value, collation))) = (columns, first) {
+            if let Some(rows) = statistics.rows_equal(value, collation) {
+                return rows;
+            }
+        }
+        if let Some(&rows) = statistics.rows.get(columns) {
+            return rows.max(1.0);
+        }
+    }
+    DEFAULT_INDEX_ROWS.get(columns - 1).copied().unwrap_or(5.0)
+}
+
+fn log(rows: f64) -> f64 {
+    rows.max(2.0).log2()
+}
+
+/// Reading every row of a table
+pub fn scan(rows: f64) -> Estimate {
+    Estimate {
+        rows,
+        cost: rows * 3.0,
+        setup: 0.0,
+    }
+}
+
+/// Finding the rows of `count` rowids
+pub fn rowid_lookup(table_rows: f64, count: f64) -> Estimate {
+    Estimate {
+        rows: count,
+        cost: count * (log(table_rows) + 1.0),
+        setup: 0.0,
+    }
+}
+
+/// Reading the `rows` rows whose rowid is within a range
+pub fn rowid_range(table_rows: f64, rows: f64) -> Estimate {
+    Estimate {
+        rows,
+        cost: log(table_rows) + rows * 3.0,
+        setup: 0.0,
+    }
+}
+
+/// Narrows down `rows` for the lower and upper `bounds` of a range. One of them is assumed to
+/// keep a quarter of the rows, and both a sixty-fourth.
+pub fn range_rows(rows: f64, bounds: usize) -> f64 {
+    let narrowed = match bounds {
+        1 => rows / 4.0,
+        _ => rows / 64.0,
+    };
+    narrowed.max(2.0).min(rows * 0.93f64.powi(bounds as i32))
+}
+
+/// Finding `rows` of the `index_rows` entries of an index with `seeks` searches, and then the
+/// row of each of them in the table unless the index is `covering`, having every column needed
+pub fn index_lookup(index_rows: f64, seeks: f64, rows: f64, covering: bool) -> Estimate {
+    Estimate {
+        rows,
+        cost: seeks * log(index_rows) + rows * 1.1 + table_lookups(rows, covering),
+        setup: 0.0,
+    }
+}
+
+/// Finding the rows of each of the conditions joined with OR in its own way, and dropping those
+/// found more than once
+pub fn or_lookups(table_rows: f64, estimates: &[Estimate]) -> Estimate {
+    let rows: f64 = estimates.iter().map(|estimate| estimate.rows).sum();
+    Estimate {
+        rows: rows.min(table_rows),
+        cost: estimates.iter().map(|estimate| estimate.cost).sum::<f64>() + rows,
+        setup: estimates.iter().map(|estimate| estimate.setup).sum(),
+    }
+}
+
+/// Reading every one of the `index_rows` entries of an index in order, and the row of each of
+/// them in the table unless the index is `covering`
+pub fn index_scan(index_rows: f64, covering: bool) -> Estimate {
+    Estimate {
+        rows: index_rows,
+        cost: index_rows * 1.1 + table_lookups(index_rows, covering),
+        setup: 0.0,
+    }
+}
+
+fn table_lookups(rows: f64, covering: bool) -> f64 {
+    match covering {
+        true => 0.0,
+        false => rows * 3.0,
+    }
+}
+
+/// Building an index on the rows of a table, and then looking rows up in it
+pub fn automatic_index(table_rows: f64) -> Estimate {
+    Estimate {
+        rows: AUTOMATIC_INDEX_ROWS,
+        cost: log(table_rows) + AUTOMATIC_INDEX_ROWS,
+        setup: table_rows * log(table_rows) * 7.0,
+    }
+}
+
+/// Sorting rows for ORDER BY
+pub fn sort(rows: f64) -> f64 {
+    rows * 3.0 * log(rows)
+}
+
+/// Narrows down the rows of a table for the terms of the WHERE clause it is checked against,
+/// besides those used to find its rows. Equalities leave at most a quarter of the table's rows.
+pub fn filtered_rows(rows: f64, table_rows: f64, terms: usize, equalities: usize) -> f64 {
+    let rows = rows * 0.93f64.powi(terms as i32);
+    if equalities > 0 {
+        rows.min(table_rows / 4.0)
+    } else {
+        rows
+    }
+}
+
+/// Finds the order of `count` tables with the lowest cost. `required[i]` has a bit set for each
+/// table that table `i` must come after. `cost` returns the cost of an order, or None when it
+/// isn't worth considering. Among orders that cost the same, the first one found is kept, so
+/// that tables stay in the order they're written in when nothing tells them apart.
+pub fn best_order(
+    count: usize,
+    required: &[u64],
+    mut cost: impl FnMut(&[usize]) -> Option<f64>,
+) -> Vec<usize> {
+    let written: Vec<usize> = (0..count).collect();
+    // Trying every order is only affordable for a few tables
+    if count > 6 {
+        return written;
+    }
+
+    let mut best: Option<(f64, Vec<usize>)> = None;
+    let mut order = vec![];
+    permute(count, required, 0, &mut order, &mut |order| {
+        if let Some(cost) = cost(order) {
+            if !matches!(&best, Some((best, _)) if cost >= best * (1.0 - 1e-9)) {
+                best = Some((cost, order.to_vec()));
+            }
+        }
+    });
+    best.map(|(_, order)| order).unwrap_or(written)
+}
+
+/// Calls `found` for each order of the tables that puts every table after those it requires,
+/// in lexicographic order
+fn permute(
+    count: usize,
+    required: &[u64],
+    joined: u64,
+    order: &mut Vec<usize>,
+    found: &mut dyn FnMut(&[usize]),
+) {
+    if order.len() == count {
+        found(order);
+        return;
+    }
+    for i in 0..count {
+        if joined & (1 << i) == 0 && required[i] & !joined == 0 {
+            order.push(i);
+            permute(count, required, joined | (1 << i), order, found);
+            order.pop();
+        }
+    }
+}
+
+/// A `column = expression` term where the expression doesn't refer to the column's table, so
+/// that it can be evaluated before reading that table, or a term that the column equals one of
+/// several values for
+pub struct Equality<'a> {
+    /// Position of the column in the table's row
+    pub column: usize,
+    pub probe: Operand<'a>,
+    /// Affinities the comparison applies to the column and to the probe
+    pub column_affinity: Option<Affinity>,
+    pub probe_affinity: Option<Affinity>,
+    pub collation: Collation,
+    /// The tables the probe refers to, one bit each
+    pub needs: u64,
+}
+
+/// A comparison such as `column < expression` that bounds the values of a column, where the
+/// expression doesn't refer to the column's table. `LIKE 'prefix%'` gives two of them.
+pub struct Bound<'a> {
+    /// Position of the column in the table's row
+    pub column: usize,
+    pub probe: BoundExpr<'a>,
+    pub column_affinity: Option<Affinity>,
+    pub probe_affinity: Option<Affinity>,
+    pub collation: Collation,
+    pub needs: u64,
+    /// Whether the values are at least the probe, rather than at most
+    pub lower: bool,
+    /// Whether the bound comes from a LIKE or GLOB pattern, which is still to be checked
+    pub pattern: bool,
+}
+
+/// The bounds a range lookup uses, by position in `TableInfo::bounds`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BoundTerms {
+    pub lower: Option<usize>,
+    pub upper: Option<usize>,
+}
+
+impl BoundTerms {
+    pub fn count(&self) -> usize {
+        self.lower.is_some() as usize + self.upper.is_some() as usize
+    }
+
+    /// Number of terms of the WHERE clause the bounds stand for
+    fn terms(&self, bounds: &[Bound]) -> usize {
+        [self.lower, self.upper]
+            .iter()
+            .flatten()
+            .filter(|&&b| !bounds[b].pattern)
+            .count()
+    }
+}
+
+/// An index the query may use, which covers all of the rows it wants when it's a partial one.
+/// The b-tree of a WITHOUT ROWID table is one too.
+pub struct IndexInfo<'s> {
+    pub schema: &'s Schema,
+    /// Whether the index is the b-tree of a WITHOUT ROWID table, keyed by its PRIMARY KEY
+    pub primary: bool,
+    /// Positions of the indexed columns in the table's row, or past its end for the expressions
+    /// in `TableInfo::expressions`, their collating sequences and whether they're in descending
+    /// order
+    pub columns: Vec<(usize, Collation, bool)>,
+    pub unique: bool,
+    pub statistics: Option<&'s IndexStatistics>,
+    /// Estimated number of entries
+    pub rows: f64,
+    /// Whether the index only has the rows its WHERE clause holds for
+    pub partial: bool,
+    /// The columns that the WHERE clause of a partial index equals to a constant, which all of
+    /// its rows have, with the affinity of the column applied
+    pub constants: Vec<(usize, Value)>,
+    /// Whether the index has every column of the table the query uses, so that the table
+    /// needn't be read
+    pub covering: bool,
+    /// When the index is covering, the expressions it has that the query uses, by position in
+    /// `TableInfo::expressions`, and the columns of the table's row that the query doesn't use
+    /// that their values are read into
+    pub slots: Vec<(usize, usize)>,
+}
+
+/// How the planner chose to read a table
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    /// Reading every row, or whatever rows a source other than a table has
+    Scan,
+    /// Finding a row by its rowid, from an equality
+    Rowid(usize),
+    /// Reading the rows whose rowid is within bounds
+    RowidRange(BoundTerms),
+    /// Finding the rows from equalities on the first columns of an index and bounds on the
+    /// next one, or reading every entry of the index in order without them
+    Index {
+        index: usize,
+        equalities: Vec<usize>,
+        bounds: BoundTerms,
+    },
+    /// Building an index on the columns of the equalities
+    Automatic(Vec<usize>),
+    /// Finding the rows for each of the conditions that a term joins with OR, by position in
+    /// `TableInfo::ors`, each in its own way
+    Or(usize, Vec<Access>),
+}
+
+/// What the planner knows about a table
+pub struct TableInfo<'s, 'a> {
+    /// Estimated number of rows
+    pub rows: f64,
+    /// Number of values in the table's row
+    pub width: usize,
+    pub equalities: Vec<Equality<'a>>,
+    pub bounds: Vec<Bound<'a>>,
+    pub indexes: Vec<IndexInfo<'s>>,
+    /// The expressions that indexes have instead of columns, which equalities and bounds refer
+    /// to by their position after the table's row
+    pub expressions: Vec<BoundExpr<'s>>,
+    /// Columns that some index starts with, even one the planner can't use, which SQLite
+    /// doesn't build automatic indexes on
+    pub indexed: Vec<usize>,
+    /// The tables that must be joined before this one, one bit each
+    pub required: u64,
+    /// Position of the rowid in the row, and of the column that aliases it
+    pub rowid: Option<(usize, Option<usize>)>,
+    /// For each term that joins conditions with OR, what the planner knows about the table
+    /// under each of the conditions
+    pub ors: Vec<Vec<OrCondition<'s, 'a>>>,
+}
+
+/// One of the conditions of a term that joins them with OR, the rows meeting which may be found
+/// in their own way
+pub struct OrCondition<'s, 'a> {
+    /// The terms the condition joins with AND, and the other tables each of them refers to, one
+    /// bit each
+    pub terms: Vec<(BoundExpr<'a>, u64)>,
+    pub info: TableInfo<'s, 'a>,
+}
+
+/// The tables an expression refers to, one bit each. Correlated subqueries may refer to any.
+pub fn needed_tables(expr: &BoundExpr, ranges: &[Range<usize>]) -> u64 {
+    let mut needs = 0;
+    for (i, range) in ranges.iter().enumerate() {
+        if expr.uses_columns(&|column| range.contains(&column)) {
+            needs |= 1 << i;
+        }
+    }
+    needs
+}
+
+/// Marks the columns an expression reads in `used`. Returns true when it may read any column,
+/// as correlated subqueries do.
+pub fn mark_columns(expr: &BoundExpr, used: &mut [bool]) -> bool {
+    let read = RefCell::new(vec![]);
+    let any = expr.uses_columns(&|column| {
+        read.borrow_mut().push(column);
+        false
+    });
+    for column in read.into_inner() {
+        if let Some(used) = used.get_mut(column) {
+            *used = true;
+        }
+    }
+    any
+}
+
+/// Whether an index of `table` has every value that `exprs` and `unindexed` read from the
+/// table, which `used` marks the columns of, so that it can be read instead. The columns the
+/// WHERE clause of a partial index equals to constants count as such, and so do the
+/// expressions the index has when `exprs` use them, whatever columns they read. Returns the
+/// expressions read from the index that would be computed from columns it lacks, with the
+/// unused columns their values go in.
+pub fn covering_slots(
+    index: &IndexInfo,
+    rowid: Option<(usize, Option<usize>)>,
+    expressions: &[BoundExpr],
+    table: &JoinedTable,
+    exprs: &[&BoundExpr],
+    unindexed: &[&BoundExpr],
+    used: &[bool],
+) -> Option<Vec<(usize, usize)>> {
+    let has = |column: usize| {
+        index
+            .columns
+            .iter()
+            .any(|&(indexed, _, _)| indexed == column)
+            || index
+                .constants
+                .iter()
+                .any(|&(constant, _)| constant == column)
+            || matches!(rowid, Some((rowid, alias)) if column == rowid || alias == Some(column))
+    };
+    let indexed: Vec<_> = index
+        .columns
+        .iter()
+        .filter_map(|&(column, _, _)| column.checked_sub(table.width))
+        .collect();
+    let mut read = vec![];
+    let mut marked;
+    let mut used = used;
+    if !indexed.is_empty() {
+        marked = vec![false; used.len()];
+        let null = BoundExpr::Literal(Value::Null);
+        for expr in exprs {
+            let mut expr = (*expr).clone();
+            for &j in &indexed {
+                if expr.replace(&expressions[j], &null) && !read.contains(&j) {
+                    read.push(j);
+                }
+            }
+            mark_columns(&expr, &mut marked);
+        }
+        for expr in unindexed {
+            mark_columns(expr, &mut marked);
+        }
+        used = &marked;
+    }
+    let used = |column: usize| used[table.start + column];
+    if !(0..table.width).all(|column| !used(column) || has(column)) {
+        return None;
+    }
+    let table_range = table.start..table.start + table.width;
+    let mut unused = (0..table.width).filter(|&column| !used(column) && !has(column));
+    read.into_iter()
+        .filter(|&j| {
+            expressions[j]
+                .uses_columns(&|column| table_range.contains(&column) && !has(column - table.start))
+        })
+        .map(|j| Some((j, unused.next()?)))
+        .collect()
+}
+
+/// Gathers what the planner needs to know about the `i`th table: its equalities from `terms`,
+/// its indexes, and the tables it must come after
+pub fn table_info<'s, 'a>(
+    table: &JoinedTable<'s>,
+    i: usize,
+    ranges: &[Range<usize>],
+    terms: &[&BoundExpr<'a>],
+) -> Result<TableInfo<'s, 'a>> {
+    let range = &ranges[i];
+    let mut required = match table.cross {
+        true => (1 << i) - 1,
+        false => 0,
+    };
+    let mut info = TableInfo {
+        rows: DEFAULT_ROWS,
+        width: range.len(),
+        equalities: vec![],
+        bounds: vec![],
+        indexes: vec![],
+        expressions: vec![],
+        indexed: vec![],
+        required: 0,
+        rowid: None,
+        ors: vec![],
+    };
+
+    match &table.source {
+        Source::Table {
+            db,
+            schema,
+            definition,
+            ..
+        } => {
+            info.rows = table_rows(db, schema);
+            if definition.primary_key.is_none() {
+                info.rowid = Some((definition.columns.len(), definition.rowid_alias));
+            }
+            let (indexes, indexed, expressions) =
+                table_indexes(db, schema, definition, range, terms)?;
+            info.indexes = indexes;
+            info.indexed = indexed;
+            info.expressions = expressions;
+        }
+        Source::Function { args, .. } => {
+            info.rows = FUNCTION_ROWS;
+            for arg in args {
+                required |= needed_tables(arg, ranges);
+            }
+        }
+        Source::Subquery {
+            query: Derived::Select(plan),
+            ..
+        } => {
+            if let Some(limit) = plan.limit {
+                info.rows = info.rows.min(limit as f64);
+            }
+        }
+        Source::Subquery { .. } => {}
+        Source::Current(_) => info.rows = 1.0,
+    }
+    info.required = required;
+
+    // Like SQLite, equalities whose column is on the right are considered after the others,
+    // last one first
+    let mut swapped = vec![];
+    let expressions = std::mem::take(&mut info.expressions);
+    let table_column = |expr: &BoundExpr| table_key(expr, range, &expressions);
+    for term in terms {
+        if let Some((equality, right)) = equality(term, i, ranges, &expressions) {
+            match right {
+                false => info.equalities.push(equality),
+                true => swapped.push(equality),
+            }
+            continue;
+        }
+        match term {
+            // The items of the list must be compared to the column the same way to be looked up
+            // together
+            BoundExpr::InList {
+                expr,
+                list,
+                negated: false,
+            } => {
+                let (column, comparator) = match (table_column(expr), list.first()) {
+                    (Some(column), Some(&(_, comparator))) => (column, comparator),
+                    _ => continue,
+                };
+                let needs = list
+                    .iter()
+                    .fold(0, |needs, (item, _)| needs | needed_tables(item, ranges));
+                if needs & (1 << i) != 0
+                    || !list.iter().all(|(_, other)| {
+                        other.left_affinity == comparator.left_affinity
+                            && other.collation == comparator.collation
+                    })
+                {
+                    continue;
+                }
+                let items = list
+                    .iter()
+                    .map(|(item, comparator)| (item.clone(), comparator.right_affinity))
+                    .collect();
+                info.equalities.push(Equality {
+                    column,
+                    probe: Operand::List(items),
+                    column_affinity: comparator.left_affinity,
+                    probe_affinity: None,
+                    collation: comparator.collation,
+                    needs,
+                });
+            }
+            BoundExpr::InSubquery {
+                expr,
+                subquery,
+                comparator,
+                negated: false,
+            } if !subquery.is_correlated() => {
+                if let Some(column) = table_column(expr) {
+                    info.equalities.push(Equality {
+                        column,
+                        probe: Operand::Subquery(subquery.clone()),
+                        column_affinity: comparator.left_affinity,
+                        probe_affinity: comparator.right_affinity,
+                        collation: comparator.collation,
+                        needs: 0,
+                    });
+                }
+            }
+            BoundExpr::Binary(_, BinaryOperator::Or, _) => {
+                let mut disjuncts = vec![];
+                disjunct_refs(term, &mut disjuncts);
+                // Equalities on the same column, compared the same way, are an IN list
+                let equalities: Option<Vec<_>> = disjuncts
+                    .iter()
+                    .map(|disjunct| equality(disjunct, i, ranges, &expressions))
+                    .collect();
+                if let Some(equalities) = equalities {
+                    let (first, _) = &equalities[0];
+                    if equalities.iter().all(|(other, _)| {
+                        other.column == first.column
+                            && other.column_affinity == first.column_affinity
+                            && other.collation == first.collation
+                    }) {
+                        let mut items = vec![];
+                        let mut needs = 0;
+                        for (equality, _) in &equalities {
+                            if let Operand::Value(expr) = &equality.probe {
+                                items.push((expr.clone(), equality.probe_affinity));
+                            }
+                            needs |= equality.needs;
+                        }
+                        info.equalities.push(Equality {
+                            probe: Operand::List(items),
+                            probe_affinity: None,
+                            needs,
+                            ..equalities.into_iter().next().unwrap().0
+                        });
+                        continue;
+                    }
+                }
+                // Otherwise the rows for each condition may be found in their own way
+                if let Source::Table { .. } = table.source {
+                    let mut conditions = vec![];
+                    for disjunct in disjuncts {
+                        let mut conjuncts = vec![];
+                        conjunct_refs(disjunct, &mut conjuncts);
+                        let terms = conjuncts
+                            .iter()
+                            .map(|&term| (term.clone(), needed_tables(term, ranges) & !(1 << i)))
+                            .collect();
+                        let info = table_info(table, i, ranges, &conjuncts)?;
+                        conditions.push(OrCondition { terms, info });
+                    }
+                    info.ors.push(conditions);
+                }
+            }
+            _ => {}
+        }
+    }
+    info.equalities.extend(swapped.into_iter().rev());
+
+    // Comparisons that bound the values of a column, and LIKE patterns that start with a prefix
+    let text_column = |column: usize| match &table.source {
+        Source::Table { definition, .. } => {
+            matches!(definition.columns.get(column), Some(column) if column.affinity == Affinity::Text)
+        }
+        _ => false,
+    };
+    for term in terms {
+        match term {
+            BoundExpr::Comparison {
+                left,
+                op,
+                right,
+                comparator,
+            } => {
+                let lower = match op {
+                    BinaryOperator::Gt | BinaryOperator::GtEq => true,
+                    BinaryOperator::Lt | BinaryOperator::LtEq => false,
+                    _ => continue,
+                };
+                let sides = [
+                    (
+                        left,
+                        right,
+                        comparator.left_affinity,
+                        comparator.right_affinity,
+                        lower,
+                    ),
+                    (
+                        right,
+                        left,
+                        comparator.right_affinity,
+                        comparator.left_affinity,
+                        !lower,
+                    ),
+                ];
+                for &(column, probe, column_affinity, probe_affinity, lower) in &sides {
+                    let column = match table_column(column) {
+                        Some(column) => column,
+                        None => continue,
+                    };
+                    let needs = needed_tables(probe, ranges);
+                    if needs & (1 << i) != 0 {
+                        continue;
+                    }
+                    info.bounds.push(Bound {
+                        column,
+                        probe: (**probe).clone(),
+                        column_affinity,
+                        probe_affinity,
+                        collation: comparator.collation,
+                        needs,
+                        lower,
+                        pattern: false,
+                    });
+                    break;
+                }
+            }
+            // Values of other types than text may match a pattern without sorting between its
+            // bounds
+            BoundExpr::Like {
+                expr,
+                pattern,
+                escape: None,
+                glob,
+                negated: false,
+            } => {
+                let column = match &**expr {
+                    BoundExpr::Column(column) if range.contains(column) => column - range.start,
+                    _ => continue,
+                };
+                let (lower, upper) = match &**pattern {
+                    BoundExpr::Literal(Value::Text(pattern)) if text_column(column) => {
+                        match pattern_bounds(pattern, *glob) {
+                            Some(bounds) => bounds,
+                            None => continue,
+                        }
+                    }
+                    _ => continue,
+                };
+                // LIKE ignores case, as NOCASE does
+                let collation = match glob {
+                    true => Collation::Binary,
+                    false => Collation::NoCase,
+                };
+                for (value, lower) in [(lower, true), (upper, false)] {
+                    info.bounds.push(Bound {
+                        column,
+                        probe: BoundExpr::Literal(value),
+                        column_affinity: None,
+                        probe_affinity: None,
+                        collation,
+                        needs: 0,
+                        lower,
+                        pattern: true,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    info.expressions = expressions;
+    Ok(info)
+}
+
+/// The position of a column of the table in `range` that an expression is, or past the table's
+/// row, of one of the `expressions` of its indexes that it's the same as
+fn table_key(expr: &BoundExpr, range: &Range<usize>, expressions: &[BoundExpr]) -> Option<usize> {
+    match expr {
+        BoundExpr::Column(column) if range.contains(column) => Some(column - range.start),
+        _ => expressions
+            .iter()
+            .position(|other| other.same_as(expr))
+            .map(|j| range.len() + j),
+    }
+}
+
+/// The equality that a `column = expression` term is for, when the column is one of the `i`th
+/// table's, or one of the `expressions` its indexes have, and the expression doesn't refer to
+/// that table, and whether the column is on the right
+fn equality<'a>(
+    term: &BoundExpr<'a>,
+    i: usize,
+    ranges: &[Range<usize>],
+    expressions: &[BoundExpr],
+) -> Option<(Equality<'a>, bool)> {
+    let (left, right, comparator) = match term {
+        BoundExpr::Comparison {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+            comparator,
+        } => (&**left, &**right, comparator),
+        _ => return None,
+    };
+    let range = &ranges[i];
+    let sides = [
+        (
+            left,
+            right,
+            comparator.left_affinity,
+            comparator.right_affinity,
+        ),
+        (
+            right,
+            left,
+            comparator.right_affinity,
+            comparator.left_affinity,
+        ),
+    ];
+    for (side, &(column, probe, column_affinity, probe_affinity)) in sides.iter().enumerate() {
+        let column = match table_key(column, range, expressions) {
+            Some(column) => column,
+            None => continue,
+        };
+        let needs = needed_tables(probe, ranges);
+        // The probe must be known before the table is read
+        if needs & (1 << i) != 0 {
+            continue;
+        }
+        let equality = Equality {
+            column,
+            probe: Operand::Value(probe.clone()),
+            column_affinity,
+            probe_affinity,
+            collation: comparator.collation,
+            needs,
+        };
+        return Some((equality, side == 1));
+    }
+    None
+}
+
+/// The strings that the values matching a LIKE or GLOB pattern sort between, from the prefix
+/// before its first wildcard: the prefix, and the prefix with its last character incremented.
+/// None when the pattern starts with a wildcard.
+fn pattern_bounds(pattern: &str, glob: bool) -> Option<(Value, Value)> {
+    let wildcards: &[char] = match glob {
+        true => &['*', '?', '['],
+        false => &['%', '_'],
+    };
+    let prefix: String = pattern
+        .chars()
+        .take_while(|c| !wildcards.contains(c))
+        .collect();
+    // NOCASE compares ASCII letters as lowercase ones
+    let mut upper: Vec<char> = match glob {
+        true => prefix.chars().collect(),
+        false => prefix.to_ascii_lowercase().chars().collect(),
+    };
+    let last = upper.pop()?;
+    upper.push(std::char::from_u32(last as u32 + 1)?);
+    Some((
+        Value::Text(prefix),
+        Value::Text(upper.into_iter().collect()),
+    ))
+}
+
+/// The indexes of a table the planner can use, the columns SQLite doesn't build automatic
+/// indexes on, such as those that any index starts with, and the expressions the indexes have
+/// instead of columns, bound against the table in `range`.
+/// Partial indexes are left out unless `terms` imply their WHERE clause, as are the indexes
+/// SQLite creates for constraints, which have no CREATE INDEX statement. A WITHOUT ROWID table
+/// comes first, its indexes being followed by the PRIMARY KEY columns they don't have.
+fn table_indexes<'s>(
+    db: &'s Database,
+    schema: &'s Schema,
+    definition: &TableDefinition,
+    range: &Range<usize>,
+    terms: &[&BoundExpr],
+) -> Result<(Vec<IndexInfo<'s>>, Vec<usize>, Vec<BoundExpr<'s>>)> {
+    let position = |name: &str| {
+        definition
+            .columns
+            .iter()
+            .position(|column| column.name.eq_ignore_ascii_case(name))
+    };
+    let scope = table_scope(definition, &schema.name);
+    let binder = Binder::new(db, &scope);
+    let bind = |expr: &Expr| {
+        binder.bind(expr).ok().map(|mut bound| {
+            bound.move_columns(range.start);
+            bound
+        })
+    };
+    let text_column = |column: usize| {
+        let column = column
+            .checked_sub(range.start)
+            .and_then(|column| definition.columns.get(column));
+        matches!(column, Some(column) if column.affinity == Affinity::Text)
+    };
+    let table_rows = table_rows(db, schema);
+
+    let mut indexes = vec![];
+    let mut indexed = vec![];
+    let mut expressions: Vec<BoundExpr<'s>> = vec![];
+    if let Some(primary_key) = &definition.primary_key {
+        indexed.push(primary_key[0].0);
+        indexes.push(IndexInfo {
+            schema,
+            primary: true,
+            columns: primary_key.clone(),
+            unique: true,
+            statistics: db.statistics.index(&schema.name),
+            rows: table_rows,
+            partial: false,
+            constants: vec![],
+            covering: true,
+            slots: vec![],
+        });
+    }
+    'indexes: for index_schema in db.indexes(&schema.name) {
+        let index = match index_schema.index_definition() {
+            Some(index) => index?,
+            None => continue,
+        };
+        let statistics = db.statistics.index(&index_schema.name);
+        for (i, column) in index.columns.iter().enumerate() {
+            let column = match column.column_name().and_then(position) {
+                Some(column) => column,
+                None => continue,
+            };
+            // Nor on the other columns of an analyzed index, when many rows share their values
+            let rows = statistics.and_then(|statistics| statistics.rows.get(i + 1));
+            if i == 0 || matches!(rows, Some(&rows) if rows > 4.0) {
+                indexed.push(column);
+            }
+        }
+        // Each term of the condition of a partial index must follow from a term of the query
+        let mut constants = vec![];
+        if let Some(condition) = &index.where_clause {
+            let condition = match bind(condition) {
+                Some(condition) => condition,
+                None => continue,
+            };
+            let mut conditions = vec![];
+            split_conjuncts(condition, &mut conditions);
+            if !conditions.iter().all(|condition| {
+                terms
+                    .iter()
+                    .any(|term| implies(term, condition, &text_column))
+            }) {
+                continue;
+            }
+            constants = conditions
+                .iter()
+                .filter_map(|condition| constant_column(condition, definition, range))
+                .collect();
+        }
+        let mut columns = vec![];
+        for indexed in &index.columns {
+            let (position, collation) = match indexed.column_name().and_then(position) {
+                Some(position) => (position, definition.columns[position].collation),
+                None => {
+                    let expr = match bind(&indexed.expr) {
+                        Some(expr) => expr,
+                        None => continue 'indexes,
+                    };
+                    let j = match expressions.iter().position(|other| other.same_as(&expr)) {
+                        Some(j) => j,
+                        None => {
+                            expressions.push(expr);
+                            expressions.len() - 1
+                        }
+                    };
+                    (range.len() + j, binder.collation(&indexed.expr).0)
+                }
+            };
+            let collation = match &indexed.collation {
+                Some(name) => Collation::from_name(name).unwrap_or(Collation::Binary),
+                None => collation,
+            };
+            columns.push((position, collation, indexed.descending));
+        }
+        if let Some(primary_key) = &definition.primary_key {
+            for &key in primary_key {
+                if !columns.iter().any(|&(column, _, _)| column == key.0) {
+                    columns.push(key);
+                }
+            }
+        }
+        // Without statistics, SQLite takes a partial index to have half of the rows
+        let partial = index.where_clause.is_some();
+        let rows = match statistics.and_then(|statistics| statistics.rows.first()) {
+            Some(&rows) if partial => rows,
+            None if partial => table_rows / 2.0,
+            _ => table_rows,
+        };
+        indexes.push(IndexInfo {
+            schema: index_schema,
+            primary: false,
+            columns,
+            unique: index.unique || definition.primary_key.is_some(),
+            statistics,
+            rows,
+            partial,
+            constants,
+            covering: false,
+            slots: vec![],
+        });
+    }
+    Ok((indexes, indexed, expressions))
+}
+
+/// The column of the table at `range` in the row, and its value, when a term of the condition
+/// of a partial index equals it to a constant, so that every row of the index has it. Like
+/// SQLite, only for a column with an affinity, compared with the BINARY collating sequence.
+fn constant_column(
+    condition: &BoundExpr,
+    definition: &TableDefinition,
+    range: &Range<usize>,
+) -> Option<(usize, Value)> {
+    let (column, constant) = match condition {
+        BoundExpr::Comparison {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+            comparator,
+        }
+        | BoundExpr::Comparison {
+            left,
+            op: BinaryOperator::Is,
+            right,
+            comparator,
+        } if comparator.collation == Collation::Binary => match &**left {
+            BoundExpr::Column(column) => (column.checked_sub(range.start)?, right),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let affinity = definition.columns.get(column)?.affinity;
+    let mut subqueries = vec![];
+    constant.subqueries(&mut subqueries);
+    if affinity == Affinity::Blob || !subqueries.is_empty() || constant.uses_columns(&|_| true) {
+        return None;
+    }
+    let value = constant.eval_constant().ok()?;
+    Some((column, value.apply_affinity(affinity)))
+}
+
+/// Whether a term being true means a condition is, in the ways SQLite recognizes: the term is
+/// the condition, with the operands of a comparison in either order, or implies one of the
+/// conditions it joins with OR, or can't be true when the expression the condition says isn't
+/// NULL is. `text_column` tells the columns with TEXT affinity.
+fn implies(term: &BoundExpr, condition: &BoundExpr, text_column: &dyn Fn(usize) -> bool) -> bool {
+    match condition {
+        BoundExpr::Binary(left, BinaryOperator::Or, right) => {
+            return implies(term, left, text_column) || implies(term, right, text_column);
+        }
+        BoundExpr::IsNull {
+            expr,
+            negated: true,
+        } if implies_not_null(term, expr, text_column) => return true,
+        BoundExpr::Comparison {
+            left,
+            op: BinaryOperator::IsNot,
+            right,
+            ..
+        } if matches!(&**right, BoundExpr::Literal(Value::Null))
+            && implies_not_null(term, left, text_column) =>
+        {
+            return true
+        }
+        _ => {}
+    }
+    if term.same_as(condition) {
+        return true;
+    }
+    match (term, condition) {
+        (
+            BoundExpr::Comparison {
+                left,
+                op,
+                right,
+                comparator,
+            },
+            BoundExpr::Comparison {
+                left: other_left,
+                op: other_op,
+                right: other_right,
+                comparator: other_comparator,
+            },
+        ) if left.same_as(other_right) && right.same_as(other_left) => {
+            let commuted = match op {
+                BinaryOperator::Lt => BinaryOperator::Gt,
+                BinaryOperator::LtEq => BinaryOperator::GtEq,
+                BinaryOperator::Gt => BinaryOperator::Lt,
+                BinaryOperator::GtEq => BinaryOperator::LtEq,
+                op => *op,
+            };
+            commuted == *other_op && comparator.collation == other_comparator.collation
+        }
+        _ => false,
+    }
+}
+
+/// Whether a term can only be true when `expr` isn't NULL, because it compares it, or computes
+/// something from it that it compares. A LIKE or GLOB pattern with a prefix counts as the
+/// comparisons it bounds a text column with.
+fn implies_not_null(
+    term: &BoundExpr,
+    expr: &BoundExpr,
+    text_column: &dyn Fn(usize) -> bool,
+) -> bool {
+    if term.same_as(expr) {
+        return !matches!(expr, BoundExpr::Literal(Value::Null));
+    }
+    let either = |left: &BoundExpr, right: &BoundExpr| {
+        implies_not_null(left, expr, text_column) || implies_not_null(right, expr, text_column)
+    };
+    match term {
+        BoundExpr::Comparison {
+            left, op, right, ..
+        } => !matches!(op, BinaryOperator::Is | BinaryOperator::IsNot) && either(left, right),
+        BoundExpr::Binary(left, op, right) => {
+            !matches!(
+                op,
+                BinaryOperator::And
+                    | BinaryOperator::Or
+                    | BinaryOperator::Extract
+                    | BinaryOperator::ExtractValue
+            ) && either(left, right)
+        }
+        BoundExpr::Unary(_, operand) => implies_not_null(operand, expr, text_column),
+        BoundExpr::InList { expr: left, .. }
+        | BoundExpr::InSubquery {
+            expr: left,
+            negated: false,
+            ..
+        } => implies_not_null(left, expr, text_column),
+        BoundExpr::Like {
+            expr: left,
+            pattern,
+            glob,
+            negated: false,
+            ..
+        } => match (&**left, &**pattern) {
+            (BoundExpr::Column(column), BoundExpr::Literal(Value::Text(pattern))) => {
+                text_column(*column)
+                    && pattern_bounds(pattern, *glob).is_some()
+                    && left.same_as(expr)
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// The conditions an expression joins with OR
+fn disjunct_refs<'e, 'a>(expr: &'e BoundExpr<'a>, terms: &mut Vec<&'e BoundExpr<'a>>) {
+    match expr {
+        BoundExpr::Binary(left, BinaryOperator::Or, right) => {
+            disjunct_refs(left, terms);
+            disjunct_refs(right, terms);
+        }
+        expr => terms.push(expr),
+    }
+}
+
+/// Ways of reading the tables, in join order, their total cost, and whether each table is read
+/// backwards so that the rows come out sorted, or None when they need sorting
+pub type Choice = (f64, Vec<Access>, Option<Vec<bool>>);
+
+/// Compares the ways of reading the tables, in a given order
+pub struct Planner<'p, 's, 'a> {
+    pub tables: &'p [JoinedTable<'s>],
+    pub infos: &'p [TableInfo<'s, 'a>],
+    pub term_needs: &'p [u64],
+    pub keys: &'p [BoundExpr<'a>],
+    pub orders: &'p [SortOrder],
+    /// Whether the rows are only needed together when their keys are equal, as with GROUP BY,
+    /// which any direction does for
+    pub grouping: bool,
+}
+
+impl<'p, 's, 'a> Planner<'p, 's, 'a> {
+    /// The cheapest way of reading each table when they're joined in `order`. Reading all of the
+    /// first table, or all of one of its indexes, is also considered, for the order of its rows.
+    pub fn best_accesses(&self, order: &[usize]) -> Choice {
+        let mut best = self.accesses(order, None);
+        if !self.keys.is_empty() {
+            let indexes = &self.infos[order[0]].indexes;
+            let scans = (0..indexes.len())
+                .filter(|&index| !indexes[index].primary)
+                .map(|index| Access::Index {
+                    index,
+                    equalities: vec![],
+                    bounds: BoundTerms::default(),
+                });
+            for first in std::iter::once(Access::Scan).chain(scans) {
+                let candidate = self.accesses(order, Some(first));
+                if candidate.0 < best.0 * (1.0 - 1e-9) {
+                    best = candidate;
+                }
+            }
+        }
+        best
+    }
+
+    fn accesses(&self, order: &[usize], first: Option<Access>) -> Choice {
+        let mut cost = 0.0;
+        let mut loops = 1.0;
+        let mut joined = 0u64;
+        let mut accesses = vec![];
+        let mut first = first;
+        for &i in order {
+            let info = &self.infos[i];
+            let (access, estimate) = match first.take() {
+                Some(access) => {
+                    let estimate = match &access {
+                        Access::Index { index, .. } => {
+                            let index = &info.indexes[*index];
+                            index_scan(index.rows, index.covering)
+                        }
+                        _ => scan(info.rows),
+                    };
+                    (access, estimate)
+                }
+                None => self.best_access(info, joined, loops),
+            };
+            cost += loops * estimate.cost + estimate.setup;
+
+            // The terms checked against the table's rows narrow them down further
+            joined |= 1 << i;
+            // Terms that the column equals one of several values for narrow the rows down less
+            let single = |e: &usize| !info.equalities[*e].probe.is_list();
+            let (used, used_equalities) = match &access {
+                Access::Rowid(e) => (1, single(e) as usize),
+                Access::RowidRange(bounds) => (bounds.terms(&info.bounds), 0),
+                Access::Index {
+                    equalities, bounds, ..
+                } => (
+                    equalities.len() + bounds.terms(&info.bounds),
+                    equalities.iter().filter(|e| single(e)).count(),
+                ),
+                Access::Automatic(equalities) => (equalities.len(), equalities.len()),
+                Access::Or(..) => (1, 0),
+                Access::Scan => (0, 0),
+            };
+            let checked = self
+                .term_needs
+                .iter()
+                .filter(|&&needs| needs & (1 << i) != 0 && needs & !joined == 0)
+                .count();
+            let equalities = info
+                .equalities
+                .iter()
+                .filter(|equality| equality.needs & !joined == 0 && !equality.probe.is_list())
+                .count();
+            let rows = filtered_rows(
+                estimate.rows,
+                info.rows,
+                checked.saturating_sub(used),
+                equalities.saturating_sub(used_equalities),
+            );
+            loops *= rows.max(1.0);
+            accesses.push(access);
+        }
+
+        let directions = match self.keys.is_empty() {
+            true => Some(vec![false; order.len()]),
+            false => self.directions(order, &accesses),
+        };
+        if directions.is_none() {
+            cost += sort(loops);
+        }
+        (cost, accesses, directions)
+    }
+
+    /// The cheapest way of reading a table, `loops` times, after the `joined` tables
+    fn best_access(&self, info: &TableInfo, joined: u64, loops: f64) -> (Access, Estimate) {
+        let mut ways = vec![(Access::Scan, scan(info.rows))];
+        if info.rowid.is_none() && info.indexes.is_empty() {
+            return ways.remove(0);
+        }
+        let mut consider = |access: Access, estimate: Estimate| {
+            ways.push((access, estimate));
+        };
+
+        let usable: Vec<_> = (0..info.equalities.len())
+            .filter(|&e| info.equalities[e].needs & !joined == 0)
+            .collect();
+        let is_rowid = |column: usize| matches!(info.rowid, Some((rowid, alias)) if column == rowid || Some(column) == alias);
+        let count = |e: usize| info.equalities[e].probe.count();
+        for &e in &usable {
+            if is_rowid(info.equalities[e].column) {
+                consider(Access::Rowid(e), rowid_lookup(info.rows, count(e)));
+            }
+        }
+        if let Some(bounds) = range_terms(info, joined, is_rowid, None) {
+            let rows = range_rows(info.rows, bounds.count());
+            consider(Access::RowidRange(bounds), rowid_range(info.rows, rows));
+        }
+
+        // When indexes are as good as each other, the one with the fewest columns is used, and
+        // then the one created last, as in SQLite
+        let mut candidates: Vec<_> = info.indexes.iter().enumerate().rev().collect();
+        candidates.sort_by_key(|(_, index_info)| index_info.columns.len());
+        for (index, index_info) in candidates {
+            // Each value of an equality with several is searched for, with each combination of
+            // the values of the others
+            let mut equalities = vec![];
+            let mut seeks = 1.0;
+            for &(column, collation, _) in &index_info.columns {
+                let found = usable
+                    .iter()
+                    .copied()
+                    .filter(|&e| {
+                        let equality = &info.equalities[e];
+                        equality.column == column
+                            && equality.collation == collation
+                            && equality.column_affinity.is_none()
+                    })
+                    .min_by(|&a, &b| count(a).partial_cmp(&count(b)).unwrap());
+                match found {
+                    Some(e) => {
+                        equalities.push(e);
+                        seeks *= count(e);
+                    }
+                    None => break,
+                }
+            }
+            // The column after those the equalities are for may be bounded, the rowid being the
+            // last one of the entries of a table that has one
+            let bounds = match index_info.columns.get(equalities.len()) {
+                Some(&(column, collation, _)) => {
+                    range_terms(info, joined, |other| other == column, Some(collation))
+                }
+                None => range_terms(info, joined, is_rowid, None),
+            };
+            let rows = match equalities.first() {
+                // Samples of the index tell how many rows have the value of a constant
+                Some(&e) => {
+                    let first = &info.equalities[e];
+                    let value = match &first.probe {
+                        Operand::Value(BoundExpr::Literal(value)) => {
+                            Some(match first.probe_affinity {
+                                Some(affinity) => value.clone().apply_affinity(affinity),
+                                None => value.clone(),
+                            })
+                        }
+                        _ => None,
+                    };
+                    let rows = index_rows(
+                        index_info.statistics,
+                        equalities.len(),
+                        index_info.columns.len(),
+                        index_info.unique,
+                        value.as_ref().map(|value| (value, first.collation)),
+                    );
+                    (rows * seeks).min(index_info.rows)
+                }
+                // An index with every column needed has fewer pages to read than its table, and
+                // a partial one fewer rows. Reading all of a WITHOUT ROWID table is a scan.
+                None if bounds.is_none() => {
+                    if !index_info.primary && (index_info.covering || index_info.partial) {
+                        let access = Access::Index {
+                            index,
+                            equalities,
+                            bounds: BoundTerms::default(),
+                        };
+                        consider(access, index_scan(index_info.rows, index_info.covering));
+                    }
+                    continue;
+                }
+                None => index_info.rows,
+            };
+            let rows = match &bounds {
+                Some(bounds) => range_rows(rows, bounds.count()),
+                None => rows,
+            };
+            let access = Access::Index {
+                index,
+                equalities,
+                bounds: bounds.unwrap_or_default(),
+            };
+            consider(
+                access,
+                index_lookup(index_info.rows, seeks, rows, index_info.covering),
+            );
+        }
+
+        // Automatic indexes are on columns of the table's row, not on the expressions after it
+        let mut automatic: Vec<usize> = vec![];
+        for &e in &usable {
+            let equality = &info.equalities[e];
+            if equality.column < info.width
+                && equality.column_affinity.is_none()
+                && !equality.probe.is_list()
+                && !info.indexed.contains(&equality.column)
+                && !automatic
+                    .iter()
+                    .any(|&other| info.equalities[other].column == equality.column)
+            {
+                automatic.push(e);
+            }
+        }
+        if !automatic.is_empty() {
+            consider(Access::Automatic(automatic), automatic_index(info.rows));
+        }
+
+        // Each of the conditions a term joins with OR must find its rows without reading all of
+        // them
+        for (o, conditions) in info.ors.iter().enumerate() {
+            let mut accesses = vec![];
+            let mut estimates = vec![];
+            for OrCondition { info, .. } in conditions {
+                let (access, estimate) = self.best_access(info, joined, loops);
+                if matches!(access, Access::Scan | Access::Automatic(_) | Access::Or(..)) {
+                    break;
+                }
+                accesses.push(access);
+                estimates.push(estimate);
+            }
+            if accesses.len() == conditions.len() {
+                consider(Access::Or(o, accesses), or_lookups(info.rows, &estimates));
+            }
+        }
+
+        // Like SQLite, a search of an index that uses every term another one uses, and more, is
+        // taken to be at least as good as it, unless only the other one is covering
+        let terms = |access: &Access| match access {
+            Access::Index {
+                index,
+                equalities,
+                bounds,
+            } => {
+                let mut terms: Vec<_> = equalities.iter().map(|&e| (e, true)).collect();
+                terms.extend(
+                    [bounds.lower, bounds.upper]
+                        .iter()
+                        .flatten()
+                        .map(|&b| (b, false)),
+                );
+                Some((terms, info.indexes[*index].covering))
+            }
+            _ => None,
+        };
+        for y in 0..ways.len() {
+            for x in 0..ways.len() {
+                let (x_terms, x_covering, y_terms, y_covering) =
+                    match (terms(&ways[x].0), terms(&ways[y].0)) {
+                        (Some((x_terms, x_covering)), Some((y_terms, y_covering))) => {
+                            (x_terms, x_covering, y_terms, y_covering)
+                        }
+                        _ => continue,
+                    };
+                let (subset, superset) = (ways[x].1, ways[y].1);
+                if x_terms.len() < y_terms.len()
+                    && x_terms.iter().all(|term| y_terms.contains(term))
+                    && (subset.cost <= superset.cost || subset.rows <= superset.rows)
+                    && (!x_covering || y_covering)
+                {
+                    let estimate = &mut ways[y].1;
+                    estimate.cost = subset.cost.min(superset.cost);
+                    estimate.rows = subset.rows.min(superset.rows) * 0.93;
+                }
+            }
+        }
+
+        // Between ways that cost the same, the one giving fewer rows is better
+        let total = |estimate: &Estimate| loops * estimate.cost + estimate.setup;
+        let mut ways = ways.into_iter();
+        let mut best = ways.next().unwrap();
+        for way in ways {
+            let (cost, best_cost) = (total(&way.1), total(&best.1));
+            if cost < best_cost * (1.0 - 1e-9)
+                || (cost <= best_cost * (1.0 + 1e-9) && way.1.rows < best.1.rows)
+            {
+                best = way;
+            }
+        }
+        best
+    }
+
+    /// Whether the rows of the tables joined in `order` can come out sorted by the ORDER BY
+    /// terms, and if so, whether each table is read backwards for that. Each table is read in
+    /// the order of its rowids or of the index used to find its rows, in either direction.
+    /// When that order tells its rows apart, the order of the next table breaks the ties.
+    fn directions(&self, order: &[usize], accesses: &[Access]) -> Option<Vec<bool>> {
+        // Columns of the `i`th table equal to a constant don't change the order
+        let fixed = |i: usize, column: usize| {
+            !self.tables[i].outer
+                && self.infos[i].equalities.iter().any(|equality| {
+                    equality.needs == 0
+                        && equality.column_affinity.is_none()
+                        && !equality.probe.is_list()
+                        && equality.column == column
+                })
+        };
+        let key_column = |k: usize| match &self.keys[k] {
+            BoundExpr::Column(column) => {
+                // The column aliasing the rowid stands for it
+                let column = self
+                    .infos
+                    .iter()
+                    .zip(self.tables)
+                    .find_map(|(info, table)| match info.rowid {
+                        Some((rowid, Some(alias))) if table.start + alias == *column => {
+                            Some(table.start + rowid)
+                        }
+                        _ => None,
+                    })
+                    .unwrap_or(*column);
+                Some(column)
+            }
+            _ => None,
+        };
+        // The position of a term in the `i`th table's row, or past it, of the expression of one
+        // of its indexes it's the same as
+        let key_position = |k: usize, i: usize| {
+            let table = &self.tables[i];
+            match key_column(k) {
+                Some(column) if column >= table.start && column < table.start + table.width => {
+                    Some(column - table.start)
+                }
+                Some(_) => None,
+                None => self.infos[i]
+                    .expressions
+                    .iter()
+                    .position(|expr| expr.same_as(&self.keys[k]))
+                    .map(|j| table.width + j),
+            }
+        };
+        let is_fixed_key = |k: usize| {
+            (0..self.tables.len())
+                .any(|i| matches!(key_position(k, i), Some(column) if fixed(i, column)))
+        };
+
+        let mut reversed = vec![false; order.len()];
+        let mut k = 0;
+        for (position, (&i, access)) in order.iter().zip(accesses).enumerate() {
+            let info = &self.infos[i];
+            // Rows come in the order of their rowid, or of the PRIMARY KEY of a WITHOUT ROWID
+            // table, which ends the entries of indexes too
+            let key = match (info.rowid, info.indexes.first()) {
+                (Some((rowid, _)), _) => vec![(rowid, Collation::Binary, false)],
+                (None, Some(index)) if index.primary => index.columns.clone(),
+                _ => return None,
+            };
+            // The tables after those that sort the rows by every term can be read in any way
+            while k < self.keys.len() && is_fixed_key(k) {
+                k += 1;
+            }
+            if k == self.keys.len() {
+                return Some(reversed);
+            }
+            // The values of an equality with several are looked up in order
+            let is_list = |e: usize| info.equalities[e].probe.is_list();
+            let entries = match access {
+                Access::Rowid(e) if is_list(*e) => key,
+                Access::Rowid(_) => continue,
+                Access::Scan | Access::RowidRange(_) => key,
+                Access::Index {
+                    index, equalities, ..
+                } => {
+                    let index = &info.indexes[*index];
+                    let lists = equalities.iter().any(|&e| is_list(e));
+                    if index.unique && equalities.len() == index.columns.len() && !lists {
+                        continue;
+                    }
+                    let mut entries: Vec<_> = index
+                        .columns
+                        .iter()
+                        .enumerate()
+                        .filter(|&(j, _)| j >= equalities.len() || is_list(equalities[j]))
+                        .map(|(_, &entry)| entry)
+                        .collect();
+                    entries.extend(key.into_iter().filter(|&(column, _, _)| {
+                        !index.columns.iter().any(|&(other, _, _)| other == column)
+                    }));
+                    entries
+                }
+                Access::Automatic(_) | Access::Or(..) => return None,
+            };
+            // The first term the table's entries are sorted by tells which way to read them
+            let mut direction = None;
+            for (column, collation, descending) in entries {
+                while k < self.keys.len() && is_fixed_key(k) {
+                    k += 1;
+                }
+                if k == self.keys.len() {
+                    reversed[position] = direction.unwrap_or(false);
+                    return Some(reversed);
+                }
+                let order = &self.orders[k];
+                let reverse = order.descending != descending;
+                let matches = key_position(k, i) == Some(column)
+                    && order.collation == collation
+                    && (self.grouping
+                        || (direction.unwrap_or(reverse) == reverse
+                            && order.nulls_first != order.descending));
+                if matches {
+                    if !self.grouping {
+                        direction = Some(reverse);
+                    }
+                    k += 1;
+                } else if !fixed(i, column) {
+                    return None;
+                }
+            }
+            reversed[position] = direction.unwrap_or(false);
+        }
+        while k < self.keys.len() && is_fixed_key(k) {
+            k += 1;
+        }
+        match k == self.keys.len() {
+            true => Some(reversed),
+            false => None,
+        }
+    }
+}
+
+/// The first usable lower and upper bounds on a column that `is_column` holds for, compared
+/// with `collation` when it matters. None when there are neither.
+fn range_terms(
+    info: &TableInfo,
+    joined: u64,
+    is_column: impl Fn(usize) -> bool,
+    collation: Option<Collation>,
+) -> Option<BoundTerms> {
+    let usable = |lower: bool| {
+        info.bounds.iter().position(|bound| {
+            bound.lower == lower
+                && bound.needs & !joined == 0
+                && is_column(bound.column)
+                && bound.column_affinity.is_none()
+                && match collation {
+                    Some(collation) => bound.collation == collation,
+                    None => true,
+                }
+        })
+    };
+    let terms = BoundTerms {
+        lower: usable(true),
+        upper: usable(false),
+    };
+    match terms.count() {
+        0 => None,
+        _ => Some(terms),
+    }
+}
@@ -0,0 +1,350 @@
+use crate::value::Value;
+use std::convert::TryFrom;
+
+/// Conversion flags and sizes of one `%` specification
+#[derive(Default)]
+struct Spec {
+    left_justify: bool,
+    plus_sign: bool,
+    blank_sign: bool,
+    zero_pad: bool,
+    alternate_form: bool,
+    /// `!`: more significant digits for reals, and lengths counted in characters
+    alternate_form2: bool,
+    thousands: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// Formats arguments like SQLite's [printf()](https://www.sqlite.org/printf.html). Missing
+/// arguments are treated as 0 or an empty string, and an unknown conversion ends the output.
+pub fn printf(format: &str, args: &[Value]) -> String {
+    let mut args = args.iter();
+    let mut next_arg = move || args.next().cloned().unwrap_or(Value::Null);
+
+    let mut output = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        while let Some(&c) = chars.peek() {
+            match c {
+                '-' => spec.left_justify = true,
+                '+' => spec.plus_sign = true,
+                ' ' => spec.blank_sign = true,
+                '0' => spec.zero_pad = true,
+                '#' => spec.alternate_form = true,
+                '!' => spec.alternate_form2 = true,
+                ',' => spec.thousands = true,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = next_arg().to_integer().unwrap_or(0);
+            if width < 0 {
+                spec.left_justify = true;
+            }
+            spec.width = width.unsigned_abs() as usize;
+        } else {
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                spec.width = spec.width * 10 + digit as usize;
+                chars.next();
+            }
+        }
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                spec.precision = Some(next_arg().to_integer().unwrap_or(0).unsigned_abs() as usize);
+            } else {
+                let mut precision = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    precision = precision * 10 + digit as usize;
+                    chars.next();
+                }
+                spec.precision = Some(precision);
+            }
+        }
+
+        // Length modifiers make no difference, every integer is 64 bits wide
+        while chars.peek() == Some(&'l') {
+            chars.next();
+        }
+
+        let conversion = match chars.next() {
+            Some(conversion) => conversion,
+            None => break,
+        };
+        let text = match conversion {
+            '%' => "%".to_string(),
+            'd' | 'i' => {
+                let value = next_arg().to_integer().unwrap_or(0);
+                let sign = sign(value < 0, &spec);
+                let digits = value.unsigned_abs().to_string();
+                format_integer(sign, "", digits, &spec)
+            }
+            'u' | 'x' | 'X' | 'o' => {
+                let value = next_arg().to_integer().unwrap_or(0) as u64;
+                let (digits, prefix) = match conversion {
+                    'u' => (value.to_string(), ""),
+                    'x' => (format!("{:x}", value), "0x"),
+                    'X' => (format!("{:X}", value), "0X"),
+                    _ => (format!("{:o}", value), "0"),
+                };
+                let prefix = if spec.alternate_form && value != 0 {
+                    prefix
+                } else {
+                    ""
+                };
+                format_integer("", prefix, digits, &spec)
+            }
+            'f' | 'e' | 'E' | 'g' | 'G' => {
+                let value = next_arg().to_real().unwrap_or(0.0);
+                format_float(value, conversion, &spec)
+            }
+            's' | 'z' => {
+                let text = next_arg().to_text().unwrap_or_default();
+                match spec.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                }
+            }
+            'c' => {
+                let text = next_arg().to_text().unwrap_or_default();
+                match text.chars().next() {
+                    Some(c) => c.to_string().repeat(spec.precision.unwrap_or(1).max(1)),
+                    None => String::new(),
+                }
+            }
+            'q' | 'Q' | 'w' => {
+                let value = next_arg();
+                let quote = if conversion == 'w' { '"' } else { '\'' };
+                match value.to_text() {
+                    None if conversion == 'Q' => "NULL".to_string(),
+                    text => {
+                        let text = text.unwrap_or_default();
+                        let text: String = match spec.precision {
+                            Some(precision) => text.chars().take(precision).collect(),
+                            None => text,
+                        };
+                        let escaped = text.replace(quote, &format!("{}{}", quote, quote));
+                        if conversion == 'Q' {
+                            format!("'{}'", escaped)
+                        } else {
+                            escaped
+                        }
+                    }
+                }
+            }
+            _ => break,
+        };
+
+        output.push_str(&pad(text, &spec));
+    }
+
+    output
+}
+
+fn sign(negative: bool, spec: &Spec) -> &'static str {
+    if negative {
+        "-"
+    } else if spec.plus_sign {
+        "+"
+    } else if spec.blank_sign {
+        " "
+    } else {
+        ""
+    }
+}
+
+/// Inserts a comma between every group of three digits
+fn group_thousands(digits: &str) -> String {
+    let mut groups: Vec<_> = digits
+        .as_bytes()
+        .rchunks(3)
+        .map(String::from_utf8_lossy)
+        .collect();
+    groups.reverse();
+    groups.join(",")
+}
+
+fn format_integer(sign: &str, prefix: &str, digits: String, spec: &Spec) -> String {
+    let mut digits = match spec.precision {
+        Some(precision) if precision > digits.len() => {
+            format!("{}{}", "0".repeat(precision - digits.len()), digits)
+        }
+        _ => digits,
+    };
+    if spec.thousands {
+        digits = group_thousands(&digits);
+    }
+    zero_pad(format!("{}{}", sign, prefix), digits, spec)
+}
+
+/// Pads a number with zeros between its sign and its digits when the `0` flag is given
+fn zero_pad(sign: String, digits: String, spec: &Spec) -> String {
+    let length = sign.len() + digits.len();
+    if spec.zero_pad && !spec.left_justify && length < spec.width {
+        format!("{}{}{}", sign, "0".repeat(spec.width - length), digits)
+    } else {
+        sign + &digits
+    }
+}
+
+fn pad(text: String, spec: &Spec) -> String {
+    let length = text.chars().count();
+    if length >= spec.width {
+        text
+    } else if spec.left_justify {
+        text + &" ".repeat(spec.width - length)
+    } else {
+        " ".repeat(spec.width - length) + &text
+    }
+}
+
+/// Formats a real number the way SQLite's printf does: the value is rounded half away from
+/// zero, and at most 16 significant digits (26 with `!`) are produced, followed by zeros
+fn format_float(value: f64, conversion: char, spec: &Spec) -> String {
+    let mut precision = spec.precision.unwrap_or(6);
+    let sign = sign(value < 0.0, spec);
+    let value = value.abs();
+
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return zero_pad(sign.to_string(), "Inf".to_string(), spec);
+    }
+
+    let generic = matches!(conversion, 'g' | 'G');
+    let mut exponential = matches!(conversion, 'e' | 'E');
+    if generic && precision > 0 {
+        precision -= 1;
+    }
+
+    let (digits, exponent) = decimal_digits(value);
+    let last = if generic || exponential {
+        exponent - precision as i32
+    } else {
+        -(precision as i32)
+    };
+    let (digits, exponent) = round_digits(digits, exponent, last);
+
+    let remove_trailing_zeros = if generic {
+        if exponent < -4 || exponent > precision as i32 {
+            exponential = true;
+        } else {
+            precision = (precision as i32 - exponent) as usize;
+        }
+        !spec.alternate_form
+    } else {
+        spec.alternate_form2
+    };
+
+    // Digit of the given power of ten, as it is shown
+    let significant_digits = if spec.alternate_form2 { 26 } else { 16 };
+    let shown_exponent = if exponential { 0 } else { exponent };
+    let digit = |power: i32| match usize::try_from(shown_exponent - power) {
+        Ok(index) if index < significant_digits => {
+            (b'0' + digits.get(index).copied().unwrap_or(0)) as char
+        }
+        _ => '0',
+    };
+
+    let mut integer_part: String = if shown_exponent < 0 {
+        "0".to_string()
+    } else {
+        (0..=shown_exponent).rev().map(digit).collect()
+    };
+    if spec.thousands {
+        integer_part = group_thousands(&integer_part);
+    }
+    let mut fraction: String = (1..=precision as i32).map(|i| digit(-i)).collect();
+
+    let mut decimal_point = !fraction.is_empty() || spec.alternate_form || spec.alternate_form2;
+    if remove_trailing_zeros && decimal_point {
+        let trimmed = fraction.trim_end_matches('0').len();
+        fraction.truncate(trimmed);
+        if fraction.is_empty() {
+            if spec.alternate_form2 {
+                fraction.push('0');
+            } else {
+                decimal_point = false;
+            }
+        }
+    }
+
+    let mut text = integer_part;
+    if decimal_point {
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    if exponential {
+        text.push(if conversion.is_ascii_uppercase() {
+            'E'
+        } else {
+            'e'
+        });
+        text.push(if exponent < 0 { '-' } else { '+' });
+        text.push_str(&format!("{:02}", exponent.abs()));
+    }
+
+    zero_pad(sign.to_string(), text, spec)
+}
+
+/// Exact decimal digits of a positive number, and the power of ten of the first one
+fn decimal_digits(value: f64) -> (Vec<u8>, i32) {
+    if value == 0.0 {
+        return (vec![0], 0);
+    }
+    let scientific = format!("{:.39e}", value);
+    let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap_or(0));
+    let digits = mantissa
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .map(|digit| digit - b'0')
+        .collect();
+    (digits, exponent[1..].parse().unwrap_or(0))
+}
+
+/// Rounds digits half away from zero, keeping those down to the power of ten `last`
+fn round_digits(mut digits: Vec<u8>, exponent: i32, last: i32) -> (Vec<u8>, i32) {
+    let keep = exponent - last + 1;
+    if keep < 0 {
+        return (vec![0], 0);
+    }
+    let keep = keep as usize;
+    if keep >= digits.len() {
+        return (digits, exponent);
+    }
+
+    let round_up = digits[keep] >= 5;
+    digits.truncate(keep);
+    if !round_up {
+        return if digits.is_empty() {
+            (vec![0], 0)
+        } else {
+            (digits, exponent)
+        };
+    }
+
+    for digit in digits.iter_mut().rev() {
+        if *digit == 9 {
+            *digit = 0;
+        } else {
+            *digit += 1;
+            return (digits, exponent);
+        }
+    }
+    // Every digit was a 9, so rounding carried into a new leading digit
+    digits.insert(0, 1);
+    (digits, exponent + 1)
+}
@@ -0,0 +1,147 @@
+use super::Row;
+use crate::value::{Collation, Value};
+use anyhow::Result;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::rc::Rc;
+
+/// How one ORDER BY term orders its values
+#[derive(Debug, Copy, Clone)]
+pub struct SortOrder {
+    pub descending: bool,
+    pub nulls_first: bool,
+    pub collation: Collation,
+}
+
+impl SortOrder {
+    pub fn new(descending: bool, nulls_first: Option<bool>, collation: Collation) -> Self {
+        SortOrder {
+            descending,
+            // NULLs are the smallest values, so they come first unless sorting in descending order
+            nulls_first: nulls_first.unwrap_or(!descending),
+            collation,
+        }
+    }
+}
+
+/// Compares two sets of sort keys term by term
+pub fn compare_keys(orders: &[SortOrder], a: &[Value], b: &[Value]) -> Ordering {
+    for ((order, a), b) in orders.iter().zip(a).zip(b) {
+        let ordering = match (a.is_null(), b.is_null()) {
+            (true, true) => Ordering::Equal,
+            (true, false) if order.nulls_first => Ordering::Less,
+            (true, false) => Ordering::Greater,
+            (false, true) if order.nulls_first => Ordering::Greater,
+            (false, true) => Ordering::Less,
+            (false, false) if order.descending => b.compare(a, order.collation),
+            (false, false) => a.compare(b, order.collation),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+/// A row waiting to be sorted. Rows with equal keys keep the order they were produced in.
+struct SortEntry {
+    keys: Vec<Value>,
+    sequence: usize,
+    row: Row,
+    orders: Rc<[SortOrder]>,
+}
+
+impl Ord for SortEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_keys(&self.orders, &self.keys, &other.keys).then(self.sequence.cmp(&other.sequence))
+    }
+}
+
+impl PartialOrd for SortEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for SortEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SortEntry {}
+
+/// Sorts rows by their keys. When only the first `limit` rows are wanted, a heap holding at most
+/// `limit` rows is used instead of sorting every row.
+pub fn sort_rows(
+    rows: impl Iterator<Item = Result<(Vec<Value>, Row)>>,
+    orders: Vec<SortOrder>,
+    limit: Option<usize>,
+) -> Result<Vec<Row>> {
+    let orders: Rc<[SortOrder]> = orders.into();
+    let entries = rows.enumerate().map(|(sequence, row)| {
+        row.map(|(keys, row)| SortEntry {
+            keys,
+            sequence,
+            row,
+            orders: orders.clone(),
+        })
+    });
+
+    let sorted = match limit {
+        Some(limit) => {
+            // Max-heap of the best `limit` rows seen so far, so the worst of them can be evicted
+            let mut heap = BinaryHeap::new();
+            for entry in entries {
+                let entry = entry?;
+                if heap.len() < limit {
+                    heap.push(entry);
+                } else if let Some(mut worst) = heap.peek_mut() {
+                    if entry < *worst {
+                        *worst = entry;
+                    }
+                }
+            }
+            heap.into_sorted_vec()
+        }
+        None => {
+            let mut entries = entries.collect::<Result<Vec<_>>>()?;
+            entries.sort();
+            entries
+        }
+    };
+
+    Ok(sorted.into_iter().map(|entry| entry.row).collect())
+}
+
+/// Rows taken out in the order of their keys, and in the order they were added when their keys
+/// are equal. Without any ORDER BY terms, rows come out first in, first out.
+pub struct SortQueue {
+    heap: BinaryHeap<Reverse<SortEntry>>,
+    orders: Rc<[SortOrder]>,
+    sequence: usize,
+}
+
+impl SortQueue {
+    pub fn new(orders: Vec<SortOrder>) -> Self {
+        SortQueue {
+            heap: BinaryHeap::new(),
+            orders: orders.into(),
+            sequence: 0,
+        }
+    }
+
+    pub fn push(&mut self, keys: Vec<Value>, row: Row) {
+        self.heap.push(Reverse(SortEntry {
+            keys,
+            sequence: self.sequence,
+            row,
+            orders: self.orders.clone(),
+        }));
+        self.sequence += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<Row> {
+        self.heap.pop().map(|Reverse(entry)| entry.row)
+    }
+}
@@ -0,0 +1,180 @@
+use super::aggregate::KeyValue;
+use super::expr::Comparator;
+use super::{Plan, Rows};
+use crate::value::{Affinity, Collation, Value};
+use anyhow::Result;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+/// A subquery used as a value, with EXISTS, or on the right of IN. The result of a subquery that
+/// doesn't refer to the enclosing query is the same for every row, so it is only computed once.
+pub struct Subquery<'a> {
+    plan: Rc<Plan<'a>>,
+    cache: RefCell<Option<Cached>>,
+    /// The values an index lookup looks up, kept apart from `cache` since the rows the lookup
+    /// finds are then checked with `contains`
+    values: RefCell<Option<Rc<Vec<Value>>>>,
+}
+
+enum Cached {
+    Value(Value),
+    Exists(bool),
+    Set(Rc<ValueSet>),
+}
+
+/// The values of the first column of a subquery, converted and normalized for `IN`
+struct ValueSet {
+    values: HashSet<KeyValue>,
+    has_null: bool,
+}
+
+impl<'a> Subquery<'a> {
+    pub fn new(plan: Plan<'a>) -> Self {
+        Subquery {
+            plan: Rc::new(plan),
+            cache: RefCell::new(None),
+            values: RefCell::new(None),
+        }
+    }
+
+    pub fn plan(&self) -> &Rc<Plan<'a>> {
+        &self.plan
+    }
+
+    /// Whether the subquery refers to the columns of an enclosing query, so that it has to be
+    /// run again for every row
+    pub fn is_correlated(&self) -> bool {
+        self.plan.outer_depth > 0
+    }
+
+    /// Affinity of the first result column
+    pub fn affinity(&self) -> Option<Affinity> {
+        self.plan.column_types[0].0
+    }
+
+    /// Collating sequence of the first result column, when it has one of its own
+    pub fn collation(&self) -> Option<Collation> {
+        self.plan.column_types[0].1
+    }
+
+    /// The first column of the first row, or NULL when there are no rows
+    pub fn value(&self, row: &[Value]) -> Result<Value> {
+        if let Some(Cached::Value(value)) = &*self.cache.borrow() {
+            return Ok(value.clone());
+        }
+        let value = match self.rows(row)?.next() {
+            Some(first) => first?.swap_remove(0),
+            None => Value::Null,
+        };
+        self.store(|| Cached::Value(value.clone()));
+        Ok(value)
+    }
+
+    pub fn exists(&self, row: &[Value]) -> Result<bool> {
+        if let Some(Cached::Exists(exists)) = &*self.cache.borrow() {
+            return Ok(*exists);
+        }
+        let exists = match self.rows(row)?.next() {
+            Some(first) => first.map(|_| true)?,
+            None => false,
+        };
+        self.store(|| Cached::Exists(exists));
+        Ok(exists)
+    }
+
+    /// Whether the subquery returns `value`, compared with `comparator`. NULL (None) when the
+    /// value is NULL, or when it isn't found and the subquery returns NULL.
+    pub fn contains(
+        &self,
+        row: &[Value],
+        value: &Value,
+        comparator: &Comparator,
+    ) -> Result<Option<bool>> {
+        let cached = match &*self.cache.borrow() {
+            Some(Cached::Set(set)) => Some(set.clone()),
+            _ => None,
+        };
+        let set = match cached {
+            Some(set) => set,
+            None => {
+                let mut set = ValueSet {
+                    values: HashSet::new(),
+                    has_null: false,
+                };
+                for result in self.rows(row)? {
+                    let value = result?.swap_remove(0);
+                    if value.is_null() {
+                        set.has_null = true;
+                        continue;
+                    }
+                    let value = match comparator.right_affinity {
+                        Some(affinity) => value.apply_affinity(affinity),
+                        None => value,
+                    };
+                    set.values
+                        .insert(KeyValue::new(&value, comparator.collation));
+                }
+                let set = Rc::new(set);
+                self.store(|| Cached::Set(set.clone()));
+                set
+            }
+        };
+
+        if value.is_null() {
+            let is_empty = set.values.is_empty() && !set.has_null;
+            return Ok(if is_empty { Some(false) } else { None });
+        }
+        let value = match comparator.left_affinity {
+            Some(affinity) => value.clone().apply_affinity(affinity),
+            None => value.clone(),
+        };
+        if set
+            .values
+            .contains(&KeyValue::new(&value, comparator.collation))
+        {
+            Ok(Some(true))
+        } else if set.has_null {
+            Ok(None)
+        } else {
+            Ok(Some(false))
+        }
+    }
+
+    /// The values of the first column, for IN lookups
+    pub fn values(&self, row: &[Value]) -> Result<Rc<Vec<Value>>> {
+        if let Some(values) = &*self.values.borrow() {
+            return Ok(values.clone());
+        }
+        let values = self
+            .rows(row)?
+            .map(|result| result.map(|mut row| row.swap_remove(0)))
+            .collect::<Result<Vec<_>>>()?;
+        let values = Rc::new(values);
+        if !self.is_correlated() {
+            *self.values.borrow_mut() = Some(values.clone());
+        }
+        Ok(values)
+    }
+
+    /// Runs the subquery for a row of the enclosing query
+    fn rows(&self, row: &[Value]) -> Result<Rows<'a>> {
+        self.plan.clone().run(&row[..self.plan.outer_width])
+    }
+
+    fn store(&self, cached: impl FnOnce() -> Cached) {
+        if !self.is_correlated() {
+            *self.cache.borrow_mut() = Some(cached());
+        }
+    }
+}
+
+impl fmt::Debug for Subquery<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subquery")
+            .field("columns", &self.plan.columns)
+            .field("correlated", &self.is_correlated())
+            .finish()
+    }
+}
@@ -0,0 +1,624 @@
+use super::aggregate::{Accumulator, AggregateCall};
+use super::expr::{Binder, BoundExpr, Scope};
+use super::sort::{compare_keys, sort_rows, SortOrder};
+use super::{project, Row, Rows};
+use crate::ast::{
+    Expr, FrameBound, FrameExclude, FrameUnits, OrderingTerm, Over, WindowDefinition,
+};
+use crate::database::Database;
+use crate::value::{Affinity, Value};
+use anyhow::{bail, Result};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ops::Range;
+
+/// Functions that can only be called with OVER, like
+/// [the built-in window functions](https://www.sqlite.org/windowfunctions.html#builtins)
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WindowFunction {
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+impl WindowFunction {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "row_number" => WindowFunction::RowNumber,
+            "rank" => WindowFunction::Rank,
+            "dense_rank" => WindowFunction::DenseRank,
+            "percent_rank" => WindowFunction::PercentRank,
+            "cume_dist" => WindowFunction::CumeDist,
+            "ntile" => WindowFunction::Ntile,
+            "lag" => WindowFunction::Lag,
+            "lead" => WindowFunction::Lead,
+            "first_value" => WindowFunction::FirstValue,
+            "last_value" => WindowFunction::LastValue,
+            "nth_value" => WindowFunction::NthValue,
+            _ => return None,
+        })
+    }
+
+    /// Whether the function accepts that many arguments
+    pub fn accepts(self, arg_count: usize) -> bool {
+        match self {
+            WindowFunction::RowNumber
+            | WindowFunction::Rank
+            | WindowFunction::DenseRank
+            | WindowFunction::PercentRank
+            | WindowFunction::CumeDist => arg_count == 0,
+            WindowFunction::Ntile | WindowFunction::FirstValue | WindowFunction::LastValue => {
+                arg_count == 1
+            }
+            WindowFunction::Lag | WindowFunction::Lead => (1..=3).contains(&arg_count),
+            WindowFunction::NthValue => arg_count == 2,
+        }
+    }
+}
+
+/// What a window function call computes for each row
+#[derive(Debug, Clone)]
+pub enum WindowCallKind<'a> {
+    /// A built-in window function and its arguments
+    Builtin(WindowFunction, Vec<BoundExpr<'a>>),
+    /// An aggregate function computed over the frame of each row
+    Aggregate(AggregateCall<'a>),
+}
+
+/// A window function call, bound against the rows the windows are computed over
+#[derive(Debug, Clone)]
+pub struct WindowCall<'a> {
+    pub kind: WindowCallKind<'a>,
+    pub window: Window<'a>,
+}
+
+impl<'a> WindowCall<'a> {
+    /// The expressions the call reads from each row: its arguments, its FILTER clause and the
+    /// PARTITION BY and ORDER BY terms of its window
+    pub fn exprs(&self) -> Vec<&BoundExpr<'a>> {
+        let args: Vec<_> = match &self.kind {
+            WindowCallKind::Builtin(_, args) => args.iter().collect(),
+            WindowCallKind::Aggregate(call) => call.args.iter().chain(&call.filter).collect(),
+        };
+        args.into_iter().chain(&self.window.keys).collect()
+    }
+
+    pub fn exprs_mut(&mut self) -> Vec<&mut BoundExpr<'a>> {
+        let args: Vec<_> = match &mut self.kind {
+            WindowCallKind::Builtin(_, args) => args.iter_mut().collect(),
+            WindowCallKind::Aggregate(call) => {
+                call.args.iter_mut().chain(&mut call.filter).collect()
+            }
+        };
+        args.into_iter().chain(&mut self.window.keys).collect()
+    }
+}
+
+/// How the rows are split into partitions and sorted within them, and which rows of its
+/// partition the frame of each row holds
+#[derive(Debug, Clone)]
+pub struct Window<'a> {
+    /// The PARTITION BY terms followed by the ORDER BY terms
+    keys: Vec<BoundExpr<'a>>,
+    orders: Vec<SortOrder>,
+    /// Number of PARTITION BY terms at the start of `keys`
+    partition_len: usize,
+    frame: Frame,
+    /// The terms as written, which tell whether two windows sort the rows the same way
+    terms: (Vec<Expr>, Vec<OrderingTerm>),
+}
+
+#[derive(Debug, Clone)]
+struct Frame {
+    units: FrameUnits,
+    start: Bound,
+    end: Bound,
+    exclude: FrameExclude,
+}
+
+/// A frame bound, with its offset evaluated
+#[derive(Debug, Copy, Clone)]
+enum Bound {
+    UnboundedPreceding,
+    Preceding(f64),
+    CurrentRow,
+    Following(f64),
+    UnboundedFollowing,
+}
+
+/// The definition of the window a call is computed over, with the windows it is based on
+/// filled in from the WINDOW clause
+pub fn resolve(over: &Over, windows: &[(String, WindowDefinition)]) -> Result<WindowDefinition> {
+    match over {
+        Over::Window(name) => named_window(name, windows, 0),
+        Over::Definition(definition) => extend_window(definition, windows, 0),
+    }
+}
+
+fn named_window(
+    name: &str,
+    windows: &[(String, WindowDefinition)],
+    depth: usize,
+) -> Result<WindowDefinition> {
+    match windows
+        .iter()
+        .find(|(window, _)| window.eq_ignore_ascii_case(name))
+    {
+        // A window can't be based on itself, even through other windows
+        Some((_, definition)) if depth < windows.len() => {
+            extend_window(definition, windows, depth + 1)
+        }
+        _ => bail!("no such window: {}", name),
+    }
+}
+
+/// Adds the clauses of a definition to those of the window it is based on, which it may only
+/// add an ORDER BY clause and a frame to
+fn extend_window(
+    definition: &WindowDefinition,
+    windows: &[(String, WindowDefinition)],
+    depth: usize,
+) -> Result<WindowDefinition> {
+    let name = match &definition.base {
+        Some(name) => name,
+        None => return Ok(definition.clone()),
+    };
+    let base = named_window(name, windows, depth)?;
+    if !definition.partition_by.is_empty() {
+        bail!("cannot override PARTITION clause of window: {}", name);
+    }
+    if !definition.order_by.is_empty() && !base.order_by.is_empty() {
+        bail!("cannot override ORDER BY clause of window: {}", name);
+    }
+    if base.frame.is_some() {
+        bail!("cannot override frame specification of window: {}", name);
+    }
+    Ok(WindowDefinition {
+        base: None,
+        partition_by: base.partition_by,
+        order_by: if definition.order_by.is_empty() {
+            base.order_by
+        } else {
+            definition.order_by.clone()
+        },
+        frame: definition.frame.clone(),
+    })
+}
+
+impl<'a> Window<'a> {
+    /// Binds the terms of a window like the arguments of its call. Frame offsets are constants.
+    pub fn prepare(
+        db: &'a Database,
+        binder: &Binder<'_, 'a>,
+        definition: &WindowDefinition,
+    ) -> Result<Self> {
+        let mut keys = vec![];
+        let mut orders = vec![];
+        for expr in &definition.partition_by {
+            keys.push(binder.bind(expr)?);
+            orders.push(SortOrder::new(false, None, binder.collation(expr).0));
+        }
+        for term in &definition.order_by {
+            keys.push(binder.bind(&term.expr)?);
+            orders.push(SortOrder::new(
+                term.descending,
+                term.nulls_first,
+                binder.collation(&term.expr).0,
+            ));
+        }
+
+        // Without a frame, the frame of a row ends with its last peer
+        let frame = match &definition.frame {
+            Some(frame) => {
+                let has_offset = |bound: &FrameBound| {
+                    matches!(bound, FrameBound::Preceding(_) | FrameBound::Following(_))
+                };
+                if matches!(
+                    (&frame.start, &frame.end),
+                    (FrameBound::CurrentRow, FrameBound::Preceding(_))
+                        | (FrameBound::Following(_), FrameBound::Preceding(_))
+                        | (FrameBound::Following(_), FrameBound::CurrentRow)
+                ) {
+                    bail!("unsupported frame specification");
+                }
+                if frame.units == FrameUnits::Range
+                    && (has_offset(&frame.start) || has_offset(&frame.end))
+                    && definition.order_by.len() != 1
+                {
+                    bail!("RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression");
+                }
+                Frame {
+                    units: frame.units,
+                    start: prepare_bound(db, &frame.start, frame.units, "starting")?,
+                    end: prepare_bound(db, &frame.end, frame.units, "ending")?,
+                    exclude: frame.exclude,
+                }
+            }
+            None => Frame {
+                units: FrameUnits::Range,
+                start: Bound::UnboundedPreceding,
+                end: Bound::CurrentRow,
+                exclude: FrameExclude::NoOthers,
+            },
+        };
+
+        Ok(Window {
+            keys,
+            orders,
+            partition_len: definition.partition_by.len(),
+            frame,
+            terms: (definition.partition_by.clone(), definition.order_by.clone()),
+        })
+    }
+
+    /// Sorts the rows by partition, and within each partition by the ORDER BY terms
+    fn sort(&self, rows: Vec<Row>) -> Result<Vec<Row>> {
+        if self.keys.is_empty() {
+            return Ok(rows);
+        }
+        let rows = rows
+            .into_iter()
+            .map(|row| Ok((project(&self.keys, &row)?, row)));
+        sort_rows(rows, self.orders.clone(), None)
+    }
+}
+
+/// Evaluates the offset of a frame bound, which must not be negative. ROWS and GROUPS count
+/// whole rows and peer groups.
+fn prepare_bound(
+    db: &Database,
+    bound: &FrameBound,
+    units: FrameUnits,
+    which: &str,
+) -> Result<Bound> {
+    let offset = |expr: &Expr| -> Result<f64> {
+        let empty_scope = Scope::default();
+        let value = Binder::new(db, &empty_scope).bind(expr)?.eval_constant()?;
+        match (units, value.apply_affinity(Affinity::Numeric)) {
+            (_, Value::Integer(v)) if v >= 0 => Ok(v as f64),
+            (FrameUnits::Range, Value::Real(v)) if v >= 0.0 => Ok(v),
+            (FrameUnits::Range, _) => bail!("frame {} offset must be a non-negative number", which),
+            _ => bail!("frame {} offset must be a non-negative integer", which),
+        }
+    };
+    Ok(match bound {
+        FrameBound::UnboundedPreceding => Bound::UnboundedPreceding,
+        FrameBound::Preceding(expr) => Bound::Preceding(offset(expr)?),
+        FrameBound::CurrentRow => Bound::CurrentRow,
+        FrameBound::Following(expr) => Bound::Following(offset(expr)?),
+        FrameBound::UnboundedFollowing => Bound::UnboundedFollowing,
+    })
+}
+
+/// The window function calls of a query, computed like
+/// [window functions](https://www.sqlite.org/windowfunctions.html) once the WHERE clause,
+/// GROUP BY and HAVING have been applied
+pub struct Windows<'a> {
+    pub calls: Vec<WindowCall<'a>>,
+    /// Number of values in an input row. The values of the calls are added after them.
+    pub width: usize,
+}
+
+impl<'a> Windows<'a> {
+    /// Whether computing the calls sorts the rows, which windows without PARTITION BY or
+    /// ORDER BY don't need
+    pub fn sorts(&self) -> bool {
+        self.calls.iter().any(|call| !call.window.keys.is_empty())
+    }
+
+    /// Adds the value of every call to each row. The rows come out sorted by the window of the
+    /// first call.
+    pub fn run<'r>(&self, rows: Rows<'r>) -> Result<Rows<'r>> {
+        let width = self.width + self.calls.len();
+        let mut rows = rows
+            .map(|row| {
+                row.map(|mut row| {
+                    row.resize(width, Value::Null);
+                    row
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The calls are computed from the last to the first, and rows that are already sorted
+        // the right way aren't sorted again
+        let mut sorted_by: Option<&Window> = None;
+        for (i, call) in self.calls.iter().enumerate().rev() {
+            let window = &call.window;
+            if !matches!(sorted_by, Some(sorted_by) if sorted_by.terms == window.terms) {
+                rows = window.sort(rows)?;
+                sorted_by = Some(window);
+            }
+
+            let keys = rows
+                .iter()
+                .map(|row| project(&window.keys, row))
+                .collect::<Result<Vec<_>>>()?;
+            let partition_orders = &window.orders[..window.partition_len];
+            let mut start = 0;
+            while start < rows.len() {
+                let end = (start + 1..rows.len())
+                    .find(|&j| {
+                        compare_keys(partition_orders, &keys[start], &keys[j]) != Ordering::Equal
+                    })
+                    .unwrap_or(rows.len());
+                let partition = Partition::new(window, &rows[start..end], &keys[start..end]);
+                let values = partition.values(call)?;
+                for (row, value) in rows[start..end].iter_mut().zip(values) {
+                    row[self.width + i] = value;
+                }
+                start = end;
+            }
+        }
+
+        Ok(Box::new(rows.into_iter().map(Ok)))
+    }
+}
+
+/// The rows of one partition, in the order of the window
+struct Partition<'p> {
+    rows: &'p [Row],
+    frame: &'p Frame,
+    /// Values of the ORDER BY terms of each row
+    keys: Vec<&'p [Value]>,
+    descending: bool,
+    /// The peer group of each row. Rows are peers when their ORDER BY terms are equal, and all
+    /// rows are peers without ORDER BY.
+    groups: Vec<usize>,
+    /// Where each peer group starts, followed by the number of rows
+    group_starts: Vec<usize>,
+    /// The rows whose first ORDER BY term is a number, which RANGE offsets apply to
+    numbers: Range<usize>,
+}
+
+impl<'p> Partition<'p> {
+    fn new(window: &'p Window, rows: &'p [Row], keys: &'p [Vec<Value>]) -> Self {
+        let orders = &window.orders[window.partition_len..];
+        let keys: Vec<_> = keys
+            .iter()
+            .map(|key| &key[window.partition_len..])
+            .collect();
+
+        let mut groups = Vec::with_capacity(rows.len());
+        let mut group_starts = vec![];
+        for i in 0..rows.len() {
+            if i == 0 || compare_keys(orders, keys[i - 1], keys[i]) != Ordering::Equal {
+                group_starts.push(i);
+            }
+            groups.push(group_starts.len() - 1);
+        }
+        group_starts.push(rows.len());
+
+        let is_number = |i: &usize| {
+            matches!(
+                keys[*i].first(),
+                Some(Value::Integer(_)) | Some(Value::Real(_))
+            )
+        };
+        let start = (0..rows.len()).find(is_number).unwrap_or(rows.len());
+        let end = (start..rows.len())
+            .find(|i| !is_number(i))
+            .unwrap_or(rows.len());
+
+        Partition {
+            rows,
+            frame: &window.frame,
+            descending: matches!(orders.first(), Some(order) if order.descending),
+            keys,
+            groups,
+            group_starts,
+            numbers: start..end,
+        }
+    }
+
+    fn values(&self, call: &WindowCall) -> Result<Vec<Value>> {
+        match &call.kind {
+            WindowCallKind::Builtin(function, args) => (0..self.rows.len())
+                .map(|i| self.builtin(*function, args, i))
+                .collect(),
+            WindowCallKind::Aggregate(aggregate) => self.aggregate(aggregate),
+        }
+    }
+
+    fn builtin(&self, function: WindowFunction, args: &[BoundExpr], i: usize) -> Result<Value> {
+        let row = &self.rows[i];
+        let len = self.rows.len();
+        let peers = self.peers(i);
+        Ok(match function {
+            WindowFunction::RowNumber => Value::Integer(i as i64 + 1),
+            WindowFunction::Rank => Value::Integer(peers.start as i64 + 1),
+            WindowFunction::DenseRank => Value::Integer(self.groups[i] as i64 + 1),
+            WindowFunction::PercentRank if len > 1 => {
+                Value::Real(peers.start as f64 / (len - 1) as f64)
+            }
+            WindowFunction::PercentRank => Value::Real(0.0),
+            WindowFunction::CumeDist => Value::Real(peers.end as f64 / len as f64),
+            WindowFunction::Ntile => {
+                let buckets = match args[0].eval(row)?.apply_affinity(Affinity::Integer) {
+                    Value::Integer(v) if v > 0 => usize::try_from(v).unwrap_or(usize::MAX),
+                    _ => bail!("argument of ntile must be a positive integer"),
+                };
+                // The first buckets get one row more when the rows don't divide evenly
+                let size = len / buckets;
+                let larger = len % buckets;
+                let bucket = if size == 0 || i < larger * (size + 1) {
+                    i / (size + 1)
+                } else {
+                    larger + (i - larger * (size + 1)) / size
+                };
+                Value::Integer(bucket as i64 + 1)
+            }
+            WindowFunction::Lag | WindowFunction::Lead => {
+                let offset = match args.get(1) {
+                    Some(offset) => match offset.eval(row)?.to_integer() {
+                        Some(offset) => offset,
+                        None => return Ok(Value::Null),
+                    },
+                    None => 1,
+                };
+                let target = match function {
+                    WindowFunction::Lag => (i as i64).checked_sub(offset),
+                    _ => (i as i64).checked_add(offset),
+                };
+                match target.and_then(|target| usize::try_from(target).ok()) {
+                    Some(target) if target < len => args[0].eval(&self.rows[target])?,
+                    _ => match args.get(2) {
+                        Some(default) => default.eval(row)?,
+                        None => Value::Null,
+                    },
+                }
+            }
+            WindowFunction::FirstValue => self.value_at(&args[0], self.frame_rows(i).next())?,
+            WindowFunction::LastValue => self.value_at(&args[0], self.frame_rows(i).next_back())?,
+            WindowFunction::NthValue => {
+                let n = match args[1].eval(row)?.apply_affinity(Affinity::Integer) {
+                    Value::Integer(v) if v > 0 => usize::try_from(v).unwrap_or(usize::MAX),
+                    _ => bail!("second argument to nth_value must be a positive integer"),
+                };
+                self.value_at(&args[0], self.frame_rows(i).nth(n - 1))?
+            }
+        })
+    }
+
+    /// Evaluates an argument against a row of the frame, or NULL when there is no such row
+    fn value_at(&self, arg: &BoundExpr, row: Option<usize>) -> Result<Value> {
+        match row {
+            Some(row) => arg.eval(&self.rows[row]),
+            None => Ok(Value::Null),
+        }
+    }
+
+    fn aggregate(&self, call: &AggregateCall) -> Result<Vec<Value>> {
+        let mut values = Vec::with_capacity(self.rows.len());
+        if matches!(self.frame.start, Bound::UnboundedPreceding)
+            && self.frame.exclude == FrameExclude::NoOthers
+        {
+            // Frames that start with the partition only grow, so each row is added once, when
+            // the first frame reaches it
+            let mut accumulator = Accumulator::new(call.function);
+            let mut added = 0;
+            for i in 0..self.rows.len() {
+                let end = self.frame_range(i).end;
+                while added < end {
+                    self.step(&mut accumulator, call, added)?;
+                    added += 1;
+                }
+                values.push(accumulator.clone().finish(call.function)?);
+            }
+        } else {
+            for i in 0..self.rows.len() {
+                let mut accumulator = Accumulator::new(call.function);
+                for j in self.frame_rows(i) {
+                    self.step(&mut accumulator, call, j)?;
+                }
+                values.push(accumulator.finish(call.function)?);
+            }
+        }
+        Ok(values)
+    }
+
+    fn step(&self, accumulator: &mut Accumulator, call: &AggregateCall, i: usize) -> Result<()> {
+        let row = &self.rows[i];
+        if let Some(filter) = &call.filter {
+            if filter.eval(row)?.truth() != Some(true) {
+                return Ok(());
+            }
+        }
+        accumulator.step(call, &project(&call.args, row)?);
+        Ok(())
+    }
+
+    fn peers(&self, i: usize) -> Range<usize> {
+        let group = self.groups[i];
+        self.group_starts[group]..self.group_starts[group + 1]
+    }
+
+    /// The rows in the frame of a row, without those its EXCLUDE clause leaves out
+    fn frame_rows(&self, i: usize) -> impl DoubleEndedIterator<Item = usize> {
+        let peers = self.peers(i);
+        let exclude = self.frame.exclude;
+        self.frame_range(i).filter(move |j| match exclude {
+            FrameExclude::NoOthers => true,
+            FrameExclude::CurrentRow => *j != i,
+            FrameExclude::Group => !peers.contains(j),
+            FrameExclude::Ties => *j == i || !peers.contains(j),
+        })
+    }
+
+    fn frame_range(&self, i: usize) -> Range<usize> {
+        let start = self.bound_position(i, self.frame.start, false);
+        let end = self.bound_position(i, self.frame.end, true);
+        start..end.max(start)
+    }
+
+    /// The first row of the frame for its start bound, or the row after the last one for its
+    /// end bound
+    fn bound_position(&self, i: usize, bound: Bound, end: bool) -> usize {
+        let len = self.rows.len();
+        let group = self.groups[i];
+        let edge = |group: usize| self.group_starts[group + end as usize];
+        let row = i + end as usize;
+        match (self.frame.units, bound) {
+            (_, Bound::UnboundedPreceding) => 0,
+            (_, Bound::UnboundedFollowing) => len,
+            (FrameUnits::Rows, Bound::CurrentRow) => row,
+            (_, Bound::CurrentRow) => edge(group),
+            (FrameUnits::Rows, Bound::Preceding(offset)) => row.saturating_sub(offset as usize),
+            (FrameUnits::Rows, Bound::Following(offset)) => {
+                row.saturating_add(offset as usize).min(len)
+            }
+            (FrameUnits::Groups, Bound::Preceding(offset)) => {
+                match group.checked_sub(offset as usize) {
+                    Some(group) => edge(group),
+                    None => 0,
+                }
+            }
+            (FrameUnits::Groups, Bound::Following(offset)) => {
+                match group.checked_add(offset as usize) {
+                    Some(group) if group + 1 < self.group_starts.len() => edge(group),
+                    _ => len,
+                }
+            }
+            (FrameUnits::Range, Bound::Preceding(offset)) => self
+                .range_position(i, -offset, end)
+                .unwrap_or_else(|| edge(group)),
+            (FrameUnits::Range, Bound::Following(offset)) => self
+                .range_position(i, offset, end)
+                .unwrap_or_else(|| edge(group)),
+        }
+    }
+
+    /// Where the rows whose ORDER BY term is `offset` away from that of row `i` start, or end.
+    /// Rows whose term isn't a number only have their peers in range.
+    fn range_position(&self, i: usize, offset: f64, end: bool) -> Option<usize> {
+        let value = match self.keys[i][0] {
+            Value::Integer(v) => v as f64,
+            Value::Real(v) => v,
+            _ => return None,
+        };
+        let numbers = &self.keys[self.numbers.clone()];
+        let number = |key: &&[Value]| key[0].to_real().unwrap_or(0.0);
+        // Following rows have larger values, or smaller ones in descending order
+        let position = if self.descending {
+            let target = value - offset;
+            match end {
+                false => numbers.partition_point(|key| number(key) > target),
+                true => numbers.partition_point(|key| number(key) >= target),
+            }
+        } else {
+            let target = value + offset;
+            match end {
+                false => numbers.partition_point(|key| number(key) < target),
+                true => numbers.partition_point(|key| number(key) <= target),
+            }
+        };
+        Some(self.numbers.start + position)
+    }
+}
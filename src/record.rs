@@ -1,4 +1,5 @@
-use crate::varint::parse_varint;
+use crate::value::Value;
+use crate::varint::{encode_varint, parse_varint};
 use anyhow::{bail, Result};
 use std::fmt::Display;
 
@@ -10,13 +11,16 @@ pub struct RecordMeta {
 
 /// Reads SQLite's "Record Format" as mentioned here:
 /// [record_format](https://www.sqlite.org/fileformat.html#record_format)
-pub fn parse_record(stream: &[u8], column_count: usize) -> Result<Vec<ColumnValue>> {
+///
+/// Reads at most `column_count` values. A record has fewer when it was written before columns
+/// were added to its table.
+pub fn parse_record(stream: &[u8], column_count: usize) -> Result<Vec<ColumnValue<'_>>> {
     // Parse number of bytes in header, and use bytes_read as offset
     let (header_size, mut offset) = parse_varint(stream);
 
     // Read each varint into serial types and modify the offset
     let mut serial_types = vec![];
-    for _ in 0..column_count {
+    while serial_types.len() < column_count && offset < header_size {
         let (varint, read_bytes) = parse_varint(&stream[offset..]);
         offset += read_bytes;
         serial_types.push(varint);
@@ -26,7 +30,7 @@ pub fn parse_record(stream: &[u8], column_count: usize) -> Result<Vec<ColumnValu
     // Parse each serial type as column into record and modify the offset
     let mut record = vec![];
     for serial_type in serial_types {
-        let column = parse_column_value(&stream[offset..], serial_type as usize)?;
+        let column = parse_column_value(&stream[offset..], serial_type)?;
         offset += column.length();
         record.push(column);
     }
@@ -34,6 +38,56 @@ pub fn parse_record(stream: &[u8], column_count: usize) -> Result<Vec<ColumnValu
     Ok(record)
 }
 
+/// Encodes values in SQLite's "Record Format", so that they can be read back with `parse_record`
+pub fn encode_record(values: &[Value]) -> Vec<u8> {
+    let mut header = vec![];
+    let mut body = vec![];
+
+    for value in values {
+        let serial_type = match value {
+            Value::Null => 0,
+            Value::Integer(0) => 8,
+            Value::Integer(1) => 9,
+            Value::Integer(v) => {
+                let (serial_type, width) = match *v {
+                    -0x80..=0x7f => (1, 1),
+                    -0x8000..=0x7fff => (2, 2),
+                    -0x80_0000..=0x7f_ffff => (3, 3),
+                    -0x8000_0000..=0x7fff_ffff => (4, 4),
+                    -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+                    _ => (6, 8),
+                };
+                body.extend_from_slice(&v.to_be_bytes()[8 - width..]);
+                serial_type
+            }
+            Value::Real(v) => {
+                body.extend_from_slice(&v.to_be_bytes());
+                7
+            }
+            Value::Text(v) => {
+                body.extend_from_slice(v.as_bytes());
+                v.len() * 2 + 13
+            }
+            Value::Blob(v) => {
+                body.extend_from_slice(v);
+                v.len() * 2 + 12
+            }
+        };
+        header.extend(encode_varint(serial_type as u64));
+    }
+
+    // The header size includes the varint holding it
+    let mut header_size = header.len() + 1;
+    while encode_varint(header_size as u64).len() + header.len() > header_size {
+        header_size += 1;
+    }
+
+    let mut record = encode_varint(header_size as u64);
+    record.extend(header);
+    record.extend(body);
+    record
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum ColumnValue<'a> {
     Null,
@@ -72,8 +126,8 @@ impl<'a> ColumnValue<'a> {
         match self {
             ColumnValue::U8(v) => *v as u32,
             ColumnValue::U16(v) => *v as u32,
-            ColumnValue::U24(v) => *v as u32,
-            ColumnValue::U32(v) => *v as u32,
+            ColumnValue::U24(v) => *v,
+            ColumnValue::U32(v) => *v,
             ColumnValue::True => 1,
             ColumnValue::False => 0,
             v => {
@@ -117,12 +171,12 @@ impl<'a> Display for ColumnValue<'a> {
             ColumnValue::False => f.write_str("false"),
             ColumnValue::True => f.write_str("true"),
             ColumnValue::Blob(v) => f.write_fmt(format_args!("{:?}", v)),
-            ColumnValue::Text(v) => f.write_str(&String::from_utf8(v.to_vec()).unwrap()),
+            ColumnValue::Text(v) => f.write_str(&String::from_utf8_lossy(v)),
         }
     }
 }
 
-fn parse_column_value(stream: &[u8], serial_type: usize) -> Result<ColumnValue> {
+fn parse_column_value(stream: &[u8], serial_type: usize) -> Result<ColumnValue<'_>> {
     Ok(match serial_type {
         0 => ColumnValue::Null,
         // 8 bit twos-complement integer
@@ -135,17 +189,30 @@ fn parse_column_value(stream: &[u8], serial_type: usize) -> Result<ColumnValue>
             stream[0], stream[1], stream[2], stream[3],
         ])),
 
+        5 => ColumnValue::U48(u64::from_be_bytes([
+            0, 0, stream[0], stream[1], stream[2], stream[3], stream[4], stream[5],
+        ])),
+
+        6 => ColumnValue::U64(u64::from_be_bytes([
+            stream[0], stream[1], stream[2], stream[3], stream[4], stream[5], stream[6], stream[7],
+        ])),
+
+        // IEEE 754-2008 64-bit floating point number
+        7 => ColumnValue::FP64(f64::from_be_bytes([
+            stream[0], stream[1], stream[2], stream[3], stream[4], stream[5], stream[6], stream[7],
+        ])),
+
         8 => ColumnValue::False,
         9 => ColumnValue::True,
 
         // Text encoding
-        n if serial_type >= 12 && serial_type % 2 == 0 => {
+        n if serial_type >= 12 && serial_type & 1 == 0 => {
             let n_bytes = (n - 12) / 2;
-            ColumnValue::Blob(&stream[0..n_bytes as usize])
+            ColumnValue::Blob(&stream[0..n_bytes])
         }
-        n if serial_type >= 13 && serial_type % 2 == 1 => {
+        n if serial_type >= 13 && serial_type & 1 == 1 => {
             let n_bytes = (n - 13) / 2;
-            let a = &stream[0..n_bytes as usize];
+            let a = &stream[0..n_bytes];
 
             ColumnValue::Text(a)
         }
@@ -1,4 +1,8 @@
+use crate::ast::{ColumnConstraint, CreateIndex, CreateView, Expr, TableConstraint, UnaryOperator};
+use crate::parser::{parse_create_index, parse_create_table, parse_create_view};
 use crate::record::ColumnValue;
+use crate::value::{Affinity, Collation, Value};
+use anyhow::{anyhow, bail, Result};
 
 #[derive(Debug)]
 pub struct Schema {
@@ -28,4 +32,188 @@ impl Schema {
         };
         Some(schema)
     }
+
+    /// Parses the CREATE TABLE statement of a table
+    pub fn table_definition(&self) -> Result<TableDefinition> {
+        let create_table = parse_create_table(&self.sql)
+            .map_err(|e| anyhow!("malformed database schema ({}) - {}", self.name, e))?;
+
+        let primary_key = create_table
+            .constraints
+            .iter()
+            .find_map(|constraint| match constraint {
+                TableConstraint::PrimaryKey(columns) => Some(columns),
+                _ => None,
+            });
+
+        let mut rowid_alias = None;
+        let mut column_key = None;
+        let columns = create_table
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                let type_name = column.type_name.clone().unwrap_or_default();
+                let affinity = Affinity::from_type_name(&type_name);
+                let mut collation = Collation::Binary;
+                let mut default = Value::Null;
+                let mut generated = None;
+
+                for constraint in &column.constraints {
+                    match constraint {
+                        // Only a column declared exactly as "INTEGER PRIMARY KEY" aliases the
+                        // rowid. "INTEGER PRIMARY KEY DESC" famously doesn't.
+                        ColumnConstraint::PrimaryKey { descending, .. } => {
+                            if type_name.eq_ignore_ascii_case("INTEGER") && !descending {
+                                rowid_alias = Some(i);
+                            }
+                            column_key = Some((i, *descending));
+                        }
+                        ColumnConstraint::Collate(name) => {
+                            collation = Collation::from_name(name)
+                                .ok_or_else(|| anyhow!("no such collation sequence: {}", name))?
+                        }
+                        ColumnConstraint::Generated { expr, stored } => {
+                            generated = Some((expr.clone(), *stored))
+                        }
+                        ColumnConstraint::Default(expr) => {
+                            default = default_value(expr).apply_affinity(affinity)
+                        }
+                        _ => {}
+                    }
+                }
+
+                Ok(Column {
+                    name: column.name.clone(),
+                    affinity,
+                    type_name,
+                    collation,
+                    default,
+                    generated,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let position = |name: &str| {
+            columns
+                .iter()
+                .position(|column| column.name.eq_ignore_ascii_case(name))
+        };
+        if let Some([key]) = primary_key.map(Vec::as_slice) {
+            if let Some(i) = key.column_name().and_then(position) {
+                if columns[i].type_name.eq_ignore_ascii_case("INTEGER") && !key.descending {
+                    rowid_alias = Some(i);
+                }
+            }
+        }
+
+        // A WITHOUT ROWID table has its PRIMARY KEY columns first, each only once
+        let mut stored_columns = vec![];
+        let primary_key = match create_table.without_rowid {
+            true => {
+                rowid_alias = None;
+                let mut key = vec![];
+                if let Some((i, descending)) = column_key {
+                    key.push((i, columns[i].collation, descending));
+                }
+                for indexed in primary_key.into_iter().flatten() {
+                    let i = indexed
+                        .column_name()
+                        .and_then(position)
+                        .ok_or_else(|| anyhow!("expressions prohibited in PRIMARY KEY"))?;
+                    let collation = match &indexed.collation {
+                        Some(name) => Collation::from_name(name)
+                            .ok_or_else(|| anyhow!("no such collation sequence: {}", name))?,
+                        None => columns[i].collation,
+                    };
+                    if !key.iter().any(|&(other, _, _)| other == i) {
+                        key.push((i, collation, indexed.descending));
+                    }
+                }
+                if key.is_empty() {
+                    bail!("PRIMARY KEY missing on table {}", create_table.name);
+                }
+                stored_columns.extend(key.iter().map(|&(i, _, _)| i));
+                Some(key)
+            }
+            false => None,
+        };
+        // VIRTUAL generated columns aren't stored at all
+        for (i, column) in columns.iter().enumerate() {
+            if !stored_columns.contains(&i) && !column.is_virtual() {
+                stored_columns.push(i);
+            }
+        }
+
+        Ok(TableDefinition {
+            name: create_table.name,
+            columns,
+            rowid_alias,
+            primary_key,
+            stored_columns,
+        })
+    }
+
+    /// Parses the CREATE VIEW statement of a view
+    pub fn view_definition(&self) -> Result<CreateView> {
+        parse_create_view(&self.sql)
+            .map_err(|e| anyhow!("malformed database schema ({}) - {}", self.name, e))
+    }
+
+    /// Parses the CREATE INDEX statement of an index. Indexes that SQLite creates automatically
+    /// for UNIQUE and PRIMARY KEY constraints don't have one.
+    pub fn index_definition(&self) -> Option<Result<CreateIndex>> {
+        if self.sql.is_empty() {
+            return None;
+        }
+        Some(
+            parse_create_index(&self.sql)
+                .map_err(|e| anyhow!("malformed database schema ({}) - {}", self.name, e)),
+        )
+    }
+}
+
+/// The columns of a table, as declared by its CREATE TABLE statement
+#[derive(Debug, Clone)]
+pub struct TableDefinition {
+    pub name: String,
+    pub columns: Vec<Column>,
+    /// Position of the INTEGER PRIMARY KEY column, whose value is the rowid
+    pub rowid_alias: Option<usize>,
+    /// For a WITHOUT ROWID table, the PRIMARY KEY columns that its b-tree is keyed by, with
+    /// their collating sequences and whether they're in descending order
+    pub primary_key: Option<Vec<(usize, Collation, bool)>>,
+    /// Positions of the columns in the order the records of the table have their values
+    pub stored_columns: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: String,
+    pub type_name: String,
+    pub affinity: Affinity,
+    pub collation: Collation,
+    /// Value of the DEFAULT clause, which rows stored before the column was added with ALTER
+    /// TABLE ADD COLUMN have for it
+    pub default: Value,
+    /// Expression of a generated column, and whether its value is STORED in the record instead
+    /// of being computed when the row is read
+    pub generated: Option<(Expr, bool)>,
+}
+
+impl Column {
+    /// Whether the column is generated and not stored
+    pub fn is_virtual(&self) -> bool {
+        matches!(self.generated, Some((_, false)))
+    }
+}
+
+/// Evaluates a DEFAULT clause. A column can only be added with a constant default, so rows
+/// are never missing a column whose default is an expression or CURRENT_TIME and the like.
+fn default_value(expr: &Expr) -> Value {
+    match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Unary(UnaryOperator::Negate, expr) => default_value(expr).negate(),
+        _ => Value::Null,
+    }
 }
@@ -0,0 +1,293 @@
+use anyhow::{bail, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// How many entries of history are kept
+const HISTORY_SIZE: usize = 1000;
+
+/// Reads the lines of input. On a terminal they can be edited, and earlier commands recalled
+/// with the arrow keys from a history that is kept in a file, one per line. Anything else is
+/// read as it is.
+pub struct Editor {
+    /// The settings of the terminal, which `stty` puts back after it has read a line, or None
+    /// when the input isn't a terminal
+    terminal: Option<String>,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+}
+
+impl Editor {
+    pub fn new(history_path: Option<PathBuf>) -> Self {
+        let terminal = stty(&["-g"]).ok();
+        let mut history = vec![];
+        if let (Some(_), Some(path)) = (&terminal, &history_path) {
+            if let Ok(file) = File::open(path) {
+                let lines = BufReader::new(file).lines().collect::<io::Result<Vec<_>>>();
+                history = lines.unwrap_or_default();
+                // The file is appended to, so it's cut down to the entries kept here
+                let excess = history.len().saturating_sub(HISTORY_SIZE);
+                if excess > 0 {
+                    history.drain(..excess);
+                    let mut contents = history.join("\n");
+                    contents.push('\n');
+                    let _ = std::fs::write(path, contents);
+                }
+            }
+        }
+        Editor {
+            terminal,
+            history,
+            history_path,
+        }
+    }
+
+    /// Whether the input is a terminal that a person types at
+    pub fn is_interactive(&self) -> bool {
+        self.terminal.is_some()
+    }
+
+    /// Reads a line, showing the prompt first on a terminal. None is the end of the input.
+    pub fn read_line(&mut self, prompt: &str) -> Result<Option<String>> {
+        let settings = match &self.terminal {
+            Some(settings) => settings.clone(),
+            None => {
+                let mut line = String::new();
+                if io::stdin().lock().read_line(&mut line)? == 0 {
+                    return Ok(None);
+                }
+                let len = line.trim_end_matches(&['\n', '\r'][..]).len();
+                line.truncate(len);
+                return Ok(Some(line));
+            }
+        };
+
+        // Keys are read one at a time, without the terminal echoing them or taking ^C
+        stty(&[
+            "-icanon", "-echo", "-isig", "-ixon", "min", "1", "time", "0",
+        ])?;
+        let line = self.edit(prompt);
+        stty(&[&settings])?;
+        line
+    }
+
+    /// Adds a command to the history once it has been read in full, on a terminal. The lines
+    /// of a statement typed over several are joined into one, so that it's recalled whole.
+    pub fn add_history(&mut self, command: &str) {
+        let entry = command
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ");
+        if self.terminal.is_none()
+            || entry.is_empty()
+            || self.history.last().map(String::as_str) == Some(&entry)
+        {
+            return;
+        }
+        // History is only a convenience, so failing to keep it isn't an error
+        if let Some(path) = &self.history_path {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            if let Ok(mut file) = file {
+                let _ = writeln!(file, "{}", entry);
+            }
+        }
+        self.history.push(entry);
+        if self.history.len() > HISTORY_SIZE {
+            self.history.remove(0);
+        }
+    }
+
+    /// Reads the keys that edit a line until Enter is pressed
+    fn edit(&mut self, prompt: &str) -> Result<Option<String>> {
+        let mut stdin = io::stdin();
+        let mut stdout = io::stdout();
+        let mut line: Vec<char> = vec![];
+        let mut cursor = 0;
+        // The line being typed is kept while older lines are recalled over it
+        let mut recalled = self.history.len();
+        let mut typed = vec![];
+
+        loop {
+            write!(
+                stdout,
+                "\r{}{}\x1b[K",
+                prompt,
+                line.iter().collect::<String>()
+            )?;
+            if cursor < line.len() {
+                write!(stdout, "\x1b[{}D", line.len() - cursor)?;
+            }
+            stdout.flush()?;
+
+            let key = match read_key(&mut stdin)? {
+                Some(key) => key,
+                None => return Ok(None),
+            };
+            match key {
+                Key::Char(c) => {
+                    line.insert(cursor, c);
+                    cursor += 1;
+                }
+                Key::Enter => {
+                    writeln!(stdout)?;
+                    return Ok(Some(line.into_iter().collect()));
+                }
+                Key::Control('c') => {
+                    writeln!(stdout, "^C")?;
+                    line.clear();
+                    cursor = 0;
+                    recalled = self.history.len();
+                }
+                Key::Control('d') if line.is_empty() => {
+                    writeln!(stdout)?;
+                    return Ok(None);
+                }
+                Key::Control('d') | Key::Delete if cursor < line.len() => {
+                    line.remove(cursor);
+                }
+                Key::Backspace | Key::Control('h') if cursor > 0 => {
+                    cursor -= 1;
+                    line.remove(cursor);
+                }
+                Key::Left | Key::Control('b') => cursor = cursor.saturating_sub(1),
+                Key::Right | Key::Control('f') => cursor = (cursor + 1).min(line.len()),
+                Key::Home | Key::Control('a') => cursor = 0,
+                Key::End | Key::Control('e') => cursor = line.len(),
+                Key::Control('k') => line.truncate(cursor),
+                Key::Control('u') => {
+                    line.drain(..cursor);
+                    cursor = 0;
+                }
+                Key::Control('w') => {
+                    let mut start = cursor;
+                    while start > 0 && line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    while start > 0 && !line[start - 1].is_whitespace() {
+                        start -= 1;
+                    }
+                    line.drain(start..cursor);
+                    cursor = start;
+                }
+                Key::Up | Key::Control('p') if recalled > 0 => {
+                    if recalled == self.history.len() {
+                        typed = line.clone();
+                    }
+                    recalled -= 1;
+                    line = self.history[recalled].chars().collect();
+                    cursor = line.len();
+                }
+                Key::Down | Key::Control('n') if recalled < self.history.len() => {
+                    recalled += 1;
+                    line = match self.history.get(recalled) {
+                        Some(entry) => entry.chars().collect(),
+                        None => typed.clone(),
+                    };
+                    cursor = line.len();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+enum Key {
+    Char(char),
+    /// A letter typed with Ctrl held down
+    Control(char),
+    Enter,
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+    Home,
+    End,
+    Other,
+}
+
+/// Reads a key from the terminal, decoding its escape sequence or UTF-8. None is the end of the
+/// input.
+fn read_key(input: &mut impl Read) -> Result<Option<Key>> {
+    let byte = match read_byte(input)? {
+        Some(byte) => byte,
+        None => return Ok(None),
+    };
+    let key = match byte {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f => Key::Backspace,
+        0x1b => match read_byte(input)? {
+            Some(b'[') | Some(b'O') => match read_byte(input)? {
+                Some(b'A') => Key::Up,
+                Some(b'B') => Key::Down,
+                Some(b'C') => Key::Right,
+                Some(b'D') => Key::Left,
+                Some(b'H') => Key::Home,
+                Some(b'F') => Key::End,
+                // VT sequences like ESC [ 3 ~, whose number may be followed by modifiers
+                Some(digit @ b'0'..=b'9') => {
+                    let mut number = vec![digit];
+                    loop {
+                        match read_byte(input)? {
+                            Some(b'~') | None => break,
+                            Some(byte) => number.push(byte),
+                        }
+                    }
+                    match number.split(|&byte| byte == b';').next() {
+                        Some(b"1") | Some(b"7") => Key::Home,
+                        Some(b"4") | Some(b"8") => Key::End,
+                        Some(b"3") => Key::Delete,
+                        _ => Key::Other,
+                    }
+                }
+                _ => Key::Other,
+            },
+            _ => Key::Other,
+        },
+        byte @ 0x01..=0x1a => Key::Control((b'a' + byte - 1) as char),
+        byte if byte < 0x20 => Key::Other,
+        byte => {
+            let len = match byte {
+                0xf0..=0xff => 4,
+                0xe0..=0xef => 3,
+                0xc0..=0xdf => 2,
+                _ => 1,
+            };
+            let mut bytes = vec![byte];
+            for _ in 1..len {
+                bytes.extend(read_byte(input)?);
+            }
+            match std::str::from_utf8(&bytes) {
+                Ok(text) => Key::Char(text.chars().next().unwrap()),
+                Err(_) => Key::Other,
+            }
+        }
+    };
+    Ok(Some(key))
+}
+
+fn read_byte(input: &mut impl Read) -> Result<Option<u8>> {
+    let mut byte = [0];
+    match input.read(&mut byte)? {
+        0 => Ok(None),
+        _ => Ok(Some(byte[0])),
+    }
+}
+
+/// Runs `stty` on the terminal that is the standard input, returning what it prints. It fails
+/// when the input isn't a terminal.
+fn stty(args: &[&str]) -> Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()?;
+    if !output.status.success() {
+        bail!("stty failed");
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
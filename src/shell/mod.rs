@@ -0,0 +1,680 @@
+//! The command-line shell, which runs SQL statements and dot-commands given on the command line
+//! or typed at its prompt, like the `sqlite3` program does
+
+mod editor;
+mod output;
+
+use crate::ast::Statement;
+use crate::database::Database;
+use crate::parser::parse_statement;
+use crate::query;
+use crate::query::expr::{glob_match, like_match};
+use crate::schema::Schema;
+use crate::value::Value;
+use anyhow::{bail, Result};
+use editor::Editor;
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+pub use output::{Mode, Output};
+
+/// The dot-commands with their arguments and what they do, as `.help` shows them
+const COMMANDS: &[(&str, &str)] = &[
+    (".dbinfo", "Show status information about the database"),
+    (".exit ?CODE?", "Exit this program with return-code CODE"),
+    (
+        ".fullschema",
+        "Show schema and the content of sqlite_stat tables",
+    ),
+    (".headers on|off", "Turn display of headers on or off"),
+    (".help", "Show help text"),
+    (".indexes ?TABLE?", "Show names of indexes"),
+    (".mode ?MODE? ?TABLE?", "Set output mode"),
+    (".nullvalue STRING", "Use STRING in place of NULL values"),
+    (".quit", "Stop interpreting input stream, exit if primary."),
+    (
+        ".recursionlimit ?ROWS?",
+        "Show or set how many rows a recursive CTE may recurse on",
+    ),
+    (
+        ".schema ?PATTERN?",
+        "Show the CREATE statements matching PATTERN",
+    ),
+    (
+        ".separator COL ?ROW?",
+        "Change the column and row separators",
+    ),
+    (
+        ".tables ?TABLE?",
+        "List names of tables matching LIKE pattern TABLE",
+    ),
+];
+
+/// The tables of statistics that `ANALYZE` fills, which `.fullschema` shows the rows of
+const STAT_TABLES: &[&str] = &["sqlite_stat1", "sqlite_stat3", "sqlite_stat4"];
+
+/// SQLite's keywords, in order. A name that is one of them is quoted.
+const KEYWORDS: &[&str] = &[
+    "ABORT",
+    "ACTION",
+    "ADD",
+    "AFTER",
+    "ALL",
+    "ALTER",
+    "ALWAYS",
+    "ANALYZE",
+    "AND",
+    "AS",
+    "ASC",
+    "ATTACH",
+    "AUTOINCREMENT",
+    "BEFORE",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASCADE",
+    "CASE",
+    "CAST",
+    "CHECK",
+    "COLLATE",
+    "COLUMN",
+    "COMMIT",
+    "CONFLICT",
+    "CONSTRAINT",
+    "CREATE",
+    "CROSS",
+    "CURRENT",
+    "CURRENT_DATE",
+    "CURRENT_TIME",
+    "CURRENT_TIMESTAMP",
+    "DATABASE",
+    "DEFAULT",
+    "DEFERRABLE",
+    "DEFERRED",
+    "DELETE",
+    "DESC",
+    "DETACH",
+    "DISTINCT",
+    "DO",
+    "DROP",
+    "EACH",
+    "ELSE",
+    "END",
+    "ESCAPE",
+    "EXCEPT",
+    "EXCLUDE",
+    "EXCLUSIVE",
+    "EXISTS",
+    "EXPLAIN",
+    "FAIL",
+    "FILTER",
+    "FIRST",
+    "FOLLOWING",
+    "FOR",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GENERATED",
+    "GLOB",
+    "GROUP",
+    "GROUPS",
+    "HAVING",
+    "IF",
+    "IGNORE",
+    "IMMEDIATE",
+    "IN",
+    "INDEX",
+    "INDEXED",
+    "INITIALLY",
+    "INNER",
+    "INSERT",
+    "INSTEAD",
+    "INTERSECT",
+    "INTO",
+    "IS",
+    "ISNULL",
+    "JOIN",
+    "KEY",
+    "LAST",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "MATCH",
+    "MATERIALIZED",
+    "NATURAL",
+    "NO",
+    "NOT",
+    "NOTHING",
+    "NOTNULL",
+    "NULL",
+    "NULLS",
+    "OF",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OTHERS",
+    "OUTER",
+    "OVER",
+    "PARTITION",
+    "PLAN",
+    "PRAGMA",
+    "PRECEDING",
+    "PRIMARY",
+    "QUERY",
+    "RAISE",
+    "RANGE",
+    "RECURSIVE",
+    "REFERENCES",
+    "REGEXP",
+    "REINDEX",
+    "RELEASE",
+    "RENAME",
+    "REPLACE",
+    "RESTRICT",
+    "RETURNING",
+    "RIGHT",
+    "ROLLBACK",
+    "ROW",
+    "ROWS",
+    "SAVEPOINT",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "TEMP",
+    "TEMPORARY",
+    "THEN",
+    "TIES",
+    "TO",
+    "TRANSACTION",
+    "TRIGGER",
+    "UNBOUNDED",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "USING",
+    "VACUUM",
+    "VALUES",
+    "VIEW",
+    "VIRTUAL",
+    "WHEN",
+    "WHERE",
+    "WINDOW",
+    "WITH",
+    "WITHOUT",
+];
+
+/// The four-byte fields of the database header that `.dbinfo` shows, and their offsets
+const HEADER_FIELDS: &[(&str, usize)] = &[
+    ("file change counter:", 24),
+    ("database page count:", 28),
+    ("freelist page count:", 36),
+    ("schema cookie:", 40),
+    ("schema format:", 44),
+    ("default cache size:", 48),
+    ("autovacuum top root:", 52),
+    ("incremental vacuum:", 64),
+    ("text encoding:", 56),
+    ("user version:", 60),
+    ("application id:", 68),
+    ("software version:", 96),
+];
+
+pub struct Shell {
+    database: Database,
+    output: Output,
+}
+
+impl Shell {
+    pub fn new(database: Database, output: Output) -> Self {
+        Shell { database, output }
+    }
+
+    /// Runs a dot-command, or the SQL statements of `input`. Returns the exit code when the
+    /// command is to end the program.
+    pub fn run(&mut self, input: &str) -> Result<Option<i32>> {
+        if input.trim_start().starts_with('.') {
+            return self.run_command(input.trim());
+        }
+        let (statements, rest) = split_statements(input);
+        for statement in statements.into_iter().chain(rest) {
+            self.run_statement(statement)?;
+        }
+        Ok(None)
+    }
+
+    /// Reads commands from the standard input until it ends or `.quit` is run, prompting for
+    /// them on a terminal. A statement goes on over lines until one ends it with a semicolon.
+    /// Returns the exit code of the program, which tells whether a command failed when the
+    /// input isn't a terminal.
+    pub fn run_interactive(&mut self) -> Result<i32> {
+        let history =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".sqlite_rust_history"));
+        let mut editor = Editor::new(history);
+        if editor.is_interactive() {
+            println!("Enter \".help\" for usage hints.");
+        }
+
+        let mut failed = false;
+        let mut sql = String::new();
+        loop {
+            let prompt = match sql.is_empty() {
+                true => "sqlite> ",
+                false => "   ...> ",
+            };
+            let line = match editor.read_line(prompt)? {
+                Some(line) => line,
+                None => break,
+            };
+            let input = match sql.is_empty() && line.trim_start().starts_with('.') {
+                true => line,
+                false => {
+                    sql.push_str(&line);
+                    sql.push('\n');
+                    match split_statements(&sql) {
+                        (_, Some(_)) => continue,
+                        (statements, None) if statements.is_empty() => {
+                            sql.clear();
+                            continue;
+                        }
+                        _ => std::mem::take(&mut sql),
+                    }
+                }
+            };
+            editor.add_history(&input);
+            match self.run(&input) {
+                Ok(Some(code)) => return Ok(code),
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    failed = true;
+                }
+            }
+        }
+
+        // What is left at the end of the input is run even without a semicolon
+        if let Err(e) = self.run(&sql) {
+            eprintln!("Error: {}", e);
+            failed = true;
+        }
+        Ok((failed && !editor.is_interactive()) as i32)
+    }
+
+    fn run_command(&mut self, command: &str) -> Result<Option<i32>> {
+        let args = split_arguments(command);
+        let args: Vec<_> = args.iter().map(String::as_str).collect();
+        match args[..] {
+            [".dbinfo"] => self.print_db_info(),
+            [".exit"] | [".quit"] => return Ok(Some(0)),
+            [".exit", code] => return Ok(Some(code.parse().unwrap_or(0))),
+            [".fullschema"] => self.print_full_schema()?,
+            [".headers", headers] => self.output.set_headers(boolean(headers)),
+            [".help"] => {
+                for (command, help) in COMMANDS {
+                    println!("{:<25}{}", command, help);
+                }
+            }
+            [".indexes"] | [".indexes", _] => {
+                let table = args.get(1).copied().unwrap_or("%");
+                self.print_names(|schema| {
+                    schema.kind == "index" && like_match(table, &schema.table_name, None)
+                })
+            }
+            [".mode"] => println!("{}", self.output.describe_mode()),
+            [".mode", mode] => self.output.set_mode(mode, None)?,
+            [".mode", mode, table] => self.output.set_mode(mode, Some(table))?,
+            [".nullvalue", null_value] => self.output.null_value = null_value.to_string(),
+            [".recursionlimit"] => println!("{}", self.database.recursion_limit),
+            [".recursionlimit", rows] => match rows.parse() {
+                Ok(rows) => self.database.recursion_limit = rows,
+                Err(_) => bail!("invalid recursion limit: {}", rows),
+            },
+            [".schema"] => self.print_schema(|_| true, true),
+            [".schema", pattern] => {
+                self.print_schema(|schema| matches(pattern, &schema.table_name), true)
+            }
+            [".separator", column] => self.output.column_separator = column.to_string(),
+            [".separator", column, row] => {
+                self.output.column_separator = column.to_string();
+                self.output.row_separator = row.to_string();
+            }
+            [".tables"] | [".tables", _] => {
+                let pattern = args.get(1).copied().unwrap_or("%");
+                self.print_names(|schema| {
+                    (schema.kind == "table" || schema.kind == "view")
+                        && !is_internal(&schema.name)
+                        && like_match(pattern, &schema.name, None)
+                })
+            }
+            _ => bail!(
+                "unknown command or invalid arguments:  \"{}\". Enter \".help\" for help",
+                args[0].trim_start_matches('.')
+            ),
+        }
+        Ok(None)
+    }
+
+    /// Prints the names of the tables or indexes `filter` holds for
+    fn print_names(&self, filter: impl Fn(&Schema) -> bool) {
+        let mut names: Vec<_> = self
+            .database
+            .schemas
+            .iter()
+            .filter(|schema| filter(schema))
+            .map(|schema| schema.name.as_str())
+            .collect();
+        names.sort_unstable();
+        print_columns(&names);
+    }
+
+    /// Prints the CREATE statements of the tables, indexes and views that `filter` holds for.
+    /// Indexes that SQLite made for constraints have none. With `view_columns`, a view is
+    /// followed by a comment with its columns, unless its query has an error.
+    fn print_schema(&self, filter: impl Fn(&Schema) -> bool, view_columns: bool) {
+        for schema in &self.database.schemas {
+            if schema.sql.is_empty() || !filter(schema) {
+                continue;
+            }
+            // A table whose name is quoted is shown the way `.dump` makes it
+            let sql = match schema.sql.strip_prefix("CREATE TABLE ") {
+                Some(rest) if rest.starts_with(&['"', '\''][..]) => {
+                    format!("CREATE TABLE IF NOT EXISTS {}", rest)
+                }
+                _ => schema.sql.clone(),
+            };
+            let columns = match schema.kind.as_str() {
+                "view" if view_columns => self.view_columns(schema).ok(),
+                _ => None,
+            };
+            match columns {
+                Some(columns) => {
+                    let columns: Vec<_> = columns.iter().map(|name| quote_name(name)).collect();
+                    let view = format!("{}({})", quote_name(&schema.name), columns.join(","));
+                    println!("{}\n/* {} */;", sql, view);
+                }
+                None => println!("{};", sql),
+            }
+        }
+    }
+
+    /// The names of the columns of a view
+    fn view_columns(&self, schema: &Schema) -> Result<Vec<String>> {
+        let view = schema.view_definition()?;
+        let columns = query::columns(&self.database, &view.select)?;
+        match view.columns.is_empty() {
+            true => Ok(columns),
+            false => Ok(view.columns),
+        }
+    }
+
+    /// Prints the fields of the database header and how many of each kind of object the schema
+    /// has, like the `sqlite3` shell does
+    fn print_db_info(&self) {
+        let header = &self.database.bytes()[..100];
+        println!("{:<20} {}", "database page size:", self.database.page_size);
+        println!("{:<20} {}", "write format:", header[18]);
+        println!("{:<20} {}", "read format:", header[19]);
+        println!("{:<20} {}", "reserved bytes:", header[20]);
+        for &(name, offset) in HEADER_FIELDS {
+            let value = u32::from_be_bytes(header[offset..offset + 4].try_into().unwrap());
+            let encoding = match (offset, value) {
+                (56, 1) => " (utf8)",
+                (56, 2) => " (utf16le)",
+                (56, 3) => " (utf16be)",
+                _ => "",
+            };
+            println!("{:<20} {}{}", name, value, encoding);
+        }
+
+        let kinds = [
+            ("table", "number of tables:"),
+            ("index", "number of indexes:"),
+            ("trigger", "number of triggers:"),
+            ("view", "number of views:"),
+        ];
+        for &(kind, name) in &kinds {
+            let count = self
+                .database
+                .schemas
+                .iter()
+                .filter(|schema| schema.kind == kind)
+                .count();
+            println!("{:<20} {}", name, count);
+        }
+        let schema_size: usize = self
+            .database
+            .schemas
+            .iter()
+            .map(|schema| schema.sql.chars().count())
+            .sum();
+        println!("{:<20} {}", "schema size:", schema_size);
+        // Nothing changes the file while it's open, but SQLite opens it with 4096-byte pages
+        // and counts reading it again with pages of another size as a change
+        let data_version = 1 + (self.database.page_size != 4096) as u32;
+        println!("{:<20} {}", "data version", data_version);
+    }
+
+    /// Prints the schema without SQLite's own tables, and the statistics that `ANALYZE` keeps
+    /// as the statements that would restore them
+    fn print_full_schema(&self) -> Result<()> {
+        self.print_schema(|schema| !is_internal(&schema.name), false);
+
+        let tables: Vec<_> = STAT_TABLES
+            .iter()
+            .filter(|&&name| self.database.table(name).is_ok())
+            .collect();
+        if tables.is_empty() {
+            println!("/* No STAT tables available */");
+            return Ok(());
+        }
+        println!("ANALYZE sqlite_schema;");
+        for table in tables {
+            let sql = format!("SELECT * FROM {}", table);
+            let select = match parse_statement(&sql)? {
+                Statement::Select(select) => select,
+                _ => unreachable!(),
+            };
+            for row in query::select(&self.database, &select)?.rows {
+                let values: Vec<_> = row?.iter().map(Value::quote).collect();
+                println!("INSERT INTO {} VALUES({});", table, values.join(","));
+            }
+        }
+        println!("ANALYZE sqlite_schema;");
+        Ok(())
+    }
+
+    fn run_statement(&mut self, sql: &str) -> Result<()> {
+        match parse_statement(sql)? {
+            Statement::Select(select) => {
+                let result = query::select(&self.database, &select)?;
+                self.output.print(&result.columns, result.rows)
+            }
+            Statement::ExplainQueryPlan(select) => {
+                let rows = query::explain(&self.database, &select)?
+                    .rows
+                    .collect::<Result<Vec<_>>>()?;
+                for line in query::render_tree(&rows) {
+                    println!("{}", line);
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Prints names in columns as wide as the longest of them, going down each column first, in as
+/// many columns as fit in 80 bytes
+fn print_columns(names: &[&str]) {
+    let width = names.iter().map(|name| name.len()).max().unwrap_or(0);
+    let columns = (80 / (width + 2)).max(1);
+    let rows = names.chunks(columns).count();
+    for row in 0..rows {
+        let line: Vec<_> = names[row..]
+            .iter()
+            .step_by(rows)
+            .map(|name| format!("{}{}", name, " ".repeat(width - name.len())))
+            .collect();
+        println!("{}", line.join("  "));
+    }
+}
+
+/// Puts a name in double quotes unless it's made of ASCII letters, digits and underscores,
+/// doesn't start with a digit and isn't a keyword
+fn quote_name(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && KEYWORDS
+            .binary_search(&name.to_ascii_uppercase().as_str())
+            .is_err();
+    match plain {
+        true => name.to_string(),
+        false => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+/// Whether a table name matches the pattern of `.schema`. Like in the `sqlite3` shell, it's a
+/// GLOB pattern when it has a wildcard of one, and otherwise a LIKE pattern with `\` to escape
+/// `%` and `_`.
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.contains(&['*', '?', '['][..]) {
+        true => glob_match(pattern, &name.to_lowercase()),
+        false => like_match(pattern, name, Some('\\')),
+    }
+}
+
+/// Reads the argument of `.headers` like SQLite reads a boolean, which it takes to be false when
+/// it isn't one
+fn boolean(arg: &str) -> bool {
+    match arg.to_ascii_lowercase().as_str() {
+        "on" | "yes" | "true" => true,
+        "off" | "no" | "false" => false,
+        _ => match arg.parse::<i64>() {
+            Ok(number) => number != 0,
+            Err(_) => {
+                eprintln!("ERROR: Not a boolean value: \"{}\". Assuming \"no\".", arg);
+                false
+            }
+        },
+    }
+}
+
+/// Splits a dot-command into its arguments. They are separated by whitespace unless it's in
+/// quotes, and backslash escapes are read in double quotes.
+fn split_arguments(command: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut chars = command.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let mut arg = String::new();
+        match c {
+            '"' | '\'' => {
+                chars.next();
+                while let Some(next) = chars.next() {
+                    match next {
+                        next if next == c => break,
+                        '\\' if c == '"' => arg.push(escape(&mut chars)),
+                        next => arg.push(next),
+                    }
+                }
+            }
+            _ => {
+                while let Some(next) = chars.next_if(|next| !next.is_whitespace()) {
+                    arg.push(next);
+                }
+            }
+        }
+        args.push(arg);
+    }
+    args
+}
+
+/// Reads the character that a backslash escape stands for, after the backslash
+fn escape(chars: &mut std::iter::Peekable<std::str::Chars>) -> char {
+    let c = match chars.next() {
+        Some(c) => c,
+        None => return '\\',
+    };
+    let (radix, mut digits) = match c {
+        'a' => return '\u{7}',
+        'b' => return '\u{8}',
+        'e' => return '\u{1b}',
+        'f' => return '\u{c}',
+        'n' => return '\n',
+        'r' => return '\r',
+        't' => return '\t',
+        'v' => return '\u{b}',
+        'x' => (16, String::new()),
+        '0'..='7' => (8, c.to_string()),
+        c => return c,
+    };
+    let max = if radix == 16 { 2 } else { 3 };
+    while digits.len() < max {
+        match chars.next_if(|next| next.is_digit(radix)) {
+            Some(next) => digits.push(next),
+            None => break,
+        }
+    }
+    u32::from_str_radix(&digits, radix)
+        .ok()
+        .and_then(std::char::from_u32)
+        .unwrap_or('\0')
+}
+
+/// Whether a table or index is one that SQLite keeps for itself
+fn is_internal(name: &str) -> bool {
+    like_match("sqlite\\_%", name, Some('\\'))
+}
+
+/// Splits SQL text into the statements that semicolons end, skipping those in string literals,
+/// quoted names and comments. Also returns the text after the last of them unless it's only
+/// whitespace and comments.
+fn split_statements(sql: &str) -> (Vec<&str>, Option<&str>) {
+    let bytes = sql.as_bytes();
+    let mut statements = vec![];
+    let mut start = 0;
+    // Whether there is more than whitespace and comments since the last statement
+    let mut content = false;
+    // The position of the last byte of `pattern` where it's next found
+    let end = |from: usize, pattern: &str| match sql[from..].find(pattern) {
+        Some(found) => from + found + pattern.len() - 1,
+        None => bytes.len(),
+    };
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            // A quote that is doubled in a literal ends it and starts another one
+            b'\'' | b'"' | b'`' => {
+                i = end(i + 1, &sql[i..i + 1]);
+                content = true;
+            }
+            b'[' => {
+                i = end(i + 1, "]");
+                content = true;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => i = end(i, "\n"),
+            // A comment that doesn't end yet leaves the statement unfinished
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = end(i + 2, "*/");
+                content |= i == bytes.len();
+            }
+            b';' => {
+                if content {
+                    statements.push(&sql[start..=i]);
+                }
+                start = i + 1;
+                content = false;
+            }
+            byte if !byte.is_ascii_whitespace() => content = true,
+            _ => {}
+        }
+        i += 1;
+    }
+    let rest = match content {
+        true => Some(&sql[start..]),
+        false => None,
+    };
+    (statements, rest)
+}
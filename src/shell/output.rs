@@ -0,0 +1,521 @@
+//! The modes in which the shell prints the rows of results, like those that `.mode` chooses in
+//! the `sqlite3` shell
+
+use super::quote_name;
+use crate::query::Row;
+use crate::value::{format_real_exact, Value};
+use anyhow::{bail, Result};
+
+/// The modes that `.mode` chooses between. `tabs` is the list mode with tabs between values.
+const MODES: &[&str] = &[
+    "box", "column", "csv", "insert", "json", "line", "list", "markdown", "table", "tabs",
+];
+
+/// How many characters the values in the columnar modes that `.mode` chooses are wrapped at
+const WRAP: usize = 60;
+
+/// How the rows of a result are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// The values with the column separator between them
+    List,
+    /// Comma-separated values, quoted as RFC 4180 describes
+    Csv,
+    /// A JSON array of an object for each row
+    Json,
+    /// Each value on a line of its own after the name of its column
+    Line,
+    /// The values lined up in columns
+    Column,
+    /// The values in a table drawn with box-drawing characters
+    Box,
+    /// The values in a Markdown table
+    Markdown,
+    /// The values in a table drawn with ASCII characters
+    Table,
+    /// An SQL INSERT statement for each row
+    Insert,
+}
+
+impl Mode {
+    fn is_columnar(self) -> bool {
+        matches!(
+            self,
+            Mode::Column | Mode::Box | Mode::Markdown | Mode::Table
+        )
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::List => "list",
+            Mode::Csv => "csv",
+            Mode::Json => "json",
+            Mode::Line => "line",
+            Mode::Column => "column",
+            Mode::Box => "box",
+            Mode::Markdown => "markdown",
+            Mode::Table => "table",
+            Mode::Insert => "insert",
+        }
+    }
+}
+
+/// The settings that decide how the shell prints rows
+pub struct Output {
+    pub mode: Mode,
+    /// Whether the names of the columns are printed before the rows. The box, Markdown and table
+    /// modes always print them.
+    pub headers: bool,
+    /// Whether `.headers` was used, after which `.mode` leaves the headers as they are
+    headers_chosen: bool,
+    pub column_separator: String,
+    pub row_separator: String,
+    /// What NULL is printed as
+    pub null_value: String,
+    /// The table that the statements of the insert mode insert into
+    pub table: String,
+    /// How many characters the values in the columnar modes are wrapped at, or 0 for them not
+    /// to be
+    pub wrap: usize,
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output {
+            mode: Mode::List,
+            headers: false,
+            headers_chosen: false,
+            column_separator: "|".to_string(),
+            row_separator: "\n".to_string(),
+            null_value: String::new(),
+            table: "table".to_string(),
+            wrap: 0,
+        }
+    }
+}
+
+impl Output {
+    /// Chooses the mode like `.mode` does, with the separators it uses. The columnar modes wrap
+    /// long values and turn on the headers unless `.headers` turned them off.
+    pub fn set_mode(&mut self, name: &str, table: Option<&str>) -> Result<()> {
+        let mode = match name {
+            "list" | "tabs" => Mode::List,
+            "csv" => Mode::Csv,
+            "json" => Mode::Json,
+            "line" => Mode::Line,
+            "column" => Mode::Column,
+            "box" => Mode::Box,
+            "markdown" => Mode::Markdown,
+            "table" => Mode::Table,
+            "insert" => Mode::Insert,
+            _ => bail!("mode should be one of: {}", MODES.join(" ")),
+        };
+        if table.is_some() && mode != Mode::Insert {
+            bail!("only the insert mode takes a table");
+        }
+        let (column_separator, row_separator) = match name {
+            "tabs" => ("\t", "\n"),
+            "csv" => (",", "\r\n"),
+            _ => ("|", "\n"),
+        };
+        self.mode = mode;
+        self.column_separator = column_separator.to_string();
+        self.row_separator = row_separator.to_string();
+        self.table = table.unwrap_or("table").to_string();
+        self.wrap = 0;
+        if mode.is_columnar() {
+            self.wrap = WRAP;
+            self.headers |= !self.headers_chosen;
+        }
+        Ok(())
+    }
+
+    /// What `.mode` shows when it's given no mode
+    pub fn describe_mode(&self) -> String {
+        format!("current output mode: {}", self.mode.name())
+    }
+
+    pub fn set_headers(&mut self, headers: bool) {
+        self.headers = headers;
+        self.headers_chosen = true;
+    }
+
+    /// Applies a command-line option like `-csv` or `-separator SEP`, taking its argument from
+    /// `args`. Returns whether it was an option about the output.
+    pub fn parse_option(
+        &mut self,
+        option: &str,
+        args: &mut impl Iterator<Item = String>,
+    ) -> Result<bool> {
+        let mut argument = || match args.next() {
+            Some(argument) => Ok(argument),
+            None => bail!("missing argument to {}", option),
+        };
+        // Unlike `.mode`, the options leave the separators and the headers alone
+        match option.trim_start_matches('-') {
+            "list" => self.mode = Mode::List,
+            "tabs" => {
+                self.mode = Mode::List;
+                self.column_separator = "\t".to_string();
+            }
+            "csv" => {
+                self.mode = Mode::Csv;
+                self.column_separator = ",".to_string();
+            }
+            "json" => self.mode = Mode::Json,
+            "line" => self.mode = Mode::Line,
+            "column" => self.mode = Mode::Column,
+            "box" => self.mode = Mode::Box,
+            "markdown" => self.mode = Mode::Markdown,
+            "table" => self.mode = Mode::Table,
+            "header" | "headers" => self.headers = true,
+            "noheader" | "noheaders" => self.headers = false,
+            "separator" => self.column_separator = argument()?,
+            "newline" => self.row_separator = argument()?,
+            "nullvalue" => self.null_value = argument()?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Prints the rows of a result with the names of its columns
+    pub fn print(&self, columns: &[String], rows: impl Iterator<Item = Result<Row>>) -> Result<()> {
+        match self.mode {
+            Mode::List | Mode::Csv => self.print_separated(columns, rows),
+            Mode::Json => print_json(columns, rows),
+            Mode::Line => self.print_lines(columns, rows),
+            Mode::Insert => self.print_inserts(columns, rows),
+            Mode::Column | Mode::Box | Mode::Markdown | Mode::Table => {
+                self.print_columnar(columns, rows)
+            }
+        }
+    }
+
+    fn print_separated(
+        &self,
+        columns: &[String],
+        rows: impl Iterator<Item = Result<Row>>,
+    ) -> Result<()> {
+        let field = |text: String| match self.mode {
+            Mode::Csv => self.csv_field(text),
+            _ => text,
+        };
+        for (i, row) in rows.enumerate() {
+            let row = row?;
+            if i == 0 && self.headers {
+                let names: Vec<_> = columns.iter().map(|name| field(name.clone())).collect();
+                print!(
+                    "{}{}",
+                    names.join(&self.column_separator),
+                    self.row_separator
+                );
+            }
+            let values: Vec<_> = row
+                .iter()
+                .map(|value| match value {
+                    Value::Null => self.null_value.clone(),
+                    value => field(self.text(value)),
+                })
+                .collect();
+            print!(
+                "{}{}",
+                values.join(&self.column_separator),
+                self.row_separator
+            );
+        }
+        Ok(())
+    }
+
+    /// Quotes a CSV field if it's empty or has anything but printable ASCII other than quotes
+    /// and commas, or the separator, which is more often than RFC 4180 needs but is what SQLite
+    /// does
+    fn csv_field(&self, text: String) -> String {
+        let quoted = text.is_empty()
+            || text
+                .bytes()
+                .any(|byte| byte <= b' ' || byte >= 0x7f || b"\"',".contains(&byte))
+            || (!self.column_separator.is_empty() && text.contains(&self.column_separator));
+        match quoted {
+            true => format!("\"{}\"", text.replace('"', "\"\"")),
+            false => text,
+        }
+    }
+
+    fn print_lines(
+        &self,
+        columns: &[String],
+        rows: impl Iterator<Item = Result<Row>>,
+    ) -> Result<()> {
+        let width = columns
+            .iter()
+            .map(|name| name.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max(5);
+        for (i, row) in rows.enumerate() {
+            let row = row?;
+            if i > 0 {
+                println!();
+            }
+            for (name, value) in columns.iter().zip(&row) {
+                println!("{:>width$} = {}", name, self.text(value), width = width);
+            }
+        }
+        Ok(())
+    }
+
+    fn print_inserts(
+        &self,
+        columns: &[String],
+        rows: impl Iterator<Item = Result<Row>>,
+    ) -> Result<()> {
+        let names = match self.headers {
+            true => {
+                let names: Vec<_> = columns.iter().map(|name| quote_name(name)).collect();
+                format!("({})", names.join(","))
+            }
+            false => String::new(),
+        };
+        for row in rows {
+            let values: Vec<_> = row?.iter().map(literal).collect();
+            println!(
+                "INSERT INTO {}{} VALUES({});",
+                quote_name(&self.table),
+                names,
+                values.join(",")
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the rows lined up in columns as wide as their widest values, which needs all of
+    /// them to be read first
+    fn print_columnar(
+        &self,
+        columns: &[String],
+        rows: impl Iterator<Item = Result<Row>>,
+    ) -> Result<()> {
+        let rows = rows
+            .map(|row| {
+                Ok(row?
+                    .iter()
+                    .map(|value| self.lines(&self.text(value)))
+                    .collect())
+            })
+            .collect::<Result<Vec<Vec<_>>>>()?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let header: Vec<_> = columns.iter().map(|name| vec![name.clone()]).collect();
+        let mut widths: Vec<_> = columns.iter().map(|name| name.chars().count()).collect();
+        for row in &rows {
+            for (width, lines) in widths.iter_mut().zip(row) {
+                let widest = lines.iter().map(|line| line.chars().count()).max();
+                *width = (*width).max(widest.unwrap_or(0));
+            }
+        }
+        // Rows are kept apart once one of them takes more than a line
+        let tall = rows.iter().flatten().any(|lines| lines.len() > 1);
+
+        let rule = |left: &str, middle: &str, right: &str, line: &str| {
+            let lines: Vec<_> = widths.iter().map(|width| line.repeat(width + 2)).collect();
+            format!("{}{}{}", left, lines.join(middle), right)
+        };
+        let print_row = |row: &[Vec<String>], centered: bool| {
+            let height = row.iter().map(Vec::len).max().unwrap_or(1);
+            for i in 0..height {
+                let cells: Vec<_> = row
+                    .iter()
+                    .zip(&widths)
+                    .map(|(lines, &width)| {
+                        let line = lines.get(i).map_or("", String::as_str);
+                        let padding = width - line.chars().count();
+                        let left = if centered { padding / 2 } else { 0 };
+                        format!("{}{}{}", " ".repeat(left), line, " ".repeat(padding - left))
+                    })
+                    .collect();
+                match self.mode {
+                    Mode::Box => println!("│ {} │", cells.join(" │ ")),
+                    Mode::Markdown | Mode::Table => println!("| {} |", cells.join(" | ")),
+                    _ => println!("{}", cells.join("  ")),
+                }
+            }
+        };
+
+        match self.mode {
+            Mode::Box => println!("{}", rule("┌", "┬", "┐", "─")),
+            Mode::Table => println!("{}", rule("+", "+", "+", "-")),
+            _ => {}
+        }
+        if self.headers || self.mode != Mode::Column {
+            print_row(&header, self.mode != Mode::Column);
+            match self.mode {
+                Mode::Box => println!("{}", rule("├", "┼", "┤", "─")),
+                Mode::Markdown => println!("{}", rule("|", "|", "|", "-")),
+                Mode::Table => println!("{}", rule("+", "+", "+", "-")),
+                _ => {
+                    let lines: Vec<_> = widths.iter().map(|&width| "-".repeat(width)).collect();
+                    println!("{}", lines.join("  "));
+                }
+            }
+        }
+        for (i, row) in rows.iter().enumerate() {
+            if i > 0 && tall {
+                match self.mode {
+                    Mode::Box => println!("{}", rule("├", "┼", "┤", "─")),
+                    Mode::Table => println!("{}", rule("+", "+", "+", "-")),
+                    Mode::Column => println!(),
+                    _ => {}
+                }
+            }
+            print_row(row, false);
+        }
+        match self.mode {
+            Mode::Box => println!("{}", rule("└", "┴", "┘", "─")),
+            Mode::Table => println!("{}", rule("+", "+", "+", "-")),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The lines that a value takes up in the columnar modes, with tabs expanded and wrapped
+    /// when `wrap` is set
+    fn lines(&self, text: &str) -> Vec<String> {
+        let text = text.strip_suffix('\n').unwrap_or(text);
+        let mut lines = vec![];
+        for line in text.split('\n') {
+            let mut chars = vec![];
+            for c in line.chars() {
+                match c {
+                    '\t' => chars.resize((chars.len() / 8 + 1) * 8, ' '),
+                    c => chars.push(c),
+                }
+            }
+            match self.wrap {
+                width if width > 0 && chars.len() > width => {
+                    lines.extend(chars.chunks(width).map(|chunk| chunk.iter().collect()))
+                }
+                _ => lines.push(chars.into_iter().collect()),
+            }
+        }
+        lines
+    }
+
+    /// The text a value is printed as. NULL is the null value, text ends at a NUL character as
+    /// it does in C, and control characters other than tabs and newlines are shown as `^X`
+    /// except in CSV.
+    fn text(&self, value: &Value) -> String {
+        let text = match value {
+            Value::Null => return self.null_value.clone(),
+            value => value.to_string(),
+        };
+        let text = text.split('\0').next().unwrap();
+        match self.mode {
+            Mode::Csv => text.to_string(),
+            _ => {
+                let mut shown = String::new();
+                for c in text.chars() {
+                    match c {
+                        c if c < ' ' && c != '\t' && c != '\n' => {
+                            shown.push('^');
+                            shown.push((c as u8 + b'@') as char);
+                        }
+                        c => shown.push(c),
+                    }
+                }
+                shown
+            }
+        }
+    }
+}
+
+fn print_json(columns: &[String], rows: impl Iterator<Item = Result<Row>>) -> Result<()> {
+    let mut first = true;
+    for row in rows {
+        let fields: Vec<_> = columns
+            .iter()
+            .zip(&row?)
+            .map(|(name, value)| format!("{}:{}", json_string(name.as_bytes()), json(value)))
+            .collect();
+        print!(
+            "{}{{{}}}",
+            if first { "[" } else { ",\n" },
+            fields.join(",")
+        );
+        first = false;
+    }
+    if !first {
+        println!("]");
+    }
+    Ok(())
+}
+
+fn json(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Integer(v) => v.to_string(),
+        Value::Real(v) => format_real_exact(*v),
+        Value::Text(v) => json_string(v.as_bytes()),
+        Value::Blob(v) => json_string(v),
+    }
+}
+
+/// A JSON string of text, in which bytes that aren't UTF-8 are taken to be characters of their
+/// own, as they are when SQLite reads a blob as text
+fn json_string(mut bytes: &[u8]) -> String {
+    let mut json = String::from("\"");
+    while !bytes.is_empty() {
+        let (text, invalid) = match std::str::from_utf8(bytes) {
+            Ok(text) => (text, 0),
+            Err(e) => {
+                let text = std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap();
+                (text, e.error_len().unwrap_or(bytes.len() - e.valid_up_to()))
+            }
+        };
+        for c in text.chars() {
+            match c {
+                '"' => json.push_str("\\\""),
+                '\\' => json.push_str("\\\\"),
+                '\u{8}' => json.push_str("\\b"),
+                '\u{c}' => json.push_str("\\f"),
+                '\n' => json.push_str("\\n"),
+                '\r' => json.push_str("\\r"),
+                '\t' => json.push_str("\\t"),
+                c if c < ' ' || c == '\u{7f}' => json.push_str(&format!("\\u{:04x}", c as u32)),
+                c => json.push(c),
+            }
+        }
+        let end = text.len() + invalid;
+        for byte in &bytes[text.len()..end] {
+            json.push_str(&format!("\\u{:04x}", byte));
+        }
+        bytes = &bytes[end..];
+    }
+    json.push('"');
+    json
+}
+
+/// A value as an SQL literal that gives it back exactly. Text with control characters in it
+/// is written with `unistr()`, which has escapes for them.
+fn literal(value: &Value) -> String {
+    match value {
+        Value::Real(v) => format_real_exact(*v),
+        Value::Text(v) if v.chars().any(|c| c < ' ') => {
+            let mut text = String::new();
+            for c in v.chars() {
+                match c {
+                    '\'' => text.push_str("''"),
+                    '\\' => text.push_str("\\\\"),
+                    c if c < ' ' => text.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => text.push(c),
+                }
+            }
+            format!("unistr('{}')", text)
+        }
+        Value::Blob(v) => {
+            let hex: String = v.iter().map(|byte| format!("{:02x}", byte)).collect();
+            format!("X'{}'", hex)
+        }
+        value => value.quote(),
+    }
+}
@@ -0,0 +1,159 @@
+//! What `ANALYZE` found out about the tables and indexes of a database, as stored in the
+//! `sqlite_stat1` and `sqlite_stat4` tables, which the planner uses to estimate how many rows
+//! reading a table or looking up an index produces. See
+//! [the format of these tables](https://www.sqlite.org/fileformat.html#the_sqlite_stat1_table).
+
+use crate::btree::scan_table;
+use crate::database::Database;
+use crate::record::parse_record;
+use crate::value::{Collation, Value};
+use anyhow::Result;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// The statistics of the tables and indexes that were analyzed, by lowercase name
+#[derive(Debug, Default)]
+pub struct Statistics {
+    tables: HashMap<String, f64>,
+    indexes: HashMap<String, IndexStatistics>,
+}
+
+#[derive(Debug, Default)]
+pub struct IndexStatistics {
+    /// Number of entries in the index, followed by the average number of entries that share
+    /// the values of the first 1, 2, ... columns
+    pub rows: Vec<f64>,
+    /// Entries of the index picked by `sqlite_stat4`, in index order
+    pub samples: Vec<Sample>,
+}
+
+/// An entry of an index, along with how many entries are equal to it
+#[derive(Debug)]
+pub struct Sample {
+    /// Number of entries whose first 1, 2, ... columns equal those of the sample
+    pub equal: Vec<f64>,
+    /// The values of the entry, the rowid being the last one
+    pub values: Vec<Value>,
+}
+
+impl Statistics {
+    /// Reads `sqlite_stat1` and `sqlite_stat4`, when the database has them
+    pub fn load(db: &Database) -> Result<Self> {
+        let mut statistics = Statistics::default();
+        if let Ok(schema) = db.table("sqlite_stat1") {
+            for row in scan_table(db, schema.root_page)? {
+                let (_, payload) = row?;
+                let record: Vec<Value> = parse_record(&payload, 3)?
+                    .into_iter()
+                    .map(Value::from)
+                    .collect();
+                let (table, index, stat) = match &record[..] {
+                    [Value::Text(table), index, Value::Text(stat)] => (table, index, stat),
+                    _ => continue,
+                };
+                let rows = numbers(stat);
+                let total = match rows.first() {
+                    Some(&total) => total,
+                    None => continue,
+                };
+                // A table without indexes has a row of its own, and else each index gives the
+                // number of rows of the table
+                statistics.tables.insert(table.to_lowercase(), total);
+                if let Value::Text(index) = index {
+                    statistics
+                        .indexes
+                        .entry(index.to_lowercase())
+                        .or_default()
+                        .rows = rows;
+                }
+            }
+        }
+
+        if let Ok(schema) = db.table("sqlite_stat4") {
+            for row in scan_table(db, schema.root_page)? {
+                let (_, payload) = row?;
+                let record: Vec<Value> = parse_record(&payload, 6)?
+                    .into_iter()
+                    .map(Value::from)
+                    .collect();
+                let (index, equal, sample) = match &record[..] {
+                    [_, Value::Text(index), Value::Text(equal), _, _, Value::Blob(sample)] => {
+                        (index, equal, sample)
+                    }
+                    _ => continue,
+                };
+                let equal = numbers(equal);
+                let values = parse_record(sample, equal.len())?
+                    .into_iter()
+                    .map(Value::from)
+                    .collect();
+                statistics
+                    .indexes
+                    .entry(index.to_lowercase())
+                    .or_default()
+                    .samples
+                    .push(Sample { equal, values });
+            }
+        }
+        Ok(statistics)
+    }
+
+    /// Number of rows of a table, if it was analyzed
+    pub fn table_rows(&self, table: &str) -> Option<f64> {
+        self.tables.get(&table.to_lowercase()).copied()
+    }
+
+    /// Statistics of an index, if it was analyzed
+    pub fn index(&self, index: &str) -> Option<&IndexStatistics> {
+        self.indexes
+            .get(&index.to_lowercase())
+            .filter(|index| !index.rows.is_empty())
+    }
+}
+
+impl IndexStatistics {
+    /// Estimated number of entries whose first column equals `value`. A sample with that value
+    /// tells exactly; other values share the entries that no sample has the value of.
+    pub fn rows_equal(&self, value: &Value, collation: Collation) -> Option<f64> {
+        if self.samples.is_empty() || self.rows.len() < 2 {
+            return None;
+        }
+        let mut sampled: Vec<&Sample> = vec![];
+        for sample in &self.samples {
+            match sample.values.first() {
+                Some(first) if first.compare(value, collation) == Ordering::Equal => {
+                    return sample.equal.first().copied();
+                }
+                Some(first) => {
+                    // Samples with the same first value count once
+                    let repeated = sampled.last().map(|last| &last.values[0]);
+                    if repeated.map(|last| last.compare(first, collation)) != Some(Ordering::Equal)
+                    {
+                        sampled.push(sample);
+                    }
+                }
+                None => {}
+            }
+        }
+        let total = self.rows[0];
+        let distinct = (total / self.rows[1]).max(1.0);
+        let sampled_rows: f64 = sampled
+            .iter()
+            .filter_map(|sample| sample.equal.first())
+            .sum();
+        let others = (distinct - sampled.len() as f64).max(1.0);
+        Some(((total - sampled_rows) / others).max(1.0))
+    }
+}
+
+/// The numbers of a statistics string, leaving out options such as `unordered` and `sz=N`
+fn numbers(stat: &str) -> Vec<f64> {
+    let mut numbers = vec![];
+    for word in stat.split_whitespace() {
+        match word.parse() {
+            Ok(number) => numbers.push(number),
+            Err(_) => break,
+        }
+    }
+    numbers
+}
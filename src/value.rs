@@ -0,0 +1,573 @@
+use crate::record::ColumnValue;
+use std::cmp::Ordering;
+use std::fmt::Display;
+
+/// A dynamically typed SQL value, as described in
+/// [datatype3](https://www.sqlite.org/datatype3.html)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+/// Column affinity, derived from the declared type of a column
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Affinity {
+    Text,
+    Numeric,
+    Integer,
+    Real,
+    Blob,
+}
+
+impl Affinity {
+    /// Determines the affinity of a declared column type using the rules in
+    /// [affinity](https://www.sqlite.org/datatype3.html#determination_of_column_affinity)
+    pub fn from_type_name(type_name: &str) -> Self {
+        let type_name = type_name.to_ascii_uppercase();
+
+        if type_name.contains("INT") {
+            Affinity::Integer
+        } else if type_name.contains("CHAR")
+            || type_name.contains("CLOB")
+            || type_name.contains("TEXT")
+        {
+            Affinity::Text
+        } else if type_name.is_empty() || type_name.contains("BLOB") {
+            Affinity::Blob
+        } else if type_name.contains("REAL")
+            || type_name.contains("FLOA")
+            || type_name.contains("DOUB")
+        {
+            Affinity::Real
+        } else {
+            Affinity::Numeric
+        }
+    }
+
+    pub fn is_numeric(self) -> bool {
+        matches!(self, Affinity::Numeric | Affinity::Integer | Affinity::Real)
+    }
+}
+
+/// Collating sequences built into SQLite
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+pub enum Collation {
+    Binary,
+    NoCase,
+    RTrim,
+}
+
+impl Collation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_uppercase().as_str() {
+            "BINARY" => Some(Collation::Binary),
+            "NOCASE" => Some(Collation::NoCase),
+            "RTRIM" => Some(Collation::RTrim),
+            _ => None,
+        }
+    }
+
+    /// Compares two strings using this collating sequence
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Binary => a.as_bytes().cmp(b.as_bytes()),
+            Collation::NoCase => a
+                .bytes()
+                .map(|c| c.to_ascii_lowercase())
+                .cmp(b.bytes().map(|c| c.to_ascii_lowercase())),
+            Collation::RTrim => a
+                .trim_end_matches(' ')
+                .as_bytes()
+                .cmp(b.trim_end_matches(' ').as_bytes()),
+        }
+    }
+}
+
+impl<'a> From<ColumnValue<'a>> for Value {
+    fn from(column: ColumnValue<'a>) -> Self {
+        // Integers are stored as big-endian twos-complement numbers, so they have to be sign
+        // extended from their stored width
+        match column {
+            ColumnValue::Null => Value::Null,
+            ColumnValue::U8(v) => Value::Integer(v as i8 as i64),
+            ColumnValue::U16(v) => Value::Integer(v as i16 as i64),
+            ColumnValue::U24(v) => Value::Integer(((v << 8) as i32 >> 8) as i64),
+            ColumnValue::U32(v) => Value::Integer(v as i32 as i64),
+            ColumnValue::U48(v) => Value::Integer(((v << 16) as i64) >> 16),
+            ColumnValue::U64(v) => Value::Integer(v as i64),
+            ColumnValue::FP64(v) => Value::Real(v),
+            ColumnValue::False => Value::Integer(0),
+            ColumnValue::True => Value::Integer(1),
+            ColumnValue::Blob(v) => Value::Blob(v.to_vec()),
+            ColumnValue::Text(v) => Value::Text(String::from_utf8_lossy(v).into_owned()),
+        }
+    }
+}
+
+impl Value {
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Name of the storage class, as returned by `typeof()`
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Integer(_) => "integer",
+            Value::Real(_) => "real",
+            Value::Text(_) => "text",
+            Value::Blob(_) => "blob",
+        }
+    }
+
+    /// Interprets the value as a boolean. NULL is neither true nor false.
+    pub fn truth(&self) -> Option<bool> {
+        match self.to_number() {
+            Value::Null => None,
+            Value::Integer(v) => Some(v != 0),
+            Value::Real(v) => Some(v != 0.0),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Converts the value to INTEGER or REAL the way arithmetic operators do: text is read up
+    /// to the longest numeric prefix, and anything that isn't a number becomes 0.
+    pub fn to_number(&self) -> Value {
+        match self {
+            Value::Null => Value::Null,
+            Value::Integer(v) => Value::Integer(*v),
+            Value::Real(v) => Value::Real(*v),
+            Value::Text(v) => parse_numeric_prefix(v.as_bytes()),
+            Value::Blob(v) => parse_numeric_prefix(v),
+        }
+    }
+
+    /// Negates the value like the unary `-` operator, which makes a number of it first
+    pub fn negate(&self) -> Value {
+        match self.to_number() {
+            Value::Integer(v) => match v.checked_neg() {
+                Some(v) => Value::Integer(v),
+                None => Value::Real(-(v as f64)),
+            },
+            Value::Real(v) => Value::Real(-v),
+            _ => Value::Null,
+        }
+    }
+
+    /// Converts the value to an integer like `CAST(x AS INTEGER)`
+    pub fn to_integer(&self) -> Option<i64> {
+        match self.to_number() {
+            Value::Null => None,
+            Value::Integer(v) => Some(v),
+            Value::Real(v) => Some(real_to_integer(v)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Converts the value to a floating point number like `CAST(x AS REAL)`
+    pub fn to_real(&self) -> Option<f64> {
+        match self.to_number() {
+            Value::Null => None,
+            Value::Integer(v) => Some(v as f64),
+            Value::Real(v) => Some(v),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The value as an SQL literal, like `quote()` gives it
+    pub fn quote(&self) -> String {
+        match self {
+            Value::Null => "NULL".to_string(),
+            Value::Integer(v) => v.to_string(),
+            Value::Real(v) => format_real(*v),
+            Value::Text(v) => format!("'{}'", v.replace('\'', "''")),
+            Value::Blob(v) => format!(
+                "X'{}'",
+                v.iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect::<String>()
+            ),
+        }
+    }
+
+    /// Converts the value to its text representation, keeping NULL as NULL
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            v => Some(v.to_string()),
+        }
+    }
+
+    /// Applies column affinity to a value as described in
+    /// [affinity](https://www.sqlite.org/datatype3.html#type_affinity)
+    pub fn apply_affinity(self, affinity: Affinity) -> Value {
+        match (affinity, self) {
+            (Affinity::Blob, v) => v,
+            (Affinity::Text, v @ Value::Integer(_)) | (Affinity::Text, v @ Value::Real(_)) => {
+                Value::Text(v.to_string())
+            }
+            (Affinity::Text, v) => v,
+            (Affinity::Real, v) => match v.apply_affinity(Affinity::Numeric) {
+                Value::Integer(i) => Value::Real(i as f64),
+                v => v,
+            },
+            (_, Value::Text(text)) => match parse_numeric(&text) {
+                Some(Value::Real(r)) if r.fract() == 0.0 && r.abs() < 9.2e18 => {
+                    Value::Integer(r as i64)
+                }
+                Some(number) => number,
+                None => Value::Text(text),
+            },
+            (_, Value::Real(r)) if r.fract() == 0.0 && r.abs() < 9.2e18 => Value::Integer(r as i64),
+            (_, v) => v,
+        }
+    }
+
+    /// Converts the value like `CAST(value AS type)` for a type with the given affinity
+    pub fn cast(self, affinity: Affinity) -> Value {
+        match (affinity, self) {
+            (_, Value::Null) => Value::Null,
+            (Affinity::Text, Value::Blob(v)) => {
+                Value::Text(String::from_utf8_lossy(&v).into_owned())
+            }
+            (Affinity::Text, v) => Value::Text(v.to_string()),
+            (Affinity::Blob, Value::Text(v)) => Value::Blob(v.into_bytes()),
+            (Affinity::Blob, v @ Value::Blob(_)) => v,
+            (Affinity::Blob, v) => Value::Blob(v.to_string().into_bytes()),
+            (Affinity::Integer, v @ Value::Integer(_)) => v,
+            (Affinity::Integer, Value::Real(v)) => Value::Integer(real_to_integer(v)),
+            // Text is read up to the end of its integer prefix, so '1e3' becomes 1
+            (Affinity::Integer, Value::Text(v)) => {
+                Value::Integer(parse_integer_prefix(v.as_bytes()))
+            }
+            (Affinity::Integer, Value::Blob(v)) => Value::Integer(parse_integer_prefix(&v)),
+            (Affinity::Real, v) => Value::Real(v.to_real().unwrap_or(0.0)),
+            (Affinity::Numeric, v @ Value::Integer(_))
+            | (Affinity::Numeric, v @ Value::Real(_)) => v,
+            (Affinity::Numeric, v) => match v.to_number() {
+                Value::Real(r) if r.fract() == 0.0 && r.abs() < 9.2e18 => Value::Integer(r as i64),
+                v => v,
+            },
+        }
+    }
+
+    /// Total ordering used for comparisons, sorting and grouping:
+    /// NULL < INTEGER, REAL < TEXT < BLOB
+    pub fn compare(&self, other: &Value, collation: Collation) -> Ordering {
+        match (self, other) {
+            (Value::Null, Value::Null) => Ordering::Equal,
+            (Value::Null, _) => Ordering::Less,
+            (_, Value::Null) => Ordering::Greater,
+
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Real(b)) => compare_integer_real(*a, *b),
+            (Value::Real(a), Value::Integer(b)) => compare_integer_real(*b, *a).reverse(),
+            (Value::Real(a), Value::Real(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Integer(_), _) | (Value::Real(_), _) => Ordering::Less,
+            (_, Value::Integer(_)) | (_, Value::Real(_)) => Ordering::Greater,
+
+            (Value::Text(a), Value::Text(b)) => collation.compare(a, b),
+            (Value::Text(_), Value::Blob(_)) => Ordering::Less,
+            (Value::Blob(_), Value::Text(_)) => Ordering::Greater,
+            (Value::Blob(a), Value::Blob(b)) => a.cmp(b),
+        }
+    }
+}
+
+fn compare_integer_real(a: i64, b: f64) -> Ordering {
+    if b.is_nan() {
+        return Ordering::Greater;
+    }
+    if b >= 9.3e18 {
+        return Ordering::Less;
+    }
+    if b <= -9.3e18 {
+        return Ordering::Greater;
+    }
+    let truncated = b.trunc() as i64;
+    match a.cmp(&truncated) {
+        Ordering::Equal => 0.0.partial_cmp(&b.fract()).unwrap_or(Ordering::Equal),
+        ordering => ordering,
+    }
+}
+
+/// Reads the optionally signed integer at the start of some text, after leading spaces.
+/// Integers that are too large saturate at the bounds of i64.
+fn parse_integer_prefix(text: &[u8]) -> i64 {
+    let mut bytes = text
+        .iter()
+        .skip_while(|b| b.is_ascii_whitespace())
+        .peekable();
+    let negative = match bytes.peek() {
+        Some(b'-') => {
+            bytes.next();
+            true
+        }
+        Some(b'+') => {
+            bytes.next();
+            false
+        }
+        _ => false,
+    };
+
+    let mut value: i64 = 0;
+    for digit in bytes.take_while(|b| b.is_ascii_digit()) {
+        let digit = (digit - b'0') as i64;
+        value = match value.checked_mul(10).and_then(|v| {
+            if negative {
+                v.checked_sub(digit)
+            } else {
+                v.checked_add(digit)
+            }
+        }) {
+            Some(v) => v,
+            None if negative => return i64::MIN,
+            None => return i64::MAX,
+        };
+    }
+    value
+}
+
+/// Converts a floating point number to an integer, saturating at the bounds of i64
+pub fn real_to_integer(v: f64) -> i64 {
+    if v.is_nan() {
+        0
+    } else if v >= 9.223372036854776e18 {
+        i64::MAX
+    } else if v <= -9.223372036854776e18 {
+        i64::MIN
+    } else {
+        v as i64
+    }
+}
+
+/// Parses text that is entirely a well-formed integer or real literal, ignoring surrounding
+/// spaces. Returns None when the text is not a number.
+pub fn parse_numeric(text: &str) -> Option<Value> {
+    let trimmed = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (value, consumed) = scan_number(trimmed.as_bytes())?;
+    if consumed == trimmed.len() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn parse_numeric_prefix(bytes: &[u8]) -> Value {
+    let start = bytes
+        .iter()
+        .position(|c| !c.is_ascii_whitespace())
+        .unwrap_or(bytes.len());
+    scan_number(&bytes[start..])
+        .map(|(value, _)| value)
+        .unwrap_or(Value::Integer(0))
+}
+
+/// Scans the longest numeric prefix of `bytes`, returning the number and the bytes consumed
+fn scan_number(bytes: &[u8]) -> Option<(Value, usize)> {
+    let mut i = 0;
+    if i < bytes.len() && (bytes[i] == b'+' || bytes[i] == b'-') {
+        i += 1;
+    }
+
+    let digits_start = i;
+    while i < bytes.len() && bytes[i].is_ascii_digit() {
+        i += 1;
+    }
+    let mut digits = i - digits_start;
+    let mut is_real = false;
+
+    if i < bytes.len() && bytes[i] == b'.' {
+        let fraction_start = i + 1;
+        let mut j = fraction_start;
+        while j < bytes.len() && bytes[j].is_ascii_digit() {
+            j += 1;
+        }
+        if digits > 0 || j > fraction_start {
+            digits += j - fraction_start;
+            is_real = true;
+            i = j;
+        }
+    }
+
+    if digits == 0 {
+        return None;
+    }
+
+    if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+        let mut j = i + 1;
+        if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+            j += 1;
+        }
+        let exponent_start = j;
+        while j < bytes.len() && bytes[j].is_ascii_digit() {
+            j += 1;
+        }
+        if j > exponent_start {
+            is_real = true;
+            i = j;
+        }
+    }
+
+    let text = std::str::from_utf8(&bytes[..i]).ok()?;
+    if !is_real {
+        if let Ok(v) = text.parse::<i64>() {
+            return Some((Value::Integer(v), i));
+        }
+    }
+    text.parse::<f64>().ok().map(|v| (Value::Real(v), i))
+}
+
+/// Formats a floating point number the way SQLite does (`%!.15g`)
+pub fn format_real(v: f64) -> String {
+    if v.is_nan() {
+        return String::new();
+    }
+    if v.is_infinite() {
+        return if v > 0.0 { "Inf" } else { "-Inf" }.to_string();
+    }
+    if v == 0.0 {
+        return "0.0".to_string();
+    }
+    format_scientific(&format!("{:.14e}", v), 15)
+}
+
+/// Formats a floating point number with enough digits to read back as the same number, the way
+/// the `sqlite3` shell writes it in SQL literals and JSON (`%!.20g`)
+pub fn format_real_exact(v: f64) -> String {
+    if v.is_infinite() {
+        return if v > 0.0 { "9.0e+999" } else { "-9.0e+999" }.to_string();
+    }
+    if v == 0.0 {
+        return "0.0".to_string();
+    }
+    let (digits, exponent) = decimal_digits(v.abs());
+    let digits = digits.to_string();
+    let sign = if v < 0.0 { "-" } else { "" };
+    let exponent = exponent + digits.len() as i32 - 1;
+    let scientific = format!("{}{}.{}e{}", sign, &digits[..1], &digits[1..], exponent);
+    format_scientific(&scientific, 20)
+}
+
+/// The digits of a positive number and the power of ten they're multiplied by, worked out like
+/// SQLite does: the number is scaled to 18 or 19 digits before the point, with the products kept
+/// as pairs of doubles for their precision. The last digit is sometimes off by one from the exact
+/// decimal expansion, but SQLite shows the same one.
+fn decimal_digits(v: f64) -> (u64, i32) {
+    let mut x = (v, 0.0);
+    let mut exponent = 0;
+    if x.0 > 9.223_372_036_854_775e18 {
+        while x.0 > 9.223_372_036_854_774e118 {
+            exponent += 100;
+            x = dekker_multiply(x, 1.0e-100, -1.999_189_980_260_288_3e-117);
+        }
+        while x.0 > 9.223_372_036_854_774e28 {
+            exponent += 10;
+            x = dekker_multiply(x, 1.0e-10, -3.643_219_731_549_774e-27);
+        }
+        while x.0 > 9.223_372_036_854_775e18 {
+            exponent += 1;
+            x = dekker_multiply(x, 1.0e-1, -5.551_115_123_125_783e-18);
+        }
+    } else {
+        while x.0 < 9.223_372_036_854_775e-83 {
+            exponent -= 100;
+            x = dekker_multiply(x, 1.0e100, -1.590_289_110_975_991_8e83);
+        }
+        while x.0 < 9.223_372_036_854_775e7 {
+            exponent -= 10;
+            x = dekker_multiply(x, 1.0e10, 0.0);
+        }
+        while x.0 < 9.223_372_036_854_775e17 {
+            exponent -= 1;
+            x = dekker_multiply(x, 1.0e1, 0.0);
+        }
+    }
+    let digits = match x.1 < 0.0 {
+        true => x.0 as u64 - (-x.1) as u64,
+        false => x.0 as u64 + x.1 as u64,
+    };
+    (digits, exponent)
+}
+
+/// Multiplies a number kept as the sum of two doubles by `y + yy`, with Dekker's algorithm for
+/// the exact product of two doubles
+fn dekker_multiply(x: (f64, f64), y: f64, yy: f64) -> (f64, f64) {
+    // The upper halves of the significands, whose products are exact
+    let split = |v: f64| f64::from_bits(v.to_bits() & 0xffff_ffff_fc00_0000);
+    let (hx, hy) = (split(x.0), split(y));
+    let (tx, ty) = (x.0 - hx, y - hy);
+    let p = hx * hy;
+    let q = hx * ty + tx * hy;
+    let c = p + q;
+    let cc = p - c + q + tx * ty;
+    let cc = x.0 * yy + x.1 * y + cc;
+    let high = c + cc;
+    (high, c - high + cc)
+}
+
+/// Lays out a number that `{:e}` formatted like `%!g` does with the given precision: without
+/// an exponent unless it's too large or small, and with no trailing zeros after the point
+/// but at least one digit
+fn format_scientific(scientific: &str, precision: i32) -> String {
+    let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap());
+    let exponent: i32 = exponent[1..].parse().unwrap();
+    let negative = mantissa.starts_with('-');
+    let digits: String = mantissa.chars().filter(|c| c.is_ascii_digit()).collect();
+    let digits = digits.trim_end_matches('0');
+    let digits = if digits.is_empty() { "0" } else { digits };
+    let sign = if negative { "-" } else { "" };
+
+    if !(-4..precision).contains(&exponent) {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        let exponent_sign = if exponent < 0 { '-' } else { '+' };
+        format!(
+            "{}{}.{}e{}{:02}",
+            sign,
+            &digits[..1],
+            fraction,
+            exponent_sign,
+            exponent.abs()
+        )
+    } else if exponent < 0 {
+        format!(
+            "{}0.{}{}",
+            sign,
+            "0".repeat((-exponent - 1) as usize),
+            digits
+        )
+    } else {
+        let integer_digits = exponent as usize + 1;
+        if digits.len() <= integer_digits {
+            format!(
+                "{}{}{}.0",
+                sign,
+                digits,
+                "0".repeat(integer_digits - digits.len())
+            )
+        } else {
+            format!(
+                "{}{}.{}",
+                sign,
+                &digits[..integer_digits],
+                &digits[integer_digits..]
+            )
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => f.write_str(""),
+            Value::Integer(v) => f.write_str(&v.to_string()),
+            Value::Real(v) => f.write_str(&format_real(*v)),
+            Value::Text(v) => f.write_str(v),
+            Value::Blob(v) => f.write_str(&String::from_utf8_lossy(v)),
+        }
+    }
+}
@@ -21,7 +21,7 @@ pub fn parse_varint(stream: &[u8]) -> (usize, usize) {
 /// Usable size is either 8 or 7
 fn usable_value(usable_size: u8, byte: u8) -> u8 {
     if usable_size == 8 {
-        usable_size
+        byte
     } else {
         byte & LAST_SEVEN_BITS_MASK
     }
@@ -30,7 +30,7 @@ fn usable_value(usable_size: u8, byte: u8) -> u8 {
 fn read_usable_bytes(stream: &[u8]) -> Vec<u8> {
     let mut usable_bytes = vec![];
 
-    for &byte in stream.iter().take(8) {
+    for &byte in stream.iter().take(9) {
         usable_bytes.push(byte);
         if starts_with_zero(byte) {
             break;
@@ -43,3 +43,27 @@ fn read_usable_bytes(stream: &[u8]) -> Vec<u8> {
 fn starts_with_zero(byte: u8) -> bool {
     (byte & IS_FIRST_BIT_ZERO_MASK) == 0
 }
+
+/// Encodes a value as a SQLite varint, the inverse of `parse_varint`
+pub fn encode_varint(value: u64) -> Vec<u8> {
+    // Values that need all 64 bits use 9 bytes, with every bit of the last byte being used
+    if value > 0x00ff_ffff_ffff_ffff {
+        let mut bytes = vec![0; 9];
+        bytes[8] = value as u8;
+        let mut rest = value >> 8;
+        for byte in bytes[..8].iter_mut().rev() {
+            *byte = (rest as u8 & LAST_SEVEN_BITS_MASK) | IS_FIRST_BIT_ZERO_MASK;
+            rest >>= 7;
+        }
+        return bytes;
+    }
+
+    let mut bytes = vec![value as u8 & LAST_SEVEN_BITS_MASK];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        bytes.push((rest as u8 & LAST_SEVEN_BITS_MASK) | IS_FIRST_BIT_ZERO_MASK);
+        rest >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
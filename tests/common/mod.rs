@@ -0,0 +1,74 @@
+//! Helpers shared by the integration tests, each of which uses some of them
+
+#![allow(dead_code)]
+
+use anyhow::Result;
+use sqlite_starter_rust::ast::Statement;
+use sqlite_starter_rust::database::Database;
+use sqlite_starter_rust::parser::parse_statement;
+use sqlite_starter_rust::query;
+use sqlite_starter_rust::value::Value;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// `emp(id, name, dept, salary, mgr)` with 12 rows, indexes on `dept`, `salary` and
+/// `lower(name)`, and statistics from ANALYZE
+pub const EMP: &[u8] = include_bytes!("../fixtures/emp.db");
+
+/// The path of the same database, for the shell to open
+pub const EMP_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/emp.db");
+
+/// Whether the shell succeeds when run with the arguments, and what it writes to standard output
+/// and standard error
+pub fn shell(args: &[&str]) -> (bool, String, String) {
+    shell_with_input(args, "")
+}
+
+/// The same, for a shell that reads its commands from `input`
+pub fn shell_with_input(args: &[&str], input: &str) -> (bool, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the shell runs");
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(input.as_bytes())
+        .expect("the shell reads its input");
+    drop(stdin);
+    let output = child.wait_with_output().expect("the shell runs");
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+/// `p(n, s, b, x)` with partial indexes `p_eng` on `n` where `s = 'eng'` and `p_b` on `x` where
+/// `b = 3`, and `e(id, name, v)` with indexes `e_lower` on `lower(name)` and `e_two` on
+/// `upper(name), v + 1`
+pub const INDEXES: &[u8] = include_bytes!("../fixtures/indexes.db");
+
+/// The rows of a SELECT statement run against the contents of a database file
+pub fn select(data: &[u8], sql: &str) -> Result<Vec<Vec<Value>>> {
+    let database = Database::new(data.to_vec())?;
+    let select = match parse_statement(sql)? {
+        Statement::Select(select) => select,
+        statement => panic!("not a SELECT: {:?}", statement),
+    };
+    let rows = query::select(&database, &select)?.rows;
+    rows.collect()
+}
+
+/// The details of the steps of the plan EXPLAIN QUERY PLAN shows for a SELECT statement
+pub fn explain(data: &[u8], sql: &str) -> Result<Vec<String>> {
+    let database = Database::new(data.to_vec())?;
+    let select = match parse_statement(&format!("explain query plan {}", sql))? {
+        Statement::ExplainQueryPlan(select) => select,
+        statement => panic!("not a SELECT: {:?}", statement),
+    };
+    let rows = query::explain(&database, &select)?.rows;
+    rows.map(|row| Ok(row?[3].to_string())).collect()
+}
@@ -0,0 +1,129 @@
+//! Reading a covering index instead of its table, which is only done when the index has every
+//! value the query reads, such as the columns a window reads
+
+mod common;
+
+use anyhow::Result;
+use common::{explain, EMP, INDEXES};
+use sqlite_starter_rust::value::Value;
+
+fn select(sql: &str) -> Result<Vec<Vec<Value>>> {
+    common::select(EMP, sql)
+}
+
+#[test]
+fn window_argument_outside_every_index() -> Result<()> {
+    let total: f64 = (1..=12)
+        .map(|i| 1000.1 * i as f64 + i as f64 * 0.0137)
+        .sum();
+    let rows = select("select id, sum(salary) over () from emp")?;
+    assert_eq!(rows.len(), 12);
+    for row in rows {
+        match row[1] {
+            Value::Real(sum) => assert!((sum - total).abs() < 1e-6),
+            ref value => panic!("expected the sum of the salaries, got {:?}", value),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn window_function_argument_and_keys() -> Result<()> {
+    let rows = select("select id, lag(name) over (order by dept, id) from emp where dept = 'eng'")?;
+    let lags: Vec<_> = rows.into_iter().map(|row| row[1].clone()).collect();
+    let expected = vec![
+        Value::Null,
+        Value::Text("N3".to_string()),
+        Value::Text("N6".to_string()),
+        Value::Text("N9".to_string()),
+    ];
+    assert_eq!(lags, expected);
+
+    let rows = select(
+        "select sum(mgr) over (order by dept, id rows 1 preceding) from emp where dept = 'sales'",
+    )?;
+    let sums: Vec<_> = rows.into_iter().map(|row| row[0].clone()).collect();
+    // mgr is id % 7 for ids 1, 4, 7 and 10
+    let expected: Vec<_> = [1, 5, 4, 3]
+        .iter()
+        .map(|&sum| Value::Integer(sum))
+        .collect();
+    assert_eq!(sums, expected);
+    Ok(())
+}
+
+#[test]
+fn window_ordered_by_column_outside_the_chosen_index() -> Result<()> {
+    let rows = select("select id, row_number() over (order by salary) from emp")?;
+    for row in rows {
+        assert_eq!(row[0], row[1]);
+    }
+    Ok(())
+}
+
+fn text(text: &str) -> Value {
+    Value::Text(text.to_string())
+}
+
+#[test]
+fn partial_index_constants() -> Result<()> {
+    // Every entry of p_eng is for a row where s is 'eng', so s needn't be read from the table
+    let sql = "select s, n from p where s = 'eng' and n > 1";
+    assert_eq!(
+        explain(INDEXES, sql)?,
+        ["SEARCH p USING COVERING INDEX p_eng (n>?)"]
+    );
+    let expected = vec![
+        vec![text("eng"), Value::Integer(3)],
+        vec![text("eng"), Value::Integer(5)],
+    ];
+    assert_eq!(common::select(INDEXES, sql)?, expected);
+
+    let sql = "select s || n from p where s = 'eng' and n < 5";
+    assert_eq!(
+        explain(INDEXES, sql)?,
+        ["SEARCH p USING COVERING INDEX p_eng (n<?)"]
+    );
+    assert_eq!(
+        common::select(INDEXES, sql)?,
+        vec![vec![text("eng1")], vec![text("eng3")]]
+    );
+
+    // Like SQLite, a column without an affinity isn't taken to have the constant
+    assert_eq!(
+        explain(INDEXES, "select b, x from p where b = 3 and x > 2")?,
+        ["SEARCH p USING INDEX p_b (x>?)"]
+    );
+    Ok(())
+}
+
+#[test]
+fn indexed_expressions() -> Result<()> {
+    // The values of lower(name) are read from the index, which doesn't have name itself
+    let sql = "select lower(name) from e where lower(name) > 'b'";
+    assert_eq!(
+        explain(INDEXES, sql)?,
+        ["SEARCH e USING COVERING INDEX e_lower (<expr>>?)"]
+    );
+    let expected = vec![vec![text("bob")], vec![text("cat")], vec![text("dan")]];
+    assert_eq!(common::select(INDEXES, sql)?, expected);
+
+    let sql = "select upper(name), v + 1 from e where upper(name) = 'BOB'";
+    assert_eq!(
+        explain(INDEXES, sql)?,
+        ["SEARCH e USING COVERING INDEX e_two (<expr>=?)"]
+    );
+    assert_eq!(
+        common::select(INDEXES, sql)?,
+        vec![vec![text("BOB"), Value::Real(3.5)]]
+    );
+
+    let sql = "select upper(name) from e where upper(name) > 'B' order by upper(name)";
+    assert_eq!(
+        explain(INDEXES, sql)?,
+        ["SEARCH e USING COVERING INDEX e_two (<expr>>?)"]
+    );
+    let expected = vec![vec![text("BOB")], vec![text("CAT")], vec![text("DAN")]];
+    assert_eq!(common::select(INDEXES, sql)?, expected);
+    Ok(())
+}
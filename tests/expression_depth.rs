@@ -0,0 +1,53 @@
+//! The limits on how deeply expressions can nest, which are checked while parsing
+
+mod common;
+
+use common::{shell, EMP_PATH};
+
+const TOO_LARGE: &str = "Expression tree is too large (maximum depth 1000)";
+
+fn sum(terms: usize) -> String {
+    format!("select {}", vec!["1"; terms].join(" + "))
+}
+
+fn nested(open: &str, inner: &str, levels: usize) -> String {
+    format!(
+        "select {}{}{}",
+        open.repeat(levels),
+        inner,
+        ")".repeat(levels)
+    )
+}
+
+#[test]
+fn parentheses_add_no_depth() {
+    let sql = nested("(", "1", 2000);
+    assert_eq!(
+        shell(&[EMP_PATH, &sql]),
+        (true, "1\n".to_string(), String::new())
+    );
+}
+
+#[test]
+fn deepest_expressions() {
+    assert_eq!(shell(&[EMP_PATH, &sum(1000)]).1, "1000\n");
+    assert_eq!(shell(&[EMP_PATH, &nested("abs(", "-1", 998)]).1, "1\n");
+}
+
+#[test]
+fn too_deep_expressions() {
+    let negated = format!("select {}1", "- ".repeat(1000));
+    for sql in &[sum(1001), sum(20000), nested("abs(", "-1", 999), negated] {
+        let (success, output, error) = shell(&[EMP_PATH, sql]);
+        assert!(!success);
+        assert_eq!(output, "");
+        assert!(error.contains(TOO_LARGE), "{}", error);
+    }
+}
+
+#[test]
+fn too_deeply_nested() {
+    let (success, _, error) = shell(&[EMP_PATH, &nested("(", "1", 10_000)]);
+    assert!(!success);
+    assert!(error.contains("parser stack overflow"), "{}", error);
+}
@@ -0,0 +1,54 @@
+//! LIMIT and OFFSET on rows that are sorted after being read
+
+mod common;
+
+use anyhow::Result;
+use common::{select, EMP};
+use sqlite_starter_rust::value::Value;
+
+fn ids(rows: Vec<Vec<Value>>) -> Vec<i64> {
+    rows.into_iter()
+        .map(|row| match row[0] {
+            Value::Integer(id) => id,
+            ref value => panic!("expected an id, got {:?}", value),
+        })
+        .collect()
+}
+
+#[test]
+fn huge_limit_keeps_every_row() -> Result<()> {
+    // No index has mgr, so the rows are sorted, keeping as many as the limit
+    let rows = select(
+        EMP,
+        "select id from emp order by mgr, id limit 9223372036854775807",
+    )?;
+    assert_eq!(ids(rows), vec![7, 1, 8, 2, 9, 3, 10, 4, 11, 5, 12, 6]);
+    let rows = select(EMP, "select id from emp order by mgr limit 100000000000")?;
+    assert_eq!(rows.len(), 12);
+    let rows = select(
+        EMP,
+        "select id from emp order by mgr, id limit 9223372036854775807 offset 10",
+    )?;
+    assert_eq!(ids(rows), vec![12, 6]);
+    Ok(())
+}
+
+#[test]
+fn huge_limit_of_compound_select() -> Result<()> {
+    let rows = select(
+        EMP,
+        "select mgr from emp union select id from emp order by 1 limit 9223372036854775807",
+    )?;
+    assert_eq!(ids(rows), (0..=12).collect::<Vec<_>>());
+    Ok(())
+}
+
+#[test]
+fn huge_offset_skips_every_row() -> Result<()> {
+    let rows = select(
+        EMP,
+        "select id from emp order by mgr limit 2 offset 9223372036854775807",
+    )?;
+    assert!(rows.is_empty());
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! The limit on how many rows a recursive common table expression may recurse on, which the
+//! shell sets with the `-recursionlimit` option and the `.recursionlimit` command
+
+mod common;
+
+use common::{shell, shell_with_input, EMP_PATH};
+
+/// Recurses on 10 rows
+const COUNT: &str = "with recursive c(x) as (select 1 union all select x + 1 from c where x < 10) \
+                     select count(*) from c";
+
+#[test]
+fn recursion_limit_option() {
+    assert_eq!(
+        shell(&["-recursionlimit", "10", EMP_PATH, COUNT]),
+        (true, "10\n".to_string(), String::new())
+    );
+    let (success, output, error) = shell(&["-recursionlimit", "9", EMP_PATH, COUNT]);
+    assert!(!success);
+    assert_eq!(output, "");
+    assert!(
+        error.contains("recursion limit of 9 rows exceeded in c"),
+        "{}",
+        error
+    );
+
+    let (success, _, error) = shell(&["-recursionlimit", "many", EMP_PATH, COUNT]);
+    assert!(!success);
+    assert!(
+        error.contains("invalid argument to -recursionlimit"),
+        "{}",
+        error
+    );
+    let (success, _, error) = shell(&[EMP_PATH, COUNT, "-recursionlimit"]);
+    assert!(!success);
+    assert!(
+        error.contains("missing argument to -recursionlimit"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn recursion_limit_command() {
+    let input = format!(
+        ".recursionlimit\n.recursionlimit 9\n.recursionlimit\n{0};\n.recursionlimit 10\n{0};\n",
+        COUNT
+    );
+    let (_, output, error) = shell_with_input(&[EMP_PATH], &input);
+    assert_eq!(output, "1000000\n9\n10\n");
+    assert!(
+        error.contains("recursion limit of 9 rows exceeded in c"),
+        "{}",
+        error
+    );
+
+    let (_, _, error) = shell_with_input(&[EMP_PATH], ".recursionlimit many\n");
+    assert!(error.contains("invalid recursion limit: many"), "{}", error);
+}
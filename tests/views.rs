@@ -0,0 +1,41 @@
+//! Views that refer to themselves, directly or through other views, subqueries and CTEs
+
+mod common;
+
+use common::select;
+use sqlite_starter_rust::value::Value;
+
+/// `t(a)` with one row, the views `v3` on itself, `v1` and `v2` on each other, `v4` and `v5`
+/// on themselves from a subquery and from a CTE, and `ok1` on `t` and `ok2` joining it twice
+const VIEWS: &[u8] = include_bytes!("fixtures/views.db");
+
+fn error(sql: &str) -> String {
+    match select(VIEWS, sql) {
+        Ok(rows) => panic!("expected an error, got {:?}", rows),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[test]
+fn view_on_itself() {
+    assert_eq!(error("select * from v3"), "view v3 is circularly defined");
+    assert_eq!(
+        error("select * from t where a in (select * from v3)"),
+        "view v3 is circularly defined"
+    );
+    assert_eq!(error("select * from v4"), "view v4 is circularly defined");
+    assert_eq!(error("select * from v5"), "view v5 is circularly defined");
+}
+
+#[test]
+fn views_on_each_other() {
+    assert_eq!(error("select * from v1"), "view v1 is circularly defined");
+    assert_eq!(error("select * from v2"), "view v2 is circularly defined");
+}
+
+#[test]
+fn view_used_twice() -> anyhow::Result<()> {
+    let rows = select(VIEWS, "select * from ok2")?;
+    assert_eq!(rows, vec![vec![Value::Integer(1), Value::Integer(1)]]);
+    Ok(())
+}